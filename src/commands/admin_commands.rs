@@ -1,5 +1,6 @@
 use super::Commands;
 
+mod bot_data;
 mod clear_bot_data;
 pub mod cotd_role;
 mod detect_message;
@@ -15,6 +16,7 @@ pub fn get_commands() -> Commands {
         cotd_role::cotd_role(),
         lua::lua(),
         clear_bot_data::clear_bot_data(),
+        bot_data::bot_data(),
         log::log(),
         detect_message::detect_message(),
         reaction_role::reaction_role(),
@@ -0,0 +1,716 @@
+use std::{collections::HashMap, str::FromStr};
+
+use poise::{
+    serenity_prelude::{
+        Attachment, ChannelId, CreateAllowedMentions, CreateAttachment, EmojiId, GuildId,
+        MessageId, ReactionType, RoleId,
+    },
+    CreateReply,
+};
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+
+use crate::{
+    managers::{
+        cotd_manager::CotdRoleData,
+        db::BotDataExport,
+        detector_manager::DetectorInfo,
+        log_manager::{LogSource, LogType},
+        lua_manager::{
+            modules::{LuaModuleInfo, MAX_MODULES_PER_GUILD},
+            LuaCommandInfo, LuaEvent, LuaEventInfo,
+        },
+        message_manager::StoredMessageData,
+    },
+    utils::IdType,
+    Context, Error,
+};
+
+/// Manage the data I have about this guild/user.
+#[poise::command(
+    slash_command,
+    subcommands("export", "import"),
+    subcommand_required,
+    install_context = "Guild",
+    interaction_context = "Guild|BotDm",
+    default_member_permissions = "ADMINISTRATOR"
+)]
+pub async fn bot_data(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Gets everything I have stored about this guild/user as a JSON file.
+#[poise::command(slash_command)]
+pub async fn export(ctx: Context<'_>) -> Result<(), Error> {
+    let id;
+
+    if let Some(guild_id) = ctx.guild_id() {
+        id = IdType::GuildId(guild_id)
+    } else {
+        id = IdType::UserId(ctx.author().id)
+    }
+
+    ctx.defer_ephemeral().await?;
+
+    let export = ctx.data().db.export_bot_data(id).await?;
+    let data = serde_json::to_string_pretty(&export)?;
+
+    // Discord doesn't allow bots to upload files bigger than this.
+    const TEN_MB_IN_BYTES: usize = 10_000_000;
+
+    if data.len() > TEN_MB_IN_BYTES {
+        ctx.send(
+            CreateReply::default()
+                .content("The data is too big to be sent as a file.")
+                .ephemeral(true),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let file_name = format!("{}_bot_data.json", id.into_db_table().replace(':', "_"));
+
+    ctx.send(
+        CreateReply::default()
+            .content(format!(
+                "Here's everything I have stored about this {}. It includes {} reminders.",
+                if id.is_user() { "user" } else { "guild" },
+                export.reminders.len()
+            ))
+            .attachment(CreateAttachment::bytes(data.as_bytes(), file_name))
+            .ephemeral(true),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Restores data from an export into this guild. Shows what will be imported before doing anything.
+#[poise::command(slash_command, guild_only)]
+pub async fn import(
+    ctx: Context<'_>,
+    #[description = "The file you got from /bot_data export."] file: Attachment,
+    #[description = "Are you sure you want to import the data? (Default: False, which only shows what would be imported)"]
+    confirmation: Option<bool>,
+) -> Result<(), Error> {
+    let confirmation = confirmation.unwrap_or(false);
+    let guild_id = ctx.guild_id().unwrap();
+
+    const TEN_MB_IN_BYTES: u32 = 10_000_000;
+
+    if file.size > TEN_MB_IN_BYTES || !file.filename.ends_with(".json") {
+        ctx.send(
+            CreateReply::default()
+                .content("Please provide the JSON file you got from `/bot_data export`.")
+                .ephemeral(true),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    ctx.defer_ephemeral().await?;
+
+    let response = reqwest::get(&file.url).await?;
+    if !response.status().is_success() {
+        return Err(Error::from(format!(
+            "{} {}",
+            response.status(),
+            response.text().await.unwrap_or_else(|err| err.to_string())
+        )));
+    }
+
+    let export: BotDataExport = serde_json::from_str(&response.text().await?)
+        .map_err(|err| format!("Couldn't read the file. Is it from `/bot_data export`? {err}"))?;
+
+    if !export.id.starts_with("guild:") {
+        ctx.send(
+            CreateReply::default()
+                .content("This file contains user data. Only guild data can be imported.")
+                .ephemeral(true),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let schema_version = ctx.data().db.get_schema_version().await?;
+    if export.schema_version != schema_version {
+        ctx.send(
+            CreateReply::default()
+                .content(format!("This file was exported from a different version of the bot (data version {}, current version {schema_version}). Please export it again.", export.schema_version))
+                .ephemeral(true),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let plan = create_import_plan(ctx, guild_id, export).await?;
+
+    if !confirmation {
+        ctx.send(
+            CreateReply::default()
+                .content(format!(
+                    "{}\nSet the `confirmation` parameter to `True` to import.",
+                    plan.summary()
+                ))
+                .allowed_mentions(CreateAllowedMentions::new())
+                .ephemeral(true),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let source_id = plan.source_id.clone();
+    let failures = apply_import_plan(ctx, guild_id, plan).await;
+
+    ctx.data()
+        .log_manager
+        .add_log(
+            IdType::GuildId(guild_id),
+            format!(
+                "{} imported bot data from {source_id}. {} things failed to import.",
+                ctx.author().id,
+                failures.len()
+            ),
+            LogType::Info,
+            LogSource::Guild,
+        )
+        .await;
+
+    let content = if failures.is_empty() {
+        "Successfully imported the data.".to_string()
+    } else {
+        format!(
+            "Imported the data, but some things failed:\n{}",
+            format_lines(&failures)
+        )
+    };
+
+    ctx.send(
+        CreateReply::default()
+            .content(content)
+            .allowed_mentions(CreateAllowedMentions::new())
+            .ephemeral(true),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Everything from an export that can be imported into the guild.
+struct ImportPlan {
+    source_id: String,
+    detectors: Vec<DetectorInfo>,
+    reaction_roles: Vec<(ChannelId, MessageId, ReactionType, RoleId)>,
+    cotd_role: Option<CotdRoleData>,
+    lua_commands: HashMap<String, LuaCommandInfo>,
+    lua_events: HashMap<LuaEvent, LuaEventInfo>,
+    lua_modules: HashMap<String, LuaModuleInfo>,
+    data_stores: HashMap<String, HashMap<String, Value>>,
+    /// Things that can't be imported and why.
+    skipped: Vec<String>,
+    /// Problems the linter found in the code of the Lua commands. They still get imported.
+    lint_warnings: Vec<String>,
+}
+
+impl ImportPlan {
+    fn summary(&self) -> String {
+        let mut lua_command_names = self.lua_commands.keys().cloned().collect::<Vec<_>>();
+        lua_command_names.sort();
+
+        let mut lua_event_names = self
+            .lua_events
+            .keys()
+            .map(LuaEvent::to_str)
+            .collect::<Vec<_>>();
+        lua_event_names.sort();
+
+        let mut lua_module_names = self.lua_modules.keys().cloned().collect::<Vec<_>>();
+        lua_module_names.sort();
+
+        let data_store_keys: usize = self.data_stores.values().map(HashMap::len).sum();
+
+        let mut summary = format!(
+            "**Importing from {}**\n- {} message detectors\n- {} reaction roles\n- COTD role: {}\n- {} Lua commands{}\n- {} Lua event handlers{}\n- {} Lua modules{}\n- {} data stores with {} keys\n",
+            self.source_id,
+            self.detectors.len(),
+            self.reaction_roles.len(),
+            self.cotd_role
+                .as_ref()
+                .map(|cotd_role| format!("<@&{}>", cotd_role.id))
+                .unwrap_or("None".to_string()),
+            lua_command_names.len(),
+            if lua_command_names.is_empty() {
+                String::new()
+            } else {
+                format!(": {}", lua_command_names.join(", "))
+            },
+            lua_event_names.len(),
+            if lua_event_names.is_empty() {
+                String::new()
+            } else {
+                format!(": {}", lua_event_names.join(", "))
+            },
+            lua_module_names.len(),
+            if lua_module_names.is_empty() {
+                String::new()
+            } else {
+                format!(": {}", lua_module_names.join(", "))
+            },
+            self.data_stores.len(),
+            data_store_keys,
+        );
+
+        summary.push_str("Lua commands, Lua event handlers, Lua modules and data store keys with the same name will be overwritten. Everything else gets added to what this guild already has.\n");
+
+        if !self.skipped.is_empty() {
+            summary.push_str(&format!(
+                "\n**These wont be imported:**\n{}",
+                format_lines(&self.skipped)
+            ));
+        }
+
+        if !self.lint_warnings.is_empty() {
+            summary.push_str(&format!(
+                "\n**Possible problems in the code of the Lua commands:**\n{}",
+                format_lines(&self.lint_warnings)
+            ));
+        }
+
+        summary
+    }
+}
+
+/// Formats lines as a list and cuts it off so the message doesn't go over the character limit.
+fn format_lines(lines: &[String]) -> String {
+    const MAX_LINES: usize = 10;
+    const MAX_LINE_LENGTH: usize = 120;
+
+    let mut formatted = lines
+        .iter()
+        .take(MAX_LINES)
+        .map(|line| {
+            if line.chars().count() > MAX_LINE_LENGTH {
+                let cut_line = line.chars().take(MAX_LINE_LENGTH).collect::<String>();
+                format!("- {cut_line}...\n")
+            } else {
+                format!("- {line}\n")
+            }
+        })
+        .collect::<String>();
+
+    if lines.len() > MAX_LINES {
+        formatted.push_str(&format!("...and {} more.\n", lines.len() - MAX_LINES));
+    }
+
+    formatted
+}
+
+/// Takes a field out of the exported record. Missing fields are treated as empty.
+fn take_field<T: DeserializeOwned + Default>(
+    record: &mut Map<String, Value>,
+    field: &str,
+) -> Result<T, Error> {
+    match record.remove(field) {
+        None | Some(Value::Null) => Ok(T::default()),
+        Some(value) => serde_json::from_value(value)
+            .map_err(|err| format!("Couldn't read {field} from the file. {err}").into()),
+    }
+}
+
+/// Checks what from the export can be imported into the guild, without changing anything.
+async fn create_import_plan(
+    ctx: Context<'_>,
+    guild_id: GuildId,
+    export: BotDataExport,
+) -> Result<ImportPlan, Error> {
+    let data = ctx.data();
+    let mut record = export.record;
+
+    let exported_detectors: Vec<DetectorInfo> = take_field(&mut record, "message_detectors")?;
+    let exported_messages: HashMap<MessageId, StoredMessageData> =
+        take_field(&mut record, "messages")?;
+    let exported_cotd_role: Option<CotdRoleData> = take_field(&mut record, "cotd_role")?;
+    let exported_lua_commands: HashMap<String, LuaCommandInfo> =
+        take_field(&mut record, "lua_commands")?;
+    let exported_lua_events: HashMap<LuaEvent, LuaEventInfo> =
+        take_field(&mut record, "lua_events")?;
+    let exported_lua_modules: HashMap<String, LuaModuleInfo> =
+        take_field(&mut record, "lua_modules")?;
+    let data_stores: HashMap<String, HashMap<String, Value>> =
+        take_field(&mut record, "data_stores")?;
+
+    let (roles, emojis, channels) = {
+        let Some(guild) = ctx.guild() else {
+            return Err("Not in a guild.".into());
+        };
+        (
+            guild.roles.clone(),
+            guild.emojis.clone(),
+            guild.channels.clone(),
+        )
+    };
+
+    let mut skipped = Vec::new();
+    let mut lint_warnings = Vec::new();
+
+    // Message detectors.
+    let current_detectors = {
+        let detectors_data = data
+            .detector_manager
+            .get_detectors_data(IdType::GuildId(guild_id))
+            .await;
+        let mut locked_detectors_data = detectors_data.lock().await;
+        locked_detectors_data.get_detectors(&data.db).await?.clone()
+    };
+
+    let mut detectors = Vec::new();
+    for detector in exported_detectors {
+        let already_exists = current_detectors.iter().any(|current| {
+            current.key == detector.key
+                && current.detect_type.to_sentence() == detector.detect_type.to_sentence()
+                && current.case_sensitive == detector.case_sensitive
+        });
+
+        if already_exists {
+            continue;
+        }
+
+        if current_detectors.len() + detectors.len() >= 10 {
+            skipped.push(format!(
+                "Message detector \"{}\": You can only have a max amount of 10 message detectors.",
+                detector.key
+            ));
+            continue;
+        }
+
+        detectors.push(detector);
+    }
+
+    // Reaction roles.
+    let mut reaction_roles = Vec::new();
+    for (message_id, message_data) in exported_messages {
+        if message_data.reaction_roles.is_empty() {
+            continue;
+        }
+
+        let channel_id = message_data.channel_id;
+
+        if !channels.contains_key(&channel_id) {
+            skipped.push(format!(
+                "Reaction roles on message {message_id}: <#{channel_id}> isn't in this guild."
+            ));
+            continue;
+        }
+
+        if channel_id.message(ctx, message_id).await.is_err() {
+            skipped.push(format!(
+                "Reaction roles on message {message_id}: Couldn't find the message in <#{channel_id}>."
+            ));
+            continue;
+        }
+
+        let current_reaction_roles = data
+            .reaction_manager
+            .get_reaction_roles(guild_id, message_id)
+            .await
+            .map_err(|err| err.to_string())?;
+
+        for (emoji_id, role_id) in message_data.reaction_roles {
+            if current_reaction_roles.get(&emoji_id) == Some(&role_id) {
+                continue;
+            }
+
+            if !roles.contains_key(&role_id) {
+                skipped.push(format!(
+                    "Reaction role on message {message_id}: The role {role_id} doesn't exist."
+                ));
+                continue;
+            }
+
+            let emoji = if emoji_id.chars().all(char::is_numeric) {
+                let Some(emoji) = EmojiId::from_str(&emoji_id)
+                    .ok()
+                    .and_then(|id| emojis.get(&id))
+                else {
+                    skipped.push(format!(
+                        "Reaction role on message {message_id}: The emoji {emoji_id} doesn't exist in this guild."
+                    ));
+                    continue;
+                };
+
+                ReactionType::Custom {
+                    animated: emoji.animated,
+                    id: emoji.id,
+                    name: Some(emoji.name.clone()),
+                }
+            } else {
+                ReactionType::Unicode(emoji_id)
+            };
+
+            reaction_roles.push((channel_id, message_id, emoji, role_id));
+        }
+    }
+
+    // COTD role.
+    let cotd_role = match exported_cotd_role {
+        Some(cotd_role) if !roles.contains_key(&cotd_role.id) => {
+            skipped.push(format!(
+                "COTD role: The role {} doesn't exist.",
+                cotd_role.id
+            ));
+            None
+        }
+        cotd_role => cotd_role,
+    };
+
+    // Lua commands.
+    let current_command_names = {
+        let guild_lua_data = data.lua_manager.get_guild_lua_data(guild_id).await;
+        let mut locked_guild_lua_data = guild_lua_data.lock().await;
+        locked_guild_lua_data
+            .get_commands(&data.db)
+            .await?
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>()
+    };
+
+    let mut new_commands_amount = 0;
+    let mut lua_commands = HashMap::new();
+    for (command_name, mut lua_command_info) in exported_lua_commands {
+        if let Err(err) = data.lua_manager.try_parse_code(&lua_command_info.lua_code) {
+            skipped.push(format!("Lua command {command_name}: Invalid code. {err}"));
+            continue;
+        }
+
+        let (required_roles, missing_roles): (Vec<_>, Vec<_>) =
+            std::mem::take(&mut lua_command_info.required_roles)
+                .into_iter()
+                .partition(|role_id| roles.contains_key(role_id));
+
+        // Without any of its required roles left, anyone could run the command.
+        if required_roles.is_empty() && !missing_roles.is_empty() {
+            skipped.push(format!(
+                "Lua command {command_name}: None of the roles required to run it exist."
+            ));
+            continue;
+        }
+
+        for role_id in missing_roles {
+            skipped.push(format!(
+                "Required role of Lua command {command_name}: The role {role_id} doesn't exist."
+            ));
+        }
+        lua_command_info.required_roles = required_roles;
+
+        if !current_command_names.contains(&command_name) {
+            if current_command_names.len() + new_commands_amount >= 25 {
+                skipped.push(format!(
+                    "Lua command {command_name}: You may only have up to 25 custom commands."
+                ));
+                continue;
+            }
+            new_commands_amount += 1;
+        }
+
+        for warning in data
+            .lua_manager
+            .lint_command_code(&lua_command_info.lua_code)
+        {
+            lint_warnings.push(format!(
+                "Lua command {command_name}, line {}: {}",
+                warning.line, warning.message
+            ));
+        }
+
+        lua_commands.insert(command_name, lua_command_info);
+    }
+
+    // Lua event handlers.
+    let mut lua_events = HashMap::new();
+    for (event, lua_event_info) in exported_lua_events {
+        if let Err(err) = data.lua_manager.try_parse_code(&lua_event_info.lua_code) {
+            skipped.push(format!(
+                "Lua event handler {}: Invalid code. {err}",
+                event.to_str()
+            ));
+            continue;
+        }
+
+        lua_events.insert(event, lua_event_info);
+    }
+
+    // Lua modules.
+    let current_module_names = data
+        .lua_manager
+        .get_modules(guild_id)
+        .await?
+        .into_keys()
+        .collect::<Vec<_>>();
+
+    let mut new_modules_amount = 0;
+    let mut lua_modules = HashMap::new();
+    for (module_name, lua_module_info) in exported_lua_modules {
+        if let Err(err) = data.lua_manager.try_parse_code(&lua_module_info.lua_code) {
+            skipped.push(format!("Lua module {module_name}: Invalid code. {err}"));
+            continue;
+        }
+
+        if !current_module_names.contains(&module_name) {
+            if current_module_names.len() + new_modules_amount >= MAX_MODULES_PER_GUILD {
+                skipped.push(format!(
+                    "Lua module {module_name}: You may only have up to {MAX_MODULES_PER_GUILD} modules."
+                ));
+                continue;
+            }
+            new_modules_amount += 1;
+        }
+
+        lua_modules.insert(module_name, lua_module_info);
+    }
+
+    Ok(ImportPlan {
+        source_id: export.id,
+        detectors,
+        reaction_roles,
+        cotd_role,
+        lua_commands,
+        lua_events,
+        lua_modules,
+        data_stores,
+        skipped,
+        lint_warnings,
+    })
+}
+
+/// Imports everything in the plan. Keeps going if something fails, and returns everything that failed.
+async fn apply_import_plan(ctx: Context<'_>, guild_id: GuildId, plan: ImportPlan) -> Vec<String> {
+    let data = ctx.data();
+    let mut failures = Vec::new();
+
+    for detector in plan.detectors {
+        let key = detector.key.clone();
+        if let Err(err) = data
+            .detector_manager
+            .add_message_detect(
+                detector.detect_type,
+                detector.key,
+                detector.response,
+                detector.case_sensitive,
+                IdType::GuildId(guild_id),
+            )
+            .await
+        {
+            failures.push(format!("Message detector \"{key}\": {}", err.to_string()));
+        }
+    }
+
+    for (channel_id, message_id, emoji, role_id) in plan.reaction_roles {
+        if let Err(err) = channel_id
+            .create_reaction(ctx, message_id, emoji.clone())
+            .await
+        {
+            failures.push(format!(
+                "Reaction role on message {message_id}: Couldn't react with {emoji}. {err}"
+            ));
+            continue;
+        }
+
+        if let Err(err) = data
+            .reaction_manager
+            .add_reaction(emoji, role_id, guild_id, channel_id, message_id)
+            .await
+        {
+            failures.push(format!(
+                "Reaction role on message {message_id}: {}",
+                err.to_string()
+            ));
+        }
+    }
+
+    if let Some(cotd_role) = plan.cotd_role {
+        let cotd_manager = &data.cotd_manager;
+        let result = match cotd_manager.get_current_color().await {
+            Ok(current_color) => cotd_manager
+                .update_role(ctx, guild_id, cotd_role.id, &cotd_role.name, &current_color)
+                .await
+                .map_err(|err| err.to_string()),
+            Err(err) => Err(err.to_string()),
+        };
+
+        let cotd_role = CotdRoleData {
+            day: cotd_manager.get_current_day(),
+            ..cotd_role
+        };
+
+        match result {
+            Ok(()) => {
+                if let Err(err) = data
+                    .db
+                    .update_guild_cotd_role(&Some(cotd_role), guild_id)
+                    .await
+                {
+                    failures.push(format!("COTD role: {err}"));
+                }
+            }
+            Err(err) => failures.push(format!("COTD role: {err}")),
+        }
+    }
+
+    // Modules go first, since commands and event handlers might need them.
+    for (module_name, lua_module_info) in plan.lua_modules {
+        if let Err(err) = data
+            .lua_manager
+            .set_module(
+                guild_id,
+                module_name.clone(),
+                lua_module_info.lua_code,
+                lua_module_info.filename,
+            )
+            .await
+        {
+            failures.push(format!("Lua module {module_name}: {err}"));
+        }
+    }
+
+    if !plan.lua_commands.is_empty() {
+        if let Err(err) = data
+            .lua_manager
+            .import_commands(guild_id, plan.lua_commands, ctx.author().id)
+            .await
+        {
+            failures.push(format!("Lua commands: {err}"));
+        }
+    }
+
+    for (event, lua_event_info) in plan.lua_events {
+        if let Err(err) = data
+            .lua_manager
+            .set_event_handler(
+                guild_id,
+                event,
+                lua_event_info.lua_code,
+                lua_event_info.filename,
+            )
+            .await
+        {
+            failures.push(format!("Lua event handler {}: {err}", event.to_str()));
+        }
+    }
+
+    for (data_store_name, values) in plan.data_stores {
+        let data_store = data
+            .lua_manager
+            .get_data_store(guild_id, data_store_name.clone())
+            .await;
+        let mut locked_data_store = data_store.lock().await;
+
+        for (key, value) in values {
+            if let Err(err) = locked_data_store.set(key.clone(), value).await {
+                failures.push(format!("Data store {data_store_name}, key {key}: {err}"));
+            }
+        }
+    }
+
+    failures
+}
@@ -1,6 +1,5 @@
 use std::{collections::HashMap, sync::Arc, time::Duration};
 
-use poise::serenity_prelude::UserId;
 use tokio::time::sleep;
 
 use crate::{utils::IdType, Context, Error};
@@ -79,28 +78,7 @@ pub async fn clear_bot_data(
         lua_lock = None;
     }
 
-    let table_id = id.into_db_table();
-    let response: Option<Vec<UserId>> = db
-        .query(format!(
-            "
-        BEGIN TRANSACTION;
-        LET $uids = array::distinct(
-            SELECT VALUE user_id FROM (
-                SELECT VALUE ->reminds->reminder FROM {table_id}
-            )
-        );
-
-        FOR $reminder IN (SELECT VALUE ->reminds->reminder FROM {table_id}) {{
-            DELETE $reminder;
-        }};
-        DELETE {table_id};
-
-        RETURN $uids;
-        COMMIT TRANSACTION;
-    "
-        ))
-        .await?
-        .take(0)?;
+    let mut affected_remind_user_ids = db.clear_bot_data(id).await?;
 
     if let Some(lock) = lua_lock.as_mut() {
         // Assign the commands to be Some so the function doesn't do a request to get the lua commands.
@@ -113,8 +91,6 @@ pub async fn clear_bot_data(
     drop(lua_lock);
     drop(guild_lua_data_for_lock);
 
-    let mut affected_remind_user_ids = response.unwrap_or_default();
-
     affected_remind_user_ids.sort();
     affected_remind_user_ids.dedup();
 
@@ -1,19 +1,87 @@
+use poise::{serenity_prelude::Attachment, CreateReply};
+
 use crate::{Context, Error};
 
 pub mod command;
+pub mod eval;
+pub mod event;
 pub mod instance;
+pub mod module;
 use command::command;
+use eval::eval;
+use event::event;
 use instance::instance;
+use module::module;
 
 /// Create your own commands! (Requires something idk)
 #[poise::command(
     slash_command,
     install_context = "Guild",
     interaction_context = "Guild",
-    subcommands("command", "instance"),
+    subcommands("command", "eval", "event", "instance", "module"),
     subcommand_required,
     default_member_permissions = "ADMINISTRATOR"
 )]
 pub async fn lua(_ctx: Context<'_>) -> Result<(), Error> {
     Ok(())
 }
+
+const FIFTY_KB_IN_BYTES: u32 = 50000;
+
+/// Downloads an attached file after making sure it's 50 KB or less and ends with one of the extensions.
+///
+/// `kind` is what the file is called when telling the user, like "file" or "manifest".
+/// Returns None if the file isn't valid, after telling the user why.
+async fn download_attachment(
+    ctx: Context<'_>,
+    attachment: &Attachment,
+    kind: &str,
+    extensions: &[&str],
+) -> Result<Option<String>, Error> {
+    if attachment.size > FIFTY_KB_IN_BYTES {
+        ctx.send(
+            CreateReply::default()
+                .content(format!(
+                    "Please make sure your {kind} is 50 KB or less in size."
+                ))
+                .ephemeral(true),
+        )
+        .await?;
+        return Ok(None);
+    }
+
+    if !extensions
+        .iter()
+        .any(|extension| attachment.filename.ends_with(&format!(".{extension}")))
+    {
+        ctx.send(
+            CreateReply::default()
+                .content(format!(
+                    "Please make sure your {kind} is a {} file.",
+                    extensions.join(" or ")
+                ))
+                .ephemeral(true),
+        )
+        .await?;
+        return Ok(None);
+    }
+
+    let response = reqwest::get(&attachment.url).await?;
+    if !response.status().is_success() {
+        return Err(Error::from(format!(
+            "{} {}",
+            response.status(),
+            response.text().await.unwrap_or_else(|err| err.to_string())
+        )));
+    }
+
+    Ok(Some(response.text().await?))
+}
+
+/// Downloads the code of a lua file, see `download_attachment`.
+async fn download_lua_file(
+    ctx: Context<'_>,
+    lua_file: &Attachment,
+) -> Result<Option<String>, Error> {
+    download_attachment(ctx, lua_file, "file", &["lua", "luau"]).await
+}
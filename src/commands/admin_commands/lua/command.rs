@@ -1,10 +1,19 @@
 use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
 use poise::{
-    serenity_prelude::{self, Attachment, CreateAttachment},
+    serenity_prelude::{self, Attachment, CreateAllowedMentions, CreateAttachment},
     CreateReply,
 };
 
-use crate::{managers::lua_manager::CommandOption, Context, Error};
+use crate::{
+    managers::lua_manager::{
+        lint::format_lint_report,
+        manifest::{CommandManifest, ManifestFormat},
+        CommandOption, LuaCommandInfo,
+    },
+    Context, Error,
+};
+
+use super::{download_attachment, download_lua_file};
 
 async fn autocomplete_command_name(
     ctx: Context<'_>,
@@ -39,11 +48,59 @@ async fn autocomplete_command_name(
         .collect()
 }
 
+/// Downloads and parses a command manifest after making sure it's a reasonable size and type.
+///
+/// Returns None if the file isn't valid, after telling the user why.
+async fn download_manifest(
+    ctx: Context<'_>,
+    manifest_file: &Attachment,
+) -> Result<Option<CommandManifest>, Error> {
+    let Some(content) =
+        download_attachment(ctx, manifest_file, "manifest", &["toml", "json"]).await?
+    else {
+        return Ok(None);
+    };
+
+    // The extension was checked while downloading.
+    let format = ManifestFormat::from_filename(&manifest_file.filename).unwrap();
+
+    Ok(Some(CommandManifest::parse(&content, format)?))
+}
+
+/// Tells the user about problems found in the code of a command.
+///
+/// Returns true if there were any, in which case the command shouldn't be saved.
+async fn report_lint_warnings(
+    ctx: Context<'_>,
+    lua_code: &str,
+    filename: &str,
+) -> Result<bool, Error> {
+    let warnings = ctx.data().lua_manager.lint_command_code(lua_code);
+    if warnings.is_empty() {
+        return Ok(false);
+    }
+
+    ctx.send(
+        CreateReply::default()
+            .content(format!(
+                "Found {} possible problems in `{filename}`, so the command wasn't saved. Fix them, or set `ignore_warnings` to save it anyway.\n{}",
+                warnings.len(),
+                format_lint_report(filename, &warnings)
+            ))
+            .ephemeral(true),
+    )
+    .await?;
+
+    Ok(true)
+}
+
 #[poise::command(
     slash_command,
     install_context = "Guild",
     interaction_context = "Guild",
-    subcommands("create", "update", "delete", "download", "refresh"),
+    subcommands(
+        "create", "update", "delete", "download", "refresh", "history", "rollback"
+    ),
     subcommand_required,
     default_member_permissions = "ADMINISTRATOR"
 )]
@@ -55,48 +112,97 @@ pub async fn command(_ctx: Context<'_>) -> Result<(), Error> {
 #[poise::command(slash_command)]
 pub async fn create(
     ctx: Context<'_>,
-    #[description = "What do you want the command name to be?"] command_name: String,
-    #[description = "What do you want the description of the command to be?"] description: String,
+    #[description = "What do you want the command name to be? (Not needed with a manifest)"]
+    command_name: Option<String>,
+    #[description = "What do you want the description of the command to be? (Not needed with a manifest)"]
+    description: Option<String>,
     #[description = "What params should the command have? (Default: None)"] params: Option<String>,
     #[description = "What's the code for the command?"] lua_file: Attachment,
+    #[description = "Permissions needed to use the command. Eg: \"MANAGE_MESSAGES, KICK_MEMBERS\" (Default: None)"]
+    permissions: Option<String>,
+    #[description = "Roles that can use the command, members need one of them. (Default: Everyone)"]
+    required_roles: Option<String>,
+    #[description = "A toml or json file describing the command, instead of the other options."]
+    manifest: Option<Attachment>,
+    #[description = "Save the command even if problems are found in the code. (Default: False)"]
+    ignore_warnings: Option<bool>,
 ) -> Result<(), Error> {
-    let params = params
-        .map(|string| CommandOption::parse_string(&string))
-        .unwrap_or(Ok(vec![]))?;
-
-    const FIFTY_KB_IN_BYTES: u32 = 50000;
-
-    if lua_file.size > FIFTY_KB_IN_BYTES {
-        ctx.send(
-            CreateReply::default()
-                .content("Please make sure your file is 50 KB or less in size.")
-                .ephemeral(true),
-        )
-        .await?;
+    let (command_name, description, params, subcommands, permissions, required_roles) =
+        match manifest {
+            Some(manifest) => {
+                if command_name.is_some()
+                    || description.is_some()
+                    || params.is_some()
+                    || permissions.is_some()
+                    || required_roles.is_some()
+                {
+                    ctx.send(
+                    CreateReply::default()
+                        .content("When using a manifest, everything except the code has to be in the manifest.")
+                        .ephemeral(true),
+                )
+                .await?;
+                    return Ok(());
+                }
+
+                let Some(manifest) = download_manifest(ctx, &manifest).await? else {
+                    return Ok(());
+                };
+
+                let permissions = manifest.default_member_permissions()?;
+                (
+                    manifest.name,
+                    manifest.description,
+                    manifest.options,
+                    manifest.subcommands,
+                    permissions,
+                    manifest.required_roles,
+                )
+            }
+            None => {
+                let (Some(command_name), Some(description)) = (command_name, description) else {
+                    ctx.send(
+                        CreateReply::default()
+                            .content(
+                                "Please provide a command name and description, or a manifest.",
+                            )
+                            .ephemeral(true),
+                    )
+                    .await?;
+                    return Ok(());
+                };
+
+                let params = params
+                    .map(|string| CommandOption::parse_string(&string))
+                    .unwrap_or(Ok(vec![]))?;
+                let permissions = permissions
+                    .map(|string| LuaCommandInfo::parse_permissions(&string))
+                    .unwrap_or(Ok(None))?;
+                let required_roles = required_roles
+                    .map(|string| LuaCommandInfo::parse_roles(&string))
+                    .unwrap_or(Ok(vec![]))?;
+
+                (
+                    command_name,
+                    description,
+                    params,
+                    vec![],
+                    permissions,
+                    required_roles,
+                )
+            }
+        };
+
+    let Some(lua_code) = download_lua_file(ctx, &lua_file).await? else {
         return Ok(());
-    }
+    };
 
-    if !lua_file.filename.ends_with(".lua") && !lua_file.filename.ends_with(".luau") {
-        ctx.send(
-            CreateReply::default()
-                .content("Please make sure your file is a lua or luau file.")
-                .ephemeral(true),
-        )
-        .await?;
+    if !ignore_warnings.unwrap_or(false)
+        && report_lint_warnings(ctx, &lua_code, &lua_file.filename).await?
+    {
         return Ok(());
     }
 
-    let response = reqwest::get(&lua_file.url).await?;
-    if !response.status().is_success() {
-        return Err(Error::from(format!(
-            "{} {}",
-            response.status(),
-            response.text().await.unwrap_or_else(|err| err.to_string())
-        )));
-    }
-
-    let lua_code = response.text().await?;
-
     let data = ctx.data();
 
     data.lua_manager
@@ -105,8 +211,12 @@ pub async fn create(
             command_name.clone(),
             description,
             params,
+            subcommands,
             lua_code,
             lua_file.filename,
+            permissions,
+            required_roles,
+            ctx.author().id,
         )
         .await?;
 
@@ -136,48 +246,94 @@ pub async fn update(
     >,
     #[description = "What's the new code for the command? (Default: Current value)"]
     lua_file: Option<Attachment>,
+    #[description = "Permissions needed to use the command, \"none\" to remove. (Default: Current value)"]
+    permissions: Option<String>,
+    #[description = "Roles that can use the command, \"none\" for everyone. (Default: Current value)"]
+    required_roles: Option<String>,
+    #[description = "A toml or json file describing the command, instead of the other options."]
+    manifest: Option<Attachment>,
+    #[description = "Save the command even if problems are found in the code. (Default: False)"]
+    ignore_warnings: Option<bool>,
 ) -> Result<(), Error> {
-    let params = match params.map(|string| CommandOption::parse_string(&string)) {
-        Some(Ok(params)) => Some(params),
-        Some(Err(err)) => return Err(err),
-        None => None,
+    let (description, params, subcommands, permissions, required_roles) = match manifest {
+        Some(manifest) => {
+            if description.is_some()
+                || params.is_some()
+                || permissions.is_some()
+                || required_roles.is_some()
+            {
+                ctx.send(
+                    CreateReply::default()
+                        .content("When using a manifest, everything except the code has to be in the manifest.")
+                        .ephemeral(true),
+                )
+                .await?;
+                return Ok(());
+            }
+
+            let Some(manifest) = download_manifest(ctx, &manifest).await? else {
+                return Ok(());
+            };
+
+            if manifest.name != command_name {
+                ctx.send(
+                    CreateReply::default()
+                        .content(format!(
+                            "The manifest is for a command named `{}`, not `{command_name}`. Commands can't be renamed.",
+                            manifest.name
+                        ))
+                        .ephemeral(true),
+                )
+                .await?;
+                return Ok(());
+            }
+
+            let permissions = manifest.default_member_permissions()?;
+            (
+                Some(manifest.description),
+                Some(manifest.options),
+                Some(manifest.subcommands),
+                Some(permissions),
+                Some(manifest.required_roles),
+            )
+        }
+        None => {
+            let params = match params.map(|string| CommandOption::parse_string(&string)) {
+                Some(Ok(params)) => Some(params),
+                Some(Err(err)) => return Err(err),
+                None => None,
+            };
+            let permissions =
+                match permissions.map(|string| LuaCommandInfo::parse_permissions(&string)) {
+                    Some(Ok(permissions)) => Some(permissions),
+                    Some(Err(err)) => return Err(err),
+                    None => None,
+                };
+            let required_roles =
+                match required_roles.map(|string| LuaCommandInfo::parse_roles(&string)) {
+                    Some(Ok(required_roles)) => Some(required_roles),
+                    Some(Err(err)) => return Err(err),
+                    None => None,
+                };
+
+            (description, params, None, permissions, required_roles)
+        }
     };
 
-    const FIFTY_KB_IN_BYTES: u32 = 50000;
-
     let lua_code_and_filename;
 
     if let Some(lua_file) = lua_file {
-        if lua_file.size > FIFTY_KB_IN_BYTES {
-            ctx.send(
-                CreateReply::default()
-                    .content("Please make sure your file is 50 KB or less in size.")
-                    .ephemeral(true),
-            )
-            .await?;
+        let Some(lua_code) = download_lua_file(ctx, &lua_file).await? else {
             return Ok(());
-        }
+        };
 
-        if !lua_file.filename.ends_with(".lua") && !lua_file.filename.ends_with(".luau") {
-            ctx.send(
-                CreateReply::default()
-                    .content("Please make sure your file is a lua or luau file.")
-                    .ephemeral(true),
-            )
-            .await?;
+        if !ignore_warnings.unwrap_or(false)
+            && report_lint_warnings(ctx, &lua_code, &lua_file.filename).await?
+        {
             return Ok(());
         }
 
-        let response = reqwest::get(&lua_file.url).await?;
-        if !response.status().is_success() {
-            return Err(Error::from(format!(
-                "{} {}",
-                response.status(),
-                response.text().await.unwrap_or("".to_string())
-            )));
-        }
-
-        lua_code_and_filename = Some((response.text().await?, lua_file.filename.clone()));
+        lua_code_and_filename = Some((lua_code, lua_file.filename.clone()));
     } else {
         lua_code_and_filename = None;
     }
@@ -190,7 +346,11 @@ pub async fn update(
             command_name.clone(),
             description,
             params,
+            subcommands,
             lua_code_and_filename,
+            permissions,
+            required_roles,
+            ctx.author().id,
         )
         .await?;
 
@@ -230,14 +390,19 @@ pub async fn delete(
     Ok(())
 }
 
-/// Sends the file of a custom command.
+/// Sends the file of a custom command, along with a manifest describing it.
 #[poise::command(slash_command)]
 pub async fn download(
     ctx: Context<'_>,
     #[autocomplete = "autocomplete_command_name"]
     #[description = "Which command would you like to download?"]
     command_name: String,
+    #[description = "Which format should the manifest be in? (Default: TOML)"] format: Option<
+        ManifestFormat,
+    >,
 ) -> Result<(), Error> {
+    let format = format.unwrap_or(ManifestFormat::Toml);
+
     let data = ctx.data();
 
     let guild_id = ctx.guild_id().unwrap();
@@ -254,12 +419,19 @@ pub async fn download(
         return Ok(());
     };
 
+    let manifest =
+        CommandManifest::from_command(&command_name, command_info).to_file_content(format)?;
+
     ctx.send(
         CreateReply::default()
             .attachment(CreateAttachment::bytes(
                 command_info.lua_code.clone(),
                 command_info.filename.clone(),
             ))
+            .attachment(CreateAttachment::bytes(
+                manifest,
+                format!("{command_name}.{}", format.extension()),
+            ))
             .ephemeral(true),
     )
     .await?;
@@ -291,3 +463,93 @@ pub async fn refresh(ctx: Context<'_>) -> Result<(), Error> {
 
     Ok(())
 }
+
+/// Shows the saved versions of a custom command.
+#[poise::command(slash_command)]
+pub async fn history(
+    ctx: Context<'_>,
+    #[autocomplete = "autocomplete_command_name"]
+    #[description = "Which command's history would you like to see?"]
+    command_name: String,
+) -> Result<(), Error> {
+    let history = ctx
+        .data()
+        .lua_manager
+        .get_command_history(ctx.guild_id().unwrap(), &command_name)
+        .await?;
+
+    if history.is_empty() {
+        ctx.send(
+            CreateReply::default()
+                .content(format!("There are no saved versions of {command_name}."))
+                .ephemeral(true),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let lines = history
+        .iter()
+        .enumerate()
+        .map(|(index, command_version)| {
+            let author = match command_version.author {
+                Some(author) => format!("<@{author}>"),
+                None => "unknown".to_string(),
+            };
+            format!(
+                "`#{}` <t:{}:f> by {} - `{}`{}",
+                command_version.version,
+                command_version.timestamp,
+                author,
+                command_version.command_info.filename,
+                if index == 0 { " (current)" } else { "" }
+            )
+        })
+        .collect::<Vec<_>>();
+
+    ctx.send(
+        CreateReply::default()
+            .content(format!(
+                "Saved versions of {command_name}, newest first. Use `/lua command rollback` to restore one.\n{}",
+                lines.join("\n")
+            ))
+            .allowed_mentions(CreateAllowedMentions::new())
+            .ephemeral(true),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Restores a saved version of a custom command.
+#[poise::command(slash_command)]
+pub async fn rollback(
+    ctx: Context<'_>,
+    #[autocomplete = "autocomplete_command_name"]
+    #[description = "Which command would you like to roll back?"]
+    command_name: String,
+    #[description = "Which version should it go back to? See `/lua command history`."]
+    #[min = 1]
+    version: u32,
+) -> Result<(), Error> {
+    ctx.data()
+        .lua_manager
+        .rollback_command(
+            ctx.guild_id().unwrap(),
+            command_name.clone(),
+            version,
+            ctx.author().id,
+        )
+        .await?;
+
+    ctx.send(
+        CreateReply::default()
+            .content(format!(
+                "Successfully rolled {command_name} back to version {version}."
+            ))
+            .ephemeral(true),
+    )
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,99 @@
+use std::time::Duration;
+
+use poise::CreateReply;
+
+use crate::{Context, Error};
+
+/// The most of each section that fits in a message along with the others.
+const MAX_SECTION_LENGTH: usize = 900;
+
+#[derive(poise::Modal)]
+#[name = "Evaluate Lua"]
+struct EvalModal {
+    #[name = "Code"]
+    #[placeholder = "return get_data_store(\"counts\"):get(\"count\")"]
+    #[paragraph]
+    #[max_length = 4000]
+    code: String,
+}
+
+/// Puts text in a code block, cutting it short if it's too long.
+fn code_block(text: &str) -> String {
+    let mut text = text.replace("```", "`\u{200b}``");
+    if text.chars().count() > MAX_SECTION_LENGTH {
+        text = text.chars().take(MAX_SECTION_LENGTH).collect::<String>() + "\n...";
+    }
+
+    format!("```\n{text}\n```")
+}
+
+/// Runs code in the Lua instance and shows what it printed and returned.
+#[poise::command(
+    slash_command,
+    install_context = "Guild",
+    interaction_context = "Guild",
+    default_member_permissions = "ADMINISTRATOR"
+)]
+pub async fn eval(
+    ctx: Context<'_>,
+    #[description = "The code to run. Leave this out to write multiple lines in a popup."]
+    code: Option<String>,
+) -> Result<(), Error> {
+    let code = match code {
+        Some(code) => {
+            ctx.defer_ephemeral().await?;
+            code
+        }
+        None => {
+            let Context::Application(app_ctx) = ctx else {
+                return Ok(());
+            };
+
+            let Some(modal) = poise::execute_modal::<_, _, EvalModal>(
+                app_ctx,
+                None,
+                Some(Duration::from_secs(600)),
+            )
+            .await?
+            else {
+                return Ok(());
+            };
+
+            modal.code
+        }
+    };
+
+    let eval_output = ctx
+        .data()
+        .lua_manager
+        .eval(ctx.guild_id().unwrap(), &code)
+        .await?;
+
+    let mut sections = Vec::new();
+
+    if !eval_output.output.is_empty() {
+        sections.push(format!(
+            "**Output**\n{}",
+            code_block(&eval_output.output.join("\n"))
+        ));
+    }
+
+    match eval_output.result {
+        Ok(values) if values.is_empty() => {
+            if sections.is_empty() {
+                sections.push("Ran without output or a result.".to_string());
+            }
+        }
+        Ok(values) => sections.push(format!("**Result**\n{}", code_block(&values.join("\n")))),
+        Err(err) => sections.push(format!("**Error**\n{}", code_block(&err))),
+    }
+
+    ctx.send(
+        CreateReply::default()
+            .content(sections.join("\n"))
+            .ephemeral(true),
+    )
+    .await?;
+
+    Ok(())
+}
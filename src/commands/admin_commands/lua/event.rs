@@ -0,0 +1,150 @@
+use poise::{
+    serenity_prelude::{Attachment, CreateAttachment},
+    CreateReply,
+};
+
+use crate::{managers::lua_manager::LuaEvent, Context, Error};
+
+use super::download_lua_file;
+
+#[poise::command(
+    slash_command,
+    install_context = "Guild",
+    interaction_context = "Guild",
+    subcommands("set", "remove", "download", "list"),
+    subcommand_required,
+    default_member_permissions = "ADMINISTRATOR"
+)]
+pub async fn event(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Sets the code that runs when an event happens. The code should return a function that takes the event.
+#[poise::command(slash_command)]
+pub async fn set(
+    ctx: Context<'_>,
+    #[description = "Which event should the code handle?"] event: LuaEvent,
+    #[description = "What's the code for the event handler?"] lua_file: Attachment,
+) -> Result<(), Error> {
+    let Some(lua_code) = download_lua_file(ctx, &lua_file).await? else {
+        return Ok(());
+    };
+
+    ctx.data()
+        .lua_manager
+        .set_event_handler(ctx.guild_id().unwrap(), event, lua_code, lua_file.filename)
+        .await?;
+
+    ctx.send(
+        CreateReply::default()
+            .content(format!(
+                "Successfully set the handler for the {} event.",
+                event.to_str()
+            ))
+            .ephemeral(true),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Removes the code that runs when an event happens.
+#[poise::command(slash_command)]
+pub async fn remove(
+    ctx: Context<'_>,
+    #[description = "Which event handler would you like to remove?"] event: LuaEvent,
+) -> Result<(), Error> {
+    ctx.data()
+        .lua_manager
+        .remove_event_handler(ctx.guild_id().unwrap(), event)
+        .await?;
+
+    ctx.send(
+        CreateReply::default()
+            .content(format!(
+                "Successfully removed the handler for the {} event.",
+                event.to_str()
+            ))
+            .ephemeral(true),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Sends the file of an event handler.
+#[poise::command(slash_command)]
+pub async fn download(
+    ctx: Context<'_>,
+    #[description = "Which event handler would you like to download?"] event: LuaEvent,
+) -> Result<(), Error> {
+    let data = ctx.data();
+
+    let guild_lua_data = data
+        .lua_manager
+        .get_guild_lua_data(ctx.guild_id().unwrap())
+        .await;
+
+    let mut guild_lua_data_lock = guild_lua_data.lock().await;
+
+    let events = guild_lua_data_lock.get_events(&data.db).await?;
+
+    let Some((event_info, _)) = events.get(&event) else {
+        ctx.send(
+            CreateReply::default()
+                .content(format!(
+                    "There is no handler for the {} event.",
+                    event.to_str()
+                ))
+                .ephemeral(true),
+        )
+        .await?;
+        return Ok(());
+    };
+
+    ctx.send(
+        CreateReply::default()
+            .attachment(CreateAttachment::bytes(
+                event_info.lua_code.clone(),
+                event_info.filename.clone(),
+            ))
+            .ephemeral(true),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Lists the events this guild handles.
+#[poise::command(slash_command)]
+pub async fn list(ctx: Context<'_>) -> Result<(), Error> {
+    let data = ctx.data();
+
+    let guild_lua_data = data
+        .lua_manager
+        .get_guild_lua_data(ctx.guild_id().unwrap())
+        .await;
+
+    let mut guild_lua_data_lock = guild_lua_data.lock().await;
+
+    let events = guild_lua_data_lock.get_events(&data.db).await?;
+
+    let mut lines = events
+        .iter()
+        .map(|(event, (event_info, _))| format!("- {}: {}", event.to_str(), event_info.filename))
+        .collect::<Vec<_>>();
+    lines.sort();
+
+    drop(guild_lua_data_lock);
+
+    let content = if lines.is_empty() {
+        "This guild doesn't handle any events.".to_string()
+    } else {
+        format!("**Event handlers**\n{}", lines.join("\n"))
+    };
+
+    ctx.send(CreateReply::default().content(content).ephemeral(true))
+        .await?;
+
+    Ok(())
+}
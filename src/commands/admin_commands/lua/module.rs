@@ -0,0 +1,225 @@
+use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
+use poise::{
+    serenity_prelude::{self, Attachment, CreateAttachment},
+    CreateReply,
+};
+
+use crate::{Context, Error};
+
+use super::download_lua_file;
+
+async fn autocomplete_module_name(
+    ctx: Context<'_>,
+    partial: &str,
+) -> Vec<poise::serenity_prelude::AutocompleteChoice> {
+    let Some(guild_id) = ctx.guild_id() else {
+        return vec![];
+    };
+
+    let Ok(modules) = ctx.data().lua_manager.get_modules(guild_id).await else {
+        return vec![];
+    };
+
+    let matcher = SkimMatcherV2::default().ignore_case();
+
+    let mut keys = modules
+        .into_keys()
+        .filter(|key| matcher.fuzzy_match(key, partial).is_some())
+        .collect::<Vec<_>>();
+
+    keys.sort_by_key(|key| matcher.fuzzy_match(key, partial).unwrap_or(-1));
+
+    keys.into_iter()
+        .rev() // Reverse because higher score is better.
+        .map(|key| serenity_prelude::AutocompleteChoice::new(key.to_string(), key))
+        .collect()
+}
+
+#[poise::command(
+    slash_command,
+    install_context = "Guild",
+    interaction_context = "Guild",
+    subcommands("create", "update", "delete", "download", "list"),
+    subcommand_required,
+    default_member_permissions = "ADMINISTRATOR"
+)]
+pub async fn module(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Creates a module that commands can load with require("name").
+#[poise::command(slash_command)]
+pub async fn create(
+    ctx: Context<'_>,
+    #[description = "What do you want the module name to be?"] module_name: String,
+    #[description = "What's the code for the module?"] lua_file: Attachment,
+) -> Result<(), Error> {
+    let data = ctx.data();
+    let guild_id = ctx.guild_id().unwrap();
+
+    if data
+        .lua_manager
+        .get_modules(guild_id)
+        .await?
+        .contains_key(&module_name)
+    {
+        ctx.send(
+            CreateReply::default()
+                .content(format!("There already is a module named {module_name}. Use `/lua module update` to change it."))
+                .ephemeral(true),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let Some(lua_code) = download_lua_file(ctx, &lua_file).await? else {
+        return Ok(());
+    };
+
+    data.lua_manager
+        .set_module(guild_id, module_name.clone(), lua_code, lua_file.filename)
+        .await?;
+
+    ctx.send(
+        CreateReply::default()
+            .content(format!(
+                "Successfully created the module! Load it using `require(\"{module_name}\")`",
+            ))
+            .ephemeral(true),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Updates the code of a module. The lua instance gets restarted so everything uses the new code.
+#[poise::command(slash_command)]
+pub async fn update(
+    ctx: Context<'_>,
+    #[autocomplete = "autocomplete_module_name"]
+    #[description = "Which module do you wanna update?"]
+    module_name: String,
+    #[description = "What's the new code for the module?"] lua_file: Attachment,
+) -> Result<(), Error> {
+    let data = ctx.data();
+    let guild_id = ctx.guild_id().unwrap();
+
+    if !data
+        .lua_manager
+        .get_modules(guild_id)
+        .await?
+        .contains_key(&module_name)
+    {
+        ctx.send(
+            CreateReply::default()
+                .content(format!("There is no module named {module_name}."))
+                .ephemeral(true),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let Some(lua_code) = download_lua_file(ctx, &lua_file).await? else {
+        return Ok(());
+    };
+
+    data.lua_manager
+        .set_module(guild_id, module_name, lua_code, lua_file.filename)
+        .await?;
+
+    ctx.send(
+        CreateReply::default()
+            .content("Successfully updated the module.")
+            .ephemeral(true),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Deletes a module.
+#[poise::command(slash_command)]
+pub async fn delete(
+    ctx: Context<'_>,
+    #[autocomplete = "autocomplete_module_name"]
+    #[description = "Which module would you like to delete?"]
+    module_name: String,
+) -> Result<(), Error> {
+    ctx.data()
+        .lua_manager
+        .delete_module(ctx.guild_id().unwrap(), &module_name)
+        .await?;
+
+    ctx.send(
+        CreateReply::default()
+            .content("Successfully deleted the module.")
+            .ephemeral(true),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Sends the file of a module.
+#[poise::command(slash_command)]
+pub async fn download(
+    ctx: Context<'_>,
+    #[autocomplete = "autocomplete_module_name"]
+    #[description = "Which module would you like to download?"]
+    module_name: String,
+) -> Result<(), Error> {
+    let modules = ctx
+        .data()
+        .lua_manager
+        .get_modules(ctx.guild_id().unwrap())
+        .await?;
+
+    let Some(module_info) = modules.get(&module_name) else {
+        ctx.send(
+            CreateReply::default()
+                .content(format!("There is no module named {module_name}."))
+                .ephemeral(true),
+        )
+        .await?;
+        return Ok(());
+    };
+
+    ctx.send(
+        CreateReply::default()
+            .attachment(CreateAttachment::bytes(
+                module_info.lua_code.clone(),
+                module_info.filename.clone(),
+            ))
+            .ephemeral(true),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Lists the modules of this guild.
+#[poise::command(slash_command)]
+pub async fn list(ctx: Context<'_>) -> Result<(), Error> {
+    let modules = ctx
+        .data()
+        .lua_manager
+        .get_modules(ctx.guild_id().unwrap())
+        .await?;
+
+    let mut lines = modules
+        .iter()
+        .map(|(module_name, module_info)| format!("- {module_name}: {}", module_info.filename))
+        .collect::<Vec<_>>();
+    lines.sort();
+
+    let content = if lines.is_empty() {
+        "This guild doesn't have any modules.".to_string()
+    } else {
+        format!("**Modules**\n{}", lines.join("\n"))
+    };
+
+    ctx.send(CreateReply::default().content(content).ephemeral(true))
+        .await?;
+
+    Ok(())
+}
@@ -32,10 +32,7 @@ async fn autocomplete_message_id(
 
     messages
         .iter()
-        .filter(|(k, v)| {
-            (v.channel_id.is_none() || v.channel_id == Some(channel_id))
-                && k.to_string().starts_with(partial)
-        })
+        .filter(|(k, v)| v.channel_id == channel_id && k.to_string().starts_with(partial))
         .map(|(k, _)| serenity_prelude::AutocompleteChoice::new(k.to_string(), k.to_string()))
         .collect::<Vec<_>>()
 }
@@ -110,7 +107,7 @@ async fn autocomplete_emoji(
 
     let channel_id = ctx.channel_id();
 
-    if message.channel_id.is_some() && message.channel_id != Some(channel_id) {
+    if message.channel_id != channel_id {
         return vec![];
     }
 
@@ -469,11 +466,7 @@ pub async fn list(
             Ok(reaction_messages) => {
                 let mut description = String::new();
                 for (message_id, channel_id, reaction_count) in reaction_messages.iter() {
-                    let append = if let Some(channel_id) = channel_id {
-                        format!("[{message_id}](https://discord.com/channels/{guild_id}/{channel_id}/{message_id})")
-                    } else {
-                        message_id.to_string()
-                    };
+                    let append = format!("[{message_id}](https://discord.com/channels/{guild_id}/{channel_id}/{message_id})");
                     description = format!(
                         "{description}{append}: {reaction_count} reaction role{}.\n",
                         if *reaction_count == 1 { "" } else { "s" }
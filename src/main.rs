@@ -2,36 +2,46 @@
 
 mod commands;
 mod managers;
+#[cfg(test)]
+mod test_utils;
 mod tokens;
 pub mod utils;
 
-use std::sync::{
-    atomic::{AtomicBool, Ordering},
-    Arc, RwLock,
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock,
+    },
+    time::Duration,
 };
 
 use managers::{
     cotd_manager::{cotd_manager_loop, CotdManager},
     currency_manager::CurrencyManager,
-    db::SurrealClient,
+    db::{
+        memory::MemoryDatabase,
+        migrations::{run_migrations, MigrationContext},
+        Database, SurrealClient,
+    },
     log_manager::{LogManager, LogSource, LogType},
-    lua_manager::LuaManager,
+    lua_manager::{components::LUA_CUSTOM_ID_PREFIX, http::LuaHttpClient, LuaEvent, LuaManager},
     remind_manager::{remind_manager_loop, RemindManager},
     storage_manager::{storage_manager_loop, StorageManager},
 };
 use poise::serenity_prelude::{
-    self as serenity, CreateInteractionResponse, CreateInteractionResponseMessage, FullEvent,
-    Webhook,
+    self as serenity, CreateInteractionResponse, CreateInteractionResponseFollowup,
+    CreateInteractionResponseMessage, CreateMessage, FullEvent, Webhook,
 };
-use utils::IdType;
+use utils::{get_seconds, IdType};
 
 use crate::managers::{
     detector_manager::{detector_manager_loop, DetectorManager},
     join_order_manager::{join_order_manager_loop, JoinOrderManager, LightweightMember},
     log_manager::log_manager_loop,
-    lua_manager::lua_manager_loop,
+    lua_manager::{lua_manager_loop, timers::lua_timer_loop},
     profile_manager::{profile_manager_loop, ProfileManager},
     reaction_manager::{reaction_manager_loop, ReactionManager},
+    reconcile_manager::{reconcile_manager_loop, ReconcileManager},
 };
 
 pub struct Data {
@@ -41,12 +51,14 @@ pub struct Data {
     remind_manager: Arc<RemindManager>,
     detector_manager: Arc<DetectorManager>,
     reaction_manager: Arc<ReactionManager>,
+    reconcile_manager: Arc<ReconcileManager>,
     currency_manager: Arc<CurrencyManager>,
     profile_manager: Arc<ProfileManager>,
     join_order_manager: Arc<JoinOrderManager>,
     lua_manager: Arc<LuaManager>,
     log_manager: Arc<LogManager>,
-    db: Arc<SurrealClient>,
+    db: Arc<dyn Database>,
+    onboarding_message: Option<String>,
 } // User data, which is stored and accessible in all command invocations
 pub struct Handler {} // User data, which is stored and accessible in all command invocations
 pub type Error = Box<dyn std::error::Error + Send + Sync>;
@@ -64,21 +76,52 @@ async fn event_handler<'thing>(
         }
         FullEvent::CacheReady { guilds } => {
             if !data.started_loops.load(Ordering::Relaxed) {
-                println!("Caches are ready! Starting all the managers.");
+                println!("Caches are ready! Running migrations.");
+                let migration_ctx = MigrationContext {
+                    db: data.db.as_ref(),
+                    log_manager: Some(data.log_manager.as_ref()),
+                    http: Some(ctx.http.as_ref()),
+                };
+                let migrated = match run_migrations(&migration_ctx).await {
+                    Ok(()) => true,
+                    Err(err) => {
+                        let err = format!("{err}\nThe managers that write to the database won't be started until the bot is restarted.");
+                        println!("{err}");
+                        data.log_manager
+                            .add_owner_log(err, LogType::Error, LogSource::Database)
+                            .await;
+                        false
+                    }
+                };
+
+                println!("Starting all the managers.");
                 let arc_ctx = Arc::new(ctx.clone());
                 storage_manager_loop(arc_ctx.clone(), data.storage_manager.clone());
                 log_manager_loop(arc_ctx.clone(), data.log_manager.clone());
-                cotd_manager_loop(
-                    arc_ctx.clone(),
-                    data.db.clone(),
-                    data.cotd_manager.clone(),
-                    data.log_manager.clone(),
-                );
-                remind_manager_loop(
-                    arc_ctx.clone(),
-                    data.remind_manager.clone(),
-                    data.log_manager.clone(),
-                );
+                // These write to the database, so they'd make a half migrated database worse.
+                if migrated {
+                    cotd_manager_loop(
+                        arc_ctx.clone(),
+                        data.db.clone(),
+                        data.cotd_manager.clone(),
+                        data.log_manager.clone(),
+                    );
+                    remind_manager_loop(
+                        arc_ctx.clone(),
+                        data.remind_manager.clone(),
+                        data.log_manager.clone(),
+                    );
+                    lua_timer_loop(
+                        arc_ctx.clone(),
+                        data.lua_manager.clone(),
+                        data.log_manager.clone(),
+                    );
+                    reconcile_manager_loop(
+                        arc_ctx.clone(),
+                        data.reconcile_manager.clone(),
+                        data.log_manager.clone(),
+                    );
+                }
                 lua_manager_loop(data.lua_manager.clone());
                 detector_manager_loop(data.detector_manager.clone());
                 reaction_manager_loop(data.reaction_manager.clone());
@@ -86,18 +129,96 @@ async fn event_handler<'thing>(
                 join_order_manager_loop(data.join_order_manager.clone());
                 data.started_loops.swap(true, Ordering::Relaxed);
             }
-            // TODO: Look through all relevant data and check if its still valid.
-            // If a reminder for a user is in a guild the user is no longer in, remove them.
-            // If a reaction role has an emoji, message or role that no longer exists, remove them.
-            // If a folder about a guild still exists even though the bot is no longer in the guild, remove them.
-            //
-            // Also do these kinds of checks whenever communication to db hasn't worked.
             data.join_order_manager.join_orders.write().await.clear();
         }
         FullEvent::Resume { event: _ } => {
             data.join_order_manager.join_orders.write().await.clear();
         }
+        FullEvent::GuildCreate { guild, is_new } => {
+            // This event also happens for every guild when the bot starts, so only handle newly added guilds.
+            if *is_new != Some(true) {
+                return Ok(());
+            }
+
+            let id = IdType::GuildId(guild.id);
+
+            if let Err(err) = data.db.set_guild_left_time(guild.id, None).await {
+                data.log_manager
+                    .add_log(
+                        id,
+                        format!("Couldn't cancel the removal of this guild's data. {err}"),
+                        LogType::Error,
+                        LogSource::Guild,
+                    )
+                    .await;
+            }
+
+            let (Some(onboarding_message), Some(system_channel_id)) =
+                (&data.onboarding_message, guild.system_channel_id)
+            else {
+                return Ok(());
+            };
+
+            if let Err(err) = system_channel_id
+                .send_message(ctx, CreateMessage::new().content(onboarding_message))
+                .await
+            {
+                data.log_manager
+                    .add_log(
+                        id,
+                        format!("Couldn't send the onboarding message. {err}"),
+                        LogType::Warning,
+                        LogSource::Guild,
+                    )
+                    .await;
+            }
+        }
+        FullEvent::GuildDelete {
+            incomplete,
+            full: _,
+        } => {
+            // Unavailable means there's an outage, not that the bot was removed.
+            if incomplete.unavailable {
+                return Ok(());
+            }
+
+            let guild_id = incomplete.id;
+            data.reconcile_manager.drop_guild_caches(guild_id).await;
+
+            let id = IdType::GuildId(guild_id);
+
+            if let Err(err) = data
+                .db
+                .set_guild_left_time(guild_id, Some(get_seconds()))
+                .await
+            {
+                // The reconciliation sweep will try to mark it again later.
+                data.log_manager
+                    .add_log(
+                        id,
+                        format!("Couldn't schedule the removal of this guild's data. {err}"),
+                        LogType::Error,
+                        LogSource::Guild,
+                    )
+                    .await;
+                return Ok(());
+            }
+
+            data.log_manager
+                .add_log(
+                    id,
+                    format!(
+                        "I was removed from this guild. All the data about this guild will be removed in {} hours unless I'm added back.",
+                        data.reconcile_manager.get_grace_period_hours()
+                    ),
+                    LogType::Info,
+                    LogSource::Guild,
+                )
+                .await;
+        }
         FullEvent::GuildMemberAddition { new_member } => {
+            data.lua_manager.on_member_join(new_member);
+
             if let Some(join_order) = data
                 .join_order_manager
                 .silent_get_join_order(new_member.guild_id)
@@ -117,6 +238,8 @@ async fn event_handler<'thing>(
             user,
             member_data_if_available: _,
         } => {
+            data.lua_manager.on_member_leave(*guild_id, user);
+
             if let Some(join_order) = data
                 .join_order_manager
                 .silent_get_join_order(*guild_id)
@@ -151,6 +274,8 @@ async fn event_handler<'thing>(
             }
         }
         FullEvent::Message { new_message } => {
+            data.lua_manager.on_message(new_message, framework.bot_id);
+
             if let Err(err) = data.detector_manager.on_message(ctx, new_message).await {
                 let id;
 
@@ -171,6 +296,9 @@ async fn event_handler<'thing>(
             }
         }
         FullEvent::ReactionAdd { add_reaction } => {
+            data.lua_manager
+                .on_reaction(LuaEvent::ReactionAdd, add_reaction, framework.bot_id);
+
             let res = data
                 .reaction_manager
                 .reaction_add_event(ctx, add_reaction, framework.bot_id)
@@ -200,6 +328,12 @@ async fn event_handler<'thing>(
             }
         }
         FullEvent::ReactionRemove { removed_reaction } => {
+            data.lua_manager.on_reaction(
+                LuaEvent::ReactionRemove,
+                removed_reaction,
+                framework.bot_id,
+            );
+
             let res = data
                 .reaction_manager
                 .reaction_remove_event(ctx, removed_reaction, framework.bot_id)
@@ -321,36 +455,138 @@ async fn event_handler<'thing>(
                                 )
                                 .await;
 
-                            command_interaction
+                            let content =
+                                format!("An error occurred while executing this command: {err}");
+
+                            // The command might've already replied or deferred before the error.
+                            if command_interaction
                                 .create_response(
                                     ctx,
                                     CreateInteractionResponse::Message(
-                                        CreateInteractionResponseMessage::new().content(format!(
-                                            "An error occurred while executing this command: {err}"
-                                        )),
+                                        CreateInteractionResponseMessage::new()
+                                            .content(content.clone()),
                                     ),
                                 )
-                                .await?
+                                .await
+                                .is_err()
+                            {
+                                command_interaction
+                                    .create_followup(
+                                        ctx,
+                                        CreateInteractionResponseFollowup::new().content(content),
+                                    )
+                                    .await?;
+                            }
                         }
                     }
                 }
             }
+            serenity::Interaction::Autocomplete(autocomplete_interaction) => {
+                // Only the lua commands are registered as guild commands.
+                if let Some(guild_id) = autocomplete_interaction.data.guild_id {
+                    let result = data
+                        .lua_manager
+                        .execute_autocomplete(guild_id, autocomplete_interaction.clone())
+                        .await;
+
+                    if let Err(err) = result {
+                        let _ = data
+                            .log_manager
+                            .add_log(
+                                IdType::GuildId(guild_id),
+                                err.to_string(),
+                                LogType::Error,
+                                LogSource::Lua,
+                            )
+                            .await;
+                    }
+                }
+            }
+            serenity::Interaction::Component(component_interaction) => {
+                let Some(guild_id) = component_interaction.guild_id else {
+                    return Ok(());
+                };
+
+                // Components made by the bot itself are handled by their own collectors.
+                if !component_interaction
+                    .data
+                    .custom_id
+                    .starts_with(LUA_CUSTOM_ID_PREFIX)
+                {
+                    return Ok(());
+                }
+
+                let result = data
+                    .lua_manager
+                    .execute_component(guild_id, component_interaction.clone())
+                    .await;
+
+                if let Err(err) = result {
+                    let _ = data
+                        .log_manager
+                        .add_log(
+                            IdType::GuildId(guild_id),
+                            err.to_string(),
+                            LogType::Error,
+                            LogSource::Lua,
+                        )
+                        .await;
+
+                    let content = format!("An error occurred while handling this component: {err}");
+
+                    if component_interaction
+                        .create_response(
+                            ctx,
+                            CreateInteractionResponse::Message(
+                                CreateInteractionResponseMessage::new()
+                                    .content(content.clone())
+                                    .ephemeral(true),
+                            ),
+                        )
+                        .await
+                        .is_err()
+                    {
+                        component_interaction
+                            .create_followup(
+                                ctx,
+                                CreateInteractionResponseFollowup::new()
+                                    .content(content)
+                                    .ephemeral(true),
+                            )
+                            .await?;
+                    }
+                }
+            }
             _ => {}
         },
         _ => {}
     }
     Ok(())
 }
+
 #[tokio::main]
 async fn main() {
     println!("Starting bot...");
 
     let bot_settings = tokens::get_bot_settings();
 
-    let db = Arc::new(SurrealClient::new(bot_settings.surrealdb));
+    let db: Arc<dyn Database> = match bot_settings.surrealdb {
+        Some(sign_in_info) => Arc::new(SurrealClient::new(sign_in_info)),
+        None => {
+            println!("No SurrealDB settings provided. Using local database.");
+            Arc::new(
+                MemoryDatabase::new(bot_settings.local_database_file)
+                    .expect("Couldn't load the local database."),
+            )
+        }
+    };
 
     let storage_manager = Arc::new(StorageManager::new(bot_settings.temp_data_directory).await);
 
+    let lua_http_client = bot_settings.lua_http.map(|http_settings| {
+        Arc::new(LuaHttpClient::new(http_settings).expect("Couldn't create the lua http client."))
+    });
+
     let framework = poise::Framework::builder()
         .options(poise::FrameworkOptions {
             commands: commands::get_commands(),
@@ -375,25 +611,46 @@ async fn main() {
                     arc_ctx.clone(),
                 ));
 
+                let profile_manager = Arc::new(ProfileManager::new());
+                let remind_manager = Arc::new(RemindManager::new(db.clone()));
+                let reaction_manager = Arc::new(ReactionManager::new(db.clone()));
+                let lua_manager = Arc::new(LuaManager::new(
+                    db.clone(),
+                    log_manager.clone(),
+                    profile_manager.clone(),
+                    arc_ctx,
+                    bot_settings.lua_limits,
+                    lua_http_client,
+                ));
+                let detector_manager = Arc::new(DetectorManager::new(db.clone()));
+                let join_order_manager = Arc::new(JoinOrderManager::new());
+
                 Ok(Data {
                     cotd_manager: Arc::new(CotdManager::new(db.clone())),
-                    remind_manager: Arc::new(RemindManager::new(db.clone())),
-                    detector_manager: Arc::new(DetectorManager::new(db.clone())),
-                    reaction_manager: Arc::new(ReactionManager::new(db.clone())),
-                    lua_manager: Arc::new(LuaManager::new(
+                    reconcile_manager: Arc::new(ReconcileManager::new(
                         db.clone(),
+                        reaction_manager.clone(),
+                        remind_manager.clone(),
+                        lua_manager.clone(),
+                        detector_manager.clone(),
+                        join_order_manager.clone(),
                         log_manager.clone(),
-                        arc_ctx,
+                        Duration::from_secs(bot_settings.guild_data_grace_period_hours * 60 * 60),
                     )),
+                    detector_manager,
+                    remind_manager,
+                    reaction_manager,
+                    lua_manager,
                     currency_manager: Arc::new(
                         CurrencyManager::new(bot_settings.open_exchange_rates_token).await,
                     ),
-                    profile_manager: Arc::new(ProfileManager::new()),
-                    join_order_manager: Arc::new(JoinOrderManager::new()),
+                    profile_manager,
+                    join_order_manager,
                     log_manager,
                     storage_manager,
                     started_loops: AtomicBool::new(false),
                     db,
+                    onboarding_message: bot_settings.onboarding_message,
                 })
             })
         })
@@ -10,7 +10,7 @@ use serde::{Deserialize, Serialize};
 use crate::{managers::remind_manager::is_user_fault, utils::IdType, Error};
 
 use super::{
-    db::SurrealClient,
+    db::Database,
     log_manager::{LogManager, LogSource, LogType},
 };
 
@@ -36,7 +36,7 @@ pub struct CotdRoleDataQuery {
 }
 
 pub struct CotdManager {
-    db: Arc<SurrealClient>,
+    db: Arc<dyn Database>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -70,7 +70,7 @@ impl CotdError {
 }
 
 impl CotdManager {
-    pub fn new(db: Arc<SurrealClient>) -> Self {
+    pub fn new(db: Arc<dyn Database>) -> Self {
         Self { db }
     }
 
@@ -206,7 +206,7 @@ impl CotdManager {
 /// Controls things such as: the color of all cotd roles.
 pub fn cotd_manager_loop(
     arc_ctx: Arc<Context>,
-    db: Arc<SurrealClient>,
+    db: Arc<dyn Database>,
     cotd_manager: Arc<CotdManager>,
     log_manager: Arc<LogManager>,
 ) {
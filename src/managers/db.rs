@@ -1,25 +1,495 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    sync::{Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
 
+use async_trait::async_trait;
 use poise::serenity_prelude::{GuildId, MessageId, UserId};
 use reqwest::{Client, RequestBuilder};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{Map, Value};
 
 use crate::{
-    managers::profile_manager::ProfileData, tokens::SurrealDbSignInInfo, utils::IdType, Error,
+    managers::profile_manager::ProfileData,
+    tokens::SurrealDbSignInInfo,
+    utils::{get_seconds, IdType},
+    Error,
 };
 
 use super::{
     cotd_manager::{ColorInfo, CotdRoleData, CotdRoleDataQuery},
     detector_manager::DetectorInfo,
-    lua_manager::LuaCommandInfo,
+    lua_manager::{
+        history::{LuaCommandVersion, MAX_COMMAND_VERSIONS},
+        modules::LuaModuleInfo,
+        timers::{LuaTimer, MAX_TIMERS_PER_GUILD},
+        LuaCommandInfo, LuaEvent, LuaEventInfo,
+    },
     message_manager::StoredMessageData,
     remind_manager::RemindInfo,
 };
 
+pub mod memory;
+pub mod migrations;
+
+/// Limits on the data stores of a guild, so one guild can't grow without bound.
+pub const MAX_DATA_STORES_PER_GUILD: usize = 25;
+pub const MAX_KEYS_PER_DATA_STORE: usize = 1000;
+/// How big all data stores of a guild can be together, in bytes of JSON.
+pub const MAX_DATA_STORE_BYTES_PER_GUILD: usize = 1_000_000;
+
+/// Checks the data store limits before `value` gets stored at `data_store_name` and `data_store_key`.
+///
+/// Both backends check with this, so the size is always the length of the data stores serialized as JSON.
+fn check_data_store_limits(
+    data_stores: &Map<String, Value>,
+    data_store_name: &str,
+    data_store_key: &str,
+    value: &Value,
+) -> Result<(), Error> {
+    let data_store = data_stores.get(data_store_name).and_then(Value::as_object);
+    let current = data_store.and_then(|data_store| data_store.get(data_store_key));
+
+    if data_store.is_none() && data_stores.len() >= MAX_DATA_STORES_PER_GUILD {
+        return Err(format!(
+            "This guild can't have more than {MAX_DATA_STORES_PER_GUILD} data stores."
+        )
+        .into());
+    }
+
+    if current.is_none() && data_store.map_or(0, Map::len) >= MAX_KEYS_PER_DATA_STORE {
+        return Err(
+            format!("A data store can't have more than {MAX_KEYS_PER_DATA_STORE} keys.").into(),
+        );
+    }
+
+    let current_size = match current {
+        Some(current) => serde_json::to_string(current)?.len(),
+        None => 0,
+    };
+    let new_size = serde_json::to_string(data_stores)?.len() - current_size
+        + serde_json::to_string(value)?.len();
+
+    if new_size > MAX_DATA_STORE_BYTES_PER_GUILD {
+        return Err(format!(
+            "The data stores of this guild can't be bigger than {MAX_DATA_STORE_BYTES_PER_GUILD} bytes."
+        )
+        .into());
+    }
+
+    Ok(())
+}
+
+/// Null if the key isn't set.
+fn get_data_store_value(
+    data_stores: &Map<String, Value>,
+    data_store_name: &str,
+    data_store_key: &str,
+) -> Value {
+    data_stores
+        .get(data_store_name)
+        .and_then(|data_store| data_store.get(data_store_key))
+        .cloned()
+        .unwrap_or(Value::Null)
+}
+
+/// What a data store value becomes after `amount` is added to it. Missing values count as 0.
+fn increment_data_store_number(current: &Value, amount: f64) -> Result<f64, Error> {
+    match current {
+        Value::Null => Ok(amount),
+        Value::Number(number) => Ok(number.as_f64().unwrap_or_default() + amount),
+        _ => Err("Only numbers can be incremented.".into()),
+    }
+}
+
+/// How many times `SurrealClient` tries a data store change when the data stores keep changing under it.
+const MAX_DATA_STORE_WRITE_ATTEMPTS: usize = 5;
+
+/// Thrown by `VERSIONED_DATA_STORE_WRITE` when the data stores changed after they were read.
+const DATA_STORES_CHANGED: &str = "The data stores changed while they were being written to.";
+
+/// Stores `$value` at `$data_store_name` and `$data_store_key`, but only if the data stores are still at `$version`.
+///
+/// Every change to the data stores bumps `data_stores_version`, so the limits checked on what was read still hold.
+/// BEGIN and COMMIT don't get responses, so the IF statement's response is at index 1, after the LET statement.
+const VERSIONED_DATA_STORE_WRITE: &str = "BEGIN TRANSACTION;
+LET $current_version = (SELECT VALUE data_stores_version FROM type::thing(\"guild\", $guild_id))[0] ?? 0;
+IF $current_version != $version {
+    THROW $changed;
+} ELSE {
+    UPDATE type::thing(\"guild\", $guild_id) SET data_stores[$data_store_name][$data_store_key] = $value, data_stores_version = $version + 1 RETURN NONE;
+};
+COMMIT TRANSACTION;";
+
+/// Everything the managers need from a storage backend.
+///
+/// `SurrealClient` talks to a SurrealDB server, while `memory::MemoryDatabase` keeps everything
+/// in-process so the bot can run without a database server.
+#[async_trait]
+pub trait Database: Send + Sync {
+    /// Whether the database can currently be reached.
+    fn health(&self) -> DatabaseHealth {
+        DatabaseHealth::Up
+    }
+
+    async fn get_data_store(
+        &self,
+        guild_id: GuildId,
+        data_store_name: &str,
+    ) -> Result<HashMap<String, Value>, Error>;
+
+    /// Fails if the guild would go over its data store limits.
+    async fn set_data_store_value(
+        &self,
+        guild_id: GuildId,
+        data_store_name: &str,
+        data_store_key: &str,
+        value: &Value,
+    ) -> Result<(), Error>;
+
+    async fn remove_data_store_value(
+        &self,
+        guild_id: GuildId,
+        data_store_name: &str,
+        data_store_key: &str,
+    ) -> Result<(), Error>;
+
+    /// Adds to a number in a data store and returns the new number. Missing keys count as 0.
+    ///
+    /// Happens in a single step, so increments from different places can't overwrite each other.
+    async fn increment_data_store_value(
+        &self,
+        guild_id: GuildId,
+        data_store_name: &str,
+        data_store_key: &str,
+        amount: f64,
+    ) -> Result<f64, Error>;
+
+    /// Sets a value, but only if the current value is still `expected`. Returns whether it was set.
+    ///
+    /// Missing keys count as null.
+    async fn compare_and_set_data_store_value(
+        &self,
+        guild_id: GuildId,
+        data_store_name: &str,
+        data_store_key: &str,
+        expected: &Value,
+        value: &Value,
+    ) -> Result<bool, Error>;
+
+    async fn get_all_guild_lua_commands(
+        &self,
+        guild_id: GuildId,
+    ) -> Result<HashMap<String, LuaCommandInfo>, Error>;
+
+    async fn add_guild_lua_command(
+        &self,
+        command_name: &str,
+        lua_command_info: &LuaCommandInfo,
+        guild_id: GuildId,
+    ) -> Result<(), Error>;
+
+    /// Removes a command along with its history.
+    async fn remove_guild_lua_command(
+        &self,
+        guild_id: GuildId,
+        command_name: &str,
+    ) -> Result<(), Error>;
+
+    /// Gets the saved versions of a command, newest first.
+    async fn get_guild_lua_command_history(
+        &self,
+        guild_id: GuildId,
+        command_name: &str,
+    ) -> Result<Vec<LuaCommandVersion>, Error>;
+
+    /// Saves a version of a command, removing the oldest versions so at most `MAX_COMMAND_VERSIONS` are kept.
+    async fn add_guild_lua_command_version(
+        &self,
+        guild_id: GuildId,
+        command_name: &str,
+        command_version: &LuaCommandVersion,
+    ) -> Result<(), Error>;
+
+    async fn get_all_guild_lua_modules(
+        &self,
+        guild_id: GuildId,
+    ) -> Result<HashMap<String, LuaModuleInfo>, Error>;
+
+    /// Adds a lua module, or replaces the one with the same name.
+    async fn set_guild_lua_module(
+        &self,
+        guild_id: GuildId,
+        module_name: &str,
+        lua_module_info: &LuaModuleInfo,
+    ) -> Result<(), Error>;
+
+    async fn remove_guild_lua_module(
+        &self,
+        guild_id: GuildId,
+        module_name: &str,
+    ) -> Result<(), Error>;
+
+    async fn get_all_guild_lua_events(
+        &self,
+        guild_id: GuildId,
+    ) -> Result<HashMap<LuaEvent, LuaEventInfo>, Error>;
+
+    async fn set_guild_lua_event(
+        &self,
+        guild_id: GuildId,
+        event: LuaEvent,
+        lua_event_info: &LuaEventInfo,
+    ) -> Result<(), Error>;
+
+    async fn remove_guild_lua_event(&self, guild_id: GuildId, event: LuaEvent)
+        -> Result<(), Error>;
+
+    /// Gets the lua timers of every guild that has any.
+    async fn get_all_lua_timers(&self) -> Result<Vec<(GuildId, HashMap<String, LuaTimer>)>, Error>;
+
+    async fn get_guild_lua_timers(
+        &self,
+        guild_id: GuildId,
+    ) -> Result<HashMap<String, LuaTimer>, Error>;
+
+    /// Adds a lua timer, or replaces the one with the same name.
+    ///
+    /// Fails if it would be a new timer and the guild already has `MAX_TIMERS_PER_GUILD` timers.
+    async fn set_lua_timer(
+        &self,
+        guild_id: GuildId,
+        timer_name: &str,
+        lua_timer: &LuaTimer,
+    ) -> Result<(), Error>;
+
+    async fn remove_lua_timer(&self, guild_id: GuildId, timer_name: &str) -> Result<(), Error>;
+
+    async fn get_guild_cotd_role(
+        &self,
+        guild_id: GuildId,
+    ) -> Result<Option<CotdRoleDataQuery>, Error>;
+
+    /// Gets the id and cotd_role from every guild where cotd_role exists
+    async fn get_all_guild_cotd_role(&self) -> Result<Vec<CotdRoleDataQuery>, Error>;
+
+    async fn update_guild_cotd_role(
+        &self,
+        cotd_role_data: &Option<CotdRoleData>,
+        guild_id: GuildId,
+    ) -> Result<(), Error>;
+
+    async fn mark_cotd_role_updated(
+        &self,
+        guild_id: GuildId,
+        current_day: u64,
+    ) -> Result<(), Error>;
+
+    async fn get_cotd(&self, day: u64) -> Result<Option<ColorInfo>, Error>;
+
+    /// Stores the color for the day unless one already exists, and returns the color that ended up being stored.
+    async fn get_or_update_cotd(&self, day: u64, color: &ColorInfo) -> Result<ColorInfo, Error>;
+
+    async fn get_all_message_detectors(&self, id: IdType) -> Result<Vec<DetectorInfo>, Error>;
+
+    async fn add_message_detector(
+        &self,
+        id: IdType,
+        detect_info: &DetectorInfo,
+    ) -> Result<(), Error>;
+
+    async fn remove_message_detector(&self, id: IdType, index: usize) -> Result<(), Error>;
+
+    async fn get_guild_messages(
+        &self,
+        guild_id: GuildId,
+    ) -> Result<HashMap<MessageId, StoredMessageData>, Error>;
+
+    async fn clear_message_data(&self, id: IdType, message_id: &MessageId) -> Result<(), Error>;
+
+    async fn set_guild_message(
+        &self,
+        guild_id: GuildId,
+        message_id: MessageId,
+        message_data: &StoredMessageData,
+    ) -> Result<(), Error>;
+
+    async fn remove_guild_message(
+        &self,
+        guild_id: GuildId,
+        message_id: MessageId,
+    ) -> Result<(), Error>;
+
+    async fn get_user_profile(&self, user_id: UserId) -> Result<ProfileData, Error>;
+
+    async fn set_user_profile(&self, user_id: UserId, profile: &ProfileData) -> Result<(), Error>;
+
+    async fn delete_user_profile(&self, user_id: UserId) -> Result<(), Error>;
+
+    async fn list_user_reminders(&self, user_id: UserId) -> Result<Vec<RemindInfo>, Error>;
+
+    /// Adds a user reminder to the database. Also assigns the id field onto the RemindInfo
+    async fn add_user_reminder(&self, remind_info: &mut RemindInfo) -> Result<(), Error>;
+
+    /// Overwrites a reminder that already has an id. Used when a looping reminder gets rescheduled.
+    async fn update_reminder(&self, remind_info: &RemindInfo) -> Result<(), Error>;
+
+    async fn delete_table_id(&self, table_id: &String) -> Result<(), Error>;
+
+    /// Returns a list of reminders that have finished and needs to be sent.
+    async fn get_pending_reminders(&self, current_time: u64) -> Result<Vec<RemindInfo>, Error>;
+
+    async fn get_next_reminder_time(&self) -> Result<Option<u64>, Error>;
+
+    async fn get_all_reminders(&self) -> Result<Vec<RemindInfo>, Error>;
+
+    /// Gets the id of every guild that has anything stored about it,
+    /// along with when the bot left the guild if it has.
+    async fn get_stored_guilds(&self) -> Result<Vec<(GuildId, Option<u64>)>, Error>;
+
+    /// Marks when the bot left a guild. Setting it to None means the bot is in the guild.
+    async fn set_guild_left_time(
+        &self,
+        guild_id: GuildId,
+        left_time: Option<u64>,
+    ) -> Result<(), Error>;
+
+    /// Deletes everything stored about a guild/user, including their reminders.
+    ///
+    /// Returns the ids of the users who had reminders removed.
+    async fn clear_bot_data(&self, id: IdType) -> Result<Vec<UserId>, Error>;
+
+    /// Collects everything stored about a guild/user, including their reminders.
+    async fn export_bot_data(&self, id: IdType) -> Result<BotDataExport, Error>;
+
+    /// Gets the version of the stored data. Returns 0 if no version has been stored yet.
+    async fn get_schema_version(&self) -> Result<u64, Error>;
+
+    async fn set_schema_version(&self, version: u64) -> Result<(), Error>;
+
+    /// Gets the record id and the value of `field` for every record in `table` where that field exists.
+    ///
+    /// Only meant for migrations. `field` gets put directly into the query, so it should never come from a user.
+    async fn get_records_with_field(
+        &self,
+        table: &str,
+        field: &'static str,
+    ) -> Result<Vec<(String, Value)>, Error>;
+
+    /// Only meant for migrations. `field` gets put directly into the query, so it should never come from a user.
+    async fn set_record_field(
+        &self,
+        table_id: &str,
+        field: &'static str,
+        value: &Value,
+    ) -> Result<(), Error>;
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DatabaseHealth {
+    /// The database can be reached. A single failed request that worked when retried still counts as up.
+    Up,
+    /// Half of the failures needed to consider the database down happened in a row, or a read ran out of retries.
+    Degraded,
+    /// Too many requests failed in a row. Queries fail immediately until the cooldown is over.
+    Down,
+}
+
+impl DatabaseHealth {
+    pub fn to_str(&self) -> &str {
+        match self {
+            DatabaseHealth::Up => "Up",
+            DatabaseHealth::Degraded => "Degraded",
+            DatabaseHealth::Down => "Down",
+        }
+    }
+}
+
 pub struct SurrealClient {
     client: Client,
     sign_in_info: SurrealDbSignInInfo,
+    connection_state: Mutex<ConnectionState>,
+}
+
+/// Keeps track of failed requests so the client can stop sending requests while the database is down.
+#[derive(Default)]
+struct ConnectionState {
+    consecutive_failures: u32,
+    /// A read failed even after all of its retries.
+    retries_exhausted: bool,
+    /// While set and in the future, queries fail without being sent.
+    down_until: Option<Instant>,
+}
+
+/// Why a query failed. Only transport errors count towards the database being down, and only they get retried.
+enum QueryFailure {
+    Transport(Error),
+    /// The database is marked as down, so the query wasn't sent.
+    Down(Error),
+    Other(Error),
+}
+
+impl From<QueryFailure> for Error {
+    fn from(value: QueryFailure) -> Self {
+        match value {
+            QueryFailure::Transport(err) | QueryFailure::Down(err) => {
+                Box::new(ConnectionError(err.to_string()))
+            }
+            QueryFailure::Other(err) => err,
+        }
+    }
+}
+
+/// The query couldn't reach the database. Callers that retry on their own can wait these out quietly.
+#[derive(Debug)]
+pub struct ConnectionError(String);
+
+impl std::fmt::Display for ConnectionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for ConnectionError {}
+
+pub fn is_connection_error(err: &Error) -> bool {
+    err.downcast_ref::<ConnectionError>().is_some()
+}
+
+/// Variables that are sent alongside a query. They can be used inside of the query as `$name`.
+#[derive(Default)]
+pub struct QueryVars(Map<String, Value>);
+
+impl QueryVars {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Binds a value to `$name`.
+    ///
+    /// The value is sent as JSON in the body of the request, so it keeps its type and can be as big as needed.
+    pub fn bind<T: Serialize + ?Sized>(mut self, name: &str, value: &T) -> Result<Self, Error> {
+        self.0
+            .insert(name.to_string(), serde_json::to_value(value)?);
+        Ok(self)
+    }
+
+    /// Binds the table and id of a record id such as `reminder:abc` to `$table` and `$id`.
+    ///
+    /// Use `type::thing($table, $id)` in the query to refer to the record.
+    pub fn bind_table_id(self, table_id: &str) -> Result<Self, Error> {
+        let Some((table, id)) = table_id.split_once(':') else {
+            return Err(format!("\"{table_id}\" is not a valid record id.").into());
+        };
+
+        let id = id.trim_start_matches('⟨').trim_end_matches('⟩');
+
+        match id.parse::<u64>() {
+            Ok(number_id) => self.bind("table", table)?.bind("id", &number_id),
+            Err(_) => self.bind("table", table)?.bind("id", id),
+        }
+    }
 }
 
 pub trait OptionOrVec {
@@ -44,12 +514,23 @@ pub struct DbResponse {
     pub time: String,
 }
 
+/// A request to the `/rpc` endpoint, which takes the variables of a query in its body.
+#[derive(Serialize)]
+struct RpcRequest<'a> {
+    id: u64,
+    method: &'static str,
+    params: (&'a str, &'a Map<String, Value>),
+}
+
+#[derive(Deserialize)]
+struct RpcResponse {
+    result: Option<Vec<DbResponse>>,
+    error: Option<RpcError>,
+}
+
 #[derive(Deserialize)]
-pub struct DbError {
-    pub code: u16,
-    pub details: String,
-    pub description: String,
-    pub information: String,
+struct RpcError {
+    message: String,
 }
 
 #[derive(Debug)]
@@ -116,6 +597,7 @@ impl SurrealClient {
         Self {
             client,
             sign_in_info,
+            connection_state: Mutex::new(ConnectionState::default()),
         }
     }
 
@@ -123,7 +605,7 @@ impl SurrealClient {
     pub fn create_builder(&self) -> RequestBuilder {
         let sign_in = &self.sign_in_info;
         self.client
-            .post(format!("{}/sql", &sign_in.address))
+            .post(format!("{}/rpc", &sign_in.address))
             .basic_auth(&sign_in.username, Some(&sign_in.password))
             .header("Accept", "application/json")
             .header("NS", &sign_in.namespace)
@@ -132,52 +614,226 @@ impl SurrealClient {
 
     /// Sends query to database and returns the result.
     ///
+    /// Any values that come from users should be passed through `vars` and referenced as `$name` in the query,
+    /// instead of being formatted into the query string.
+    ///
     /// Errors if database is offline or if address is invalid.
-    pub async fn query<S: Into<String>>(&self, query: S) -> Result<Responses, Error> {
+    pub async fn query<S: Into<String>>(
+        &self,
+        query: S,
+        vars: QueryVars,
+    ) -> Result<Responses, Error> {
         let query: String = query.into();
-        let builder = self.create_builder().body(query.clone());
+        Ok(self.send_query(&query, &vars).await?)
+    }
+
+    /// Same as `query`, but retries with exponential backoff if the database couldn't be reached.
+    ///
+    /// Only use this for queries that are safe to run more than once, such as SELECT.
+    pub async fn read_query<S: Into<String>>(
+        &self,
+        query: S,
+        vars: QueryVars,
+    ) -> Result<Responses, Error> {
+        let query: String = query.into();
+        let retry_settings = &self.sign_in_info.retry;
+        let mut attempt = 0;
+
+        loop {
+            match self.send_query(&query, &vars).await {
+                Ok(responses) => return Ok(responses),
+                Err(QueryFailure::Transport(err)) if attempt < retry_settings.max_retries => {
+                    let delay = retry_settings
+                        .base_delay_ms
+                        .saturating_mul(1u64 << attempt.min(16))
+                        .min(retry_settings.max_delay_ms);
+                    println!("Database request failed, retrying in {delay}ms. {err}");
+                    tokio::time::sleep(Duration::from_millis(delay)).await;
+                    attempt += 1;
+                }
+                Err(err) => {
+                    if matches!(err, QueryFailure::Transport(_)) {
+                        self.lock_connection_state().retries_exhausted = true;
+                    }
+                    return Err(err.into());
+                }
+            }
+        }
+    }
+
+    /// Changes a data store value after checking the limits on what was read, like `MemoryDatabase` does.
+    ///
+    /// `change` gets the current value, null if it isn't set, and returns the value to store, if any,
+    /// along with what to return. It runs again if something else changed the data stores in the meantime.
+    async fn change_data_store_value<T>(
+        &self,
+        guild_id: GuildId,
+        data_store_name: &str,
+        data_store_key: &str,
+        change: impl Fn(&Value) -> Result<(Option<Value>, T), Error>,
+    ) -> Result<T, Error> {
+        #[derive(Deserialize)]
+        struct VersionedDataStores {
+            data_stores: Option<Map<String, Value>>,
+            data_stores_version: Option<u64>,
+        }
+
+        for _ in 0..MAX_DATA_STORE_WRITE_ATTEMPTS {
+            let versioned: Option<VersionedDataStores> = self
+                .read_query(
+                    "SELECT data_stores, data_stores_version FROM type::thing(\"guild\", $guild_id);",
+                    QueryVars::new().bind("guild_id", &guild_id.get())?,
+                )
+                .await?
+                .take(0)?;
+            let (data_stores, version) = versioned.map_or((Map::new(), 0), |versioned| {
+                (
+                    versioned.data_stores.unwrap_or_default(),
+                    versioned.data_stores_version.unwrap_or_default(),
+                )
+            });
+
+            let current = get_data_store_value(&data_stores, data_store_name, data_store_key);
+            let (new_value, result) = change(&current)?;
+            let Some(new_value) = new_value else {
+                return Ok(result);
+            };
+
+            check_data_store_limits(&data_stores, data_store_name, data_store_key, &new_value)?;
+
+            let err = self
+                .query(
+                    VERSIONED_DATA_STORE_WRITE,
+                    QueryVars::new()
+                        .bind("guild_id", &guild_id.get())?
+                        .bind("data_store_name", data_store_name)?
+                        .bind("data_store_key", data_store_key)?
+                        .bind("value", &new_value)?
+                        .bind("version", &version)?
+                        .bind("changed", DATA_STORES_CHANGED)?,
+                )
+                .await?
+                .take_err(1);
+
+            match err {
+                None => return Ok(result),
+                Some(err) if err.to_string() == DATA_STORES_CHANGED => continue,
+                Some(err) => return Err(err),
+            }
+        }
+
+        Err(format!(
+            "Couldn't change \"{data_store_key}\" because the data stores kept changing. Try again later."
+        )
+        .into())
+    }
+
+    async fn send_query(&self, query: &str, vars: &QueryVars) -> Result<Responses, QueryFailure> {
+        let down_until = self.lock_connection_state().down_until;
+        if let Some(down_until) = down_until {
+            let now = Instant::now();
+            if down_until > now {
+                return Err(QueryFailure::Down(
+                    format!(
+                        "Database is down. Trying again in {} seconds.",
+                        (down_until - now).as_secs() + 1
+                    )
+                    .into(),
+                ));
+            }
+        }
+
+        let builder = self.create_builder().json(&RpcRequest {
+            id: 1,
+            method: "query",
+            params: (query, &vars.0),
+        });
 
         let built_request = match builder.build() {
             Ok(request) => request,
             Err(err) => {
-                return Err(format!("Failed to build request to database. {err}").into());
+                return Err(QueryFailure::Other(
+                    format!("Failed to build request to database. {err}").into(),
+                ));
             }
         };
         let response = match self.client.execute(built_request).await {
             Ok(response) => response,
             Err(err) => {
-                return Err(format!(
-                    "Failed to execute request to database. Maybe it's offline? {err}"
-                )
-                .into());
+                self.record_failure();
+                return Err(QueryFailure::Transport(
+                    format!("Failed to execute request to database. Maybe it's offline? {err}")
+                        .into(),
+                ));
             }
         };
 
-        if !response.status().is_success() {
-            println!("Failed query: {}", query);
-            match response.json::<DbError>().await {
-                Ok(ok_err) => {
-                    println!("{}", ok_err.information);
-                    return Err(format!(
-                        "Database failed to understand query. {}, {}",
-                        ok_err.details, ok_err.description
-                    )
-                    .into());
-                }
-                Err(err) => {
-                    return Err(format!(
+        self.record_success();
+
+        let status = response.status();
+        let rpc_response = match response.json::<RpcResponse>().await {
+            Ok(rpc_response) => rpc_response,
+            Err(err) if status.is_success() => return Err(QueryFailure::Other(err.into())),
+            Err(err) => {
+                println!("Failed query: {}", query);
+                return Err(QueryFailure::Other(
+                    format!(
                         "Database failed to understand query. Failed to parse error. {}",
                         err
                     )
-                    .into())
-                }
+                    .into(),
+                ));
             }
+        };
+
+        if let Some(rpc_error) = rpc_response.error {
+            println!("Failed query: {}", query);
+            println!("{}", rpc_error.message);
+            return Err(QueryFailure::Other(
+                format!("Database failed to understand query. {}", rpc_error.message).into(),
+            ));
         }
 
-        let db_responses = response.json::<Vec<DbResponse>>().await?;
+        let Some(db_responses) = rpc_response.result else {
+            return Err(QueryFailure::Other(
+                "Database response is missing a result.".into(),
+            ));
+        };
 
         Ok(Responses(db_responses))
     }
+
+    fn lock_connection_state(&self) -> MutexGuard<'_, ConnectionState> {
+        // The state is always valid, so a poisoned lock can still be used.
+        self.connection_state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn record_success(&self) {
+        let mut state = self.lock_connection_state();
+        if state.down_until.is_some() {
+            println!("Database is reachable again.");
+        }
+        *state = ConnectionState::default();
+    }
+
+    fn record_failure(&self) {
+        let retry_settings = &self.sign_in_info.retry;
+        let mut state = self.lock_connection_state();
+        state.consecutive_failures = state.consecutive_failures.saturating_add(1);
+
+        if state.consecutive_failures >= retry_settings.failure_threshold {
+            if state.down_until.is_none() {
+                println!(
+                    "Database failed {} requests in a row. Marking it as down.",
+                    state.consecutive_failures
+                );
+            }
+            state.down_until =
+                Some(Instant::now() + Duration::from_secs(retry_settings.cooldown_secs));
+        }
+    }
 }
 
 /// Returns value that can be deserialized using Option<T>.
@@ -198,176 +854,552 @@ pub fn value_option_fixer(value: &Value) -> Result<&Value, Error> {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct StoredData {
-    pub content: String,
-}
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StoredData {
+    pub content: String,
+}
+
+/// Everything stored about a guild/user.
+#[derive(Serialize, Deserialize)]
+pub struct BotDataExport {
+    /// The schema version the data was exported from.
+    pub schema_version: u64,
+    /// The record id, such as `guild:123`.
+    pub id: String,
+    pub exported_at: u64,
+    /// The guild/user record. Contains things like message detectors, lua commands, data stores and the profile.
+    pub record: Map<String, Value>,
+    pub reminders: Vec<RemindInfo>,
+}
+
+#[derive(Deserialize)]
+struct StoredGuild {
+    id: String,
+    left_time: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct StoredLuaTimers {
+    id: String,
+    lua_timers: HashMap<String, LuaTimer>,
+}
+
+/// Gets the guild id from a record id such as `guild:123` or `guild:⟨123⟩`.
+fn parse_guild_record_id(record_id: &str) -> Option<GuildId> {
+    let id = record_id.split(':').last()?;
+    let guild_id = id
+        .trim_start_matches('⟨')
+        .trim_end_matches('⟩')
+        .parse::<u64>()
+        .ok()?;
+    Some(GuildId::new(guild_id))
+}
+
+#[derive(Deserialize)]
+struct RecordField {
+    id: String,
+    value: Value,
+}
+
+#[async_trait]
+impl Database for SurrealClient {
+    fn health(&self) -> DatabaseHealth {
+        let state = self.lock_connection_state();
+        let degraded_threshold = self.sign_in_info.retry.failure_threshold.div_ceil(2).max(1);
+
+        match state.down_until {
+            Some(down_until) if down_until > Instant::now() => DatabaseHealth::Down,
+            _ if state.retries_exhausted || state.consecutive_failures >= degraded_threshold => {
+                DatabaseHealth::Degraded
+            }
+            _ => DatabaseHealth::Up,
+        }
+    }
+
+    async fn get_data_store(
+        &self,
+        guild_id: GuildId,
+        data_store_name: &str,
+    ) -> Result<HashMap<String, serde_json::Value>, crate::Error> {
+        let lua_command_infos: Option<_> = self
+            .read_query(
+                "SELECT VALUE data_stores[$data_store_name] FROM type::thing(\"guild\", $guild_id);",
+                QueryVars::new()
+                    .bind("guild_id", &guild_id.get())?
+                    .bind("data_store_name", data_store_name)?,
+            )
+            .await?
+            .take(0)?;
+
+        Ok(lua_command_infos.unwrap_or_default())
+    }
+
+    async fn set_data_store_value(
+        &self,
+        guild_id: GuildId,
+        data_store_name: &str,
+        data_store_key: &str,
+        value: &serde_json::Value,
+    ) -> Result<(), crate::Error> {
+        self.change_data_store_value(guild_id, data_store_name, data_store_key, |_| {
+            Ok((Some(value.clone()), ()))
+        })
+        .await
+    }
+
+    async fn remove_data_store_value(
+        &self,
+        guild_id: GuildId,
+        data_store_name: &str,
+        data_store_key: &str,
+    ) -> Result<(), crate::Error> {
+        // Empty data stores get removed too, so they don't count towards the limit.
+        // The version is bumped so changes that checked the limits before this one try again.
+        if let Some(err) = self
+            .query(
+                "UPDATE type::thing(\"guild\", $guild_id) SET data_stores[$data_store_name][$data_store_key] = NONE, data_stores_version = (data_stores_version ?? 0) + 1 RETURN NONE;
+                UPDATE type::thing(\"guild\", $guild_id) SET data_stores[$data_store_name] = NONE WHERE data_stores[$data_store_name] == {} RETURN NONE;",
+                QueryVars::new()
+                    .bind("guild_id", &guild_id.get())?
+                    .bind("data_store_name", data_store_name)?
+                    .bind("data_store_key", data_store_key)?,
+            )
+            .await?
+            .take_err(0)
+        {
+            return Err(err);
+        }
+
+        Ok(())
+    }
+
+    async fn increment_data_store_value(
+        &self,
+        guild_id: GuildId,
+        data_store_name: &str,
+        data_store_key: &str,
+        amount: f64,
+    ) -> Result<f64, crate::Error> {
+        self.change_data_store_value(guild_id, data_store_name, data_store_key, |current| {
+            let new_value = increment_data_store_number(current, amount)?;
+            Ok((Some(new_value.into()), new_value))
+        })
+        .await
+    }
+
+    async fn compare_and_set_data_store_value(
+        &self,
+        guild_id: GuildId,
+        data_store_name: &str,
+        data_store_key: &str,
+        expected: &serde_json::Value,
+        value: &serde_json::Value,
+    ) -> Result<bool, crate::Error> {
+        self.change_data_store_value(guild_id, data_store_name, data_store_key, |current| {
+            if current != expected {
+                return Ok((None, false));
+            }
+
+            Ok((Some(value.clone()), true))
+        })
+        .await
+    }
+
+    async fn get_all_guild_lua_commands(
+        &self,
+        guild_id: GuildId,
+    ) -> Result<HashMap<String, LuaCommandInfo>, crate::Error> {
+        let lua_command_infos: Option<_> = self
+            .read_query(
+                "SELECT VALUE lua_commands FROM type::thing(\"guild\", $guild_id) WHERE lua_commands;",
+                QueryVars::new().bind("guild_id", &guild_id.get())?,
+            )
+            .await?
+            .take(0)?;
+
+        Ok(lua_command_infos.unwrap_or_default())
+    }
+
+    async fn add_guild_lua_command(
+        &self,
+        command_name: &str,
+        lua_command_info: &LuaCommandInfo,
+        guild_id: GuildId,
+    ) -> Result<(), crate::Error> {
+        if let Some(err) = self
+            .query(
+                "UPDATE type::thing(\"guild\", $guild_id) SET lua_commands[$command_name] = $lua_command_info;",
+                QueryVars::new()
+                    .bind("guild_id", &guild_id.get())?
+                    .bind("command_name", command_name)?
+                    .bind("lua_command_info", lua_command_info)?,
+            )
+            .await?
+            .take_err(0)
+        {
+            return Err(err);
+        }
+
+        Ok(())
+    }
+
+    async fn remove_guild_lua_command(
+        &self,
+        guild_id: GuildId,
+        command_name: &str,
+    ) -> Result<(), Error> {
+        if let Some(err) = self
+            .query(
+                "UPDATE type::thing(\"guild\", $guild_id) SET lua_commands[$command_name] = NONE, lua_command_history[$command_name] = NONE;",
+                QueryVars::new()
+                    .bind("guild_id", &guild_id.get())?
+                    .bind("command_name", command_name)?,
+            )
+            .await?
+            .take_err(0)
+        {
+            return Err(err);
+        }
+
+        Ok(())
+    }
+
+    async fn get_guild_lua_command_history(
+        &self,
+        guild_id: GuildId,
+        command_name: &str,
+    ) -> Result<Vec<LuaCommandVersion>, Error> {
+        let command_versions: Option<_> = self
+            .read_query(
+                "SELECT VALUE lua_command_history[$command_name] FROM type::thing(\"guild\", $guild_id) WHERE lua_command_history[$command_name];",
+                QueryVars::new()
+                    .bind("guild_id", &guild_id.get())?
+                    .bind("command_name", command_name)?,
+            )
+            .await?
+            .take(0)?;
+
+        Ok(command_versions.unwrap_or_default())
+    }
+
+    async fn add_guild_lua_command_version(
+        &self,
+        guild_id: GuildId,
+        command_name: &str,
+        command_version: &LuaCommandVersion,
+    ) -> Result<(), Error> {
+        if let Some(err) = self
+            .query(
+                "UPDATE type::thing(\"guild\", $guild_id) SET lua_command_history[$command_name] = array::slice(array::prepend(lua_command_history[$command_name] ?? [], $command_version), 0, $max_versions);",
+                QueryVars::new()
+                    .bind("guild_id", &guild_id.get())?
+                    .bind("command_name", command_name)?
+                    .bind("command_version", command_version)?
+                    .bind("max_versions", &MAX_COMMAND_VERSIONS)?,
+            )
+            .await?
+            .take_err(0)
+        {
+            return Err(err);
+        }
+
+        Ok(())
+    }
+
+    async fn get_all_guild_lua_modules(
+        &self,
+        guild_id: GuildId,
+    ) -> Result<HashMap<String, LuaModuleInfo>, Error> {
+        let lua_module_infos: Option<_> = self
+            .read_query(
+                "SELECT VALUE lua_modules FROM type::thing(\"guild\", $guild_id) WHERE lua_modules;",
+                QueryVars::new().bind("guild_id", &guild_id.get())?,
+            )
+            .await?
+            .take(0)?;
+
+        Ok(lua_module_infos.unwrap_or_default())
+    }
+
+    async fn set_guild_lua_module(
+        &self,
+        guild_id: GuildId,
+        module_name: &str,
+        lua_module_info: &LuaModuleInfo,
+    ) -> Result<(), Error> {
+        if let Some(err) = self
+            .query(
+                "UPDATE type::thing(\"guild\", $guild_id) SET lua_modules[$module_name] = $lua_module_info;",
+                QueryVars::new()
+                    .bind("guild_id", &guild_id.get())?
+                    .bind("module_name", module_name)?
+                    .bind("lua_module_info", lua_module_info)?,
+            )
+            .await?
+            .take_err(0)
+        {
+            return Err(err);
+        }
+
+        Ok(())
+    }
 
-impl SurrealClient {
-    pub async fn get_data_store(
+    async fn remove_guild_lua_module(
         &self,
         guild_id: GuildId,
-        data_store_name: &str,
-    ) -> Result<HashMap<String, serde_json::Value>, crate::Error> {
-        let data_store_name = serde_json::to_string(data_store_name)?;
+        module_name: &str,
+    ) -> Result<(), Error> {
+        if let Some(err) = self
+            .query(
+                "UPDATE type::thing(\"guild\", $guild_id) SET lua_modules[$module_name] = NONE;",
+                QueryVars::new()
+                    .bind("guild_id", &guild_id.get())?
+                    .bind("module_name", module_name)?,
+            )
+            .await?
+            .take_err(0)
+        {
+            return Err(err);
+        }
 
-        let lua_command_infos: Option<_> = self
-            .query(format!(
-                "SELECT VALUE data_stores[{data_store_name}] FROM guild:{guild_id};"
-            ))
+        Ok(())
+    }
+
+    async fn get_all_guild_lua_events(
+        &self,
+        guild_id: GuildId,
+    ) -> Result<HashMap<LuaEvent, LuaEventInfo>, Error> {
+        let lua_event_infos: Option<_> = self
+            .read_query(
+                "SELECT VALUE lua_events FROM type::thing(\"guild\", $guild_id) WHERE lua_events;",
+                QueryVars::new().bind("guild_id", &guild_id.get())?,
+            )
             .await?
             .take(0)?;
 
-        Ok(lua_command_infos.unwrap_or_default())
+        Ok(lua_event_infos.unwrap_or_default())
     }
 
-    pub async fn set_data_store_value(
+    async fn set_guild_lua_event(
         &self,
         guild_id: GuildId,
-        data_store_name: &str,
-        data_store_key: &str,
-        value: &serde_json::Value,
-    ) -> Result<(), crate::Error> {
-        let data_store_name = serde_json::to_string(data_store_name)?;
-        let data_store_key = serde_json::to_string(data_store_key)?;
-        let value_json = serde_json::to_string(value)?;
+        event: LuaEvent,
+        lua_event_info: &LuaEventInfo,
+    ) -> Result<(), Error> {
         if let Some(err) = self
-            .query(format!(
-                "UPDATE guild:{guild_id} SET data_stores[{data_store_name}][{data_store_key}] = {value_json};"
-            ))
+            .query(
+                "UPDATE type::thing(\"guild\", $guild_id) SET lua_events[$event] = $lua_event_info;",
+                QueryVars::new()
+                    .bind("guild_id", &guild_id.get())?
+                    .bind("event", event.to_str())?
+                    .bind("lua_event_info", lua_event_info)?,
+            )
             .await?
-            .take_err(0) {
-                return Err(err)
-            }
+            .take_err(0)
+        {
+            return Err(err);
+        }
 
         Ok(())
     }
 
-    pub async fn get_all_guild_lua_commands(
+    async fn remove_guild_lua_event(
         &self,
         guild_id: GuildId,
-    ) -> Result<HashMap<String, LuaCommandInfo>, crate::Error> {
-        let lua_command_infos: Option<_> = self
-            .query(format!(
-                "SELECT VALUE lua_commands FROM guild:{guild_id} WHERE lua_commands;"
-            ))
+        event: LuaEvent,
+    ) -> Result<(), Error> {
+        if let Some(err) = self
+            .query(
+                "UPDATE type::thing(\"guild\", $guild_id) SET lua_events[$event] = NONE;",
+                QueryVars::new()
+                    .bind("guild_id", &guild_id.get())?
+                    .bind("event", event.to_str())?,
+            )
+            .await?
+            .take_err(0)
+        {
+            return Err(err);
+        }
+
+        Ok(())
+    }
+
+    async fn get_all_lua_timers(&self) -> Result<Vec<(GuildId, HashMap<String, LuaTimer>)>, Error> {
+        let stored_timers: Vec<StoredLuaTimers> = self
+            .read_query(
+                "SELECT id, lua_timers FROM guild WHERE lua_timers;",
+                QueryVars::new(),
+            )
             .await?
             .take(0)?;
 
-        Ok(lua_command_infos.unwrap_or_default())
+        Ok(stored_timers
+            .into_iter()
+            .filter_map(|stored_timers| {
+                let guild_id = parse_guild_record_id(&stored_timers.id)?;
+                Some((guild_id, stored_timers.lua_timers))
+            })
+            .collect())
     }
 
-    pub async fn add_guild_lua_command(
+    async fn get_guild_lua_timers(
         &self,
-        command_name: &str,
-        lua_command_info: &LuaCommandInfo,
         guild_id: GuildId,
-    ) -> Result<(), crate::Error> {
-        let lua_command_info_string = serde_json::to_string(&lua_command_info)?;
-
-        //TODO: Perhaps check for error.
-        let _responses = self
-            .query(format!(
-                "UPDATE guild:{guild_id} SET lua_commands.{command_name} = {lua_command_info_string};"
-            ))
-            .await?;
+    ) -> Result<HashMap<String, LuaTimer>, Error> {
+        let lua_timers: Option<_> = self
+            .read_query(
+                "SELECT VALUE lua_timers FROM type::thing(\"guild\", $guild_id) WHERE lua_timers;",
+                QueryVars::new().bind("guild_id", &guild_id.get())?,
+            )
+            .await?
+            .take(0)?;
 
-        Ok(())
+        Ok(lua_timers.unwrap_or_default())
     }
 
-    pub async fn remove_guild_lua_command(
+    async fn set_lua_timer(
         &self,
         guild_id: GuildId,
-        command_name: &str,
+        timer_name: &str,
+        lua_timer: &LuaTimer,
     ) -> Result<(), Error> {
-        let _responses = self
-            .query(format!(
-                "UPDATE guild:{guild_id} SET lua_commands.{command_name} = NONE;"
-            ))
-            .await?;
+        // The count and the change are in one transaction, so timers set at the same time can't go over the limit.
+        if let Some(err) = self
+            .query(
+                "
+        BEGIN TRANSACTION;
+        LET $timers = (SELECT VALUE lua_timers FROM type::thing(\"guild\", $guild_id))[0] ?? {};
+
+        IF $timers[$timer_name] == NONE AND array::len(object::keys($timers)) >= $max_timers {
+            THROW \"You may only have up to \" + <string> $max_timers + \" timers.\";
+        } ELSE {
+            UPDATE type::thing(\"guild\", $guild_id) SET lua_timers[$timer_name] = $lua_timer RETURN NONE;
+        };
+        COMMIT TRANSACTION;
+        ",
+                QueryVars::new()
+                    .bind("guild_id", &guild_id.get())?
+                    .bind("timer_name", timer_name)?
+                    .bind("lua_timer", lua_timer)?
+                    .bind("max_timers", &MAX_TIMERS_PER_GUILD)?,
+            )
+            .await?
+            .take_err(1)
+        {
+            return Err(err);
+        }
+
+        Ok(())
+    }
+
+    async fn remove_lua_timer(&self, guild_id: GuildId, timer_name: &str) -> Result<(), Error> {
+        if let Some(err) = self
+            .query(
+                "UPDATE type::thing(\"guild\", $guild_id) SET lua_timers[$timer_name] = NONE;",
+                QueryVars::new()
+                    .bind("guild_id", &guild_id.get())?
+                    .bind("timer_name", timer_name)?,
+            )
+            .await?
+            .take_err(0)
+        {
+            return Err(err);
+        }
 
         Ok(())
     }
 
-    pub async fn get_guild_cotd_role(
+    async fn get_guild_cotd_role(
         &self,
         guild_id: GuildId,
     ) -> Result<Option<CotdRoleDataQuery>, crate::Error> {
         //id is needed in the query because another function uses it and I don't wanna make another struct.
         let cotd_role_data: Option<CotdRoleDataQuery> = self
-            .query(format!(
-                "SELECT id, cotd_role FROM guild:{guild_id} WHERE cotd_role;"
-            ))
+            .read_query(
+                "SELECT id, cotd_role FROM type::thing(\"guild\", $guild_id) WHERE cotd_role;",
+                QueryVars::new().bind("guild_id", &guild_id.get())?,
+            )
             .await?
             .take(0)?;
 
         Ok(cotd_role_data)
     }
 
-    /// Gets the id and cotd_role from every guild where cotd_role exists
-    pub async fn get_all_guild_cotd_role(&self) -> Result<Vec<CotdRoleDataQuery>, crate::Error> {
+    async fn get_all_guild_cotd_role(&self) -> Result<Vec<CotdRoleDataQuery>, crate::Error> {
         let cotd_roles_data: Vec<CotdRoleDataQuery> = self
-            .query(format!("SELECT id, cotd_role FROM guild WHERE cotd_role;"))
+            .read_query(
+                "SELECT id, cotd_role FROM guild WHERE cotd_role;",
+                QueryVars::new(),
+            )
             .await?
             .take(0)?;
 
         Ok(cotd_roles_data)
     }
 
-    pub async fn update_guild_cotd_role(
+    async fn update_guild_cotd_role(
         &self,
         cotd_role_data: &Option<CotdRoleData>,
         guild_id: GuildId,
     ) -> Result<(), crate::Error> {
-        let cotd_role_data_string = serde_json::to_string(&cotd_role_data)?;
-
-        //TODO: Perhaps check for error.
-        let _cotd_role_data = self
-            .query(format!(
-                "UPDATE guild:{guild_id} SET cotd_role = {cotd_role_data_string};"
-            ))
-            .await?;
+        if let Some(err) = self
+            .query(
+                "UPDATE type::thing(\"guild\", $guild_id) SET cotd_role = $cotd_role_data;",
+                QueryVars::new()
+                    .bind("guild_id", &guild_id.get())?
+                    .bind("cotd_role_data", cotd_role_data)?,
+            )
+            .await?
+            .take_err(0)
+        {
+            return Err(err);
+        }
 
         Ok(())
     }
 
-    pub async fn mark_cotd_role_updated(
+    async fn mark_cotd_role_updated(
         &self,
         guild_id: GuildId,
         current_day: u64,
     ) -> Result<(), Error> {
-        self.query(format!("UPDATE guild:{guild_id} MERGE {{ cotd_role: {{ day:{current_day} }} }} WHERE cotd_role;")).await?;
+        self.query(
+            "UPDATE type::thing(\"guild\", $guild_id) MERGE { cotd_role: { day: $current_day } } WHERE cotd_role;",
+            QueryVars::new()
+                .bind("guild_id", &guild_id.get())?
+                .bind("current_day", &current_day)?,
+        )
+        .await?;
         Ok(())
     }
 
-    pub async fn get_cotd(&self, day: u64) -> Result<Option<ColorInfo>, Error> {
+    async fn get_cotd(&self, day: u64) -> Result<Option<ColorInfo>, Error> {
         let day_color: Option<ColorInfo> = self
-            .query(format!("SELECT * FROM cotd:{day};"))
+            .read_query(
+                "SELECT * FROM type::thing(\"cotd\", $day);",
+                QueryVars::new().bind("day", &day)?,
+            )
             .await?
             .take(0)?;
 
         Ok(day_color)
     }
 
-    pub async fn get_or_update_cotd(
-        &self,
-        day: u64,
-        color: &ColorInfo,
-    ) -> Result<ColorInfo, Error> {
-        let color_json = serde_json::to_string(color)?;
+    async fn get_or_update_cotd(&self, day: u64, color: &ColorInfo) -> Result<ColorInfo, Error> {
         //1 returns None if it updated or Some if there already was a color.
         let opt: Option<ColorInfo> = self
-            .query(format!(
+            .query(
                 "
-            LET $day_color = (SELECT * FROM cotd:{day});
+            LET $day_color = (SELECT * FROM type::thing(\"cotd\", $day));
 
-            IF array::len($day_color) == 0 {{
-                CREATE cotd:{day} CONTENT {color_json};
-            }} ELSE {{
+            IF array::len($day_color) == 0 {
+                CREATE type::thing(\"cotd\", $day) CONTENT $color;
+            } ELSE {
                 RETURN $day_color[0]
-            }}
-            "
-            ))
+            }
+            ",
+                QueryVars::new().bind("day", &day)?.bind("color", color)?,
+            )
             .await?
             .take(1)?;
 
@@ -378,51 +1410,50 @@ impl SurrealClient {
         }
     }
 
-    pub async fn get_all_message_detectors(&self, id: IdType) -> Result<Vec<DetectorInfo>, Error> {
-        let table_id = id.into_db_table();
-
+    async fn get_all_message_detectors(&self, id: IdType) -> Result<Vec<DetectorInfo>, Error> {
         let res: Option<Vec<DetectorInfo>> = self
-            .query(format!(
-                "SELECT VALUE message_detectors FROM {table_id} WHERE message_detectors"
-            ))
+            .read_query(
+                "SELECT VALUE message_detectors FROM type::thing($table, $id) WHERE message_detectors",
+                QueryVars::new().bind_table_id(&id.into_db_table())?,
+            )
             .await?
             .take(0)?;
 
         Ok(res.unwrap_or_default())
     }
 
-    pub async fn add_message_detector(
+    async fn add_message_detector(
         &self,
         id: IdType,
         detect_info: &DetectorInfo,
     ) -> Result<(), Error> {
-        let table_id = id.into_db_table();
-
-        let detect_info_json = serde_json::to_string(&detect_info)?;
-
-        self.query(format!(
-            "UPDATE {table_id} SET message_detectors += {detect_info_json}"
-        ))
+        self.query(
+            "UPDATE type::thing($table, $id) SET message_detectors += $detect_info",
+            QueryVars::new()
+                .bind_table_id(&id.into_db_table())?
+                .bind("detect_info", detect_info)?,
+        )
         .await?;
 
         Ok(())
     }
 
-    pub async fn remove_message_detector(&self, id: IdType, index: usize) -> Result<(), Error> {
-        let table_id = id.into_db_table();
-
+    async fn remove_message_detector(&self, id: IdType, index: usize) -> Result<(), Error> {
         let err = self
-            .query(format!(
-            "LET $detectors = (SELECT VALUE message_detectors FROM {table_id} WHERE message_detectors);
+            .query(
+                "LET $detectors = (SELECT VALUE message_detectors FROM type::thing($table, $id) WHERE message_detectors);
 
-            IF array::len($detectors) == 0 {{
+            IF array::len($detectors) == 0 {
                 THROW \"Index isn't valid.\";
-            }} ELSE IF array::len($detectors[0]) <= {index} {{
+            } ELSE IF array::len($detectors[0]) <= $index {
                 THROW \"Index isn't valid.\";
-            }} ELSE {{
-                RETURN (UPDATE {table_id} SET message_detectors = array::remove(message_detectors, {index}));
-            }};"
-        ))
+            } ELSE {
+                RETURN (UPDATE type::thing($table, $id) SET message_detectors = array::remove(message_detectors, $index));
+            };",
+                QueryVars::new()
+                    .bind_table_id(&id.into_db_table())?
+                    .bind("index", &index)?,
+            )
             .await?
             .take_err(1);
 
@@ -433,28 +1464,29 @@ impl SurrealClient {
         Ok(())
     }
 
-    pub async fn get_guild_messages(
+    async fn get_guild_messages(
         &self,
         guild_id: GuildId,
     ) -> Result<HashMap<MessageId, StoredMessageData>, Error> {
         let res = self
-            .query(format!("SELECT VALUE messages from guild:{guild_id};"))
+            .read_query(
+                "SELECT VALUE messages from type::thing(\"guild\", $guild_id);",
+                QueryVars::new().bind("guild_id", &guild_id.get())?,
+            )
             .await?
             .take::<Option<_>>(0)?;
 
         Ok(res.unwrap_or_default())
     }
 
-    pub async fn clear_message_data(
-        &self,
-        id: IdType,
-        message_id: &MessageId,
-    ) -> Result<(), Error> {
-        let table_id = id.into_db_table();
+    async fn clear_message_data(&self, id: IdType, message_id: &MessageId) -> Result<(), Error> {
         let res = self
-            .query(format!(
-                "UPDATE {table_id} SET messages.{message_id} = NONE;"
-            ))
+            .query(
+                "UPDATE type::thing($table, $id) SET messages[$message_id] = NONE;",
+                QueryVars::new()
+                    .bind_table_id(&id.into_db_table())?
+                    .bind("message_id", &message_id.to_string())?,
+            )
             .await?;
 
         if let Some(err) = res.take_err(0) {
@@ -464,51 +1496,58 @@ impl SurrealClient {
         Ok(())
     }
 
-    pub async fn set_guild_message(
+    async fn set_guild_message(
         &self,
         guild_id: GuildId,
         message_id: MessageId,
         message_data: &StoredMessageData,
     ) -> Result<(), Error> {
-        let message_data_json = serde_json::to_string(&message_data).unwrap();
-        self.query(format!(
-            "UPDATE guild:{guild_id} SET messages.{message_id} = {message_data_json}"
-        ))
+        self.query(
+            "UPDATE type::thing(\"guild\", $guild_id) SET messages[$message_id] = $message_data",
+            QueryVars::new()
+                .bind("guild_id", &guild_id.get())?
+                .bind("message_id", &message_id.to_string())?
+                .bind("message_data", message_data)?,
+        )
         .await?;
         Ok(())
     }
 
-    pub async fn remove_guild_message(
+    async fn remove_guild_message(
         &self,
         guild_id: GuildId,
         message_id: MessageId,
     ) -> Result<(), Error> {
-        self.query(format!(
-            "UPDATE guild:{guild_id} SET messages.{message_id} = NONE"
-        ))
+        self.query(
+            "UPDATE type::thing(\"guild\", $guild_id) SET messages[$message_id] = NONE",
+            QueryVars::new()
+                .bind("guild_id", &guild_id.get())?
+                .bind("message_id", &message_id.to_string())?,
+        )
         .await?;
         Ok(())
     }
 
-    pub async fn get_user_profile(&self, user_id: UserId) -> Result<ProfileData, Error> {
+    async fn get_user_profile(&self, user_id: UserId) -> Result<ProfileData, Error> {
         let profile: Option<ProfileData> = self
-            .query(format!("SELECT VALUE profile FROM user:{user_id};"))
+            .read_query(
+                "SELECT VALUE profile FROM type::thing(\"user\", $user_id);",
+                QueryVars::new().bind("user_id", &user_id.get())?,
+            )
             .await?
             .take(0)?;
 
         Ok(profile.unwrap_or_default())
     }
 
-    pub async fn set_user_profile(
-        &self,
-        user_id: UserId,
-        profile: &ProfileData,
-    ) -> Result<(), Error> {
-        let profile_string = serde_json::to_string(&profile).unwrap();
+    async fn set_user_profile(&self, user_id: UserId, profile: &ProfileData) -> Result<(), Error> {
         let err = self
-            .query(format!(
-                "UPDATE user:{user_id} SET profile = {profile_string};"
-            ))
+            .query(
+                "UPDATE type::thing(\"user\", $user_id) SET profile = $profile;",
+                QueryVars::new()
+                    .bind("user_id", &user_id.get())?
+                    .bind("profile", profile)?,
+            )
             .await?
             .take_err(0);
         if let Some(err) = err {
@@ -517,9 +1556,12 @@ impl SurrealClient {
         Ok(())
     }
 
-    pub async fn delete_user_profile(&self, user_id: UserId) -> Result<(), Error> {
+    async fn delete_user_profile(&self, user_id: UserId) -> Result<(), Error> {
         let err = self
-            .query(format!("UPDATE user:{user_id} SET profile = NONE;"))
+            .query(
+                "UPDATE type::thing(\"user\", $user_id) SET profile = NONE;",
+                QueryVars::new().bind("user_id", &user_id.get())?,
+            )
             .await?
             .take_err(0);
         if let Some(err) = err {
@@ -528,50 +1570,46 @@ impl SurrealClient {
         Ok(())
     }
 
-    pub async fn list_user_reminders(&self, user_id: UserId) -> Result<Vec<RemindInfo>, Error> {
+    async fn list_user_reminders(&self, user_id: UserId) -> Result<Vec<RemindInfo>, Error> {
         let user_reminders: Vec<RemindInfo> = self
-            .query(format!(
+            .read_query(
                 "
-            LET $reminders = SELECT VALUE ->reminds->reminder FROM user:{user_id};
+            LET $reminders = SELECT VALUE ->reminds->reminder FROM type::thing(\"user\", $user_id);
 
             IF array::len($reminders) THEN
                 SELECT * FROM array::first($reminders) ORDER BY original_time;
             ELSE
                 RETURN [];
             END
-        "
-            ))
+        ",
+                QueryVars::new().bind("user_id", &user_id.get())?,
+            )
             .await?
             .take(1)?;
 
         Ok(user_reminders)
     }
 
-    /// Adds a user reminder to the database. Also assigns the id field onto the RemindInfo
-    pub async fn add_user_reminder(&self, remind_info: &mut RemindInfo) -> Result<(), Error> {
-        let remind_info_json = serde_json::to_string(&remind_info)?;
-
-        let user_id = remind_info.user_id;
-        let guild_relate_statement = if let Some(guild_id) = remind_info.guild_id {
-            format!(
-                "
-            UPDATE guild:{guild_id};
-            RELATE guild:{guild_id}->reminds->$reminder;
+    async fn add_user_reminder(&self, remind_info: &mut RemindInfo) -> Result<(), Error> {
+        let guild_relate_statement = if remind_info.guild_id.is_some() {
+            "
+            UPDATE type::thing(\"guild\", $guild_id);
+            RELATE (type::thing(\"guild\", $guild_id))->reminds->$reminder;
             "
-            )
         } else {
-            "RETURN;RETURN;".to_owned()
+            "RETURN;RETURN;"
         };
 
         let remind_info_id = self
-            .query(format!(
-                "
+            .query(
+                format!(
+                    "
         BEGIN TRANSACTION;
 
-        LET $reminder = (CREATE reminder CONTENT {remind_info_json});
+        LET $reminder = (CREATE reminder CONTENT $remind_info);
 
-        UPDATE user:{user_id};
-        RELATE user:{user_id}->reminds->$reminder;
+        UPDATE type::thing(\"user\", $user_id);
+        RELATE (type::thing(\"user\", $user_id))->reminds->$reminder;
 
         {guild_relate_statement}
 
@@ -579,7 +1617,15 @@ impl SurrealClient {
 
         COMMIT TRANSACTION;
         "
-            ))
+                ),
+                QueryVars::new()
+                    .bind("remind_info", &*remind_info)?
+                    .bind("user_id", &remind_info.user_id.get())?
+                    .bind(
+                        "guild_id",
+                        &remind_info.guild_id.map(|guild_id| guild_id.get()),
+                    )?,
+            )
             .await?
             .take(0)?;
 
@@ -588,33 +1634,417 @@ impl SurrealClient {
         Ok(())
     }
 
-    pub async fn delete_table_id(&self, table_id: &String) -> Result<(), Error> {
-        self.query(format!("DELETE {table_id}"))
+    async fn update_reminder(&self, remind_info: &RemindInfo) -> Result<(), Error> {
+        let Some(reminder_id) = &remind_info.id else {
+            return Err("Reminder is missing a database id.".into());
+        };
+
+        if let Some(err) = self
+            .query(
+                "UPDATE type::thing($table, $id) CONTENT $remind_info",
+                QueryVars::new()
+                    .bind_table_id(reminder_id)?
+                    .bind("remind_info", remind_info)?,
+            )
             .await?
-            .take::<Vec<Value>>(0)?;
+            .take_err(0)
+        {
+            return Err(err);
+        }
+
         Ok(())
     }
 
-    /// Returns a list of reminders that have finished and needs to be sent.
-    pub async fn get_pending_reminders(&self, current_time: u64) -> Result<Vec<RemindInfo>, Error> {
+    async fn delete_table_id(&self, table_id: &String) -> Result<(), Error> {
+        self.query(
+            "DELETE type::thing($table, $id)",
+            QueryVars::new().bind_table_id(table_id)?,
+        )
+        .await?
+        .take::<Vec<Value>>(0)?;
+        Ok(())
+    }
+
+    async fn get_pending_reminders(&self, current_time: u64) -> Result<Vec<RemindInfo>, Error> {
+        let reminders = self
+            .read_query(
+                "SELECT * FROM reminder WHERE finish_time <= $current_time;",
+                QueryVars::new().bind("current_time", &current_time)?,
+            )
+            .await?
+            .take(0)?;
+
+        Ok(reminders)
+    }
+
+    async fn get_next_reminder_time(&self) -> Result<Option<u64>, Error> {
         let reminders = self
-            .query(format!(
-                "SELECT * FROM reminder WHERE finish_time <= {current_time};"
-            ))
+            .read_query(
+                "SELECT VALUE finish_time FROM reminder ORDER BY finish_time LIMIT 1;",
+                QueryVars::new(),
+            )
             .await?
             .take(0)?;
 
         Ok(reminders)
     }
 
-    pub async fn get_next_reminder_time(&self) -> Result<Option<u64>, Error> {
+    async fn get_all_reminders(&self) -> Result<Vec<RemindInfo>, Error> {
         let reminders = self
-            .query(format!(
-                "SELECT VALUE finish_time FROM reminder ORDER BY finish_time LIMIT 1;"
-            ))
+            .read_query("SELECT * FROM reminder;", QueryVars::new())
             .await?
             .take(0)?;
 
         Ok(reminders)
     }
+
+    async fn get_stored_guilds(&self) -> Result<Vec<(GuildId, Option<u64>)>, Error> {
+        let stored_guilds: Vec<StoredGuild> = self
+            .read_query("SELECT id, left_time FROM guild;", QueryVars::new())
+            .await?
+            .take(0)?;
+
+        Ok(stored_guilds
+            .into_iter()
+            .filter_map(|stored_guild| {
+                let guild_id = parse_guild_record_id(&stored_guild.id)?;
+                Some((guild_id, stored_guild.left_time))
+            })
+            .collect())
+    }
+
+    async fn set_guild_left_time(
+        &self,
+        guild_id: GuildId,
+        left_time: Option<u64>,
+    ) -> Result<(), Error> {
+        let query = match left_time {
+            Some(_) => "UPDATE type::thing(\"guild\", $guild_id) SET left_time = $left_time;",
+            // WHERE makes sure it doesn't create a record for guilds without any data.
+            None => {
+                "UPDATE type::thing(\"guild\", $guild_id) SET left_time = NONE WHERE left_time;"
+            }
+        };
+
+        if let Some(err) = self
+            .query(
+                query,
+                QueryVars::new()
+                    .bind("guild_id", &guild_id.get())?
+                    .bind("left_time", &left_time)?,
+            )
+            .await?
+            .take_err(0)
+        {
+            return Err(err);
+        }
+
+        Ok(())
+    }
+
+    async fn clear_bot_data(&self, id: IdType) -> Result<Vec<UserId>, Error> {
+        let response: Option<Vec<UserId>> = self
+            .query(
+                "
+        BEGIN TRANSACTION;
+        LET $record = type::thing($table, $id);
+        LET $uids = array::distinct(
+            SELECT VALUE user_id FROM (
+                SELECT VALUE ->reminds->reminder FROM $record
+            )
+        );
+
+        FOR $reminder IN (SELECT VALUE ->reminds->reminder FROM $record) {
+            DELETE $reminder;
+        };
+        DELETE $record;
+
+        RETURN $uids;
+        COMMIT TRANSACTION;
+    ",
+                QueryVars::new().bind_table_id(&id.into_db_table())?,
+            )
+            .await?
+            .take(0)?;
+
+        Ok(response.unwrap_or_default())
+    }
+
+    async fn export_bot_data(&self, id: IdType) -> Result<BotDataExport, Error> {
+        let responses = self
+            .read_query(
+                "
+            SELECT * FROM type::thing($table, $id);
+            LET $reminders = SELECT VALUE ->reminds->reminder FROM type::thing($table, $id);
+
+            IF array::len($reminders) THEN
+                SELECT * FROM array::first($reminders) ORDER BY original_time;
+            ELSE
+                RETURN [];
+            END
+        ",
+                QueryVars::new().bind_table_id(&id.into_db_table())?,
+            )
+            .await?;
+
+        let mut record: Map<String, Value> = responses
+            .take::<Option<Map<String, Value>>>(0)?
+            .unwrap_or_default();
+        // The id is already stored outside of the record.
+        record.remove("id");
+
+        Ok(BotDataExport {
+            schema_version: self.get_schema_version().await?,
+            id: id.into_db_table(),
+            exported_at: get_seconds(),
+            record,
+            reminders: responses.take(2)?,
+        })
+    }
+
+    async fn get_schema_version(&self) -> Result<u64, Error> {
+        let version: Option<u64> = self
+            .read_query("SELECT VALUE version FROM meta:schema;", QueryVars::new())
+            .await?
+            .take(0)?;
+
+        Ok(version.unwrap_or(0))
+    }
+
+    async fn set_schema_version(&self, version: u64) -> Result<(), Error> {
+        if let Some(err) = self
+            .query(
+                "UPDATE meta:schema SET version = $version;",
+                QueryVars::new().bind("version", &version)?,
+            )
+            .await?
+            .take_err(0)
+        {
+            return Err(err);
+        }
+
+        Ok(())
+    }
+
+    async fn get_records_with_field(
+        &self,
+        table: &str,
+        field: &'static str,
+    ) -> Result<Vec<(String, Value)>, Error> {
+        let records: Vec<RecordField> = self
+            .read_query(
+                format!(
+                    "SELECT id, {field} AS value FROM type::table($table) WHERE {field} != NONE;"
+                ),
+                QueryVars::new().bind("table", table)?,
+            )
+            .await?
+            .take(0)?;
+
+        Ok(records
+            .into_iter()
+            .map(|record| (record.id, record.value))
+            .collect())
+    }
+
+    async fn set_record_field(
+        &self,
+        table_id: &str,
+        field: &'static str,
+        value: &Value,
+    ) -> Result<(), Error> {
+        if let Some(err) = self
+            .query(
+                format!("UPDATE type::thing($table, $id) SET {field} = $value;"),
+                QueryVars::new()
+                    .bind_table_id(table_id)?
+                    .bind("value", value)?,
+            )
+            .await?
+            .take_err(0)
+        {
+            return Err(err);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::TcpListener,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+
+    use crate::{test_utils::start_server, tokens::SurrealRetrySettings};
+
+    use super::*;
+
+    /// Every query gets a single result with the path of the request and the `$value` variable it was sent with.
+    fn start_echo_server() -> String {
+        start_server(|request| {
+            let rpc_request: Value = serde_json::from_slice(&request.body).unwrap();
+            serde_json::json!({
+                "id": rpc_request["id"],
+                "result": [{
+                    "result": [{ "path": request.path, "value": rpc_request["params"][1]["value"] }],
+                    "status": "OK",
+                    "time": "1ms",
+                }],
+            })
+            .to_string()
+            .into_bytes()
+        })
+    }
+
+    #[derive(Deserialize)]
+    struct EchoedQuery {
+        path: String,
+        value: String,
+    }
+
+    fn create_client(address: String) -> SurrealClient {
+        SurrealClient::new(SurrealDbSignInInfo {
+            address,
+            namespace: "test".to_string(),
+            database: "test".to_string(),
+            username: "root".to_string(),
+            password: "root".to_string(),
+            retry: SurrealRetrySettings::default(),
+        })
+    }
+
+    #[tokio::test]
+    async fn big_variables_are_sent_in_the_body() {
+        let client = create_client(start_echo_server());
+
+        // Bigger than the 64 KB that fits in the url of a request.
+        let value = "a".repeat(100_000);
+
+        let responses = client
+            .query(
+                "RETURN $value",
+                QueryVars::new().bind("value", &value).unwrap(),
+            )
+            .await
+            .unwrap();
+        let echoed_query: Option<EchoedQuery> = responses.take(0).unwrap();
+        let echoed_query = echoed_query.unwrap();
+
+        assert_eq!(echoed_query.path, "/rpc");
+        assert_eq!(echoed_query.value, value);
+    }
+
+    #[tokio::test]
+    async fn single_failures_dont_count_as_degraded() {
+        // Nothing listens on the port once the listener is dropped, so every request fails.
+        let address = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let client = create_client(format!("http://{address}"));
+        let failure_threshold = client.sign_in_info.retry.failure_threshold;
+
+        let mut healths = Vec::new();
+        for _ in 0..failure_threshold {
+            assert!(client.query("RETURN 1", QueryVars::new()).await.is_err());
+            healths.push(client.health());
+        }
+
+        assert_eq!(healths[0], DatabaseHealth::Up);
+        assert_eq!(
+            healths[failure_threshold as usize / 2],
+            DatabaseHealth::Degraded
+        );
+        assert_eq!(healths.last(), Some(&DatabaseHealth::Down));
+    }
+
+    /// Answers reads of the data stores with `data_stores`, and the first `conflicts` writes as if
+    /// something else changed the data stores. Returns the address and how many writes were sent.
+    fn start_data_store_server(data_stores: Value, conflicts: usize) -> (String, Arc<AtomicUsize>) {
+        let writes = Arc::new(AtomicUsize::new(0));
+        let server_writes = writes.clone();
+
+        let address = start_server(move |request| {
+            let rpc_request: Value = serde_json::from_slice(&request.body).unwrap();
+            let query = rpc_request["params"][0].as_str().unwrap();
+
+            let result = if query.starts_with("SELECT data_stores") {
+                serde_json::json!([{
+                    "result": [{ "data_stores": data_stores, "data_stores_version": 3 }],
+                    "status": "OK",
+                    "time": "1ms",
+                }])
+            } else if server_writes.fetch_add(1, Ordering::SeqCst) < conflicts {
+                serde_json::json!([
+                    { "result": null, "status": "OK", "time": "1ms" },
+                    {
+                        "result": format!("An error occurred: {DATA_STORES_CHANGED}"),
+                        "status": "ERR",
+                        "time": "1ms",
+                    },
+                ])
+            } else {
+                serde_json::json!([
+                    { "result": null, "status": "OK", "time": "1ms" },
+                    { "result": null, "status": "OK", "time": "1ms" },
+                ])
+            };
+
+            serde_json::json!({ "id": rpc_request["id"], "result": result })
+                .to_string()
+                .into_bytes()
+        });
+
+        (address, writes)
+    }
+
+    #[tokio::test]
+    async fn data_store_sizes_are_json_bytes() {
+        // Each "é" is one character but two bytes, so this only goes over the limit when counting bytes.
+        let half = "é".repeat(MAX_DATA_STORE_BYTES_PER_GUILD / 4 + 1);
+        let (address, writes) =
+            start_data_store_server(serde_json::json!({ "store": { "a": half } }), 0);
+        let client = create_client(address);
+
+        let result = client
+            .set_data_store_value(GuildId::new(1), "store", "b", &Value::String(half))
+            .await;
+
+        assert!(result.is_err_and(|err| err.to_string().contains("bytes")));
+        assert_eq!(writes.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn data_store_changes_retry_when_the_data_stores_changed() {
+        let (address, writes) =
+            start_data_store_server(serde_json::json!({ "store": { "a": 2 } }), 2);
+        let client = create_client(address);
+
+        let new_value = client
+            .increment_data_store_value(GuildId::new(1), "store", "a", 3.0)
+            .await
+            .unwrap();
+
+        assert_eq!(new_value, 5.0);
+        assert_eq!(writes.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn data_store_changes_give_up_when_the_data_stores_keep_changing() {
+        let (address, writes) =
+            start_data_store_server(serde_json::json!({}), MAX_DATA_STORE_WRITE_ATTEMPTS);
+        let client = create_client(address);
+
+        let result = client
+            .set_data_store_value(GuildId::new(1), "store", "a", &Value::Bool(true))
+            .await;
+
+        assert!(result.is_err_and(|err| err.to_string().contains("kept changing")));
+        assert_eq!(writes.load(Ordering::SeqCst), MAX_DATA_STORE_WRITE_ATTEMPTS);
+    }
 }
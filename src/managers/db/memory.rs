@@ -0,0 +1,1334 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+};
+
+use async_trait::async_trait;
+use poise::serenity_prelude::{GuildId, MessageId, UserId};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tokio::{io::AsyncWriteExt, sync::Mutex};
+
+use crate::{
+    managers::{
+        cotd_manager::{ColorInfo, CotdRoleData, CotdRoleDataQuery},
+        detector_manager::DetectorInfo,
+        lua_manager::{
+            history::{LuaCommandVersion, MAX_COMMAND_VERSIONS},
+            modules::LuaModuleInfo,
+            timers::{LuaTimer, MAX_TIMERS_PER_GUILD},
+            LuaCommandInfo, LuaEvent, LuaEventInfo,
+        },
+        message_manager::StoredMessageData,
+        profile_manager::ProfileData,
+        remind_manager::RemindInfo,
+    },
+    utils::{get_seconds, IdType},
+    Error,
+};
+
+use super::{
+    check_data_store_limits, get_data_store_value, increment_data_store_number, BotDataExport,
+    Database,
+};
+
+/// The records are stored the same way SurrealDB would store them.
+/// The key is the record id (`guild:123`, `reminder:1`) and the value is the record content.
+///
+/// Records should only be changed through the methods that take `&mut self`,
+/// so that `MemoryDatabase::update` can put them back if a change fails.
+#[derive(Serialize, Deserialize, Default)]
+struct MemoryData {
+    records: BTreeMap<String, Map<String, Value>>,
+    next_id: u64,
+    /// What the records changed since the last save were before, or None if they didn't exist.
+    #[serde(skip)]
+    changed_records: HashMap<String, Option<Map<String, Value>>>,
+}
+
+impl MemoryData {
+    fn get(&self, table_id: &str) -> Option<&Map<String, Value>> {
+        self.records.get(table_id)
+    }
+
+    /// Keeps what the record is before its first change, so it can be put back.
+    fn remember(&mut self, table_id: &str) {
+        if !self.changed_records.contains_key(table_id) {
+            self.changed_records
+                .insert(table_id.to_string(), self.records.get(table_id).cloned());
+        }
+    }
+
+    /// Works like UPDATE in SurrealDB. If the record doesn't exist it will be created.
+    fn get_mut(&mut self, table_id: String) -> &mut Map<String, Value> {
+        self.remember(&table_id);
+        self.records.entry(table_id).or_default()
+    }
+
+    /// Like `get_mut`, but doesn't create the record if it doesn't exist.
+    fn get_existing_mut(&mut self, table_id: &str) -> Option<&mut Map<String, Value>> {
+        self.remember(table_id);
+        self.records.get_mut(table_id)
+    }
+
+    fn insert(&mut self, table_id: String, record: Map<String, Value>) {
+        self.remember(&table_id);
+        self.records.insert(table_id, record);
+    }
+
+    fn remove(&mut self, table_id: &str) {
+        self.remember(table_id);
+        self.records.remove(table_id);
+    }
+
+    /// Puts back the records that were changed since the last save.
+    fn rollback(&mut self) {
+        for (table_id, record) in self.changed_records.drain() {
+            match record {
+                Some(record) => self.records.insert(table_id, record),
+                None => self.records.remove(&table_id),
+            };
+        }
+    }
+
+    fn get_field<T: DeserializeOwned + Default>(
+        &self,
+        table_id: &str,
+        field: &str,
+    ) -> Result<T, Error> {
+        let Some(value) = self.get(table_id).and_then(|record| record.get(field)) else {
+            return Ok(T::default());
+        };
+
+        Ok(serde_json::from_value(value.clone())?)
+    }
+
+    /// Gets a field that is an object, and creates it if it doesn't exist or isn't an object.
+    fn get_object_mut(&mut self, table_id: String, field: &str) -> &mut Map<String, Value> {
+        let value = self
+            .get_mut(table_id)
+            .entry(field)
+            .or_insert_with(|| Value::Object(Map::new()));
+
+        if !value.is_object() {
+            *value = Value::Object(Map::new());
+        }
+
+        value.as_object_mut().unwrap()
+    }
+
+    fn reminders(&self) -> Result<Vec<RemindInfo>, Error> {
+        let mut reminders = Vec::new();
+        for (table_id, record) in self.records.range("reminder:".to_string()..) {
+            if !table_id.starts_with("reminder:") {
+                break;
+            }
+            let mut record = record.clone();
+            record.insert("id".to_string(), Value::String(table_id.clone()));
+            reminders.push(serde_json::from_value(Value::Object(record))?);
+        }
+        Ok(reminders)
+    }
+}
+
+/// In-process database. Useful when running the bot without a SurrealDB server.
+///
+/// If a file is provided, everything will be saved to it after every change and loaded from it on startup.
+pub struct MemoryDatabase {
+    data: Mutex<MemoryData>,
+    file: Option<PathBuf>,
+}
+
+/// Gets called in the same `update` as the change, which holds the lock the whole time,
+/// so nothing can change the data stores between the limit checks and the change.
+fn set_data_store_value(
+    data_stores: &mut Map<String, Value>,
+    data_store_name: &str,
+    data_store_key: &str,
+    value: &Value,
+) -> Result<(), Error> {
+    check_data_store_limits(data_stores, data_store_name, data_store_key, value)?;
+
+    let data_store = data_stores
+        .entry(data_store_name)
+        .or_insert_with(|| Value::Object(Map::new()));
+
+    if !data_store.is_object() {
+        *data_store = Value::Object(Map::new());
+    }
+
+    data_store
+        .as_object_mut()
+        .unwrap()
+        .insert(data_store_key.to_string(), value.clone());
+
+    Ok(())
+}
+
+impl MemoryDatabase {
+    pub fn new(file: Option<PathBuf>) -> Result<Self, Error> {
+        let data = match &file {
+            Some(path) if path.exists() => {
+                let json_data = std::fs::read_to_string(path)
+                    .map_err(|err| format!("Couldn't read local database file: {err}"))?;
+                serde_json::from_str(&json_data)
+                    .map_err(|err| format!("Couldn't deserialize local database file: {err}"))?
+            }
+            _ => MemoryData::default(),
+        };
+
+        Ok(Self {
+            data: Mutex::new(data),
+            file,
+        })
+    }
+
+    /// Makes the changes and saves them. If the changes fail halfway through or can't be saved,
+    /// the changed records are put back, so the data never differs from the file.
+    async fn update<T>(
+        &self,
+        update: impl FnOnce(&mut MemoryData) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let mut data = self.data.lock().await;
+        let next_id = data.next_id;
+
+        let result = match update(&mut data) {
+            Ok(result) => self.save(&data).await.map(|_| result),
+            Err(err) => Err(err),
+        };
+
+        if result.is_err() {
+            data.rollback();
+            data.next_id = next_id;
+        } else {
+            data.changed_records.clear();
+        }
+
+        result
+    }
+
+    async fn save(&self, data: &MemoryData) -> Result<(), Error> {
+        let Some(path) = &self.file else {
+            return Ok(());
+        };
+
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        // The data is written to a temporary file that then replaces the old one,
+        // so a crash while writing can't leave a half written database behind.
+        let mut temp_path = path.clone().into_os_string();
+        temp_path.push(".tmp");
+
+        let mut temp_file = tokio::fs::File::create(&temp_path).await?;
+        temp_file
+            .write_all(serde_json::to_string(data)?.as_bytes())
+            .await?;
+        temp_file.sync_all().await?;
+
+        tokio::fs::rename(&temp_path, path).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Database for MemoryDatabase {
+    async fn get_data_store(
+        &self,
+        guild_id: GuildId,
+        data_store_name: &str,
+    ) -> Result<HashMap<String, Value>, Error> {
+        let data = self.data.lock().await;
+        let data_stores: HashMap<String, HashMap<String, Value>> =
+            data.get_field(&format!("guild:{guild_id}"), "data_stores")?;
+
+        Ok(data_stores
+            .get(data_store_name)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn set_data_store_value(
+        &self,
+        guild_id: GuildId,
+        data_store_name: &str,
+        data_store_key: &str,
+        value: &Value,
+    ) -> Result<(), Error> {
+        self.update(|data| {
+            let data_stores = data.get_object_mut(format!("guild:{guild_id}"), "data_stores");
+
+            set_data_store_value(data_stores, data_store_name, data_store_key, value)?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn remove_data_store_value(
+        &self,
+        guild_id: GuildId,
+        data_store_name: &str,
+        data_store_key: &str,
+    ) -> Result<(), Error> {
+        self.update(|data| {
+            let data_stores = data.get_object_mut(format!("guild:{guild_id}"), "data_stores");
+
+            let Some(data_store) = data_stores
+                .get_mut(data_store_name)
+                .and_then(Value::as_object_mut)
+            else {
+                return Ok(());
+            };
+
+            data_store.remove(data_store_key);
+
+            // Empty data stores get removed too, so they don't count towards the limit.
+            if data_store.is_empty() {
+                data_stores.remove(data_store_name);
+            }
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn increment_data_store_value(
+        &self,
+        guild_id: GuildId,
+        data_store_name: &str,
+        data_store_key: &str,
+        amount: f64,
+    ) -> Result<f64, Error> {
+        self.update(|data| {
+            let data_stores = data.get_object_mut(format!("guild:{guild_id}"), "data_stores");
+
+            let new_value = increment_data_store_number(
+                &get_data_store_value(data_stores, data_store_name, data_store_key),
+                amount,
+            )?;
+
+            set_data_store_value(
+                data_stores,
+                data_store_name,
+                data_store_key,
+                &new_value.into(),
+            )?;
+
+            Ok(new_value)
+        })
+        .await
+    }
+
+    async fn compare_and_set_data_store_value(
+        &self,
+        guild_id: GuildId,
+        data_store_name: &str,
+        data_store_key: &str,
+        expected: &Value,
+        value: &Value,
+    ) -> Result<bool, Error> {
+        self.update(|data| {
+            let data_stores = data.get_object_mut(format!("guild:{guild_id}"), "data_stores");
+
+            if &get_data_store_value(data_stores, data_store_name, data_store_key) != expected {
+                return Ok(false);
+            }
+
+            set_data_store_value(data_stores, data_store_name, data_store_key, value)?;
+
+            Ok(true)
+        })
+        .await
+    }
+
+    async fn get_all_guild_lua_commands(
+        &self,
+        guild_id: GuildId,
+    ) -> Result<HashMap<String, LuaCommandInfo>, Error> {
+        let data = self.data.lock().await;
+        data.get_field(&format!("guild:{guild_id}"), "lua_commands")
+    }
+
+    async fn add_guild_lua_command(
+        &self,
+        command_name: &str,
+        lua_command_info: &LuaCommandInfo,
+        guild_id: GuildId,
+    ) -> Result<(), Error> {
+        self.update(|data| {
+            data.get_object_mut(format!("guild:{guild_id}"), "lua_commands")
+                .insert(
+                    command_name.to_string(),
+                    serde_json::to_value(lua_command_info)?,
+                );
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn remove_guild_lua_command(
+        &self,
+        guild_id: GuildId,
+        command_name: &str,
+    ) -> Result<(), Error> {
+        self.update(|data| {
+            data.get_object_mut(format!("guild:{guild_id}"), "lua_commands")
+                .remove(command_name);
+            data.get_object_mut(format!("guild:{guild_id}"), "lua_command_history")
+                .remove(command_name);
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_guild_lua_command_history(
+        &self,
+        guild_id: GuildId,
+        command_name: &str,
+    ) -> Result<Vec<LuaCommandVersion>, Error> {
+        let data = self.data.lock().await;
+        let mut history: HashMap<String, Vec<LuaCommandVersion>> =
+            data.get_field(&format!("guild:{guild_id}"), "lua_command_history")?;
+        Ok(history.remove(command_name).unwrap_or_default())
+    }
+
+    async fn add_guild_lua_command_version(
+        &self,
+        guild_id: GuildId,
+        command_name: &str,
+        command_version: &LuaCommandVersion,
+    ) -> Result<(), Error> {
+        self.update(|data| {
+            let history = data.get_object_mut(format!("guild:{guild_id}"), "lua_command_history");
+
+            let mut versions = match history.remove(command_name) {
+                Some(Value::Array(versions)) => versions,
+                _ => Vec::new(),
+            };
+            versions.insert(0, serde_json::to_value(command_version)?);
+            versions.truncate(MAX_COMMAND_VERSIONS);
+
+            history.insert(command_name.to_string(), Value::Array(versions));
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_all_guild_lua_modules(
+        &self,
+        guild_id: GuildId,
+    ) -> Result<HashMap<String, LuaModuleInfo>, Error> {
+        let data = self.data.lock().await;
+        data.get_field(&format!("guild:{guild_id}"), "lua_modules")
+    }
+
+    async fn set_guild_lua_module(
+        &self,
+        guild_id: GuildId,
+        module_name: &str,
+        lua_module_info: &LuaModuleInfo,
+    ) -> Result<(), Error> {
+        self.update(|data| {
+            data.get_object_mut(format!("guild:{guild_id}"), "lua_modules")
+                .insert(
+                    module_name.to_string(),
+                    serde_json::to_value(lua_module_info)?,
+                );
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn remove_guild_lua_module(
+        &self,
+        guild_id: GuildId,
+        module_name: &str,
+    ) -> Result<(), Error> {
+        self.update(|data| {
+            data.get_object_mut(format!("guild:{guild_id}"), "lua_modules")
+                .remove(module_name);
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_all_guild_lua_events(
+        &self,
+        guild_id: GuildId,
+    ) -> Result<HashMap<LuaEvent, LuaEventInfo>, Error> {
+        let data = self.data.lock().await;
+        data.get_field(&format!("guild:{guild_id}"), "lua_events")
+    }
+
+    async fn set_guild_lua_event(
+        &self,
+        guild_id: GuildId,
+        event: LuaEvent,
+        lua_event_info: &LuaEventInfo,
+    ) -> Result<(), Error> {
+        self.update(|data| {
+            data.get_object_mut(format!("guild:{guild_id}"), "lua_events")
+                .insert(
+                    event.to_str().to_string(),
+                    serde_json::to_value(lua_event_info)?,
+                );
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn remove_guild_lua_event(
+        &self,
+        guild_id: GuildId,
+        event: LuaEvent,
+    ) -> Result<(), Error> {
+        self.update(|data| {
+            data.get_object_mut(format!("guild:{guild_id}"), "lua_events")
+                .remove(event.to_str());
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_all_lua_timers(&self) -> Result<Vec<(GuildId, HashMap<String, LuaTimer>)>, Error> {
+        let data = self.data.lock().await;
+        let mut all_timers = Vec::new();
+
+        for (table_id, record) in data
+            .records
+            .range("guild:".to_string()..)
+            .take_while(|(table_id, _)| table_id.starts_with("guild:"))
+        {
+            let Ok(guild_id) = table_id["guild:".len()..].parse::<u64>() else {
+                continue;
+            };
+
+            let Some(lua_timers) = record.get("lua_timers") else {
+                continue;
+            };
+
+            all_timers.push((
+                GuildId::new(guild_id),
+                serde_json::from_value(lua_timers.clone())?,
+            ));
+        }
+
+        Ok(all_timers)
+    }
+
+    async fn get_guild_lua_timers(
+        &self,
+        guild_id: GuildId,
+    ) -> Result<HashMap<String, LuaTimer>, Error> {
+        let data = self.data.lock().await;
+        data.get_field(&format!("guild:{guild_id}"), "lua_timers")
+    }
+
+    async fn set_lua_timer(
+        &self,
+        guild_id: GuildId,
+        timer_name: &str,
+        lua_timer: &LuaTimer,
+    ) -> Result<(), Error> {
+        self.update(|data| {
+            let lua_timers = data.get_object_mut(format!("guild:{guild_id}"), "lua_timers");
+
+            if !lua_timers.contains_key(timer_name) && lua_timers.len() >= MAX_TIMERS_PER_GUILD {
+                return Err(
+                    format!("You may only have up to {MAX_TIMERS_PER_GUILD} timers.").into(),
+                );
+            }
+
+            lua_timers.insert(timer_name.to_string(), serde_json::to_value(lua_timer)?);
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn remove_lua_timer(&self, guild_id: GuildId, timer_name: &str) -> Result<(), Error> {
+        self.update(|data| {
+            data.get_object_mut(format!("guild:{guild_id}"), "lua_timers")
+                .remove(timer_name);
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_guild_cotd_role(
+        &self,
+        guild_id: GuildId,
+    ) -> Result<Option<CotdRoleDataQuery>, Error> {
+        let data = self.data.lock().await;
+        let table_id = format!("guild:{guild_id}");
+        let cotd_role: Option<CotdRoleData> = data.get_field(&table_id, "cotd_role")?;
+
+        Ok(cotd_role.map(|cotd_role| CotdRoleDataQuery {
+            cotd_role,
+            id: table_id,
+        }))
+    }
+
+    async fn get_all_guild_cotd_role(&self) -> Result<Vec<CotdRoleDataQuery>, Error> {
+        let data = self.data.lock().await;
+        let mut cotd_roles_data = Vec::new();
+
+        for (table_id, record) in data.records.iter() {
+            if !table_id.starts_with("guild:") {
+                continue;
+            }
+
+            let Some(cotd_role) = record.get("cotd_role") else {
+                continue;
+            };
+
+            if cotd_role.is_null() {
+                continue;
+            }
+
+            cotd_roles_data.push(CotdRoleDataQuery {
+                cotd_role: serde_json::from_value(cotd_role.clone())?,
+                id: table_id.clone(),
+            });
+        }
+
+        Ok(cotd_roles_data)
+    }
+
+    async fn update_guild_cotd_role(
+        &self,
+        cotd_role_data: &Option<CotdRoleData>,
+        guild_id: GuildId,
+    ) -> Result<(), Error> {
+        self.update(|data| {
+            let record = data.get_mut(format!("guild:{guild_id}"));
+
+            match cotd_role_data {
+                Some(cotd_role_data) => {
+                    record.insert(
+                        "cotd_role".to_string(),
+                        serde_json::to_value(cotd_role_data)?,
+                    );
+                }
+                None => {
+                    record.remove("cotd_role");
+                }
+            }
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn mark_cotd_role_updated(
+        &self,
+        guild_id: GuildId,
+        current_day: u64,
+    ) -> Result<(), Error> {
+        self.update(|data| {
+            let table_id = format!("guild:{guild_id}");
+
+            let Some(cotd_role) = data
+                .get_existing_mut(&table_id)
+                .and_then(|record| record.get_mut("cotd_role"))
+                .and_then(|cotd_role| cotd_role.as_object_mut())
+            else {
+                return Ok(());
+            };
+
+            cotd_role.insert("day".to_string(), Value::from(current_day));
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_cotd(&self, day: u64) -> Result<Option<ColorInfo>, Error> {
+        let data = self.data.lock().await;
+        let Some(record) = data.get(&format!("cotd:{day}")) else {
+            return Ok(None);
+        };
+
+        Ok(Some(serde_json::from_value(Value::Object(record.clone()))?))
+    }
+
+    async fn get_or_update_cotd(&self, day: u64, color: &ColorInfo) -> Result<ColorInfo, Error> {
+        self.update(|data| {
+            let table_id = format!("cotd:{day}");
+
+            if let Some(record) = data.get(&table_id) {
+                return Ok(serde_json::from_value(Value::Object(record.clone()))?);
+            }
+
+            let Value::Object(record) = serde_json::to_value(color)? else {
+                return Err("Color couldn't be turned into a record.".into());
+            };
+
+            data.insert(table_id, record);
+            Ok(color.clone())
+        })
+        .await
+    }
+
+    async fn get_all_message_detectors(&self, id: IdType) -> Result<Vec<DetectorInfo>, Error> {
+        let data = self.data.lock().await;
+        data.get_field(&id.into_db_table(), "message_detectors")
+    }
+
+    async fn add_message_detector(
+        &self,
+        id: IdType,
+        detect_info: &DetectorInfo,
+    ) -> Result<(), Error> {
+        self.update(|data| {
+            let detector_value = serde_json::to_value(detect_info)?;
+
+            let detectors = data
+                .get_mut(id.into_db_table())
+                .entry("message_detectors")
+                .or_insert_with(|| Value::Array(Vec::new()));
+
+            match detectors.as_array_mut() {
+                Some(detectors) => detectors.push(detector_value),
+                None => *detectors = Value::Array(vec![detector_value]),
+            }
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn remove_message_detector(&self, id: IdType, index: usize) -> Result<(), Error> {
+        self.update(|data| {
+            let Some(detectors) = data
+                .get_existing_mut(&id.into_db_table())
+                .and_then(|record| record.get_mut("message_detectors"))
+                .and_then(|detectors| detectors.as_array_mut())
+            else {
+                return Err("Index isn't valid.".into());
+            };
+
+            if detectors.len() <= index {
+                return Err("Index isn't valid.".into());
+            }
+
+            detectors.remove(index);
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_guild_messages(
+        &self,
+        guild_id: GuildId,
+    ) -> Result<HashMap<MessageId, StoredMessageData>, Error> {
+        let data = self.data.lock().await;
+        data.get_field(&format!("guild:{guild_id}"), "messages")
+    }
+
+    async fn clear_message_data(&self, id: IdType, message_id: &MessageId) -> Result<(), Error> {
+        self.update(|data| {
+            data.get_object_mut(id.into_db_table(), "messages")
+                .remove(&message_id.to_string());
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn set_guild_message(
+        &self,
+        guild_id: GuildId,
+        message_id: MessageId,
+        message_data: &StoredMessageData,
+    ) -> Result<(), Error> {
+        self.update(|data| {
+            data.get_object_mut(format!("guild:{guild_id}"), "messages")
+                .insert(message_id.to_string(), serde_json::to_value(message_data)?);
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn remove_guild_message(
+        &self,
+        guild_id: GuildId,
+        message_id: MessageId,
+    ) -> Result<(), Error> {
+        self.clear_message_data(IdType::GuildId(guild_id), &message_id)
+            .await
+    }
+
+    async fn get_user_profile(&self, user_id: UserId) -> Result<ProfileData, Error> {
+        let data = self.data.lock().await;
+        data.get_field(&format!("user:{user_id}"), "profile")
+    }
+
+    async fn set_user_profile(&self, user_id: UserId, profile: &ProfileData) -> Result<(), Error> {
+        self.update(|data| {
+            data.get_mut(format!("user:{user_id}"))
+                .insert("profile".to_string(), serde_json::to_value(profile)?);
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn delete_user_profile(&self, user_id: UserId) -> Result<(), Error> {
+        self.update(|data| {
+            data.get_mut(format!("user:{user_id}")).remove("profile");
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn list_user_reminders(&self, user_id: UserId) -> Result<Vec<RemindInfo>, Error> {
+        let data = self.data.lock().await;
+        let mut user_reminders = data
+            .reminders()?
+            .into_iter()
+            .filter(|reminder| reminder.user_id == user_id)
+            .collect::<Vec<_>>();
+
+        user_reminders.sort_by_key(|reminder| reminder.original_time);
+
+        Ok(user_reminders)
+    }
+
+    async fn add_user_reminder(&self, remind_info: &mut RemindInfo) -> Result<(), Error> {
+        let reminder_id = self
+            .update(|data| {
+                let Value::Object(record) = serde_json::to_value(&remind_info)? else {
+                    return Err("Reminder couldn't be turned into a record.".into());
+                };
+
+                data.next_id += 1;
+                let reminder_id = format!("reminder:{}", data.next_id);
+
+                data.insert(reminder_id.clone(), record);
+                Ok(reminder_id)
+            })
+            .await?;
+
+        remind_info.id = Some(reminder_id);
+
+        Ok(())
+    }
+
+    async fn update_reminder(&self, remind_info: &RemindInfo) -> Result<(), Error> {
+        let Some(reminder_id) = &remind_info.id else {
+            return Err("Reminder is missing a database id.".into());
+        };
+
+        self.update(|data| {
+            let Value::Object(record) = serde_json::to_value(&remind_info)? else {
+                return Err("Reminder couldn't be turned into a record.".into());
+            };
+
+            data.insert(reminder_id.clone(), record);
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn delete_table_id(&self, table_id: &String) -> Result<(), Error> {
+        self.update(|data| {
+            data.remove(table_id);
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_pending_reminders(&self, current_time: u64) -> Result<Vec<RemindInfo>, Error> {
+        let data = self.data.lock().await;
+        Ok(data
+            .reminders()?
+            .into_iter()
+            .filter(|reminder| reminder.finish_time <= current_time)
+            .collect())
+    }
+
+    async fn get_next_reminder_time(&self) -> Result<Option<u64>, Error> {
+        let data = self.data.lock().await;
+        Ok(data
+            .reminders()?
+            .into_iter()
+            .map(|reminder| reminder.finish_time)
+            .min())
+    }
+
+    async fn get_all_reminders(&self) -> Result<Vec<RemindInfo>, Error> {
+        let data = self.data.lock().await;
+        data.reminders()
+    }
+
+    async fn get_stored_guilds(&self) -> Result<Vec<(GuildId, Option<u64>)>, Error> {
+        let data = self.data.lock().await;
+        Ok(data
+            .records
+            .range("guild:".to_string()..)
+            .take_while(|(table_id, _)| table_id.starts_with("guild:"))
+            .filter_map(|(table_id, record)| {
+                let guild_id = table_id["guild:".len()..].parse::<u64>().ok()?;
+                let left_time = record.get("left_time").and_then(Value::as_u64);
+                Some((GuildId::new(guild_id), left_time))
+            })
+            .collect())
+    }
+
+    async fn set_guild_left_time(
+        &self,
+        guild_id: GuildId,
+        left_time: Option<u64>,
+    ) -> Result<(), Error> {
+        self.update(|data| {
+            let table_id = format!("guild:{guild_id}");
+
+            match left_time {
+                Some(left_time) => {
+                    data.get_mut(table_id)
+                        .insert("left_time".to_string(), Value::from(left_time));
+                }
+                None => {
+                    let Some(record) = data.get_existing_mut(&table_id) else {
+                        return Ok(());
+                    };
+                    record.remove("left_time");
+                }
+            }
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn clear_bot_data(&self, id: IdType) -> Result<Vec<UserId>, Error> {
+        self.update(|data| {
+            let mut removed_reminder_ids = Vec::new();
+            let mut user_ids = Vec::new();
+
+            for reminder in data.reminders()? {
+                let is_affected = match id {
+                    IdType::UserId(user_id) => reminder.user_id == user_id,
+                    IdType::GuildId(guild_id) => reminder.guild_id == Some(guild_id),
+                };
+
+                if !is_affected {
+                    continue;
+                }
+
+                if !user_ids.contains(&reminder.user_id) {
+                    user_ids.push(reminder.user_id);
+                }
+
+                if let Some(reminder_id) = reminder.id {
+                    removed_reminder_ids.push(reminder_id);
+                }
+            }
+
+            for reminder_id in removed_reminder_ids {
+                data.remove(&reminder_id);
+            }
+
+            data.remove(&id.into_db_table());
+
+            Ok(user_ids)
+        })
+        .await
+    }
+
+    async fn export_bot_data(&self, id: IdType) -> Result<BotDataExport, Error> {
+        let data = self.data.lock().await;
+
+        let mut reminders = data
+            .reminders()?
+            .into_iter()
+            .filter(|reminder| match id {
+                IdType::UserId(user_id) => reminder.user_id == user_id,
+                IdType::GuildId(guild_id) => reminder.guild_id == Some(guild_id),
+            })
+            .collect::<Vec<_>>();
+        reminders.sort_by_key(|reminder| reminder.original_time);
+
+        let schema_version: Option<u64> = data.get_field("meta:schema", "version")?;
+
+        Ok(BotDataExport {
+            schema_version: schema_version.unwrap_or(0),
+            id: id.into_db_table(),
+            exported_at: get_seconds(),
+            record: data.get(&id.into_db_table()).cloned().unwrap_or_default(),
+            reminders,
+        })
+    }
+
+    async fn get_schema_version(&self) -> Result<u64, Error> {
+        let data = self.data.lock().await;
+        let version: Option<u64> = data.get_field("meta:schema", "version")?;
+        Ok(version.unwrap_or(0))
+    }
+
+    async fn set_schema_version(&self, version: u64) -> Result<(), Error> {
+        self.update(|data| {
+            data.get_mut("meta:schema".to_string())
+                .insert("version".to_string(), Value::from(version));
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_records_with_field(
+        &self,
+        table: &str,
+        field: &'static str,
+    ) -> Result<Vec<(String, Value)>, Error> {
+        let data = self.data.lock().await;
+        let prefix = format!("{table}:");
+
+        Ok(data
+            .records
+            .iter()
+            .filter(|(table_id, _)| table_id.starts_with(&prefix))
+            .filter_map(|(table_id, record)| {
+                let value = record.get(field)?;
+                Some((table_id.clone(), value.clone()))
+            })
+            .collect())
+    }
+
+    async fn set_record_field(
+        &self,
+        table_id: &str,
+        field: &'static str,
+        value: &Value,
+    ) -> Result<(), Error> {
+        self.update(|data| {
+            data.get_mut(table_id.to_string())
+                .insert(field.to_string(), value.clone());
+
+            Ok(())
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use poise::serenity_prelude::ChannelId;
+    use serde_json::json;
+
+    use crate::managers::{
+        db::{MAX_DATA_STORES_PER_GUILD, MAX_DATA_STORE_BYTES_PER_GUILD, MAX_KEYS_PER_DATA_STORE},
+        lua_manager::timers::TimerSchedule,
+    };
+
+    use super::*;
+
+    const GUILD_ID: GuildId = GuildId::new(1);
+
+    fn reminder(user_id: u64, finish_time: u64) -> RemindInfo {
+        RemindInfo {
+            original_time: finish_time,
+            request_time: 0,
+            finish_time,
+            channel_id: ChannelId::new(2),
+            guild_id: Some(GUILD_ID),
+            user_id: UserId::new(user_id),
+            id: None,
+            message_id: None,
+            message: Some("Reminder".to_string()),
+            looping: false,
+        }
+    }
+
+    fn timer(next_time: u64) -> LuaTimer {
+        LuaTimer {
+            schedule: TimerSchedule::Once,
+            next_time,
+            data: Value::Null,
+        }
+    }
+
+    fn command_version(version: u32) -> LuaCommandVersion {
+        LuaCommandVersion {
+            version,
+            author: Some(UserId::new(3)),
+            timestamp: 0,
+            command_info: LuaCommandInfo {
+                lua_code: format!("return function() print({version}) end"),
+                filename: "command.luau".to_string(),
+                description: "Test".to_string(),
+                options: Vec::new(),
+                default_member_permissions: None,
+                required_roles: Vec::new(),
+                subcommands: Vec::new(),
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn data_store_values_can_be_set_and_removed() {
+        let db = MemoryDatabase::new(None).unwrap();
+
+        db.set_data_store_value(GUILD_ID, "scores", "bob", &json!(5))
+            .await
+            .unwrap();
+        db.set_data_store_value(GUILD_ID, "scores", "bob", &json!(7))
+            .await
+            .unwrap();
+        db.set_data_store_value(GUILD_ID, "scores", "alice", &json!("high"))
+            .await
+            .unwrap();
+
+        let scores = db.get_data_store(GUILD_ID, "scores").await.unwrap();
+        assert_eq!(scores.get("bob"), Some(&json!(7)));
+        assert_eq!(scores.get("alice"), Some(&json!("high")));
+        assert!(db
+            .get_data_store(GUILD_ID, "other")
+            .await
+            .unwrap()
+            .is_empty());
+
+        db.remove_data_store_value(GUILD_ID, "scores", "bob")
+            .await
+            .unwrap();
+
+        let scores = db.get_data_store(GUILD_ID, "scores").await.unwrap();
+        assert_eq!(scores.len(), 1);
+        assert!(!scores.contains_key("bob"));
+    }
+
+    #[tokio::test]
+    async fn failed_changes_are_rolled_back() {
+        let db = MemoryDatabase::new(None).unwrap();
+
+        for index in 0..MAX_DATA_STORES_PER_GUILD {
+            db.set_data_store_value(GUILD_ID, &format!("store{index}"), "key", &json!(1))
+                .await
+                .unwrap();
+        }
+
+        assert!(db
+            .set_data_store_value(GUILD_ID, "one_too_many", "key", &json!(1))
+            .await
+            .is_err());
+        assert!(db
+            .get_data_store(GUILD_ID, "one_too_many")
+            .await
+            .unwrap()
+            .is_empty());
+        // Stores that already exist can still get new keys.
+        assert!(db
+            .set_data_store_value(GUILD_ID, "store0", "other_key", &json!(2))
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn data_stores_cant_go_over_the_key_limit() {
+        let db = MemoryDatabase::new(None).unwrap();
+
+        for index in 0..MAX_KEYS_PER_DATA_STORE {
+            db.set_data_store_value(GUILD_ID, "scores", &format!("key{index}"), &json!(1))
+                .await
+                .unwrap();
+        }
+
+        let result = db
+            .set_data_store_value(GUILD_ID, "scores", "one_too_many", &json!(1))
+            .await;
+        assert!(result.is_err_and(|err| err.to_string().contains("keys")));
+        assert!(db
+            .increment_data_store_value(GUILD_ID, "scores", "one_too_many", 1.0)
+            .await
+            .is_err());
+        assert_eq!(
+            db.get_data_store(GUILD_ID, "scores").await.unwrap().len(),
+            MAX_KEYS_PER_DATA_STORE
+        );
+
+        // Keys that already exist can still change.
+        assert_eq!(
+            db.increment_data_store_value(GUILD_ID, "scores", "key0", 1.0)
+                .await
+                .unwrap(),
+            2.0
+        );
+    }
+
+    #[tokio::test]
+    async fn data_stores_cant_go_over_the_byte_limit() {
+        let db = MemoryDatabase::new(None).unwrap();
+
+        let half = json!("a".repeat(MAX_DATA_STORE_BYTES_PER_GUILD / 2));
+
+        db.set_data_store_value(GUILD_ID, "texts", "first", &half)
+            .await
+            .unwrap();
+
+        let result = db
+            .set_data_store_value(GUILD_ID, "other_texts", "second", &half)
+            .await;
+        assert!(result.is_err_and(|err| err.to_string().contains("bytes")));
+        assert!(db
+            .get_data_store(GUILD_ID, "other_texts")
+            .await
+            .unwrap()
+            .is_empty());
+
+        // Replacing a value only counts the new one.
+        assert!(db
+            .set_data_store_value(GUILD_ID, "texts", "first", &half)
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn reminders_can_be_added_updated_and_deleted() {
+        let db = MemoryDatabase::new(None).unwrap();
+
+        let mut first = reminder(10, 100);
+        let mut second = reminder(10, 50);
+        let mut other_user = reminder(20, 200);
+        db.add_user_reminder(&mut first).await.unwrap();
+        db.add_user_reminder(&mut second).await.unwrap();
+        db.add_user_reminder(&mut other_user).await.unwrap();
+
+        assert!(first.id.is_some());
+        assert_ne!(first.id, second.id);
+
+        let user_reminders = db.list_user_reminders(UserId::new(10)).await.unwrap();
+        assert_eq!(
+            user_reminders
+                .iter()
+                .map(|reminder| reminder.finish_time)
+                .collect::<Vec<_>>(),
+            [50, 100]
+        );
+
+        assert_eq!(db.get_next_reminder_time().await.unwrap(), Some(50));
+        assert_eq!(db.get_pending_reminders(100).await.unwrap().len(), 2);
+
+        second.finish_time = 300;
+        db.update_reminder(&second).await.unwrap();
+        assert_eq!(db.get_next_reminder_time().await.unwrap(), Some(100));
+
+        db.delete_table_id(first.id.as_ref().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(
+            db.list_user_reminders(UserId::new(10)).await.unwrap().len(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn data_is_loaded_from_the_file() {
+        let path =
+            std::env::temp_dir().join(format!("nizubot-memory-test-{}.json", std::process::id()));
+
+        let db = MemoryDatabase::new(Some(path.clone())).unwrap();
+        db.set_data_store_value(GUILD_ID, "scores", "bob", &json!(5))
+            .await
+            .unwrap();
+        drop(db);
+
+        let db = MemoryDatabase::new(Some(path.clone())).unwrap();
+        let scores = db.get_data_store(GUILD_ID, "scores").await.unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(scores.get("bob"), Some(&json!(5)));
+    }
+
+    #[tokio::test]
+    async fn command_history_keeps_the_newest_versions() {
+        let db = MemoryDatabase::new(None).unwrap();
+
+        for version in 1..=MAX_COMMAND_VERSIONS as u32 + 3 {
+            db.add_guild_lua_command_version(GUILD_ID, "greet", &command_version(version))
+                .await
+                .unwrap();
+        }
+
+        let history = db
+            .get_guild_lua_command_history(GUILD_ID, "greet")
+            .await
+            .unwrap();
+        assert_eq!(history.len(), MAX_COMMAND_VERSIONS);
+        assert_eq!(history[0].version, MAX_COMMAND_VERSIONS as u32 + 3);
+        assert_eq!(history.last().unwrap().version, 4);
+
+        db.remove_guild_lua_command(GUILD_ID, "greet")
+            .await
+            .unwrap();
+        assert!(db
+            .get_guild_lua_command_history(GUILD_ID, "greet")
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn timers_can_be_set_replaced_and_removed() {
+        let db = MemoryDatabase::new(None).unwrap();
+
+        db.set_lua_timer(GUILD_ID, "daily", &timer(100))
+            .await
+            .unwrap();
+        db.set_lua_timer(GUILD_ID, "daily", &timer(200))
+            .await
+            .unwrap();
+        db.set_lua_timer(GUILD_ID, "weekly", &timer(300))
+            .await
+            .unwrap();
+
+        let timers = db.get_guild_lua_timers(GUILD_ID).await.unwrap();
+        assert_eq!(timers.len(), 2);
+        assert_eq!(timers["daily"].next_time, 200);
+
+        let all_timers = db.get_all_lua_timers().await.unwrap();
+        assert_eq!(all_timers.len(), 1);
+        assert_eq!(all_timers[0].0, GUILD_ID);
+
+        db.remove_lua_timer(GUILD_ID, "daily").await.unwrap();
+        let timers = db.get_guild_lua_timers(GUILD_ID).await.unwrap();
+        assert!(!timers.contains_key("daily"));
+        assert!(timers.contains_key("weekly"));
+    }
+
+    #[tokio::test]
+    async fn timers_cant_go_over_the_limit() {
+        let db = MemoryDatabase::new(None).unwrap();
+
+        for index in 0..MAX_TIMERS_PER_GUILD {
+            db.set_lua_timer(GUILD_ID, &format!("timer{index}"), &timer(100))
+                .await
+                .unwrap();
+        }
+
+        assert!(db
+            .set_lua_timer(GUILD_ID, "new", &timer(100))
+            .await
+            .is_err());
+        // Replacing a timer doesn't add one.
+        assert!(db
+            .set_lua_timer(GUILD_ID, "timer0", &timer(200))
+            .await
+            .is_ok());
+    }
+}
@@ -0,0 +1,512 @@
+use poise::serenity_prelude::{ChannelId, ChannelType, GuildId, Http, MessageId};
+use serde_json::Value;
+
+use crate::{
+    managers::log_manager::{LogManager, LogSource, LogType},
+    utils::is_not_found,
+    Error,
+};
+
+use super::{parse_guild_record_id, Database};
+
+/// Every migration in the order they have to run.
+///
+/// The schema version stored in the database is the amount of migrations that have been run,
+/// so new migrations must always be added to the end.
+const MIGRATIONS: [&str; 2] = [
+    "Rename old message detector fields",
+    "Fill in the channel of reaction role messages",
+];
+
+pub struct MigrationContext<'a> {
+    pub db: &'a dyn Database,
+    /// Where the progress gets logged. Nothing gets logged if this is None.
+    pub log_manager: Option<&'a LogManager>,
+    /// Used to look things up on discord. Migrations that need it fail if this is None, so they run again next time.
+    pub http: Option<&'a Http>,
+}
+
+impl MigrationContext<'_> {
+    async fn log(&self, message: String) {
+        if let Some(log_manager) = self.log_manager {
+            log_manager
+                .add_owner_log(message, LogType::Info, LogSource::Database)
+                .await;
+        }
+    }
+}
+
+/// Runs every migration that hasn't been run yet.
+///
+/// Should be called before any of the managers start using the database.
+/// Stops at the first migration that fails, and that migration will be tried again next time.
+pub async fn run_migrations(migration_ctx: &MigrationContext<'_>) -> Result<(), Error> {
+    let db = migration_ctx.db;
+    let current_version = db.get_schema_version().await?;
+
+    for (index, name) in MIGRATIONS.iter().enumerate() {
+        let version = index as u64 + 1;
+
+        if version <= current_version {
+            continue;
+        }
+
+        migration_ctx
+            .log(format!("Running migration {version}: {name}"))
+            .await;
+
+        let summary = run_migration(version, migration_ctx)
+            .await
+            .map_err(|err| format!("Migration {version} ({name}) failed. {err}"))?;
+
+        db.set_schema_version(version).await?;
+
+        migration_ctx
+            .log(format!("Finished migration {version}: {summary}"))
+            .await;
+    }
+
+    Ok(())
+}
+
+/// Runs a single migration and returns a summary of what it did.
+async fn run_migration(
+    version: u64,
+    migration_ctx: &MigrationContext<'_>,
+) -> Result<String, Error> {
+    match version {
+        1 => rename_detector_fields(migration_ctx).await,
+        2 => fill_in_message_channels(migration_ctx).await,
+        _ => Err(format!("No migration with version {version}.").into()),
+    }
+}
+
+/// Message detectors used to be stored with camel case field names.
+async fn rename_detector_fields(migration_ctx: &MigrationContext<'_>) -> Result<String, Error> {
+    const RENAMES: [(&str, &str); 2] = [
+        ("detectionType", "detect_type"),
+        ("caseSensitive", "case_sensitive"),
+    ];
+
+    let mut updated_records = 0;
+
+    for table in ["guild", "user"] {
+        let records = migration_ctx
+            .db
+            .get_records_with_field(table, "message_detectors")
+            .await?;
+
+        for (table_id, mut detectors) in records {
+            let Some(detectors_array) = detectors.as_array_mut() else {
+                continue;
+            };
+
+            let mut changed = false;
+
+            for detector in detectors_array.iter_mut() {
+                let Some(detector) = detector.as_object_mut() else {
+                    continue;
+                };
+
+                for (old_name, new_name) in RENAMES {
+                    if let Some(value) = detector.remove(old_name) {
+                        detector.insert(new_name.to_string(), value);
+                        changed = true;
+                    }
+                }
+            }
+
+            if changed {
+                migration_ctx
+                    .db
+                    .set_record_field(&table_id, "message_detectors", &detectors)
+                    .await?;
+                updated_records += 1;
+            }
+        }
+    }
+
+    Ok(format!(
+        "Updated the message detectors of {updated_records} guilds/users."
+    ))
+}
+
+/// Old versions didn't store the channel of reaction role messages.
+///
+/// The channel gets looked up on discord. Messages are only removed if every channel they could be in says they aren't there,
+/// any other error fails the migration so it runs again next time.
+async fn fill_in_message_channels(migration_ctx: &MigrationContext<'_>) -> Result<String, Error> {
+    let mut filled_in_messages = 0;
+    let mut removed_messages = 0;
+
+    let records = migration_ctx
+        .db
+        .get_records_with_field("guild", "messages")
+        .await?;
+
+    for (table_id, mut messages) in records {
+        let Some(messages_map) = messages.as_object_mut() else {
+            continue;
+        };
+
+        let missing_channel = messages_map
+            .iter()
+            .filter(|(_, message_data)| {
+                message_data.as_object().is_some_and(|message_data| {
+                    message_data.get("channel_id").map_or(true, Value::is_null)
+                })
+            })
+            .map(|(message_id, _)| message_id.clone())
+            .collect::<Vec<_>>();
+
+        if missing_channel.is_empty() {
+            continue;
+        }
+
+        let Some(http) = migration_ctx.http else {
+            return Err("Discord is needed to look up the channels of messages.".into());
+        };
+
+        let Some(guild_id) = parse_guild_record_id(&table_id) else {
+            continue;
+        };
+
+        // The channels are only fetched once per guild, and only if there is a message to look for.
+        let mut channels = None;
+
+        for message_id_string in missing_channel {
+            // Ids that aren't valid can't be looked up, and couldn't be loaded either.
+            let Ok(message_id) = message_id_string.parse::<MessageId>() else {
+                messages_map.remove(&message_id_string);
+                removed_messages += 1;
+                continue;
+            };
+
+            if channels.is_none() {
+                channels = Some(get_message_channels(http, guild_id).await?);
+            }
+
+            match find_message_channel(http, channels.as_ref().unwrap(), message_id).await? {
+                Some(channel_id) => {
+                    messages_map[&message_id_string]["channel_id"] =
+                        serde_json::to_value(channel_id)?;
+                    filled_in_messages += 1;
+                }
+                None => {
+                    messages_map.remove(&message_id_string);
+                    removed_messages += 1;
+                }
+            }
+        }
+
+        migration_ctx
+            .db
+            .set_record_field(&table_id, "messages", &messages)
+            .await?;
+    }
+
+    Ok(format!(
+        "Filled in the channel of {filled_in_messages} messages and removed {removed_messages} messages that couldn't be found."
+    ))
+}
+
+/// Gets every channel of a guild that messages can be in, threads included.
+///
+/// Errors if something couldn't be listed, since a message can't be called gone without looking everywhere.
+async fn get_message_channels(http: &Http, guild_id: GuildId) -> Result<Vec<ChannelId>, Error> {
+    let guild_channels = http.get_channels(guild_id).await?;
+
+    let mut channels = guild_channels
+        .iter()
+        .filter(|channel| channel.is_text_based())
+        .map(|channel| channel.id)
+        .collect::<Vec<_>>();
+
+    let active_threads = http.get_guild_active_threads(guild_id).await?;
+    channels.extend(active_threads.threads.iter().map(|thread| thread.id));
+
+    for channel in guild_channels.iter().filter(|channel| {
+        matches!(
+            channel.kind,
+            ChannelType::Text | ChannelType::News | ChannelType::Forum
+        )
+    }) {
+        let archived_threads = http
+            .get_channel_archived_public_threads(channel.id, None, None)
+            .await?;
+
+        if archived_threads.has_more {
+            return Err(format!(
+                "<#{}> has more archived threads than can be looked through.",
+                channel.id
+            )
+            .into());
+        }
+
+        channels.extend(archived_threads.threads.iter().map(|thread| thread.id));
+    }
+
+    Ok(channels)
+}
+
+/// None if every channel responded that the message isn't there.
+async fn find_message_channel(
+    http: &Http,
+    channels: &[ChannelId],
+    message_id: MessageId,
+) -> Result<Option<ChannelId>, Error> {
+    let mut lookup_error = None;
+
+    for channel_id in channels {
+        match http.get_message(*channel_id, message_id).await {
+            Ok(_) => return Ok(Some(*channel_id)),
+            Err(err) if is_not_found(&err) => {}
+            Err(err) => {
+                lookup_error.get_or_insert(err);
+            }
+        }
+    }
+
+    match lookup_error {
+        Some(err) => Err(format!("Couldn't look for message {message_id}. {err}").into()),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use poise::serenity_prelude::HttpBuilder;
+    use serde_json::json;
+
+    use crate::{
+        managers::{
+            db::memory::MemoryDatabase, detector_manager::DetectorInfo,
+            message_manager::StoredMessageData,
+        },
+        test_utils::start_server_with_status,
+    };
+
+    use super::*;
+
+    const GUILD_ID: GuildId = GuildId::new(1);
+
+    fn migration_ctx(db: &MemoryDatabase) -> MigrationContext<'_> {
+        MigrationContext {
+            db,
+            log_manager: None,
+            http: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn camel_case_detectors_are_renamed() {
+        let db = MemoryDatabase::new(None).unwrap();
+        db.set_record_field(
+            "guild:1",
+            "message_detectors",
+            &json!([{
+                "key": "hi",
+                "response": "hello",
+                "detectionType": "Contains",
+                "caseSensitive": true,
+            }]),
+        )
+        .await
+        .unwrap();
+
+        run_migrations(&migration_ctx(&db)).await.unwrap();
+
+        let records = db
+            .get_records_with_field("guild", "message_detectors")
+            .await
+            .unwrap();
+        let detector = &records[0].1[0];
+        assert_eq!(detector["detect_type"], json!("Contains"));
+        assert_eq!(detector["case_sensitive"], json!(true));
+        assert!(detector.get("detectionType").is_none());
+        assert!(detector.get("caseSensitive").is_none());
+
+        let detectors: Vec<DetectorInfo> = serde_json::from_value(records[0].1.clone()).unwrap();
+        assert_eq!(detectors[0].key, "hi");
+        assert!(detectors[0].case_sensitive);
+
+        assert_eq!(
+            db.get_schema_version().await.unwrap(),
+            MIGRATIONS.len() as u64
+        );
+    }
+
+    /// Http that sends its requests to a local server instead of discord.
+    fn mock_http(respond: impl Fn(&str) -> (u16, Value) + Send + 'static) -> Http {
+        let url = start_server_with_status(move |request| {
+            let (status, body) = respond(&request.path);
+            (status, body.to_string().into_bytes())
+        });
+
+        HttpBuilder::new("token")
+            .proxy(url)
+            .ratelimiter_disabled(true)
+            .build()
+    }
+
+    fn text_channel(id: u64) -> Value {
+        json!({ "id": id.to_string(), "type": 0, "guild_id": "1", "name": "general", "position": 0, "permission_overwrites": [] })
+    }
+
+    fn threads(threads: Vec<Value>) -> Value {
+        json!({ "threads": threads, "members": [], "has_more": false })
+    }
+
+    fn message(id: u64, channel_id: u64) -> Value {
+        json!({
+            "id": id.to_string(),
+            "channel_id": channel_id.to_string(),
+            "author": { "id": "7", "username": "bot", "discriminator": "0000", "avatar": null },
+            "content": "",
+            "timestamp": "2024-01-01T00:00:00+00:00",
+            "edited_timestamp": null,
+            "tts": false,
+            "mention_everyone": false,
+            "mentions": [],
+            "mention_roles": [],
+            "attachments": [],
+            "embeds": [],
+            "pinned": false,
+            "type": 0,
+        })
+    }
+
+    fn not_found() -> (u16, Value) {
+        (404, json!({ "code": 10008, "message": "Unknown Message" }))
+    }
+
+    async fn add_messages_without_a_channel(db: &MemoryDatabase) {
+        db.set_record_field(
+            "guild:1",
+            "messages",
+            &json!({
+                "10": { "reaction_roles": { "👍": "5" } },
+                "11": { "channel_id": "3", "reaction_roles": { "👍": "5" } },
+                "12": { "reaction_roles": { "👍": "5" } },
+            }),
+        )
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn message_channels_are_looked_up_on_discord() {
+        let db = MemoryDatabase::new(None).unwrap();
+        add_messages_without_a_channel(&db).await;
+
+        // Message 10 is in a thread, message 12 is gone.
+        let http = mock_http(|path| {
+            if path.contains("/guilds/1/channels") {
+                (200, json!([text_channel(3), text_channel(4)]))
+            } else if path.contains("/guilds/1/threads/active") {
+                (
+                    200,
+                    threads(vec![json!({
+                        "id": "5",
+                        "type": 11,
+                        "guild_id": "1",
+                        "parent_id": "3",
+                        "name": "thread",
+                        "thread_metadata": {
+                            "archived": false,
+                            "auto_archive_duration": 60,
+                            "archive_timestamp": "2024-01-01T00:00:00+00:00",
+                            "locked": false,
+                        },
+                    })]),
+                )
+            } else if path.contains("/threads/archived/public") {
+                (200, threads(Vec::new()))
+            } else if path.contains("/channels/5/messages/10") {
+                (200, message(10, 5))
+            } else {
+                not_found()
+            }
+        });
+
+        run_migrations(&MigrationContext {
+            db: &db,
+            log_manager: None,
+            http: Some(&http),
+        })
+        .await
+        .unwrap();
+
+        let messages = db.get_guild_messages(GUILD_ID).await.unwrap();
+        assert_eq!(messages.len(), 2);
+        let message_data: &StoredMessageData = &messages[&MessageId::new(10)];
+        assert_eq!(message_data.channel_id, ChannelId::new(5));
+        assert_eq!(messages[&MessageId::new(11)].channel_id, ChannelId::new(3));
+        assert_eq!(
+            db.get_schema_version().await.unwrap(),
+            MIGRATIONS.len() as u64
+        );
+    }
+
+    #[tokio::test]
+    async fn messages_are_kept_when_they_cant_be_looked_up() {
+        let db = MemoryDatabase::new(None).unwrap();
+        add_messages_without_a_channel(&db).await;
+
+        // Without discord, nothing can be looked up.
+        assert!(run_migrations(&migration_ctx(&db)).await.is_err());
+
+        // Missing access doesn't mean the message is gone.
+        let http = mock_http(|path| {
+            if path.contains("/guilds/1/channels") {
+                (200, json!([text_channel(3)]))
+            } else if path.contains("/threads") {
+                (200, threads(Vec::new()))
+            } else {
+                (403, json!({ "code": 50001, "message": "Missing Access" }))
+            }
+        });
+
+        assert!(run_migrations(&MigrationContext {
+            db: &db,
+            log_manager: None,
+            http: Some(&http),
+        })
+        .await
+        .is_err());
+
+        let records = db
+            .get_records_with_field("guild", "messages")
+            .await
+            .unwrap();
+        let messages = records[0].1.as_object().unwrap();
+        assert_eq!(messages.len(), 3);
+        assert!(messages["10"].get("channel_id").is_none());
+
+        // The detectors were migrated, but the messages will be tried again.
+        assert_eq!(db.get_schema_version().await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn migrations_that_already_ran_are_skipped() {
+        let db = MemoryDatabase::new(None).unwrap();
+        db.set_schema_version(MIGRATIONS.len() as u64)
+            .await
+            .unwrap();
+        db.set_record_field(
+            "guild:1",
+            "message_detectors",
+            &json!([{ "key": "hi", "response": "hello", "detectionType": "Contains", "caseSensitive": true }]),
+        )
+        .await
+        .unwrap();
+
+        run_migrations(&migration_ctx(&db)).await.unwrap();
+
+        let records = db
+            .get_records_with_field("guild", "message_detectors")
+            .await
+            .unwrap();
+        assert!(records[0].1[0].get("detectionType").is_some());
+    }
+}
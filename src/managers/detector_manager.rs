@@ -9,7 +9,7 @@ use crate::{
     Error,
 };
 
-use super::db::SurrealClient;
+use super::db::{Database, DatabaseHealth};
 
 #[derive(Serialize, Deserialize, Clone, poise::ChoiceParameter)]
 pub enum DetectType {
@@ -42,10 +42,8 @@ pub struct DetectorInfo {
     /// Response to when it detects the key.
     pub response: String,
     /// How to detect the key.
-    #[serde(alias = "detectionType", alias = "detect_type")]
     pub detect_type: DetectType,
     /// If the detector should be case sensitive.
-    #[serde(alias = "caseSensitive", alias = "case_sensitive")]
     pub case_sensitive: bool,
 }
 
@@ -85,7 +83,7 @@ impl DetectorsData {
 
     pub async fn get_detectors(
         &mut self,
-        db: &SurrealClient,
+        db: &dyn Database,
     ) -> Result<&mut Vec<DetectorInfo>, Error> {
         let detectors_mut = &mut self.detectors;
         match detectors_mut {
@@ -102,7 +100,7 @@ impl DetectorsData {
     pub async fn add_detector(
         &mut self,
         detect_info: DetectorInfo,
-        db: &SurrealClient,
+        db: &dyn Database,
     ) -> Result<(), Error> {
         let id = self.id;
         let detectors = self.get_detectors(db).await?;
@@ -111,7 +109,7 @@ impl DetectorsData {
         Ok(())
     }
 
-    pub async fn delete_detector(&mut self, index: usize, db: &SurrealClient) -> Result<(), Error> {
+    pub async fn delete_detector(&mut self, index: usize, db: &dyn Database) -> Result<(), Error> {
         let id = self.id;
         let detectors = self.get_detectors(db).await?;
         db.remove_message_detector(id, index).await?;
@@ -121,7 +119,7 @@ impl DetectorsData {
 }
 
 pub struct DetectorManager {
-    pub db: Arc<SurrealClient>,
+    pub db: Arc<dyn Database>,
     /// Holds detectors for different guilds/users.
     ///
     /// DetectorsData is inside of an Arc so that the RwLock gets locked as little as possible.
@@ -134,7 +132,7 @@ pub struct DetectorManager {
 }
 
 impl DetectorManager {
-    pub fn new(db: Arc<SurrealClient>) -> Self {
+    pub fn new(db: Arc<dyn Database>) -> Self {
         Self {
             db,
             detectors_data: RwLock::new(TtlMap::new(Duration::from_secs(60 * 60))),
@@ -264,6 +262,11 @@ impl DetectorManager {
         let mut locked_detectors_data = detectors_data.lock().await;
         let db = &self.db;
 
+        // Every message would otherwise turn into a failed query and a log while the database is down.
+        if locked_detectors_data.detectors.is_none() && db.health() == DatabaseHealth::Down {
+            return Ok(());
+        }
+
         let detectors = locked_detectors_data
             .get_detectors(db)
             .await
@@ -182,6 +182,7 @@ pub enum LogSource {
     Reminder,
     Lua,
     LogManager,
+    Database,
     Custom(String),
 }
 
@@ -196,6 +197,7 @@ impl LogSource {
             LogSource::Reminder => "REMINDER",
             LogSource::Lua => "LUA",
             LogSource::LogManager => "LOG_MANAGER",
+            LogSource::Database => "DATABASE",
             LogSource::Custom(string) => string,
         }
     }
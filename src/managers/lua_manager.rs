@@ -1,5 +1,5 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     ops::Deref,
     sync::{
         atomic::{AtomicBool, AtomicU64, Ordering},
@@ -8,38 +8,84 @@ use std::{
     time::Duration,
 };
 
-use mlua::{Function, IntoLua, Lua, UserData, UserDataMethods, VmState};
+use async_trait::async_trait;
+use mlua::{FromLua, Function, IntoLua, Lua, UserData, UserDataMethods, Variadic, VmState};
 use poise::serenity_prelude::{
-    self, CacheHttp, CommandDataOptionValue, CommandInteraction, CommandOptionType, CreateCommand,
-    CreateCommandOption, CreateInteractionResponse, CreateInteractionResponseMessage, GuildId,
-    Http,
+    self, AutocompleteChoice, CacheHttp, CommandDataOption, CommandDataOptionValue,
+    CommandDataResolved, CommandInteraction, CommandOptionType, ComponentInteraction,
+    ComponentInteractionDataKind, CreateAutocompleteResponse, CreateCommand, CreateCommandOption,
+    CreateInteractionResponse, CreateInteractionResponseFollowup, CreateInteractionResponseMessage,
+    EditInteractionResponse, GuildId, Http, Member, Message, Permissions, Reaction, RoleId, User,
+    UserId,
 };
 use serde::{Deserialize, Serialize};
-use tokio::{
-    sync::{Mutex, Notify, RwLock},
-    time::sleep,
-};
+use serde_json::json;
+use tokio::sync::{Mutex, Notify, RwLock};
 
 use crate::{
     managers::{
         log_manager::{LogManager, LogSource, LogType},
-        lua_manager::serde_and_lua::{lua_to_serde, serde_to_lua},
+        lua_manager::{
+            components::LUA_CUSTOM_ID_PREFIX,
+            globals::{format_print_values, register_globals, LuaGlobals, LuaHost},
+            guild_api::{ActionLimiter, GuildApi},
+            history::LuaCommandVersion,
+            http::{HttpLimiter, LuaHttpClient},
+            limits::{is_memory_limit_error, run_with_limits},
+            lint::{lint_command_code, LintWarning},
+            modules::GuildLuaModules,
+            replies::LuaReply,
+            serde_and_lua::{lua_to_serde, serde_to_lua},
+        },
+        profile_manager::ProfileManager,
     },
-    utils::{IdType, TtlMap, TtlMapWithArcTokioMutex},
+    tokens::LuaLimits,
+    utils::{get_seconds, IdType, TtlMap, TtlMapWithArcTokioMutex},
     Error,
 };
 
-use super::db::SurrealClient;
-
+use super::db::{Database, DatabaseHealth};
+
+pub mod components;
+pub mod globals;
+pub mod guild_api;
+#[cfg(test)]
+mod harness;
+pub mod history;
+pub mod http;
+pub mod limits;
+pub mod lint;
+pub mod manifest;
+pub mod modules;
+pub mod replies;
 pub mod serde_and_lua;
+pub mod stdlib;
+pub mod timers;
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
 pub struct CommandOption {
     #[serde(rename = "type")]
     pub kind: String,
     pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub description: Option<String>,
+    #[serde(default = "required_by_default")]
     pub required: bool,
+    /// Values the user has to pick from. Only for string, integer and number options.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub choices: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_value: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_value: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_length: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_length: Option<u16>,
+    /// Whether the autocomplete function of the command suggests values for this option.
+    #[serde(default)]
+    pub autocomplete: bool,
 }
 
 impl CommandOption {
@@ -57,6 +103,12 @@ impl CommandOption {
             let mut param_type = None;
             let mut description = None;
             let mut required = true;
+            let mut choices = Vec::new();
+            let mut min_value = None;
+            let mut max_value = None;
+            let mut min_length = None;
+            let mut max_length = None;
+            let mut autocomplete = false;
 
             for property in properties {
                 let Some((property_name, property_value)) = property.split_once('=') else {
@@ -67,17 +119,28 @@ impl CommandOption {
                     "name" => param_name = Some(property_value),
                     "type" => param_type = Some(property_value),
                     "description" => description = Some(property_value),
-                    "required" => {
-                        let lower = property_value.to_lowercase();
-                        if lower == "true".to_string() {
-                            required = true;
-                        } else if lower == "false".to_string() {
-                            required = false;
-                        } else {
-                            return Err(
-                                format!("The value for \"required\" must be either \"true\" or \"false\". You provided \"{property_value}\".").into()
-                            );
-                        }
+                    "required" => required = parse_bool_property(property_name, property_value)?,
+                    "choices" => {
+                        choices = property_value
+                            .split(',')
+                            .map(|choice| choice.trim().to_string())
+                            .filter(|choice| !choice.is_empty())
+                            .collect()
+                    }
+                    "min_value" => {
+                        min_value = Some(parse_number_property(property_name, property_value)?)
+                    }
+                    "max_value" => {
+                        max_value = Some(parse_number_property(property_name, property_value)?)
+                    }
+                    "min_length" => {
+                        min_length = Some(parse_number_property(property_name, property_value)?)
+                    }
+                    "max_length" => {
+                        max_length = Some(parse_number_property(property_name, property_value)?)
+                    }
+                    "autocomplete" => {
+                        autocomplete = parse_bool_property(property_name, property_value)?
                     }
                     _ => return Err(format!("\"{property_name}\" is not a valid property.").into()),
                 }
@@ -95,16 +158,224 @@ impl CommandOption {
                 );
             };
 
-            options.push(CommandOption {
+            let option = CommandOption {
                 kind: param_type.to_string(),
                 name: param_name.to_string(),
                 description: description.map(|str| str.to_string()),
                 required,
-            });
+                choices,
+                min_value,
+                max_value,
+                min_length,
+                max_length,
+                autocomplete,
+            };
+            option.to_create_option()?;
+
+            options.push(option);
         }
 
         Ok(options)
     }
+
+    /// Makes the discord option, erroring if a property doesn't work with the type of the option.
+    pub fn to_create_option(&self) -> Result<CreateCommandOption, Error> {
+        let name = &self.name;
+        let kind = get_command_option_type_from_str(&self.kind)?;
+        let description = self.description.clone().unwrap_or(self.name.clone());
+
+        let mut create_option =
+            CreateCommandOption::new(kind, name.clone(), description).required(self.required);
+
+        let is_text = kind == CommandOptionType::String;
+        let is_numeric = kind == CommandOptionType::Integer || kind == CommandOptionType::Number;
+
+        if !self.choices.is_empty() {
+            if !is_text && !is_numeric {
+                return Err(format!("The parameter {name} can't have choices, only string, integer and number parameters can.").into());
+            }
+            if self.autocomplete {
+                return Err(format!(
+                    "The parameter {name} can't have both choices and autocomplete."
+                )
+                .into());
+            }
+            if self.choices.len() > 25 {
+                return Err(
+                    format!("The parameter {name} can't have more than 25 choices.").into(),
+                );
+            }
+
+            for choice in self.choices.iter() {
+                create_option = match kind {
+                    CommandOptionType::Integer => {
+                        let Ok(value) = choice.parse::<i32>() else {
+                            return Err(format!(
+                                "The choice \"{choice}\" of {name} is not a valid integer."
+                            )
+                            .into());
+                        };
+                        create_option.add_int_choice(choice, value)
+                    }
+                    CommandOptionType::Number => {
+                        let Ok(value) = choice.parse::<f64>() else {
+                            return Err(format!(
+                                "The choice \"{choice}\" of {name} is not a valid number."
+                            )
+                            .into());
+                        };
+                        create_option.add_number_choice(choice, value)
+                    }
+                    _ => create_option.add_string_choice(choice, choice),
+                };
+            }
+        }
+
+        if self.autocomplete {
+            if !is_text && !is_numeric {
+                return Err(format!("The parameter {name} can't use autocomplete, only string, integer and number parameters can.").into());
+            }
+            create_option = create_option.set_autocomplete(true);
+        }
+
+        if self.min_value.is_some() || self.max_value.is_some() {
+            if !is_numeric {
+                return Err(format!("The parameter {name} can't have a min or max value, only integer and number parameters can.").into());
+            }
+
+            if let (Some(min_value), Some(max_value)) = (self.min_value, self.max_value) {
+                if min_value > max_value {
+                    return Err(format!(
+                        "The min_value of {name} can't be more than its max_value."
+                    )
+                    .into());
+                }
+            }
+
+            for (property_name, value) in
+                [("min_value", self.min_value), ("max_value", self.max_value)]
+            {
+                let Some(value) = value else {
+                    continue;
+                };
+
+                create_option = match (kind, property_name) {
+                    (CommandOptionType::Integer, _) if value < 0.0 || value.fract() != 0.0 => {
+                        return Err(format!("The {property_name} of {name} must be a whole number that isn't negative.").into());
+                    }
+                    (CommandOptionType::Integer, "min_value") => {
+                        create_option.min_int_value(value as u64)
+                    }
+                    (CommandOptionType::Integer, _) => create_option.max_int_value(value as u64),
+                    (_, "min_value") => create_option.min_number_value(value),
+                    _ => create_option.max_number_value(value),
+                };
+            }
+        }
+
+        if self.min_length.is_some() || self.max_length.is_some() {
+            if !is_text {
+                return Err(format!("The parameter {name} can't have a min or max length, only string parameters can.").into());
+            }
+            if let (Some(min_length), Some(max_length)) = (self.min_length, self.max_length) {
+                if min_length > max_length {
+                    return Err(format!(
+                        "The min_length of {name} can't be more than its max_length."
+                    )
+                    .into());
+                }
+            }
+            if let Some(min_length) = self.min_length {
+                create_option = create_option.min_length(min_length);
+            }
+            if let Some(max_length) = self.max_length {
+                create_option = create_option.max_length(max_length);
+            }
+        }
+
+        Ok(create_option)
+    }
+}
+
+fn required_by_default() -> bool {
+    true
+}
+
+fn parse_bool_property(property_name: &str, property_value: &str) -> Result<bool, Error> {
+    match property_value.to_lowercase().as_str() {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(format!("The value for \"{property_name}\" must be either \"true\" or \"false\". You provided \"{property_value}\".").into()),
+    }
+}
+
+fn parse_number_property<T: std::str::FromStr>(
+    property_name: &str,
+    property_value: &str,
+) -> Result<T, Error> {
+    property_value.parse::<T>().map_err(|_| {
+        format!("The value for \"{property_name}\" must be a number. You provided \"{property_value}\".").into()
+    })
+}
+
+/// A subcommand of a custom command, used like `/c <command> <subcommand>`.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct LuaSubcommand {
+    pub name: String,
+    pub description: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub options: Vec<CommandOption>,
+}
+
+impl LuaSubcommand {
+    pub fn to_create_option(&self) -> Result<CreateCommandOption, Error> {
+        let mut sub_command = CreateCommandOption::new(
+            CommandOptionType::SubCommand,
+            self.name.clone(),
+            self.description.clone(),
+        );
+
+        for option in self.options.iter() {
+            sub_command = sub_command.add_sub_option(option.to_create_option()?);
+        }
+
+        Ok(sub_command)
+    }
+}
+
+/// What the code of a command evaluates to.
+///
+/// Either the function that runs the command, or a table like `{ run = function(ctx, args) end, autocomplete = function(option, partial, args) end }`.
+///
+/// For commands with subcommands, the name of the subcommand that was used is given after the args.
+///
+/// The table can also have `component = function(ctx, event) end`, which handles the components with custom ids like `<command>:<id>`.
+#[derive(Clone)]
+pub struct LuaCommandFunctions {
+    pub run: Function,
+    pub autocomplete: Option<Function>,
+    pub component: Option<Function>,
+}
+
+impl FromLua for LuaCommandFunctions {
+    fn from_lua(value: mlua::Value, _lua: &Lua) -> mlua::Result<Self> {
+        match value {
+            mlua::Value::Function(run) => Ok(Self {
+                run,
+                autocomplete: None,
+                component: None,
+            }),
+            mlua::Value::Table(table) => Ok(Self {
+                run: table.get::<Function>("run")?,
+                autocomplete: table.get::<Option<Function>>("autocomplete")?,
+                component: table.get::<Option<Function>>("component")?,
+            }),
+            _ => Err(mlua::Error::runtime(
+                "A command must return a function, or a table with a run function.",
+            )),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -113,17 +384,169 @@ pub struct LuaCommandInfo {
     pub filename: String,
     pub description: String,
     pub options: Vec<CommandOption>,
+    /// Permissions a member needs to run the command.
+    #[serde(default)]
+    pub default_member_permissions: Option<Permissions>,
+    /// A member needs at least one of these roles to run the command. Anyone can run it if it's empty.
+    #[serde(default)]
+    pub required_roles: Vec<RoleId>,
+    /// Makes the command a group used like `/c <command> <subcommand>`, the options go on the subcommands instead.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub subcommands: Vec<LuaSubcommand>,
+}
+
+impl LuaCommandInfo {
+    /// Parses a comma separated list of permission names, like "MANAGE_MESSAGES, KICK_MEMBERS".
+    /// "none" means no permissions are needed.
+    pub fn parse_permissions(string: &str) -> Result<Option<Permissions>, Error> {
+        if string.trim().eq_ignore_ascii_case("none") {
+            return Ok(None);
+        }
+
+        let mut permissions = Permissions::empty();
+        for name in string.split(',') {
+            let name = name.trim().to_uppercase().replace(' ', "_");
+            if name.is_empty() {
+                continue;
+            }
+
+            let Some(permission) = Permissions::from_name(&name) else {
+                return Err(format!("\"{name}\" is not a valid permission.").into());
+            };
+            permissions |= permission;
+        }
+
+        if permissions.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(permissions))
+    }
+
+    /// Parses a list of role mentions or role ids separated by commas or spaces.
+    /// "none" means no roles are needed.
+    pub fn parse_roles(string: &str) -> Result<Vec<RoleId>, Error> {
+        if string.trim().eq_ignore_ascii_case("none") {
+            return Ok(vec![]);
+        }
+
+        let mut roles = Vec::new();
+        for role in string.split([',', ' ']) {
+            let role = role.trim();
+            if role.is_empty() {
+                continue;
+            }
+
+            let id = role
+                .strip_prefix("<@&")
+                .and_then(|role| role.strip_suffix('>'))
+                .unwrap_or(role);
+
+            let Ok(role_id) = id.parse::<RoleId>() else {
+                return Err(format!("\"{role}\" is not a valid role.").into());
+            };
+
+            if !roles.contains(&role_id) {
+                roles.push(role_id);
+            }
+        }
+
+        Ok(roles)
+    }
+
+    /// Checks if a member is allowed to run the command. Returns the reason if they're not.
+    ///
+    /// All custom commands are subcommands of the same guild command, so discord can't do this for us.
+    pub fn check_member(&self, member: Option<&Member>) -> Result<(), String> {
+        let Some(member) = member else {
+            return Err("Custom commands can only be used in guilds.".to_string());
+        };
+
+        if let Some(required_permissions) = self.default_member_permissions {
+            let member_permissions = member.permissions.unwrap_or_default();
+            if !member_permissions.administrator()
+                && !member_permissions.contains(required_permissions)
+            {
+                return Err(format!(
+                    "You need these permissions to use this command: {}",
+                    required_permissions.get_permission_names().join(", ")
+                ));
+            }
+        }
+
+        if !self.required_roles.is_empty()
+            && !member
+                .roles
+                .iter()
+                .any(|role_id| self.required_roles.contains(role_id))
+        {
+            let roles = self
+                .required_roles
+                .iter()
+                .map(|role_id| format!("<@&{role_id}>"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            return Err(format!(
+                "You need one of these roles to use this command: {roles}"
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Gateway events that guilds can handle with lua code.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, poise::ChoiceParameter)]
+#[serde(rename_all = "snake_case")]
+pub enum LuaEvent {
+    #[name = "Message"]
+    Message,
+    #[name = "Member join"]
+    MemberJoin,
+    #[name = "Member leave"]
+    MemberLeave,
+    #[name = "Reaction add"]
+    ReactionAdd,
+    #[name = "Reaction remove"]
+    ReactionRemove,
+    /// A timer made with the `timers` global is due.
+    #[name = "Timer"]
+    Timer,
+    /// A button or select menu sent by lua code was used, and no command handles it. The handler gets the ctx to respond with, and the event.
+    #[name = "Component"]
+    Component,
+}
+
+impl LuaEvent {
+    /// The name used to store the event handler.
+    pub fn to_str(&self) -> &'static str {
+        match self {
+            LuaEvent::Message => "message",
+            LuaEvent::MemberJoin => "member_join",
+            LuaEvent::MemberLeave => "member_leave",
+            LuaEvent::ReactionAdd => "reaction_add",
+            LuaEvent::ReactionRemove => "reaction_remove",
+            LuaEvent::Timer => "timer",
+            LuaEvent::Component => "component",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LuaEventInfo {
+    pub lua_code: String,
+    pub filename: String,
 }
 
 pub struct DataStore {
     guild_id: GuildId,
     data_store_name: String,
-    db: Arc<SurrealClient>,
+    db: Arc<dyn Database>,
     pub data: Option<HashMap<String, serde_json::Value>>,
 }
 
 impl DataStore {
-    pub fn new(guild_id: GuildId, data_store_name: String, db: Arc<SurrealClient>) -> Self {
+    pub fn new(guild_id: GuildId, data_store_name: String, db: Arc<dyn Database>) -> Self {
         Self {
             guild_id,
             data_store_name,
@@ -167,6 +590,65 @@ impl DataStore {
 
         Ok(data.insert(key, value).unwrap_or(serde_json::Value::Null))
     }
+
+    pub async fn remove(&mut self, key: &str) -> Result<serde_json::Value, Error> {
+        let guild_id = self.guild_id;
+        let data_store_name = self.data_store_name.clone();
+        let db = self.db.clone();
+        let data = self.get_data().await?;
+        db.remove_data_store_value(guild_id, &data_store_name, key)
+            .await?;
+
+        Ok(data.remove(key).unwrap_or(serde_json::Value::Null))
+    }
+
+    pub async fn keys(&mut self) -> Result<Vec<String>, Error> {
+        let mut keys = self.get_data().await?.keys().cloned().collect::<Vec<_>>();
+        keys.sort();
+        Ok(keys)
+    }
+
+    pub async fn increment(&mut self, key: String, amount: f64) -> Result<f64, Error> {
+        let guild_id = self.guild_id;
+        let data_store_name = self.data_store_name.clone();
+        let db = self.db.clone();
+        let data = self.get_data().await?;
+        let new_value = db
+            .increment_data_store_value(guild_id, &data_store_name, &key, amount)
+            .await?;
+
+        data.insert(key, new_value.into());
+        Ok(new_value)
+    }
+
+    /// Sets the value only if it's still `expected`. Returns whether it was set.
+    ///
+    /// If it wasn't, the data gets fetched again, since something else changed it.
+    pub async fn compare_and_set(
+        &mut self,
+        key: String,
+        expected: &serde_json::Value,
+        value: serde_json::Value,
+    ) -> Result<bool, Error> {
+        let was_set = self
+            .db
+            .compare_and_set_data_store_value(
+                self.guild_id,
+                &self.data_store_name,
+                &key,
+                expected,
+                &value,
+            )
+            .await?;
+
+        if was_set {
+            self.get_data().await?.insert(key, value);
+        } else {
+            self.data = None;
+        }
+
+        Ok(was_set)
+    }
 }
 
 pub struct DataStoreWrapper(Arc<Mutex<DataStore>>);
@@ -218,6 +700,76 @@ impl UserData for DataStoreWrapper {
                 Ok(previous_lua_value)
             },
         );
+
+        methods.add_async_method("remove", async |lua, this, key: String| {
+            let mut lock = this.lock().await;
+
+            let previous_serde_value = lock
+                .remove(&key)
+                .await
+                .map_err(|err| mlua::Error::runtime(err))?;
+
+            let previous_lua_value = serde_to_lua(previous_serde_value, &lua)?;
+
+            Ok(previous_lua_value)
+        });
+
+        methods.add_async_method("keys", async |_lua, this, ()| {
+            let mut lock = this.lock().await;
+
+            lock.keys().await.map_err(|err| mlua::Error::runtime(err))
+        });
+
+        methods.add_async_method(
+            "increment",
+            async |_lua, this, (key, amount): (String, Option<f64>)| {
+                let mut lock = this.lock().await;
+
+                lock.increment(key, amount.unwrap_or(1.0))
+                    .await
+                    .map_err(|err| mlua::Error::runtime(err))
+            },
+        );
+
+        // Calls the function with the current value and stores what it returns.
+        // If the value was changed by something else in the meantime, it tries again with the new value.
+        methods.add_async_method(
+            "update",
+            async |lua, this, (key, function): (String, Function)| {
+                const MAX_ATTEMPTS: usize = 5;
+
+                for _ in 0..MAX_ATTEMPTS {
+                    let current_serde_value = {
+                        let mut lock = this.lock().await;
+                        lock.get(&key)
+                            .await
+                            .map_err(|err| mlua::Error::runtime(err))?
+                    };
+
+                    // The lock isn't held while the function runs, since it could use the data store itself.
+                    let new_lua_value = function
+                        .call_async::<mlua::Value>(serde_to_lua(current_serde_value.clone(), &lua)?)
+                        .await?;
+                    let new_serde_value =
+                        lua_to_serde(new_lua_value).map_err(|err| mlua::Error::runtime(err))?;
+
+                    let mut lock = this.lock().await;
+
+                    let was_set = lock
+                        .compare_and_set(key.clone(), &current_serde_value, new_serde_value.clone())
+                        .await
+                        .map_err(|err| mlua::Error::runtime(err))?;
+
+                    if was_set {
+                        return serde_to_lua(new_serde_value, &lua);
+                    }
+                }
+
+                Err(mlua::Error::runtime(format!(
+                    "Couldn't update \"{key}\" because it kept being changed by something else."
+                )))
+            },
+        );
     }
 }
 
@@ -227,24 +779,28 @@ pub struct GuildLuaData {
     stop_notify: Arc<Notify>,
     stop_bool: Arc<AtomicBool>,
     lua_manager: Weak<LuaManager>,
-    log_manager: Arc<LogManager>,
-    pub commands: Option<HashMap<String, (LuaCommandInfo, Option<Function>)>>,
+    action_limiter: Arc<std::sync::Mutex<ActionLimiter>>,
+    http_limiter: Arc<std::sync::Mutex<HttpLimiter>>,
+    limits: LuaLimits,
+    pub modules: GuildLuaModules,
+    pub commands: Option<HashMap<String, (LuaCommandInfo, Option<LuaCommandFunctions>)>>,
+    pub events: Option<HashMap<LuaEvent, (LuaEventInfo, Option<Function>)>>,
 }
 
 impl GuildLuaData {
-    pub fn new(
-        guild_id: GuildId,
-        lua_manager: Arc<LuaManager>,
-        log_manager: Arc<LogManager>,
-    ) -> Self {
+    pub fn new(guild_id: GuildId, lua_manager: Arc<LuaManager>) -> Self {
         Self {
             lua: None,
             guild_id,
             stop_notify: Arc::new(Notify::new()),
             stop_bool: Arc::new(AtomicBool::new(false)),
             lua_manager: Arc::downgrade(&lua_manager),
-            log_manager,
+            action_limiter: Arc::new(std::sync::Mutex::new(ActionLimiter::new())),
+            http_limiter: Arc::new(std::sync::Mutex::new(HttpLimiter::new())),
+            limits: lua_manager.limits,
+            modules: Arc::new(Mutex::new(None)),
             commands: None,
+            events: None,
         }
     }
 
@@ -255,6 +811,11 @@ impl GuildLuaData {
                 *function = None
             }
         }
+        if let Some(events) = self.events.as_mut() {
+            for (_, (_, function)) in events.iter_mut() {
+                *function = None
+            }
+        }
 
         if force_quit {
             self.stop_bool.store(true, Ordering::Relaxed);
@@ -263,98 +824,52 @@ impl GuildLuaData {
         }
     }
 
+    /// Restarts the lua instance if the lua code ran out of memory, since whatever filled it up would make everything after fail too.
+    pub fn restart_after_memory_error<T>(&mut self, result: Result<T, Error>) -> Result<T, Error> {
+        match result {
+            Err(err) if is_memory_limit_error(&err) => {
+                self.restart(false);
+                Err(
+                    format!("{err} The Lua instance has been restarted to free up its memory.")
+                        .into(),
+                )
+            }
+            result => result,
+        }
+    }
+
     pub fn get_lua(&mut self) -> Result<Lua, mlua::Error> {
         if let Some(lua) = &self.lua {
             return Ok(lua.clone());
         };
 
-        let lua = Lua::new();
-
-        let stop_bool = self.stop_bool.clone();
-        let count = Arc::new(AtomicU64::new(0));
-
-        let yield_frequency = 50;
-
-        lua.set_interrupt(move |_lua| {
-            if stop_bool.load(Ordering::SeqCst)
-                || count.fetch_add(1, Ordering::Relaxed) % yield_frequency == 0
-            {
-                Ok(VmState::Yield)
-            } else {
-                Ok(VmState::Continue)
-            }
-        });
-
-        // For debug purposes only.
-        lua.globals().set(
-            "wait",
-            lua.create_async_function(async |_lua, wait: f32| {
-                sleep(Duration::from_secs_f32(wait)).await;
-                Ok(())
-            })?,
-        )?;
-
-        let guild_id = self.guild_id;
-        let log_manager = self.log_manager.clone();
-
-        lua.globals().set(
-            "print",
-            lua.create_async_function(move |_lua, value| {
-                let log_manager = log_manager.clone();
-                async move {
-                    let _ = log_manager
-                        .add_log(
-                            IdType::GuildId(guild_id),
-                            value,
-                            LogType::Info,
-                            LogSource::Lua,
-                        )
-                        .await;
-                    Ok(())
-                }
-            })?,
-        )?;
-
-        let log_manager = self.log_manager.clone();
-
-        lua.globals().set(
-            "warn",
-            lua.create_async_function(move |_lua, value| {
-                let log_manager = log_manager.clone();
-                async move {
-                    let _ = log_manager
-                        .add_log(
-                            IdType::GuildId(guild_id),
-                            value,
-                            LogType::Warning,
-                            LogSource::Lua,
-                        )
-                        .await;
-                    Ok(())
-                }
-            })?,
-        )?;
-
-        let lua_manager = self.lua_manager.clone();
-
-        lua.globals().set(
-            "get_data_store",
-            lua.create_async_function(move |_lua, data_store_name| {
-                let lua_manager = lua_manager.clone();
-                async move {
-                    let Some(lua_manager) = lua_manager.upgrade() else {
-                        return Err(mlua::Error::runtime("Failed to get LuaManager."));
-                    };
+        let Some(lua_manager) = self.lua_manager.upgrade() else {
+            return Err(mlua::Error::runtime("Failed to get LuaManager."));
+        };
 
-                    Ok(DataStoreWrapper::from(
-                        lua_manager.get_data_store(guild_id, data_store_name).await,
-                    ))
-                }
-            })?,
+        let lua = create_limited_lua(&self.limits, self.stop_bool.clone())?;
+
+        register_globals(
+            &lua,
+            LuaGlobals {
+                guild_id: self.guild_id,
+                host: self.lua_manager.clone(),
+                db: lua_manager.db.clone(),
+                modules: self.modules.clone(),
+                profile_manager: lua_manager.profile_manager.clone(),
+                timer_wait_until: lua_manager.timer_wait_until.clone(),
+                guild_api: GuildApi::new(
+                    self.guild_id,
+                    Some(lua_manager.arc_ctx.clone()),
+                    self.action_limiter.clone(),
+                ),
+                http: lua_manager
+                    .http_client
+                    .clone()
+                    .map(|http_client| (http_client, self.http_limiter.clone())),
+            },
         )?;
 
-        lua.sandbox(true)?;
-
         self.lua = Some(lua.clone());
 
         Ok(lua)
@@ -362,8 +877,8 @@ impl GuildLuaData {
 
     pub async fn get_commands(
         &mut self,
-        db: &SurrealClient,
-    ) -> Result<&mut HashMap<String, (LuaCommandInfo, Option<Function>)>, Error> {
+        db: &dyn Database,
+    ) -> Result<&mut HashMap<String, (LuaCommandInfo, Option<LuaCommandFunctions>)>, Error> {
         let commands_mut = &mut self.commands;
         match commands_mut {
             Some(commands) => return Ok(commands),
@@ -380,35 +895,100 @@ impl GuildLuaData {
         }
     }
 
+    /// Adds or replaces a command and saves it as a new version in the command's history.
     pub async fn add_or_replace_command(
         &mut self,
         command_name: String,
         lua_command_info: LuaCommandInfo,
-        db: &SurrealClient,
+        author: UserId,
+        db: &dyn Database,
     ) -> Result<(), Error> {
         let guild_id = self.guild_id;
         let commands = self.get_commands(db).await?;
+
+        // The version is saved first, so a saved command always has its newest code in the history.
+        let history = db
+            .get_guild_lua_command_history(guild_id, &command_name)
+            .await?;
+        let mut version = history.first().map_or(1, |newest| newest.version + 1);
+
+        // Commands made before the history existed don't have any versions, so their current code is saved first to not lose it.
+        if history.is_empty() {
+            if let Some((current_command_info, _)) = commands.get(&command_name) {
+                let current_version = LuaCommandVersion {
+                    version,
+                    author: None,
+                    timestamp: get_seconds(),
+                    command_info: current_command_info.clone(),
+                };
+                db.add_guild_lua_command_version(guild_id, &command_name, &current_version)
+                    .await?;
+                version += 1;
+            }
+        }
+
+        let command_version = LuaCommandVersion {
+            version,
+            author: Some(author),
+            timestamp: get_seconds(),
+            command_info: lua_command_info.clone(),
+        };
+        db.add_guild_lua_command_version(guild_id, &command_name, &command_version)
+            .await?;
+
         db.add_guild_lua_command(&command_name, &lua_command_info, guild_id)
             .await?;
         commands.insert(command_name, (lua_command_info, None));
         Ok(())
     }
 
-    pub async fn delete_command(
+    /// Restores an older version of a command.
+    ///
+    /// The restored version is saved as a new version, so the rollback itself can be undone.
+    pub async fn rollback_command(
         &mut self,
         command_name: String,
-        db: &SurrealClient,
+        version: u32,
+        author: UserId,
+        db: &dyn Database,
     ) -> Result<(), Error> {
         let guild_id = self.guild_id;
         let commands = self.get_commands(db).await?;
-        db.remove_guild_lua_command(guild_id, &command_name).await?;
-        commands.remove(&command_name);
-        Ok(())
+
+        if !commands.contains_key(&command_name) {
+            return Err(format!("A command with the name {command_name} doesn't exists.").into());
+        }
+
+        let history = db
+            .get_guild_lua_command_history(guild_id, &command_name)
+            .await?;
+
+        let Some(command_version) = history
+            .into_iter()
+            .find(|command_version| command_version.version == version)
+        else {
+            return Err(format!("Version {version} of {command_name} doesn't exist. Use `/lua command history` to see the saved versions.").into());
+        };
+
+        self.add_or_replace_command(command_name, command_version.command_info, author, db)
+            .await
     }
 
-    pub async fn update_guild_commands(
+    pub async fn delete_command(
+        &mut self,
+        command_name: String,
+        db: &dyn Database,
+    ) -> Result<(), Error> {
+        let guild_id = self.guild_id;
+        let commands = self.get_commands(db).await?;
+        db.remove_guild_lua_command(guild_id, &command_name).await?;
+        commands.remove(&command_name);
+        Ok(())
+    }
+
+    pub async fn update_guild_commands(
         &mut self,
-        db: &SurrealClient,
+        db: &dyn Database,
         http: &Http,
     ) -> Result<(), Error> {
         let guild_id = self.guild_id;
@@ -423,6 +1003,22 @@ impl GuildLuaData {
 
         let mut create_command = CreateCommand::new("c").description("Custom commands");
         for (command_name, (command_info, _)) in commands {
+            if !command_info.subcommands.is_empty() {
+                let mut sub_command_group = CreateCommandOption::new(
+                    CommandOptionType::SubCommandGroup,
+                    command_name,
+                    command_info.description.clone(),
+                );
+
+                for subcommand in command_info.subcommands.iter() {
+                    sub_command_group =
+                        sub_command_group.add_sub_option(subcommand.to_create_option()?);
+                }
+
+                create_command = create_command.add_option(sub_command_group);
+                continue;
+            }
+
             let mut sub_command = CreateCommandOption::new(
                 CommandOptionType::SubCommand,
                 command_name,
@@ -430,16 +1026,7 @@ impl GuildLuaData {
             );
 
             for option in command_info.options.iter() {
-                let description = option.description.clone().unwrap_or(option.name.clone());
-
-                sub_command = sub_command.add_sub_option(
-                    CreateCommandOption::new(
-                        get_command_option_type_from_str(&option.kind)?,
-                        option.name.clone(),
-                        description,
-                    )
-                    .required(option.required),
-                );
+                sub_command = sub_command.add_sub_option(option.to_create_option()?);
             }
 
             create_command = create_command.add_option(sub_command);
@@ -453,9 +1040,10 @@ impl GuildLuaData {
     pub async fn get_command_function(
         &mut self,
         command_name: &str,
-        db: &SurrealClient,
-    ) -> Result<(Function, Lua, Arc<Notify>), Error> {
+        db: &dyn Database,
+    ) -> Result<(LuaCommandFunctions, Lua, Arc<Notify>), Error> {
         let lua = self.get_lua()?;
+        let limits = self.limits;
 
         let commands = self.get_commands(db).await?;
 
@@ -463,28 +1051,140 @@ impl GuildLuaData {
             return Err(format!("No command with name: {command_name}").into());
         };
 
-        if let Some(function) = command_function {
-            return Ok((function.clone(), lua, self.stop_notify.clone()));
+        if let Some(functions) = command_function {
+            return Ok((functions.clone(), lua, self.stop_notify.clone()));
+        };
+
+        let result = run_with_limits(
+            &limits,
+            lua.load(&command_info.lua_code)
+                .set_name(format!(
+                    "={} (Command: {command_name})",
+                    command_info.filename
+                ))
+                .eval_async(),
+        )
+        .await;
+
+        let functions: LuaCommandFunctions = match result {
+            Ok(functions) => functions,
+            Err(err) => return self.restart_after_memory_error(Err(err)),
+        };
+
+        *command_function = Some(functions.clone());
+
+        return Ok((functions, lua, self.stop_notify.clone()));
+    }
+
+    pub async fn get_events(
+        &mut self,
+        db: &dyn Database,
+    ) -> Result<&mut HashMap<LuaEvent, (LuaEventInfo, Option<Function>)>, Error> {
+        if self.events.is_none() {
+            let fetched_events = db.get_all_guild_lua_events(self.guild_id).await?;
+            let mapped_events = fetched_events
+                .into_iter()
+                .map(|(k, v)| (k, (v, None)))
+                .collect();
+
+            self.events = Some(mapped_events);
+            self.update_handled_events();
+        }
+
+        Ok(self.events.as_mut().unwrap())
+    }
+
+    /// Lets the lua manager know which events this guild handles. See `LuaManager::handled_events`.
+    fn update_handled_events(&self) {
+        let (Some(lua_manager), Some(events)) = (self.lua_manager.upgrade(), self.events.as_ref())
+        else {
+            return;
         };
 
-        let function: Function = lua
-            .load(&command_info.lua_code)
-            .set_name(format!(
-                "={} (Command: {command_name})",
-                command_info.filename
-            ))
-            .eval_async()
+        lua_manager
+            .handled_events
+            .write()
+            .unwrap()
+            .insert(self.guild_id, events.keys().copied().collect());
+    }
+
+    pub async fn set_event(
+        &mut self,
+        event: LuaEvent,
+        lua_event_info: LuaEventInfo,
+        db: &dyn Database,
+    ) -> Result<(), Error> {
+        let guild_id = self.guild_id;
+        let events = self.get_events(db).await?;
+        db.set_guild_lua_event(guild_id, event, &lua_event_info)
             .await?;
+        events.insert(event, (lua_event_info, None));
+        self.update_handled_events();
+        Ok(())
+    }
+
+    pub async fn remove_event(&mut self, event: LuaEvent, db: &dyn Database) -> Result<(), Error> {
+        let guild_id = self.guild_id;
+        let events = self.get_events(db).await?;
+        db.remove_guild_lua_event(guild_id, event).await?;
+        events.remove(&event);
+        self.update_handled_events();
+        Ok(())
+    }
+
+    /// Returns None if the guild doesn't handle the event.
+    ///
+    /// The lua instance only gets created if there is a handler, so guilds that don't use events don't get one.
+    pub async fn get_event_function(
+        &mut self,
+        event: LuaEvent,
+        db: &dyn Database,
+    ) -> Result<Option<(Function, Lua, Arc<Notify>)>, Error> {
+        if !self.get_events(db).await?.contains_key(&event) {
+            return Ok(None);
+        }
+
+        let lua = self.get_lua()?;
+        let stop_notify = self.stop_notify.clone();
+        let limits = self.limits;
+
+        let events = self.get_events(db).await?;
+
+        let Some((event_info, event_function)) = events.get_mut(&event) else {
+            return Ok(None);
+        };
+
+        if let Some(function) = event_function {
+            return Ok(Some((function.clone(), lua, stop_notify)));
+        };
 
-        *command_function = Some(function.clone());
+        let result = run_with_limits(
+            &limits,
+            lua.load(&event_info.lua_code)
+                .set_name(format!(
+                    "={} (Event: {})",
+                    event_info.filename,
+                    event.to_str()
+                ))
+                .eval_async(),
+        )
+        .await;
+
+        let function: Function = match result {
+            Ok(function) => function,
+            Err(err) => return self.restart_after_memory_error(Err(err)),
+        };
+
+        *event_function = Some(function.clone());
 
-        return Ok((function, lua, self.stop_notify.clone()));
+        Ok(Some((function, lua, stop_notify)))
     }
 }
 
 pub struct LuaManager {
-    db: Arc<SurrealClient>,
+    db: Arc<dyn Database>,
     log_manager: Arc<LogManager>,
+    profile_manager: Arc<ProfileManager>,
     /// Holds data such as the guild commands and the lua instance and functions.
     ///
     /// GuildLuaData is inside of an Arc so that the RwLock gets locked as little as possible.
@@ -495,11 +1195,253 @@ pub struct LuaManager {
     /// and something still has an Arc from that entry and end up doing things that wont be properly saved.
     pub guild_data: RwLock<TtlMap<GuildId, Arc<Mutex<GuildLuaData>>>>,
     pub data_stores: RwLock<HashMap<GuildId, Mutex<TtlMapWithArcTokioMutex<String, DataStore>>>>,
+    /// The events each guild has a handler for, so events nothing handles don't spawn a task.
+    /// Guilds that haven't had their handlers loaded yet aren't in here.
+    pub handled_events: std::sync::RwLock<HashMap<GuildId, HashSet<LuaEvent>>>,
+    /// When the next lua timer is due. See `timers::lua_timer_loop`.
+    pub timer_wait_until: Arc<Mutex<u64>>,
+    limits: LuaLimits,
+    /// None if the bot owner hasn't allowed lua code to make http requests.
+    http_client: Option<Arc<LuaHttpClient>>,
     arc_ctx: Arc<serenity_prelude::Context>,
 }
 
-pub fn response(content: String) -> CreateInteractionResponse {
-    CreateInteractionResponse::Message(CreateInteractionResponseMessage::new().content(content))
+/// What a snippet run with `LuaManager::eval` printed and returned.
+pub struct LuaEvalOutput {
+    /// What the snippet printed or warned, in order. Cut off with "...output truncated" if it printed too much.
+    pub output: Vec<String>,
+    /// The values the snippet returned, or the error it ran into.
+    pub result: Result<Vec<String>, String>,
+}
+
+/// How much of what a snippet run with `LuaManager::eval` prints gets kept. `/lua eval` only shows the start of it anyway.
+const MAX_EVAL_OUTPUT_BYTES: usize = 4000;
+
+/// Collects what a snippet prints, and stops once it has printed `MAX_EVAL_OUTPUT_BYTES`.
+#[derive(Default)]
+struct EvalOutputCapture {
+    lines: Vec<String>,
+    bytes: usize,
+    truncated: bool,
+}
+
+impl EvalOutputCapture {
+    fn push(&mut self, line: String) {
+        if self.truncated {
+            return;
+        }
+
+        // Every line counts as at least one byte, so empty lines can't be printed forever either.
+        self.bytes += line.len() + 1;
+        if self.bytes > MAX_EVAL_OUTPUT_BYTES {
+            self.truncated = true;
+            self.lines.push("...output truncated".to_string());
+            return;
+        }
+
+        self.lines.push(line);
+    }
+}
+
+/// Formats a value for `/lua eval`, showing tables as json when possible.
+fn format_eval_value(value: &mlua::Value) -> String {
+    if let mlua::Value::Table(_) = value {
+        if let Ok(serde_value) = lua_to_serde(value.clone()) {
+            if let Ok(json) = serde_json::to_string_pretty(&serde_value) {
+                return json;
+            }
+        }
+    }
+
+    value
+        .to_string()
+        .unwrap_or_else(|_| value.type_name().to_string())
+}
+
+fn user_event_data(user: &User) -> serde_json::Value {
+    json!({
+        "id": user.id.to_string(),
+        "name": user.name,
+        "display_name": user.display_name(),
+        "bot": user.bot,
+    })
+}
+
+fn resolved_user_data(
+    user_id: UserId,
+    resolved: &CommandDataResolved,
+) -> Result<serde_json::Value, Error> {
+    let Some(user) = resolved.users.get(&user_id) else {
+        return Err("The user of an option wasn't resolved.".into());
+    };
+
+    let mut data = user_event_data(user);
+    // Only there when the user is in the guild.
+    if let Some(member) = resolved.members.get(&user_id) {
+        data["member"] = json!({
+            "nick": member.nick,
+            "roles": member.roles.iter().map(RoleId::to_string).collect::<Vec<_>>(),
+            "joined_at": member.joined_at.map(|joined_at| joined_at.unix_timestamp()),
+        });
+    }
+
+    Ok(data)
+}
+
+fn resolved_role_data(
+    role_id: RoleId,
+    resolved: &CommandDataResolved,
+) -> Result<serde_json::Value, Error> {
+    let Some(role) = resolved.roles.get(&role_id) else {
+        return Err("The role of an option wasn't resolved.".into());
+    };
+
+    Ok(json!({
+        "id": role.id.to_string(),
+        "name": role.name,
+        "color": role.colour.0,
+        "position": role.position,
+        "mentionable": role.mentionable,
+    }))
+}
+
+/// Gets the args a custom command was used with, and the name of the subcommand if the command has subcommands.
+fn get_custom_command_args(
+    value: &CommandDataOptionValue,
+) -> Result<(&[CommandDataOption], Option<&str>), Error> {
+    match value {
+        CommandDataOptionValue::SubCommand(args) => Ok((args.as_slice(), None)),
+        CommandDataOptionValue::SubCommandGroup(sub_commands) => {
+            let [sub_command] = sub_commands.as_slice() else {
+                return Err("Unexpected subcommand group options length.".into());
+            };
+            let CommandDataOptionValue::SubCommand(args) = &sub_command.value else {
+                return Err("Option wasn't a subcommand.".into());
+            };
+            Ok((args.as_slice(), Some(sub_command.name.as_str())))
+        }
+        _ => Err("Option wasn't a subcommand.".into()),
+    }
+}
+
+/// Turns the value of a command option into what lua code gets in its args.
+///
+/// Users, roles, channels and attachments become read only tables, everything else stays a plain value.
+fn command_option_value_to_lua(
+    value: &CommandDataOptionValue,
+    resolved: &CommandDataResolved,
+    lua: &Lua,
+) -> Result<mlua::Value, Error> {
+    let data = match value {
+        CommandDataOptionValue::Boolean(v) => return Ok(mlua::Value::Boolean(*v)),
+        CommandDataOptionValue::Integer(v) => return Ok(mlua::Value::Integer(*v as _)),
+        CommandDataOptionValue::Number(v) => return Ok(mlua::Value::Number(*v)),
+        CommandDataOptionValue::String(v) => return Ok(v.clone().into_lua(lua)?),
+        CommandDataOptionValue::User(user_id) => resolved_user_data(*user_id, resolved)?,
+        CommandDataOptionValue::Role(role_id) => resolved_role_data(*role_id, resolved)?,
+        CommandDataOptionValue::Mentionable(id) => {
+            let user_id = UserId::new(id.get());
+            let (kind, mut data) = if resolved.users.contains_key(&user_id) {
+                ("user", resolved_user_data(user_id, resolved)?)
+            } else {
+                ("role", resolved_role_data(RoleId::new(id.get()), resolved)?)
+            };
+            data["type"] = kind.into();
+            data
+        }
+        CommandDataOptionValue::Channel(channel_id) => {
+            let Some(channel) = resolved.channels.get(channel_id) else {
+                return Err("The channel of an option wasn't resolved.".into());
+            };
+            json!({
+                "id": channel.id.to_string(),
+                "name": channel.name,
+                "type": channel.kind.name(),
+                "parent_id": channel.parent_id.map(|parent_id| parent_id.to_string()),
+            })
+        }
+        CommandDataOptionValue::Attachment(attachment_id) => {
+            let Some(attachment) = resolved.attachments.get(attachment_id) else {
+                return Err("The attachment of an option wasn't resolved.".into());
+            };
+            json!({
+                "id": attachment.id.to_string(),
+                "filename": attachment.filename,
+                "url": attachment.url,
+                "size": attachment.size,
+                "content_type": attachment.content_type,
+            })
+        }
+        _ => return Err("Unsupported command option type.".into()),
+    };
+
+    let table = serde_to_lua(data, lua)?;
+    set_readonly_recursive(&table)?;
+    Ok(table)
+}
+
+/// Autocomplete functions return a list where every choice is a string, a number or a table like `{ name = "Red", value = "red" }`.
+fn parse_autocomplete_choice(choice: mlua::Value) -> Result<AutocompleteChoice, Error> {
+    match choice {
+        mlua::Value::String(value) => {
+            let value = value.to_str()?.to_string();
+            Ok(AutocompleteChoice::new(value.clone(), value))
+        }
+        mlua::Value::Integer(value) => Ok(AutocompleteChoice::new(value.to_string(), value)),
+        mlua::Value::Number(value) => Ok(AutocompleteChoice::new(value.to_string(), value)),
+        mlua::Value::Table(table) => {
+            let name = table.get::<String>("name")?;
+            let value = match table.get::<mlua::Value>("value")? {
+                mlua::Value::Integer(value) => serde_json::Value::from(value),
+                mlua::Value::Number(value) => serde_json::Value::from(value),
+                mlua::Value::String(value) => serde_json::Value::from(value.to_str()?.to_string()),
+                _ => {
+                    return Err(
+                        "The value of an autocomplete choice must be a string or a number.".into(),
+                    )
+                }
+            };
+            Ok(AutocompleteChoice::new(name, value))
+        }
+        _ => Err(
+            "Autocomplete choices must be strings, numbers or tables with a name and value.".into(),
+        ),
+    }
+}
+
+/// Makes a lua value and every table inside of it read only.
+fn set_readonly_recursive(value: &mlua::Value) -> mlua::Result<()> {
+    let mlua::Value::Table(table) = value else {
+        return Ok(());
+    };
+
+    table.for_each::<mlua::Value, mlua::Value>(|_, value| set_readonly_recursive(&value))?;
+    table.set_readonly(true);
+
+    Ok(())
+}
+
+/// Makes a lua instance with the memory limit, that yields often so it can be stopped or run out of CPU time.
+fn create_limited_lua(limits: &LuaLimits, stop_bool: Arc<AtomicBool>) -> mlua::Result<Lua> {
+    let lua = Lua::new();
+
+    lua.set_memory_limit(limits.memory_limit_mb * 1024 * 1024)?;
+
+    let count = Arc::new(AtomicU64::new(0));
+
+    let yield_frequency = 50;
+
+    lua.set_interrupt(move |_lua| {
+        if stop_bool.load(Ordering::SeqCst)
+            || count.fetch_add(1, Ordering::Relaxed) % yield_frequency == 0
+        {
+            Ok(VmState::Yield)
+        } else {
+            Ok(VmState::Continue)
+        }
+    });
+
+    Ok(lua)
 }
 
 pub fn get_command_option_type_from_str(value: &str) -> Result<CommandOptionType, String> {
@@ -508,42 +1450,308 @@ pub fn get_command_option_type_from_str(value: &str) -> Result<CommandOptionType
         "integer" => Ok(CommandOptionType::Integer),
         "number" => Ok(CommandOptionType::Number),
         "string" => Ok(CommandOptionType::String),
+        "user" | "member" => Ok(CommandOptionType::User),
+        "role" => Ok(CommandOptionType::Role),
+        "channel" => Ok(CommandOptionType::Channel),
+        "mentionable" => Ok(CommandOptionType::Mentionable),
+        "attachment" => Ok(CommandOptionType::Attachment),
+        "sub_command" | "subcommand" | "sub_command_group" | "subcommand_group" => Err(format!(
+            "\"{value}\" options are unsupported, add `subcommands` to the manifest of the command instead."
+        )),
         _ => Err(format!("\"{value}\" is not a valid option type.")),
     }
 }
 
-pub struct ContextContainer {
+/// How far along a command is with responding to its interaction.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ResponseState {
+    NotResponded,
+    /// Discord shows that the bot is thinking until a follow-up or an edit is sent.
+    Deferred,
+    Responded,
+}
+
+/// The interaction that lua code is responding to.
+pub enum LuaInteraction {
+    Command(CommandInteraction),
+    Component(ComponentInteraction),
+}
+
+impl LuaInteraction {
+    async fn create_response(
+        &self,
+        http: impl CacheHttp,
+        builder: CreateInteractionResponse,
+    ) -> serenity_prelude::Result<()> {
+        match self {
+            LuaInteraction::Command(interaction) => {
+                interaction.create_response(http, builder).await
+            }
+            LuaInteraction::Component(interaction) => {
+                interaction.create_response(http, builder).await
+            }
+        }
+    }
+
+    async fn create_followup(
+        &self,
+        http: impl CacheHttp,
+        builder: CreateInteractionResponseFollowup,
+    ) -> serenity_prelude::Result<Message> {
+        match self {
+            LuaInteraction::Command(interaction) => {
+                interaction.create_followup(http, builder).await
+            }
+            LuaInteraction::Component(interaction) => {
+                interaction.create_followup(http, builder).await
+            }
+        }
+    }
+
+    async fn edit_response(
+        &self,
+        http: impl CacheHttp,
+        builder: EditInteractionResponse,
+    ) -> serenity_prelude::Result<Message> {
+        match self {
+            LuaInteraction::Command(interaction) => interaction.edit_response(http, builder).await,
+            LuaInteraction::Component(interaction) => {
+                interaction.edit_response(http, builder).await
+            }
+        }
+    }
+}
+
+/// Where the replies that lua code makes through its ctx go.
+///
+/// `InteractionReplies` sends them to discord, while the test harness records them.
+#[async_trait]
+pub trait LuaReplySink: Send + Sync + 'static {
+    /// Whether there is a message that `ctx:update()` can edit, which is only the case for components.
+    fn can_update(&self) -> bool;
+
+    async fn reply(&self, reply: LuaReply) -> Result<(), Error>;
+
+    async fn defer(&self, ephemeral: bool) -> Result<(), Error>;
+
+    async fn update(&self, reply: LuaReply) -> Result<(), Error>;
+
+    /// Returns the id of the followup message.
+    async fn followup(&self, reply: LuaReply) -> Result<String, Error>;
+
+    async fn edit_reply(&self, reply: LuaReply) -> Result<(), Error>;
+}
+
+/// Sends the replies of lua code as responses to the interaction it's handling.
+pub struct InteractionReplies {
     arc_ctx: Arc<serenity_prelude::Context>,
-    command_interaction: CommandInteraction,
-    sent_reply: bool,
+    interaction: LuaInteraction,
+}
+
+#[async_trait]
+impl LuaReplySink for InteractionReplies {
+    fn can_update(&self) -> bool {
+        matches!(self.interaction, LuaInteraction::Component(_))
+    }
+
+    async fn reply(&self, reply: LuaReply) -> Result<(), Error> {
+        self.interaction
+            .create_response(
+                &self.arc_ctx,
+                CreateInteractionResponse::Message(reply.into_response_message()),
+            )
+            .await?;
+        Ok(())
+    }
+
+    // Gives the command up to 15 minutes to reply instead of 3 seconds.
+    async fn defer(&self, ephemeral: bool) -> Result<(), Error> {
+        self.interaction
+            .create_response(
+                &self.arc_ctx,
+                CreateInteractionResponse::Defer(
+                    CreateInteractionResponseMessage::new().ephemeral(ephemeral),
+                ),
+            )
+            .await?;
+        Ok(())
+    }
+
+    // Edits the message the component is on, instead of sending a new one.
+    async fn update(&self, reply: LuaReply) -> Result<(), Error> {
+        self.interaction
+            .create_response(
+                &self.arc_ctx,
+                CreateInteractionResponse::UpdateMessage(reply.into_response_message()),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn followup(&self, reply: LuaReply) -> Result<String, Error> {
+        let message = self
+            .interaction
+            .create_followup(&self.arc_ctx, reply.into_followup())
+            .await?;
+        Ok(message.id.to_string())
+    }
+
+    async fn edit_reply(&self, reply: LuaReply) -> Result<(), Error> {
+        self.interaction
+            .edit_response(&self.arc_ctx, reply.into_edit())
+            .await?;
+        Ok(())
+    }
+}
+
+/// The ctx that commands and component handlers get. Makes sure they respond in an order discord allows.
+pub struct ContextContainer<S> {
+    replies: S,
+    response_state: ResponseState,
+}
+
+impl<S: LuaReplySink> ContextContainer<S> {
+    fn new(replies: S) -> Self {
+        Self {
+            replies,
+            response_state: ResponseState::NotResponded,
+        }
+    }
+
+    fn ensure_responded(&self, method_name: &str) -> mlua::Result<()> {
+        if self.response_state == ResponseState::NotResponded {
+            return Err(mlua::Error::runtime(format!(
+                "ctx:{method_name}() can only be used after ctx:reply() or ctx:defer()."
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn ensure_not_responded(&self, method_name: &str) -> mlua::Result<()> {
+        if self.response_state != ResponseState::NotResponded {
+            return Err(mlua::Error::runtime(format!(
+                "ctx:{method_name}() can only be used once. Use ctx:followup() or ctx:edit_reply() instead."
+            )));
+        }
+
+        Ok(())
+    }
 }
 
-impl UserData for ContextContainer {
+impl<S: LuaReplySink> UserData for ContextContainer<S> {
     fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
-        methods.add_async_method_mut("reply", |_, mut this, content: String| async move {
-            this.command_interaction
-                .create_response(&this.arc_ctx, response(content))
+        methods.add_async_method_mut("reply", |_, mut this, reply: LuaReply| async move {
+            this.ensure_not_responded("reply")?;
+            this.replies
+                .reply(reply)
+                .await
+                .map_err(mlua::Error::external)?;
+            this.response_state = ResponseState::Responded;
+            Ok(())
+        });
+
+        methods.add_async_method_mut("defer", |_, mut this, ephemeral: Option<bool>| async move {
+            this.ensure_not_responded("defer")?;
+            this.replies
+                .defer(ephemeral.unwrap_or(false))
+                .await
+                .map_err(mlua::Error::external)?;
+            this.response_state = ResponseState::Deferred;
+            Ok(())
+        });
+
+        methods.add_async_method_mut("update", |_, mut this, reply: LuaReply| async move {
+            if !this.replies.can_update() {
+                return Err(mlua::Error::runtime(
+                    "ctx:update() can only be used when handling a component.",
+                ));
+            }
+            this.ensure_not_responded("update")?;
+            this.replies
+                .update(reply)
+                .await
+                .map_err(mlua::Error::external)?;
+            this.response_state = ResponseState::Responded;
+            Ok(())
+        });
+
+        methods.add_async_method_mut("followup", |_, mut this, reply: LuaReply| async move {
+            this.ensure_responded("followup")?;
+            let message_id = this
+                .replies
+                .followup(reply)
+                .await
+                .map_err(mlua::Error::external)?;
+            this.response_state = ResponseState::Responded;
+            Ok(message_id)
+        });
+
+        methods.add_async_method_mut("edit_reply", |_, mut this, reply: LuaReply| async move {
+            this.ensure_responded("edit_reply")?;
+            this.replies
+                .edit_reply(reply)
                 .await
-                .map_err(|err| mlua::Error::external(err))?;
-            this.sent_reply = true;
+                .map_err(mlua::Error::external)?;
+            this.response_state = ResponseState::Responded;
             Ok(())
         });
     }
 }
 
+#[async_trait]
+impl LuaHost for LuaManager {
+    async fn log(&self, guild_id: GuildId, log_type: LogType, message: String) {
+        self.log_manager
+            .add_log(IdType::GuildId(guild_id), message, log_type, LogSource::Lua)
+            .await;
+    }
+
+    async fn data_store(
+        &self,
+        guild_id: GuildId,
+        data_store_name: String,
+    ) -> Arc<Mutex<DataStore>> {
+        self.get_data_store(guild_id, data_store_name).await
+    }
+}
+
 impl LuaManager {
     pub fn new(
-        db: Arc<SurrealClient>,
+        db: Arc<dyn Database>,
         log_manager: Arc<LogManager>,
+        profile_manager: Arc<ProfileManager>,
         arc_ctx: Arc<serenity_prelude::Context>,
+        limits: LuaLimits,
+        http_client: Option<Arc<LuaHttpClient>>,
     ) -> Self {
         Self {
             db,
             log_manager,
+            profile_manager,
             arc_ctx,
+            limits,
+            http_client,
             guild_data: RwLock::new(TtlMap::new(Duration::from_secs(60 * 60))),
             data_stores: RwLock::new(HashMap::new()),
+            handled_events: std::sync::RwLock::new(HashMap::new()),
+            timer_wait_until: Arc::new(Mutex::new(0)),
+        }
+    }
+
+    /// Restarts the lua instance of a guild if the lua code ran out of memory. See `GuildLuaData::restart_after_memory_error`.
+    async fn restart_after_memory_error<T>(
+        self: &Arc<Self>,
+        guild_id: GuildId,
+        result: Result<T, Error>,
+    ) -> Result<T, Error> {
+        if !result.as_ref().is_err_and(is_memory_limit_error) {
+            return result;
         }
+
+        let guild_info = self.get_guild_lua_data(guild_id).await;
+        let mut locked_guild_info = guild_info.lock().await;
+        locked_guild_info.restart_after_memory_error(result)
     }
 
     /// NOTE: It is VERY IMPORTANT that you do not store this Arc anywhere for long term use!
@@ -560,11 +1768,7 @@ impl LuaManager {
             return guild_lua_data;
         }
 
-        let guild_lua_data = Arc::new(Mutex::new(GuildLuaData::new(
-            guild_id,
-            self.clone(),
-            self.log_manager.clone(),
-        )));
+        let guild_lua_data = Arc::new(Mutex::new(GuildLuaData::new(guild_id, self.clone())));
 
         guild_data_mut.insert(guild_id, guild_lua_data.clone());
 
@@ -580,7 +1784,7 @@ impl LuaManager {
             map: &mut TtlMapWithArcTokioMutex<String, DataStore>,
             guild_id: GuildId,
             data_store_name: String,
-            db: Arc<SurrealClient>,
+            db: Arc<dyn Database>,
         ) -> Arc<Mutex<DataStore>> {
             if let Some(data_store) = map.get(&data_store_name) {
                 return data_store;
@@ -624,14 +1828,19 @@ impl LuaManager {
     }
 
     /// Registers a command and updates the guild command, but only if the guild hasn't reached the limit or if a command with a similar name doesn't exist.
+    #[allow(clippy::too_many_arguments)]
     pub async fn register_command(
         self: &Arc<Self>,
         guild_id: GuildId,
         command_name: String,
         description: String,
         options: Vec<CommandOption>,
+        subcommands: Vec<LuaSubcommand>,
         lua_code: String,
         filename: String,
+        default_member_permissions: Option<Permissions>,
+        required_roles: Vec<RoleId>,
+        author: UserId,
     ) -> Result<(), Error> {
         let guild_info = self.get_guild_lua_data(guild_id).await;
         let mut locked_guild_info = guild_info.lock().await;
@@ -646,6 +1855,10 @@ impl LuaManager {
             return Err(format!("A command with the name {command_name} already exists. Try updating or removing the command instead.").into());
         }
 
+        if !options.is_empty() && !subcommands.is_empty() {
+            return Err("Commands with subcommands can't have options of their own, give the options to the subcommands instead.".into());
+        }
+
         // Make sure the provided code is valid lua code.
         self.try_parse_code(&lua_code)?;
 
@@ -654,10 +1867,13 @@ impl LuaManager {
             filename,
             description,
             options,
+            default_member_permissions,
+            required_roles,
+            subcommands,
         };
 
         locked_guild_info
-            .add_or_replace_command(command_name, lua_command_info, &self.db)
+            .add_or_replace_command(command_name, lua_command_info, author, &self.db)
             .await?;
         locked_guild_info
             .update_guild_commands(&self.db, self.arc_ctx.http())
@@ -667,13 +1883,18 @@ impl LuaManager {
     }
 
     /// Updates a command and updates the guild command, but only if the command it will update exists.
+    #[allow(clippy::too_many_arguments)]
     pub async fn update_command(
         self: &Arc<Self>,
         guild_id: GuildId,
         command_name: String,
         description: Option<String>,
         options: Option<Vec<CommandOption>>,
+        subcommands: Option<Vec<LuaSubcommand>>,
         lua_code_and_filename: Option<(String, String)>,
+        default_member_permissions: Option<Option<Permissions>>,
+        required_roles: Option<Vec<RoleId>>,
+        author: UserId,
     ) -> Result<(), Error> {
         let guild_info = self.get_guild_lua_data(guild_id).await;
         let mut locked_guild_info = guild_info.lock().await;
@@ -686,8 +1907,16 @@ impl LuaManager {
 
         let description = description.unwrap_or(command.description.clone());
         let options = options.unwrap_or(command.options.clone());
+        let subcommands = subcommands.unwrap_or(command.subcommands.clone());
         let (lua_code, filename) =
             lua_code_and_filename.unwrap_or((command.lua_code.clone(), command.filename.clone()));
+        let default_member_permissions =
+            default_member_permissions.unwrap_or(command.default_member_permissions);
+        let required_roles = required_roles.unwrap_or(command.required_roles.clone());
+
+        if !options.is_empty() && !subcommands.is_empty() {
+            return Err("Commands with subcommands can't have options of their own, give the options to the subcommands instead.".into());
+        }
 
         // Make sure the provided code is valid lua code.
         self.try_parse_code(&lua_code)?;
@@ -697,10 +1926,13 @@ impl LuaManager {
             filename,
             description,
             options,
+            default_member_permissions,
+            required_roles,
+            subcommands,
         };
 
         locked_guild_info
-            .add_or_replace_command(command_name, lua_command_info, &self.db)
+            .add_or_replace_command(command_name, lua_command_info, author, &self.db)
             .await?;
         locked_guild_info
             .update_guild_commands(&self.db, self.arc_ctx.http())
@@ -708,34 +1940,39 @@ impl LuaManager {
         Ok(())
     }
 
-    pub fn try_parse_code(&self, lua_code: &str) -> Result<(), Error> {
-        let lua = Lua::new();
-
-        let chunk = lua.load(lua_code);
-
-        chunk.into_function()?;
-
-        Ok(())
-    }
-
-    /// Deletes a command and updates the guild command, but only if the command it will delete exists.
-    pub async fn delete_command(
+    /// Adds or replaces multiple commands and updates the guild commands once at the end.
+    ///
+    /// Errors without changing anything if the guild would end up with more than 25 commands or if any of the code is invalid.
+    pub async fn import_commands(
         self: &Arc<Self>,
         guild_id: GuildId,
-        command_name: String,
+        lua_commands: HashMap<String, LuaCommandInfo>,
+        author: UserId,
     ) -> Result<(), Error> {
         let guild_info = self.get_guild_lua_data(guild_id).await;
         let mut locked_guild_info = guild_info.lock().await;
 
         let commands = locked_guild_info.get_commands(&self.db).await?;
 
-        if !commands.contains_key(&command_name) {
-            return Err(format!("A command with the name {command_name} doesn't exists. If you can still see the command on discord and it's still there after a refresh, try running the refresh command.").into());
-        };
+        let new_commands_amount = lua_commands
+            .keys()
+            .filter(|command_name| !commands.contains_key(*command_name))
+            .count();
 
-        locked_guild_info
-            .delete_command(command_name, &self.db)
-            .await?;
+        if commands.len() + new_commands_amount > 25 {
+            return Err("You may only have up to 25 custom commands.".into());
+        }
+
+        for (command_name, lua_command_info) in lua_commands.iter() {
+            self.try_parse_code(&lua_command_info.lua_code)
+                .map_err(|err| format!("Command {command_name} has invalid code. {err}"))?;
+        }
+
+        for (command_name, lua_command_info) in lua_commands {
+            locked_guild_info
+                .add_or_replace_command(command_name, lua_command_info, author, &self.db)
+                .await?;
+        }
         locked_guild_info
             .update_guild_commands(&self.db, self.arc_ctx.http())
             .await?;
@@ -743,8 +1980,160 @@ impl LuaManager {
         Ok(())
     }
 
-    pub async fn execute_command(
-        self: &Arc<Self>,
+    /// Gets the saved versions of a command, newest first.
+    pub async fn get_command_history(
+        &self,
+        guild_id: GuildId,
+        command_name: &str,
+    ) -> Result<Vec<LuaCommandVersion>, Error> {
+        self.db
+            .get_guild_lua_command_history(guild_id, command_name)
+            .await
+    }
+
+    /// Restores an older version of a command and updates the guild command. See `GuildLuaData::rollback_command`.
+    pub async fn rollback_command(
+        self: &Arc<Self>,
+        guild_id: GuildId,
+        command_name: String,
+        version: u32,
+        author: UserId,
+    ) -> Result<(), Error> {
+        let guild_info = self.get_guild_lua_data(guild_id).await;
+        let mut locked_guild_info = guild_info.lock().await;
+
+        locked_guild_info
+            .rollback_command(command_name, version, author, &self.db)
+            .await?;
+        locked_guild_info
+            .update_guild_commands(&self.db, self.arc_ctx.http())
+            .await?;
+
+        Ok(())
+    }
+
+    /// Runs a snippet in the lua instance of a guild, like a console would.
+    ///
+    /// Expressions like `1 + 1` give back their value. Prints and warnings are captured instead of being logged,
+    /// and globals the snippet sets only last for that snippet.
+    pub async fn eval(
+        self: &Arc<Self>,
+        guild_id: GuildId,
+        lua_code: &str,
+    ) -> Result<LuaEvalOutput, Error> {
+        let guild_info = self.get_guild_lua_data(guild_id).await;
+        let mut locked_guild_info = guild_info.lock().await;
+        let lua = locked_guild_info.get_lua()?;
+        let notify = locked_guild_info.stop_notify.clone();
+        drop(locked_guild_info);
+        drop(guild_info);
+
+        let output = Arc::new(std::sync::Mutex::new(EvalOutputCapture::default()));
+
+        // The globals are read-only, so the snippet gets its own environment that falls back to them.
+        let environment = lua.create_table()?;
+        for (name, prefix) in [("print", ""), ("warn", "[warn] ")] {
+            let output = output.clone();
+            environment.set(
+                name,
+                lua.create_function(move |_lua, values: Variadic<mlua::Value>| {
+                    if output.lock().unwrap().truncated {
+                        return Ok(());
+                    }
+                    let line = format_print_values(&values)?;
+                    output.lock().unwrap().push(format!("{prefix}{line}"));
+                    Ok(())
+                })?,
+            )?;
+        }
+        let metatable = lua.create_table()?;
+        metatable.set("__index", lua.globals())?;
+        environment.set_metatable(Some(metatable))?;
+
+        // Try it as an expression first, so its value can be shown.
+        let function = match lua
+            .load(format!("return {lua_code}"))
+            .set_name("=eval")
+            .set_environment(environment.clone())
+            .into_function()
+        {
+            Ok(function) => function,
+            Err(_) => match lua
+                .load(lua_code)
+                .set_name("=eval")
+                .set_environment(environment)
+                .into_function()
+            {
+                Ok(function) => function,
+                Err(err) => {
+                    return Ok(LuaEvalOutput {
+                        output: Vec::new(),
+                        result: Err(err.to_string()),
+                    })
+                }
+            },
+        };
+
+        let result = tokio::select! {
+            _ = notify.notified() => {
+                Err(Error::from("Operation cancelled by an admin."))
+            }
+            result = run_with_limits(&self.limits, function.call_async::<mlua::MultiValue>(())) => result,
+        };
+        let result = self.restart_after_memory_error(guild_id, result).await;
+
+        let output = std::mem::take(&mut output.lock().unwrap().lines);
+
+        Ok(LuaEvalOutput {
+            output,
+            result: result
+                .map(|values| values.iter().map(format_eval_value).collect())
+                .map_err(|err| err.to_string()),
+        })
+    }
+
+    /// Looks for mistakes in the code of a command that would only show up once it runs.
+    pub fn lint_command_code(&self, lua_code: &str) -> Vec<LintWarning> {
+        lint_command_code(lua_code, self.http_client.is_some())
+    }
+
+    pub fn try_parse_code(&self, lua_code: &str) -> Result<(), Error> {
+        let lua = Lua::new();
+
+        let chunk = lua.load(lua_code);
+
+        chunk.into_function()?;
+
+        Ok(())
+    }
+
+    /// Deletes a command and updates the guild command, but only if the command it will delete exists.
+    pub async fn delete_command(
+        self: &Arc<Self>,
+        guild_id: GuildId,
+        command_name: String,
+    ) -> Result<(), Error> {
+        let guild_info = self.get_guild_lua_data(guild_id).await;
+        let mut locked_guild_info = guild_info.lock().await;
+
+        let commands = locked_guild_info.get_commands(&self.db).await?;
+
+        if !commands.contains_key(&command_name) {
+            return Err(format!("A command with the name {command_name} doesn't exists. If you can still see the command on discord and it's still there after a refresh, try running the refresh command.").into());
+        };
+
+        locked_guild_info
+            .delete_command(command_name, &self.db)
+            .await?;
+        locked_guild_info
+            .update_guild_commands(&self.db, self.arc_ctx.http())
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn execute_command(
+        self: &Arc<Self>,
         guild_id: GuildId,
         command_interaction: CommandInteraction,
     ) -> Result<bool, Error> {
@@ -759,55 +2148,493 @@ impl LuaManager {
 
         let mut locked_guild_info = guild_info.lock().await;
 
-        let (function, lua, notify) = locked_guild_info
+        let commands = locked_guild_info.get_commands(&self.db).await?;
+        if let Some((command_info, _)) = commands.get(command_name) {
+            if let Err(reason) = command_info.check_member(command_interaction.member.as_deref()) {
+                drop(locked_guild_info);
+                command_interaction
+                    .create_response(
+                        &self.arc_ctx,
+                        CreateInteractionResponse::Message(
+                            CreateInteractionResponseMessage::new()
+                                .content(reason)
+                                .ephemeral(true),
+                        ),
+                    )
+                    .await?;
+                return Ok(true);
+            }
+        }
+
+        let (functions, lua, notify) = locked_guild_info
             .get_command_function(command_name, &self.db)
             .await?;
 
         drop(locked_guild_info);
         drop(guild_info);
 
-        let CommandDataOptionValue::SubCommand(sub_command_args) = &command_option.value else {
-            return Err("Option wasn't a subcommand.".into());
-        };
+        let (sub_command_args, subcommand_name) = get_custom_command_args(&command_option.value)?;
 
         let command_args_lua = lua.create_table()?;
 
         for argument in sub_command_args.iter() {
-            let argument_value = match &argument.value {
-                CommandDataOptionValue::Boolean(v) => mlua::Value::Boolean(*v),
-                CommandDataOptionValue::Integer(v) => mlua::Value::Integer(*v as _),
-                CommandDataOptionValue::Number(v) => mlua::Value::Number(*v),
-                CommandDataOptionValue::String(v) => v.clone().into_lua(&lua)?,
-                _ => return Err("Unsupported command option type.".into()),
-            };
+            let argument_value = command_option_value_to_lua(
+                &argument.value,
+                &command_interaction.data.resolved,
+                &lua,
+            )?;
             command_args_lua.set(argument.name.clone(), argument_value)?;
         }
 
-        let context_container = ContextContainer {
+        let context_container = ContextContainer::new(InteractionReplies {
             arc_ctx: self.arc_ctx.clone(),
-            command_interaction,
-            sent_reply: false,
+            interaction: LuaInteraction::Command(command_interaction),
+        });
+
+        let context_container_userdata = lua.create_userdata(context_container)?;
+
+        let result = tokio::select! {
+            _ = notify.notified() => {
+                Err(Error::from("Operation cancelled by an admin."))
+            }
+            result = run_with_limits(
+                &self.limits,
+                functions.run.call_async::<mlua::Value>((&context_container_userdata, command_args_lua, subcommand_name)),
+            ) => result,
+        };
+        self.restart_after_memory_error(guild_id, result).await?;
+
+        let context_container =
+            context_container_userdata.take::<ContextContainer<InteractionReplies>>()?;
+
+        let response_state = context_container.response_state;
+
+        // Don't leave the command thinking forever if it deferred but never replied.
+        if response_state == ResponseState::Deferred {
+            context_container
+                .replies
+                .interaction
+                .edit_response(
+                    &self.arc_ctx,
+                    EditInteractionResponse::new()
+                        .content("This command has executed, but didn't send a reply."),
+                )
+                .await?;
+        }
+
+        return Ok(response_state != ResponseState::NotResponded);
+    }
+
+    /// Runs the autocomplete function of a command and responds with the choices it returns.
+    pub async fn execute_autocomplete(
+        self: &Arc<Self>,
+        guild_id: GuildId,
+        autocomplete_interaction: CommandInteraction,
+    ) -> Result<(), Error> {
+        let [command_option] = autocomplete_interaction.data.options.as_slice() else {
+            return Err("Unexpected command options length.".into());
+        };
+        let command_name = &command_option.name;
+
+        let (sub_command_args, subcommand_name) = get_custom_command_args(&command_option.value)?;
+
+        let guild_info = self.get_guild_lua_data(guild_id).await;
+
+        let mut locked_guild_info = guild_info.lock().await;
+
+        let commands = locked_guild_info.get_commands(&self.db).await?;
+        let can_use = commands.get(command_name).is_some_and(|(command_info, _)| {
+            command_info
+                .check_member(autocomplete_interaction.member.as_deref())
+                .is_ok()
+        });
+
+        let (functions, lua, notify) = locked_guild_info
+            .get_command_function(command_name, &self.db)
+            .await?;
+
+        drop(locked_guild_info);
+        drop(guild_info);
+
+        let mut choices = Vec::new();
+
+        if let (true, Some(autocomplete_function)) = (can_use, functions.autocomplete) {
+            let mut focused = None;
+            let args_lua = lua.create_table()?;
+
+            for argument in sub_command_args.iter() {
+                match &argument.value {
+                    CommandDataOptionValue::Autocomplete { value, .. } => {
+                        focused = Some((argument.name.clone(), value.clone()));
+                    }
+                    // Ids aren't resolved while autocompleting, so only plain values are given.
+                    value => {
+                        if let Ok(argument_value) = command_option_value_to_lua(
+                            value,
+                            &autocomplete_interaction.data.resolved,
+                            &lua,
+                        ) {
+                            args_lua.set(argument.name.clone(), argument_value)?;
+                        }
+                    }
+                }
+            }
+
+            let Some((option_name, partial)) = focused else {
+                return Err("No option was focused.".into());
+            };
+
+            let result = tokio::select! {
+                _ = notify.notified() => {
+                    Err(Error::from("Operation cancelled by an admin."))
+                }
+                result = run_with_limits(
+                    &self.limits,
+                    autocomplete_function.call_async::<Option<mlua::Table>>((option_name, partial, args_lua, subcommand_name)),
+                ) => result,
+            };
+            let returned_choices = self.restart_after_memory_error(guild_id, result).await?;
+
+            if let Some(returned_choices) = returned_choices {
+                // Discord only shows 25 choices.
+                for choice in returned_choices.sequence_values::<mlua::Value>().take(25) {
+                    choices.push(parse_autocomplete_choice(choice?)?);
+                }
+            }
+        }
+
+        autocomplete_interaction
+            .create_response(
+                &self.arc_ctx,
+                CreateInteractionResponse::Autocomplete(
+                    CreateAutocompleteResponse::new().set_choices(choices),
+                ),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Runs the handler of a button or select menu made by lua code.
+    ///
+    /// Custom ids like `<command>:<id>` go to the component function of the command, if it has one.
+    /// Everything else goes to the component event handler of the guild.
+    pub async fn execute_component(
+        self: &Arc<Self>,
+        guild_id: GuildId,
+        component_interaction: ComponentInteraction,
+    ) -> Result<(), Error> {
+        let Some(custom_id) = component_interaction
+            .data
+            .custom_id
+            .strip_prefix(LUA_CUSTOM_ID_PREFIX)
+        else {
+            return Err("The component wasn't made by lua code.".into());
+        };
+
+        let guild_info = self.get_guild_lua_data(guild_id).await;
+
+        let mut locked_guild_info = guild_info.lock().await;
+
+        let mut handler = None;
+
+        if let Some((command_name, command_custom_id)) = custom_id.split_once(':') {
+            let commands = locked_guild_info.get_commands(&self.db).await?;
+            if let Some((command_info, _)) = commands.get(command_name) {
+                if let Err(reason) =
+                    command_info.check_member(component_interaction.member.as_deref())
+                {
+                    drop(locked_guild_info);
+                    component_interaction
+                        .create_response(
+                            &self.arc_ctx,
+                            CreateInteractionResponse::Message(
+                                CreateInteractionResponseMessage::new()
+                                    .content(reason)
+                                    .ephemeral(true),
+                            ),
+                        )
+                        .await?;
+                    return Ok(());
+                }
+
+                let (functions, lua, notify) = locked_guild_info
+                    .get_command_function(command_name, &self.db)
+                    .await?;
+
+                if let Some(component_function) = functions.component {
+                    handler = Some((component_function, lua, notify, command_custom_id));
+                }
+            }
+        }
+
+        if handler.is_none() {
+            handler = locked_guild_info
+                .get_event_function(LuaEvent::Component, &self.db)
+                .await?
+                .map(|(function, lua, notify)| (function, lua, notify, custom_id));
+        }
+
+        let Some((function, lua, notify, custom_id)) = handler else {
+            drop(locked_guild_info);
+            component_interaction
+                .create_response(
+                    &self.arc_ctx,
+                    CreateInteractionResponse::Message(
+                        CreateInteractionResponseMessage::new()
+                            .content("This guild doesn't handle components anymore.")
+                            .ephemeral(true),
+                    ),
+                )
+                .await?;
+            return Ok(());
+        };
+
+        drop(locked_guild_info);
+        drop(guild_info);
+
+        let values = match &component_interaction.data.kind {
+            ComponentInteractionDataKind::StringSelect { values } => values.clone(),
+            _ => Vec::new(),
         };
 
+        let event_data = json!({
+            "custom_id": custom_id,
+            "values": values,
+            "user": user_event_data(&component_interaction.user),
+            "channel_id": component_interaction.channel_id.to_string(),
+            "message_id": component_interaction.message.id.to_string(),
+        });
+
+        let event_table = serde_to_lua(event_data, &lua)?;
+        set_readonly_recursive(&event_table)?;
+
+        let context_container = ContextContainer::new(InteractionReplies {
+            arc_ctx: self.arc_ctx.clone(),
+            interaction: LuaInteraction::Component(component_interaction),
+        });
+
         let context_container_userdata = lua.create_userdata(context_container)?;
 
-        let _result = tokio::select! {
+        let result = tokio::select! {
             _ = notify.notified() => {
-                Err(mlua::Error::runtime("Operation cancelled by an admin."))
+                Err(Error::from("Operation cancelled by an admin."))
+            }
+            result = run_with_limits(
+                &self.limits,
+                function.call_async::<mlua::Value>((&context_container_userdata, event_table)),
+            ) => result,
+        };
+        self.restart_after_memory_error(guild_id, result).await?;
+
+        let context_container =
+            context_container_userdata.take::<ContextContainer<InteractionReplies>>()?;
+
+        // Discord shows the component as failed if the interaction never gets a response.
+        match context_container.response_state {
+            ResponseState::NotResponded => {
+                context_container
+                    .replies
+                    .interaction
+                    .create_response(&self.arc_ctx, CreateInteractionResponse::Acknowledge)
+                    .await?;
+            }
+            ResponseState::Deferred => {
+                context_container
+                    .replies
+                    .interaction
+                    .edit_response(
+                        &self.arc_ctx,
+                        EditInteractionResponse::new()
+                            .content("This component was handled, but didn't send a reply."),
+                    )
+                    .await?;
             }
-            result = function
-            .call_async::<mlua::Value>((&context_container_userdata, command_args_lua))
-            => result,
-        }?;
+            ResponseState::Responded => {}
+        }
 
-        let context_container = context_container_userdata.borrow::<ContextContainer>()?;
+        Ok(())
+    }
 
-        let sent_reply = context_container.sent_reply;
+    /// Sets the code that handles an event in a guild. Replaces the previous handler if there was one.
+    pub async fn set_event_handler(
+        self: &Arc<Self>,
+        guild_id: GuildId,
+        event: LuaEvent,
+        lua_code: String,
+        filename: String,
+    ) -> Result<(), Error> {
+        // Make sure the provided code is valid lua code.
+        self.try_parse_code(&lua_code)?;
+
+        let guild_info = self.get_guild_lua_data(guild_id).await;
+        let mut locked_guild_info = guild_info.lock().await;
+
+        locked_guild_info
+            .set_event(event, LuaEventInfo { lua_code, filename }, &self.db)
+            .await
+    }
+
+    pub async fn remove_event_handler(
+        self: &Arc<Self>,
+        guild_id: GuildId,
+        event: LuaEvent,
+    ) -> Result<(), Error> {
+        let guild_info = self.get_guild_lua_data(guild_id).await;
+        let mut locked_guild_info = guild_info.lock().await;
+
+        if !locked_guild_info
+            .get_events(&self.db)
+            .await?
+            .contains_key(&event)
+        {
+            return Err(format!("There is no handler for the {} event.", event.to_str()).into());
+        }
+
+        locked_guild_info.remove_event(event, &self.db).await
+    }
+
+    /// Runs the guild's handler for the event in the background, if it has one.
+    ///
+    /// `event_data` is given to the handler as a read only table. Errors get logged to the guild.
+    pub fn dispatch_event(
+        self: &Arc<Self>,
+        guild_id: GuildId,
+        event: LuaEvent,
+        event_data: serde_json::Value,
+    ) {
+        if self
+            .handled_events
+            .read()
+            .unwrap()
+            .get(&guild_id)
+            .is_some_and(|events| !events.contains(&event))
+        {
+            return;
+        }
 
-        drop(context_container);
-        let _ = context_container_userdata.destroy();
+        let lua_manager = self.clone();
+        tokio::spawn(async move {
+            if let Err(err) = lua_manager
+                .run_event_handler(guild_id, event, event_data)
+                .await
+            {
+                lua_manager
+                    .log_manager
+                    .add_log(
+                        IdType::GuildId(guild_id),
+                        format!("The {} event handler failed. {err}", event.to_str()),
+                        LogType::Error,
+                        LogSource::Lua,
+                    )
+                    .await;
+            }
+        });
+    }
 
-        return Ok(sent_reply);
+    pub fn on_message(self: &Arc<Self>, message: &Message, bot_id: UserId) {
+        let Some(guild_id) = message.guild_id else {
+            return;
+        };
+
+        // The handler could send a message itself, which shouldn't trigger it again.
+        if message.author.id == bot_id {
+            return;
+        }
+
+        self.dispatch_event(
+            guild_id,
+            LuaEvent::Message,
+            json!({
+                "message_id": message.id.to_string(),
+                "channel_id": message.channel_id.to_string(),
+                "content": message.content,
+                "author": user_event_data(&message.author),
+            }),
+        );
+    }
+
+    pub fn on_member_join(self: &Arc<Self>, member: &Member) {
+        self.dispatch_event(
+            member.guild_id,
+            LuaEvent::MemberJoin,
+            json!({
+                "user": user_event_data(&member.user),
+                "joined_at": member.joined_at.map(|joined_at| joined_at.unix_timestamp()),
+            }),
+        );
+    }
+
+    pub fn on_member_leave(self: &Arc<Self>, guild_id: GuildId, user: &User) {
+        self.dispatch_event(
+            guild_id,
+            LuaEvent::MemberLeave,
+            json!({
+                "user": user_event_data(user),
+            }),
+        );
+    }
+
+    /// `event` must be either `LuaEvent::ReactionAdd` or `LuaEvent::ReactionRemove`.
+    pub fn on_reaction(self: &Arc<Self>, event: LuaEvent, reaction: &Reaction, bot_id: UserId) {
+        let Some(guild_id) = reaction.guild_id else {
+            return;
+        };
+
+        // The handler could react itself, which shouldn't trigger it again.
+        if reaction.user_id == Some(bot_id) {
+            return;
+        }
+
+        self.dispatch_event(
+            guild_id,
+            event,
+            json!({
+                "message_id": reaction.message_id.to_string(),
+                "channel_id": reaction.channel_id.to_string(),
+                "user_id": reaction.user_id.map(|user_id| user_id.to_string()),
+                "emoji": reaction.emoji.to_string(),
+            }),
+        );
+    }
+
+    async fn run_event_handler(
+        self: &Arc<Self>,
+        guild_id: GuildId,
+        event: LuaEvent,
+        event_data: serde_json::Value,
+    ) -> Result<(), Error> {
+        let guild_info = self.get_guild_lua_data(guild_id).await;
+
+        let mut locked_guild_info = guild_info.lock().await;
+
+        // Events happen a lot, so don't keep trying to load the handlers while the database is down.
+        if locked_guild_info.events.is_none() && self.db.health() == DatabaseHealth::Down {
+            return Ok(());
+        }
+
+        let Some((function, lua, notify)) = locked_guild_info
+            .get_event_function(event, &self.db)
+            .await?
+        else {
+            return Ok(());
+        };
+
+        drop(locked_guild_info);
+        drop(guild_info);
+
+        let event_table = serde_to_lua(event_data, &lua)?;
+        set_readonly_recursive(&event_table)?;
+
+        let result = tokio::select! {
+            _ = notify.notified() => {
+                Err(Error::from("Operation cancelled by an admin."))
+            }
+            result = run_with_limits(&self.limits, function.call_async::<mlua::Value>(event_table)) => result,
+        };
+        self.restart_after_memory_error(guild_id, result).await?;
+
+        Ok(())
     }
 }
 
@@ -834,3 +2661,283 @@ pub fn lua_manager_loop(lua_manager: Arc<LuaManager>) {
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::managers::{db::memory::MemoryDatabase, lua_manager::harness::LuaHarness};
+
+    use super::*;
+
+    const GUILD_ID: GuildId = GuildId::new(1);
+    const AUTHOR: UserId = UserId::new(2);
+
+    /// GuildLuaData without a LuaManager, which is fine as long as no lua instance is needed.
+    fn guild_lua_data() -> GuildLuaData {
+        GuildLuaData {
+            lua: None,
+            guild_id: GUILD_ID,
+            stop_notify: Arc::new(Notify::new()),
+            stop_bool: Arc::new(AtomicBool::new(false)),
+            lua_manager: Weak::new(),
+            action_limiter: Arc::new(std::sync::Mutex::new(ActionLimiter::new())),
+            http_limiter: Arc::new(std::sync::Mutex::new(HttpLimiter::new())),
+            limits: LuaLimits::default(),
+            modules: Arc::new(Mutex::new(None)),
+            commands: None,
+            events: None,
+        }
+    }
+
+    fn command(lua_code: &str) -> LuaCommandInfo {
+        LuaCommandInfo {
+            lua_code: lua_code.to_string(),
+            filename: "greet.luau".to_string(),
+            description: "Test".to_string(),
+            options: Vec::new(),
+            default_member_permissions: None,
+            required_roles: Vec::new(),
+            subcommands: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn eval_output_gets_truncated() {
+        let mut output = EvalOutputCapture::default();
+        for index in 0..10_000 {
+            output.push(index.to_string());
+        }
+
+        assert!(output.truncated);
+        assert_eq!(output.lines.last().unwrap(), "...output truncated");
+        assert!(output.lines.iter().map(String::len).sum::<usize>() < MAX_EVAL_OUTPUT_BYTES + 100);
+
+        // Empty lines count too.
+        let mut output = EvalOutputCapture::default();
+        for _ in 0..10_000 {
+            output.push(String::new());
+        }
+        assert!(output.truncated);
+        assert!(output.lines.len() <= MAX_EVAL_OUTPUT_BYTES + 1);
+    }
+
+    #[tokio::test]
+    async fn data_store_increment_starts_missing_keys_at_zero() {
+        let harness = LuaHarness::new().unwrap();
+
+        let replies = harness
+            .run_command(
+                &command(
+                    r#"
+                    return function(ctx)
+                        local store = get_data_store("counts")
+                        store:set("existing", 10)
+                        local missing = store:increment("missing")
+                        local existing = store:increment("existing", -2.5)
+                        ctx:reply(missing .. " " .. existing)
+                    end
+                    "#,
+                ),
+                json!({}),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(replies[0].content.as_deref(), Some("1 7.5"));
+
+        let data = harness.data_store("counts").await.unwrap();
+        assert_eq!(data.get("missing"), Some(&json!(1.0)));
+        assert_eq!(data.get("existing"), Some(&json!(7.5)));
+
+        let result = harness
+            .run_command(
+                &command(
+                    r#"
+                    return function()
+                        local store = get_data_store("counts")
+                        store:set("name", "bob")
+                        store:increment("name")
+                    end
+                    "#,
+                ),
+                json!({}),
+            )
+            .await;
+        assert!(result.is_err_and(|err| err.to_string().contains("Only numbers")));
+    }
+
+    #[tokio::test]
+    async fn data_store_update_retries_after_a_concurrent_set() {
+        let harness = LuaHarness::new().unwrap();
+
+        // Each get_data_store call has its own cache, like two commands running at once.
+        let replies = harness
+            .run_command(
+                &command(
+                    r#"
+                    return function(ctx)
+                        local store = get_data_store("counts")
+                        store:set("count", 1)
+
+                        local calls = 0
+                        local result = store:update("count", function(value)
+                            calls += 1
+                            if calls == 1 then
+                                get_data_store("counts"):set("count", 10)
+                            end
+                            return value + 1
+                        end)
+
+                        ctx:reply(result .. " after " .. calls .. " calls")
+                    end
+                    "#,
+                ),
+                json!({}),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(replies[0].content.as_deref(), Some("11 after 2 calls"));
+
+        let data = harness.data_store("counts").await.unwrap();
+        assert_eq!(data.get("count"), Some(&json!(11.0)));
+
+        let result = harness
+            .run_command(
+                &command(
+                    r#"
+                    return function()
+                        local store = get_data_store("counts")
+                        store:update("count", function(value)
+                            get_data_store("counts"):set("count", value + 100)
+                            return value + 1
+                        end)
+                    end
+                    "#,
+                ),
+                json!({}),
+            )
+            .await;
+        assert!(result.is_err_and(|err| err.to_string().contains("kept being changed")));
+    }
+
+    #[tokio::test]
+    async fn data_store_quota_errors_reach_lua() {
+        let harness = LuaHarness::new().unwrap();
+
+        let replies = harness
+            .run_command(
+                &command(
+                    r#"
+                    return function(ctx)
+                        local store = get_data_store("texts")
+                        local ok, err = pcall(function()
+                            store:set("big", string.rep("a", 1000001))
+                        end)
+                        ctx:reply(tostring(ok) .. " " .. tostring(store:get("big")) .. " " .. tostring(err))
+                    end
+                    "#,
+                ),
+                json!({}),
+            )
+            .await
+            .unwrap();
+
+        let content = replies[0].content.as_deref().unwrap();
+        assert!(content.starts_with("false nil"), "{content}");
+        assert!(content.contains("bytes"), "{content}");
+        assert!(harness.data_store("texts").await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn memory_errors_restart_the_lua_instance() {
+        let mut guild_lua_data = guild_lua_data();
+        guild_lua_data.lua = Some(Lua::new());
+
+        let result: Result<(), Error> = guild_lua_data
+            .restart_after_memory_error(Err(mlua::Error::runtime("Some other error").into()));
+        assert!(result.is_err());
+        assert!(guild_lua_data.lua.is_some());
+
+        let result: Result<(), Error> = guild_lua_data.restart_after_memory_error(Err(
+            mlua::Error::MemoryError("not enough memory".to_string()).into(),
+        ));
+        assert!(result.is_err_and(|err| err.to_string().contains("restarted")));
+        assert!(guild_lua_data.lua.is_none());
+    }
+
+    #[tokio::test]
+    async fn commands_from_before_the_history_can_be_rolled_back() {
+        let db = MemoryDatabase::new(None).unwrap();
+        let mut guild_lua_data = guild_lua_data();
+
+        // Saved without a version, like commands from before the history existed.
+        db.add_guild_lua_command("greet", &command("-- old"), GUILD_ID)
+            .await
+            .unwrap();
+
+        guild_lua_data
+            .add_or_replace_command("greet".to_string(), command("-- new"), AUTHOR, &db)
+            .await
+            .unwrap();
+
+        let history = db
+            .get_guild_lua_command_history(GUILD_ID, "greet")
+            .await
+            .unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].version, 2);
+        assert_eq!(history[0].author, Some(AUTHOR));
+        assert_eq!(history[1].version, 1);
+        assert_eq!(history[1].author, None);
+        assert_eq!(history[1].command_info.lua_code, "-- old");
+
+        guild_lua_data
+            .rollback_command("greet".to_string(), 1, AUTHOR, &db)
+            .await
+            .unwrap();
+
+        let commands = db.get_all_guild_lua_commands(GUILD_ID).await.unwrap();
+        assert_eq!(commands["greet"].lua_code, "-- old");
+        assert_eq!(
+            guild_lua_data.commands.as_ref().unwrap()["greet"]
+                .0
+                .lua_code,
+            "-- old"
+        );
+
+        let history = db
+            .get_guild_lua_command_history(GUILD_ID, "greet")
+            .await
+            .unwrap();
+        assert_eq!(history.len(), 3);
+        assert_eq!(history[0].version, 3);
+        assert_eq!(history[0].command_info.lua_code, "-- old");
+    }
+
+    #[tokio::test]
+    async fn rolling_back_to_a_missing_version_errors() {
+        let db = MemoryDatabase::new(None).unwrap();
+        let mut guild_lua_data = guild_lua_data();
+
+        guild_lua_data
+            .add_or_replace_command("greet".to_string(), command("-- first"), AUTHOR, &db)
+            .await
+            .unwrap();
+
+        assert!(guild_lua_data
+            .rollback_command("greet".to_string(), 5, AUTHOR, &db)
+            .await
+            .is_err());
+        assert!(guild_lua_data
+            .rollback_command("other".to_string(), 1, AUTHOR, &db)
+            .await
+            .is_err());
+        assert_eq!(
+            db.get_guild_lua_command_history(GUILD_ID, "greet")
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+}
@@ -0,0 +1,159 @@
+use mlua::Table;
+use poise::serenity_prelude::{
+    ButtonStyle, CreateActionRow, CreateButton, CreateSelectMenu, CreateSelectMenuKind,
+    CreateSelectMenuOption, ReactionType,
+};
+
+/// Added in front of the custom ids of components made by lua code, so the bot knows which
+/// component interactions should go to lua. See `LuaManager::execute_component`.
+pub const LUA_CUSTOM_ID_PREFIX: &str = "lua:";
+
+const MAX_ROWS: usize = 5;
+const MAX_BUTTONS_PER_ROW: usize = 5;
+const MAX_SELECT_OPTIONS: usize = 25;
+const MAX_CUSTOM_ID_LENGTH: usize = 100 - LUA_CUSTOM_ID_PREFIX.len();
+
+fn parse_custom_id(table: &Table) -> mlua::Result<String> {
+    let custom_id = table.get::<String>("custom_id")?;
+
+    if custom_id.is_empty() || custom_id.chars().count() > MAX_CUSTOM_ID_LENGTH {
+        return Err(mlua::Error::runtime(format!(
+            "Custom ids must be between 1 and {MAX_CUSTOM_ID_LENGTH} characters long."
+        )));
+    }
+
+    Ok(format!("{LUA_CUSTOM_ID_PREFIX}{custom_id}"))
+}
+
+fn parse_button(table: Table) -> mlua::Result<CreateButton> {
+    let style = table
+        .get::<Option<String>>("style")?
+        .unwrap_or_else(|| "primary".to_string());
+
+    let mut button = if style == "link" {
+        CreateButton::new_link(table.get::<String>("url")?)
+    } else {
+        let style = match style.as_str() {
+            "primary" => ButtonStyle::Primary,
+            "secondary" => ButtonStyle::Secondary,
+            "success" => ButtonStyle::Success,
+            "danger" => ButtonStyle::Danger,
+            _ => {
+                return Err(mlua::Error::runtime(format!(
+                    "\"{style}\" is not a valid button style. Use primary, secondary, success, danger or link."
+                )))
+            }
+        };
+        CreateButton::new(parse_custom_id(&table)?).style(style)
+    };
+
+    if let Some(label) = table.get::<Option<String>>("label")? {
+        button = button.label(label);
+    }
+
+    if let Some(emoji) = table.get::<Option<String>>("emoji")? {
+        button = button.emoji(ReactionType::Unicode(emoji));
+    }
+
+    if let Some(disabled) = table.get::<Option<bool>>("disabled")? {
+        button = button.disabled(disabled);
+    }
+
+    Ok(button)
+}
+
+fn parse_select_menu(table: Table) -> mlua::Result<CreateSelectMenu> {
+    let option_tables = table.get::<Table>("options")?;
+    if option_tables.raw_len() == 0 || option_tables.raw_len() > MAX_SELECT_OPTIONS {
+        return Err(mlua::Error::runtime(format!(
+            "Select menus must have between 1 and {MAX_SELECT_OPTIONS} options."
+        )));
+    }
+
+    let mut options = Vec::new();
+    for option_table in option_tables.sequence_values::<Table>() {
+        let option_table = option_table?;
+        let mut option = CreateSelectMenuOption::new(
+            option_table.get::<String>("label")?,
+            option_table.get::<String>("value")?,
+        );
+
+        if let Some(description) = option_table.get::<Option<String>>("description")? {
+            option = option.description(description);
+        }
+
+        if let Some(default) = option_table.get::<Option<bool>>("default")? {
+            option = option.default_selection(default);
+        }
+
+        options.push(option);
+    }
+
+    let mut select_menu = CreateSelectMenu::new(
+        parse_custom_id(&table)?,
+        CreateSelectMenuKind::String { options },
+    );
+
+    if let Some(placeholder) = table.get::<Option<String>>("placeholder")? {
+        select_menu = select_menu.placeholder(placeholder);
+    }
+
+    if let Some(min_values) = table.get::<Option<u8>>("min_values")? {
+        select_menu = select_menu.min_values(min_values);
+    }
+
+    if let Some(max_values) = table.get::<Option<u8>>("max_values")? {
+        select_menu = select_menu.max_values(max_values);
+    }
+
+    if let Some(disabled) = table.get::<Option<bool>>("disabled")? {
+        select_menu = select_menu.disabled(disabled);
+    }
+
+    Ok(select_menu)
+}
+
+/// Parses a list of rows, where every row is a list of buttons or a list with one select menu.
+///
+/// For example `{ { { type = "button", custom_id = "yes", label = "Yes" }, { type = "button", custom_id = "no", label = "No" } } }`.
+pub fn parse_components(rows: Table) -> mlua::Result<Vec<CreateActionRow>> {
+    if rows.raw_len() > MAX_ROWS {
+        return Err(mlua::Error::runtime(format!(
+            "Messages can't have more than {MAX_ROWS} rows of components."
+        )));
+    }
+
+    let mut action_rows = Vec::new();
+
+    for row in rows.sequence_values::<Table>() {
+        let row = row?;
+
+        let mut buttons = Vec::new();
+        let mut select_menus = Vec::new();
+
+        for component in row.sequence_values::<Table>() {
+            let component = component?;
+            match component.get::<String>("type")?.as_str() {
+                "button" => buttons.push(parse_button(component)?),
+                "select" => select_menus.push(parse_select_menu(component)?),
+                other => {
+                    return Err(mlua::Error::runtime(format!(
+                        "\"{other}\" is not a valid component type. Use button or select."
+                    )))
+                }
+            }
+        }
+
+        match (buttons.len(), select_menus.len()) {
+            (0, 1) => action_rows.push(CreateActionRow::SelectMenu(select_menus.remove(0))),
+            (1..=MAX_BUTTONS_PER_ROW, 0) => action_rows.push(CreateActionRow::Buttons(buttons)),
+            _ => {
+                return Err(mlua::Error::runtime(format!(
+                    "Every row must have between 1 and {MAX_BUTTONS_PER_ROW} buttons, or a single select menu."
+                )))
+            }
+        }
+    }
+
+    Ok(action_rows)
+}
@@ -0,0 +1,137 @@
+use std::{
+    sync::{Arc, Weak},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use mlua::{Lua, Variadic};
+use poise::serenity_prelude::GuildId;
+use tokio::{sync::Mutex, time::sleep};
+
+use crate::managers::{db::Database, log_manager::LogType, profile_manager::ProfileManager};
+
+use super::{
+    guild_api::GuildApi,
+    http::{create_http_table, HttpLimiter, LuaHttpClient},
+    modules::{create_require_function, GuildLuaModules},
+    stdlib::{create_profile_table, register_stdlib},
+    timers::TimersApi,
+    DataStore, DataStoreWrapper,
+};
+
+/// What the globals of a lua instance call into while lua code runs.
+///
+/// The `LuaManager` is the host of guild instances. The test harness has its own host,
+/// so that lua code can run without discord.
+#[async_trait]
+pub trait LuaHost: Send + Sync {
+    /// Where `print` and `warn` send what they're given.
+    async fn log(&self, guild_id: GuildId, log_type: LogType, message: String);
+
+    async fn data_store(&self, guild_id: GuildId, data_store_name: String)
+        -> Arc<Mutex<DataStore>>;
+}
+
+/// Everything the globals of a lua instance are made from.
+pub struct LuaGlobals {
+    pub guild_id: GuildId,
+    /// Weak so that the lua instance doesn't keep its host alive.
+    pub host: Weak<dyn LuaHost>,
+    pub db: Arc<dyn Database>,
+    pub modules: GuildLuaModules,
+    pub profile_manager: Arc<ProfileManager>,
+    pub timer_wait_until: Arc<Mutex<u64>>,
+    pub guild_api: GuildApi,
+    /// None if lua code isn't allowed to make http requests.
+    pub http: Option<(Arc<LuaHttpClient>, Arc<std::sync::Mutex<HttpLimiter>>)>,
+}
+
+/// Joins values the way `print` does, with a tab between them.
+pub fn format_print_values(values: &[mlua::Value]) -> mlua::Result<String> {
+    Ok(values
+        .iter()
+        .map(|value| value.to_string())
+        .collect::<mlua::Result<Vec<_>>>()?
+        .join("\t"))
+}
+
+/// Sets every global that lua code can use and then sandboxes the instance.
+///
+/// Both `GuildLuaData::get_lua` and the test harness use this, so lua code sees the same globals in both.
+pub fn register_globals(lua: &Lua, globals: LuaGlobals) -> mlua::Result<()> {
+    let guild_id = globals.guild_id;
+
+    // For debug purposes only.
+    lua.globals().set(
+        "wait",
+        lua.create_async_function(async |_lua, wait: f32| {
+            sleep(Duration::from_secs_f32(wait)).await;
+            Ok(())
+        })?,
+    )?;
+
+    for (name, log_type) in [("print", LogType::Info), ("warn", LogType::Warning)] {
+        let host = globals.host.clone();
+
+        lua.globals().set(
+            name,
+            lua.create_async_function(move |_lua, values: Variadic<mlua::Value>| {
+                let host = host.clone();
+                let message = format_print_values(&values);
+                async move {
+                    let message = message?;
+                    if let Some(host) = host.upgrade() {
+                        host.log(guild_id, log_type, message).await;
+                    }
+                    Ok(())
+                }
+            })?,
+        )?;
+    }
+
+    let host = globals.host.clone();
+
+    lua.globals().set(
+        "get_data_store",
+        lua.create_async_function(move |_lua, data_store_name: String| {
+            let host = host.clone();
+            async move {
+                let Some(host) = host.upgrade() else {
+                    return Err(mlua::Error::runtime("Failed to get LuaManager."));
+                };
+
+                Ok(DataStoreWrapper::from(
+                    host.data_store(guild_id, data_store_name).await,
+                ))
+            }
+        })?,
+    )?;
+
+    lua.globals().set("guild", globals.guild_api)?;
+
+    lua.globals().set(
+        "timers",
+        TimersApi::new(guild_id, globals.db.clone(), globals.timer_wait_until),
+    )?;
+
+    if let Some((http_client, http_limiter)) = globals.http {
+        lua.globals()
+            .set("http", create_http_table(lua, http_client, http_limiter)?)?;
+    }
+
+    lua.globals().set(
+        "require",
+        create_require_function(lua, guild_id, globals.modules, globals.db.clone())?,
+    )?;
+
+    register_stdlib(lua)?;
+
+    lua.globals().set(
+        "profile",
+        create_profile_table(lua, globals.profile_manager, globals.db)?,
+    )?;
+
+    lua.sandbox(true)?;
+
+    Ok(())
+}
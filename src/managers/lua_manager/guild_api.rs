@@ -0,0 +1,417 @@
+use std::{
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use mlua::{Lua, Table, UserData, UserDataFields, UserDataMethods};
+use poise::serenity_prelude::{
+    self, ChannelId, CreateAllowedMentions, CreateMessage, Guild, GuildChannel, GuildId, Member,
+    MessageId, Permissions, ReactionType, Role, RoleId, UserId,
+};
+
+use crate::utils::is_not_found;
+
+/// How many discord actions the lua code of a guild can do within `ACTION_WINDOW`.
+const ACTIONS_PER_WINDOW: u32 = 20;
+const ACTION_WINDOW: Duration = Duration::from_secs(10);
+
+/// The longest message discord allows.
+const MAX_MESSAGE_LENGTH: usize = 2000;
+
+const AUDIT_LOG_REASON: &str = "Custom lua command";
+
+/// Limits how many discord actions the lua code of a guild can do.
+///
+/// This is stored outside of the lua instance so that restarting it doesn't reset the limit.
+pub struct ActionLimiter {
+    window_start: Instant,
+    used: u32,
+}
+
+impl Default for ActionLimiter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ActionLimiter {
+    pub fn new() -> Self {
+        Self {
+            window_start: Instant::now(),
+            used: 0,
+        }
+    }
+
+    /// Returns false if the limit has been reached.
+    fn try_use(&mut self) -> bool {
+        if self.window_start.elapsed() >= ACTION_WINDOW {
+            self.window_start = Instant::now();
+            self.used = 0;
+        }
+
+        if self.used >= ACTIONS_PER_WINDOW {
+            return false;
+        }
+
+        self.used += 1;
+        true
+    }
+}
+
+/// The `guild` global that lets lua code do things in the guild it belongs to.
+///
+/// Every action is checked against the bot's permissions in the guild,
+/// so lua code can never do something in another guild or something the bot isn't allowed to do.
+pub struct GuildApi {
+    guild_id: GuildId,
+    /// None when lua runs without discord, like in the test harness. Everything but the id fails then.
+    arc_ctx: Option<Arc<serenity_prelude::Context>>,
+    action_limiter: Arc<std::sync::Mutex<ActionLimiter>>,
+}
+
+impl GuildApi {
+    pub fn new(
+        guild_id: GuildId,
+        arc_ctx: Option<Arc<serenity_prelude::Context>>,
+        action_limiter: Arc<std::sync::Mutex<ActionLimiter>>,
+    ) -> Self {
+        Self {
+            guild_id,
+            arc_ctx,
+            action_limiter,
+        }
+    }
+
+    fn ctx(&self) -> mlua::Result<&Arc<serenity_prelude::Context>> {
+        self.arc_ctx
+            .as_ref()
+            .ok_or_else(|| mlua::Error::runtime("The guild isn't available without discord."))
+    }
+
+    fn use_action(&self) -> mlua::Result<()> {
+        let mut limiter = self
+            .action_limiter
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        if !limiter.try_use() {
+            return Err(mlua::Error::runtime(format!(
+                "Rate limited. Lua code can only do {ACTIONS_PER_WINDOW} discord actions every {} seconds.",
+                ACTION_WINDOW.as_secs()
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Runs `callback` with the cached guild and the bot's member in it.
+    fn with_guild<T>(
+        &self,
+        callback: impl FnOnce(&Guild, &Member) -> mlua::Result<T>,
+    ) -> mlua::Result<T> {
+        let cache = &self.ctx()?.cache;
+        let bot_id = cache.current_user().id;
+
+        let Some(guild) = cache.guild(self.guild_id) else {
+            return Err(mlua::Error::runtime("Couldn't find the guild."));
+        };
+
+        let Some(bot_member) = guild.members.get(&bot_id) else {
+            return Err(mlua::Error::runtime(
+                "Couldn't find my member in the guild.",
+            ));
+        };
+
+        callback(&guild, bot_member)
+    }
+
+    fn check_channel_permissions(
+        &self,
+        channel_id: ChannelId,
+        required_permissions: Permissions,
+    ) -> mlua::Result<()> {
+        self.with_guild(|guild, bot_member| {
+            let Some(channel) = guild.channels.get(&channel_id) else {
+                return Err(mlua::Error::runtime(format!(
+                    "The channel {channel_id} isn't in this guild."
+                )));
+            };
+
+            let missing_permissions =
+                required_permissions.difference(guild.user_permissions_in(channel, bot_member));
+
+            if !missing_permissions.is_empty() {
+                return Err(mlua::Error::runtime(format!(
+                    "I'm missing these permissions in <#{channel_id}>: {}",
+                    missing_permissions.get_permission_names().join(", ")
+                )));
+            }
+
+            Ok(())
+        })
+    }
+
+    fn check_role_manageable(&self, role_id: RoleId) -> mlua::Result<()> {
+        self.with_guild(|guild, bot_member| {
+            if !guild_permissions(guild, bot_member).manage_roles() {
+                return Err(mlua::Error::runtime(
+                    "I'm missing the Manage Roles permission.",
+                ));
+            }
+
+            let Some(role) = guild.roles.get(&role_id) else {
+                return Err(mlua::Error::runtime(format!(
+                    "The role {role_id} isn't in this guild."
+                )));
+            };
+
+            if role.id.get() == guild.id.get() || role.managed {
+                return Err(mlua::Error::runtime(format!(
+                    "The role {} can't be given or removed.",
+                    role.name
+                )));
+            }
+
+            if role.permissions.administrator() {
+                return Err(mlua::Error::runtime(format!(
+                    "Lua code isn't allowed to give or remove the role {} because it has administrator.",
+                    role.name
+                )));
+            }
+
+            let highest_position = bot_member
+                .roles
+                .iter()
+                .filter_map(|role_id| guild.roles.get(role_id))
+                .map(|role| role.position)
+                .max()
+                .unwrap_or(0);
+
+            if role.position >= highest_position {
+                return Err(mlua::Error::runtime(format!(
+                    "The role {} is above my highest role.",
+                    role.name
+                )));
+            }
+
+            Ok(())
+        })
+    }
+}
+
+/// The permissions a member has in the guild, ignoring channel overwrites.
+fn guild_permissions(guild: &Guild, member: &Member) -> Permissions {
+    if guild.owner_id == member.user.id {
+        return Permissions::all();
+    }
+
+    // The @everyone role has the same id as the guild.
+    let everyone_role_id = RoleId::new(guild.id.get());
+
+    let permissions = std::iter::once(&everyone_role_id)
+        .chain(member.roles.iter())
+        .filter_map(|role_id| guild.roles.get(role_id))
+        .fold(Permissions::empty(), |permissions, role| {
+            permissions | role.permissions
+        });
+
+    if permissions.administrator() {
+        return Permissions::all();
+    }
+
+    permissions
+}
+
+fn parse_id<T: FromStr>(id: &str, kind: &str) -> mlua::Result<T> {
+    id.parse()
+        .map_err(|_| mlua::Error::runtime(format!("\"{id}\" is not a valid {kind} id.")))
+}
+
+fn member_to_table(lua: &Lua, member: &Member) -> mlua::Result<Table> {
+    let table = lua.create_table()?;
+    table.set("id", member.user.id.to_string())?;
+    table.set("name", member.user.name.clone())?;
+    table.set("display_name", member.display_name().to_string())?;
+    table.set("nick", member.nick.clone())?;
+    table.set("bot", member.user.bot)?;
+    table.set(
+        "roles",
+        lua.create_sequence_from(member.roles.iter().map(RoleId::to_string))?,
+    )?;
+    table.set(
+        "joined_at",
+        member.joined_at.map(|joined_at| joined_at.unix_timestamp()),
+    )?;
+    Ok(table)
+}
+
+fn role_to_table(lua: &Lua, role: &Role) -> mlua::Result<Table> {
+    let table = lua.create_table()?;
+    table.set("id", role.id.to_string())?;
+    table.set("name", role.name.clone())?;
+    table.set("color", role.colour.0)?;
+    table.set("position", role.position)?;
+    table.set("mentionable", role.mentionable)?;
+    table.set("hoist", role.hoist)?;
+    Ok(table)
+}
+
+fn channel_to_table(lua: &Lua, channel: &GuildChannel) -> mlua::Result<Table> {
+    let table = lua.create_table()?;
+    table.set("id", channel.id.to_string())?;
+    table.set("name", channel.name.clone())?;
+    table.set("kind", channel.kind.name())?;
+    table.set("topic", channel.topic.clone())?;
+    table.set("nsfw", channel.nsfw)?;
+    table.set(
+        "parent_id",
+        channel.parent_id.map(|parent_id| parent_id.to_string()),
+    )?;
+    Ok(table)
+}
+
+impl UserData for GuildApi {
+    fn add_fields<F: UserDataFields<Self>>(fields: &mut F) {
+        fields.add_field_method_get("id", |_, this| Ok(this.guild_id.to_string()));
+    }
+
+    fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
+        methods.add_async_method(
+            "send_message",
+            async |_lua, this, (channel_id, content): (String, String)| {
+                let channel_id: ChannelId = parse_id(&channel_id, "channel")?;
+
+                if content.is_empty() || content.chars().count() > MAX_MESSAGE_LENGTH {
+                    return Err(mlua::Error::runtime(format!(
+                        "Messages must be between 1 and {MAX_MESSAGE_LENGTH} characters long."
+                    )));
+                }
+
+                this.check_channel_permissions(
+                    channel_id,
+                    Permissions::VIEW_CHANNEL | Permissions::SEND_MESSAGES,
+                )?;
+                this.use_action()?;
+
+                // Lua code shouldn't be able to ping everyone or roles.
+                let message = channel_id
+                    .send_message(
+                        this.ctx()?,
+                        CreateMessage::new()
+                            .content(content)
+                            .allowed_mentions(CreateAllowedMentions::new().all_users(true)),
+                    )
+                    .await
+                    .map_err(mlua::Error::external)?;
+
+                Ok(message.id.to_string())
+            },
+        );
+
+        methods.add_async_method(
+            "add_role",
+            async |_lua, this, (user_id, role_id): (String, String)| {
+                let user_id: UserId = parse_id(&user_id, "user")?;
+                let role_id: RoleId = parse_id(&role_id, "role")?;
+
+                this.check_role_manageable(role_id)?;
+                this.use_action()?;
+
+                this.ctx()?
+                    .http
+                    .add_member_role(this.guild_id, user_id, role_id, Some(AUDIT_LOG_REASON))
+                    .await
+                    .map_err(mlua::Error::external)?;
+
+                Ok(())
+            },
+        );
+
+        methods.add_async_method(
+            "remove_role",
+            async |_lua, this, (user_id, role_id): (String, String)| {
+                let user_id: UserId = parse_id(&user_id, "user")?;
+                let role_id: RoleId = parse_id(&role_id, "role")?;
+
+                this.check_role_manageable(role_id)?;
+                this.use_action()?;
+
+                this.ctx()?
+                    .http
+                    .remove_member_role(this.guild_id, user_id, role_id, Some(AUDIT_LOG_REASON))
+                    .await
+                    .map_err(mlua::Error::external)?;
+
+                Ok(())
+            },
+        );
+
+        methods.add_async_method(
+            "add_reaction",
+            async |_lua, this, (channel_id, message_id, emoji): (String, String, String)| {
+                let channel_id: ChannelId = parse_id(&channel_id, "channel")?;
+                let message_id: MessageId = parse_id(&message_id, "message")?;
+                let Ok(reaction) = ReactionType::try_from(emoji.as_str()) else {
+                    return Err(mlua::Error::runtime(format!(
+                        "\"{emoji}\" is not a valid emoji."
+                    )));
+                };
+
+                this.check_channel_permissions(
+                    channel_id,
+                    Permissions::VIEW_CHANNEL
+                        | Permissions::READ_MESSAGE_HISTORY
+                        | Permissions::ADD_REACTIONS,
+                )?;
+                this.use_action()?;
+
+                channel_id
+                    .create_reaction(this.ctx()?, message_id, reaction)
+                    .await
+                    .map_err(mlua::Error::external)?;
+
+                Ok(())
+            },
+        );
+
+        methods.add_async_method("get_member", async |lua, this, user_id: String| {
+            let user_id: UserId = parse_id(&user_id, "user")?;
+
+            // Fetching a member might need a request to discord.
+            this.use_action()?;
+
+            let member = match this.guild_id.member(this.ctx()?, user_id).await {
+                Ok(member) => member,
+                Err(err) if is_not_found(&err) => return Ok(None),
+                Err(err) => return Err(mlua::Error::external(err)),
+            };
+
+            Ok(Some(member_to_table(&lua, &member)?))
+        });
+
+        methods.add_method("get_role", |lua, this, role_id: String| {
+            let role_id: RoleId = parse_id(&role_id, "role")?;
+
+            this.with_guild(|guild, _| {
+                guild
+                    .roles
+                    .get(&role_id)
+                    .map(|role| role_to_table(lua, role))
+                    .transpose()
+            })
+        });
+
+        methods.add_method("get_channel", |lua, this, channel_id: String| {
+            let channel_id: ChannelId = parse_id(&channel_id, "channel")?;
+
+            this.with_guild(|guild, _| {
+                guild
+                    .channels
+                    .get(&channel_id)
+                    .map(|channel| channel_to_table(lua, channel))
+                    .transpose()
+            })
+        });
+    }
+}
@@ -0,0 +1,384 @@
+//! Runs lua commands without discord or surrealdb, so guild scripts and the sandbox apis can be tested.
+
+use std::{
+    collections::HashMap,
+    sync::{atomic::AtomicBool, Arc},
+};
+
+use async_trait::async_trait;
+use mlua::Lua;
+use poise::serenity_prelude::GuildId;
+use tokio::sync::Mutex;
+
+use crate::{
+    managers::{
+        db::{memory::MemoryDatabase, Database},
+        log_manager::LogType,
+        lua_manager::{
+            create_limited_lua,
+            globals::{register_globals, LuaGlobals, LuaHost},
+            guild_api::{ActionLimiter, GuildApi},
+            http::{HttpLimiter, LuaHttpClient},
+            limits::run_with_limits,
+            modules::LuaModuleInfo,
+            replies::LuaReply,
+            serde_and_lua::serde_to_lua,
+            ContextContainer, DataStore, LuaCommandFunctions, LuaCommandInfo, LuaReplySink,
+        },
+        profile_manager::ProfileManager,
+    },
+    tokens::{LuaHttpSettings, LuaLimits},
+    Error,
+};
+
+/// A reply that a command made through its ctx.
+pub struct HarnessReply {
+    /// Which ctx method sent it, like "reply" or "followup".
+    pub method: &'static str,
+    pub content: Option<String>,
+    pub embeds: usize,
+    pub ephemeral: bool,
+}
+
+/// Records the replies of a command instead of sending them.
+#[derive(Default)]
+struct HarnessReplies(std::sync::Mutex<Vec<HarnessReply>>);
+
+impl HarnessReplies {
+    fn record(&self, method: &'static str, reply: LuaReply) -> usize {
+        let mut replies = self.0.lock().unwrap();
+        replies.push(HarnessReply {
+            method,
+            content: reply.content,
+            embeds: reply.embeds.len(),
+            ephemeral: reply.ephemeral,
+        });
+        replies.len()
+    }
+}
+
+#[async_trait]
+impl LuaReplySink for HarnessReplies {
+    fn can_update(&self) -> bool {
+        false
+    }
+
+    async fn reply(&self, reply: LuaReply) -> Result<(), Error> {
+        self.record("reply", reply);
+        Ok(())
+    }
+
+    async fn defer(&self, _ephemeral: bool) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn update(&self, reply: LuaReply) -> Result<(), Error> {
+        self.record("update", reply);
+        Ok(())
+    }
+
+    async fn followup(&self, reply: LuaReply) -> Result<String, Error> {
+        Ok(self.record("followup", reply).to_string())
+    }
+
+    async fn edit_reply(&self, reply: LuaReply) -> Result<(), Error> {
+        self.record("edit_reply", reply);
+        Ok(())
+    }
+}
+
+/// Captures logs and hands out data stores backed by the harness' database.
+struct HarnessHost {
+    db: Arc<dyn Database>,
+    logs: std::sync::Mutex<Vec<(LogType, String)>>,
+}
+
+#[async_trait]
+impl LuaHost for HarnessHost {
+    async fn log(&self, _guild_id: GuildId, log_type: LogType, message: String) {
+        self.logs.lock().unwrap().push((log_type, message));
+    }
+
+    async fn data_store(
+        &self,
+        guild_id: GuildId,
+        data_store_name: String,
+    ) -> Arc<Mutex<DataStore>> {
+        Arc::new(Mutex::new(DataStore::new(
+            guild_id,
+            data_store_name,
+            self.db.clone(),
+        )))
+    }
+}
+
+/// A lua instance with the same globals and limits as a guild's, but with recorded replies and captured logs.
+///
+/// `guild` is there too, but errors when used since there is no discord to talk to.
+pub struct LuaHarness {
+    lua: Lua,
+    guild_id: GuildId,
+    db: Arc<MemoryDatabase>,
+    host: Arc<HarnessHost>,
+    limits: LuaLimits,
+}
+
+impl LuaHarness {
+    pub fn new() -> Result<Self, Error> {
+        Self::create(None)
+    }
+
+    /// Like `new`, but lua code can also make http requests, as if the bot owner allowed them.
+    pub fn with_http(settings: LuaHttpSettings) -> Result<Self, Error> {
+        Self::create(Some(settings))
+    }
+
+    fn create(http_settings: Option<LuaHttpSettings>) -> Result<Self, Error> {
+        let guild_id = GuildId::new(1);
+        let db = Arc::new(MemoryDatabase::new(None)?);
+        let limits = LuaLimits::default();
+        let host = Arc::new(HarnessHost {
+            db: db.clone(),
+            logs: std::sync::Mutex::new(Vec::new()),
+        });
+
+        let http = match http_settings {
+            Some(settings) => Some((
+                Arc::new(LuaHttpClient::new(settings)?),
+                Arc::new(std::sync::Mutex::new(HttpLimiter::new())),
+            )),
+            None => None,
+        };
+
+        let lua = create_limited_lua(&limits, Arc::new(AtomicBool::new(false)))?;
+
+        register_globals(
+            &lua,
+            LuaGlobals {
+                guild_id,
+                host: Arc::downgrade(&host),
+                db: db.clone(),
+                modules: Arc::new(Mutex::new(None)),
+                profile_manager: Arc::new(ProfileManager::new()),
+                timer_wait_until: Arc::new(Mutex::new(0)),
+                guild_api: GuildApi::new(
+                    guild_id,
+                    None,
+                    Arc::new(std::sync::Mutex::new(ActionLimiter::new())),
+                ),
+                http,
+            },
+        )?;
+
+        Ok(Self {
+            lua,
+            guild_id,
+            db,
+            host,
+            limits,
+        })
+    }
+
+    /// Adds a module that commands can require. Must be called before running a command that requires it.
+    pub async fn add_module(&self, module_name: &str, lua_code: &str) -> Result<(), Error> {
+        self.db
+            .set_guild_lua_module(
+                self.guild_id,
+                module_name,
+                &LuaModuleInfo {
+                    lua_code: lua_code.to_string(),
+                    filename: format!("{module_name}.luau"),
+                },
+            )
+            .await
+    }
+
+    /// Runs a command with the given args, like `json!({ "amount": 5 })`, and returns the replies it made.
+    pub async fn run_command(
+        &self,
+        command_info: &LuaCommandInfo,
+        args: serde_json::Value,
+    ) -> Result<Vec<HarnessReply>, Error> {
+        let functions: LuaCommandFunctions = run_with_limits(
+            &self.limits,
+            self.lua
+                .load(&command_info.lua_code)
+                .set_name(format!("={}", command_info.filename))
+                .eval_async(),
+        )
+        .await?;
+
+        let args = serde_to_lua(args, &self.lua)?;
+
+        let ctx = self
+            .lua
+            .create_userdata(ContextContainer::new(HarnessReplies::default()))?;
+
+        run_with_limits(
+            &self.limits,
+            functions.run.call_async::<mlua::Value>((&ctx, args)),
+        )
+        .await?;
+
+        Ok(ctx
+            .take::<ContextContainer<HarnessReplies>>()?
+            .replies
+            .0
+            .into_inner()
+            .unwrap())
+    }
+
+    pub async fn data_store(
+        &self,
+        data_store_name: &str,
+    ) -> Result<HashMap<String, serde_json::Value>, Error> {
+        self.db.get_data_store(self.guild_id, data_store_name).await
+    }
+
+    /// The lua instance itself, to check what lua code can see.
+    pub fn lua(&self) -> &Lua {
+        &self.lua
+    }
+
+    pub fn logs(&self) -> Vec<(LogType, String)> {
+        self.host.logs.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn command(lua_code: &str) -> LuaCommandInfo {
+        LuaCommandInfo {
+            lua_code: lua_code.to_string(),
+            filename: "test.luau".to_string(),
+            description: "Test".to_string(),
+            options: Vec::new(),
+            default_member_permissions: None,
+            required_roles: Vec::new(),
+            subcommands: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn command_replies_and_writes_data() {
+        let harness = LuaHarness::new().unwrap();
+
+        let replies = harness
+            .run_command(
+                &command(
+                    r#"
+                    return function(ctx, args)
+                        local store = get_data_store("counts")
+                        store:set("count", args.amount)
+                        print("set count")
+                        ctx:reply({ content = "Set to " .. args.amount, ephemeral = true })
+                    end
+                    "#,
+                ),
+                json!({ "amount": 5 }),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0].method, "reply");
+        assert_eq!(replies[0].content.as_deref(), Some("Set to 5"));
+        assert!(replies[0].ephemeral);
+        assert_eq!(replies[0].embeds, 0);
+
+        let data = harness.data_store("counts").await.unwrap();
+        assert_eq!(data.get("count"), Some(&json!(5.0)));
+
+        let logs = harness.logs();
+        assert!(matches!(logs.as_slice(), [(LogType::Info, message)] if message == "set count"));
+    }
+
+    #[tokio::test]
+    async fn followup_needs_a_reply_first() {
+        let harness = LuaHarness::new().unwrap();
+
+        let result = harness
+            .run_command(
+                &command(r#"return function(ctx) ctx:followup("Too early") end"#),
+                json!({}),
+            )
+            .await;
+
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn commands_can_require_modules() {
+        let harness = LuaHarness::new().unwrap();
+        harness
+            .add_module("greet", r#"return function(name) return "Hi " .. name end"#)
+            .await
+            .unwrap();
+
+        let replies = harness
+            .run_command(
+                &command(
+                    r#"
+                    local greet = require("greet")
+                    return { run = function(ctx, args) ctx:reply(greet(args.name)) end }
+                    "#,
+                ),
+                json!({ "name": "Bob" }),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(replies[0].content.as_deref(), Some("Hi Bob"));
+    }
+
+    #[tokio::test]
+    async fn modules_requiring_each_other_error() {
+        let harness = LuaHarness::new().unwrap();
+        harness
+            .add_module("a", r#"return require("b")"#)
+            .await
+            .unwrap();
+        harness
+            .add_module("b", r#"return require("a")"#)
+            .await
+            .unwrap();
+
+        let result = harness
+            .run_command(
+                &command(r#"local a = require("a") return function(ctx) end"#),
+                json!({}),
+            )
+            .await;
+
+        assert!(result.is_err_and(|err| err.to_string().contains("in a loop")));
+    }
+
+    #[tokio::test]
+    async fn modules_can_be_required_at_the_same_time() {
+        let harness = LuaHarness::new().unwrap();
+        harness
+            .add_module("slow", r#"wait(0.05) print("loaded") return "done""#)
+            .await
+            .unwrap();
+
+        let command = command(
+            r#"
+            local slow = require("slow")
+            return function(ctx) ctx:reply(slow) end
+            "#,
+        );
+
+        let (first, second) = tokio::join!(
+            harness.run_command(&command, json!({})),
+            harness.run_command(&command, json!({}))
+        );
+
+        assert_eq!(first.unwrap()[0].content.as_deref(), Some("done"));
+        assert_eq!(second.unwrap()[0].content.as_deref(), Some("done"));
+        assert_eq!(harness.logs().len(), 1);
+    }
+}
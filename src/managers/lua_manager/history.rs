@@ -0,0 +1,20 @@
+use poise::serenity_prelude::UserId;
+use serde::{Deserialize, Serialize};
+
+use super::LuaCommandInfo;
+
+/// How many versions of a command are kept. The oldest ones get removed first.
+pub const MAX_COMMAND_VERSIONS: usize = 10;
+
+/// A version of a command from when it was created, updated or rolled back, so that a bad update can be undone.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LuaCommandVersion {
+    /// Counts up from 1 for every version of the command.
+    pub version: u32,
+    /// Who created the version. None for the code commands had from before the history existed.
+    #[serde(default)]
+    pub author: Option<UserId>,
+    /// Unix time in seconds of when the version was created.
+    pub timestamp: u64,
+    pub command_info: LuaCommandInfo,
+}
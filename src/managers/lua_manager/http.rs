@@ -0,0 +1,386 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use mlua::{Lua, Table};
+use reqwest::{redirect, Client, Method, Url};
+
+use crate::{tokens::LuaHttpSettings, Error};
+
+use super::serde_and_lua::lua_to_serde;
+
+const REQUEST_WINDOW: Duration = Duration::from_secs(60);
+const MAX_REDIRECTS: usize = 5;
+
+fn is_allowed_url(allowed_domains: &[String], url: &Url) -> bool {
+    if !matches!(url.scheme(), "http" | "https") {
+        return false;
+    }
+
+    let Some(host) = url.host_str() else {
+        return false;
+    };
+    let host = host.to_lowercase();
+
+    allowed_domains.iter().any(|domain| {
+        let domain = domain.trim().to_lowercase();
+        !domain.is_empty() && (host == domain || host.ends_with(&format!(".{domain}")))
+    })
+}
+
+/// The http client that all lua instances share, which only makes requests to the allowed domains.
+pub struct LuaHttpClient {
+    client: Client,
+    settings: LuaHttpSettings,
+}
+
+impl LuaHttpClient {
+    pub fn new(settings: LuaHttpSettings) -> Result<Self, Error> {
+        // Redirects could lead anywhere, so they have to be allowed too.
+        let allowed_domains = settings.allowed_domains.clone();
+        let redirect_policy = redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() > MAX_REDIRECTS {
+                attempt.error("The request was redirected too many times.")
+            } else if is_allowed_url(&allowed_domains, attempt.url()) {
+                attempt.follow()
+            } else {
+                attempt.error("The request was redirected to a domain that isn't allowed.")
+            }
+        });
+
+        let client = Client::builder()
+            .timeout(Duration::from_secs(settings.timeout_secs))
+            .redirect(redirect_policy)
+            .build()?;
+
+        Ok(Self { client, settings })
+    }
+
+    fn request_error(&self, err: reqwest::Error) -> mlua::Error {
+        if err.is_timeout() {
+            mlua::Error::runtime(format!(
+                "The request took longer than {} seconds.",
+                self.settings.timeout_secs
+            ))
+        } else {
+            mlua::Error::runtime(format!("The request failed: {err}"))
+        }
+    }
+
+    fn too_big_error(&self) -> mlua::Error {
+        mlua::Error::runtime(format!(
+            "The response was bigger than {} bytes.",
+            self.settings.max_response_bytes
+        ))
+    }
+
+    async fn request(
+        &self,
+        lua: &Lua,
+        method: Method,
+        url: &str,
+        body: Option<mlua::Value>,
+        options: Option<Table>,
+    ) -> mlua::Result<Table> {
+        let Ok(parsed_url) = Url::parse(url) else {
+            return Err(mlua::Error::runtime(format!(
+                "\"{url}\" is not a valid url."
+            )));
+        };
+
+        if !is_allowed_url(&self.settings.allowed_domains, &parsed_url) {
+            return Err(mlua::Error::runtime(format!(
+                "Requests to \"{}\" aren't allowed. Ask the bot owner to add the domain to the allowlist.",
+                parsed_url.host_str().unwrap_or(url)
+            )));
+        }
+
+        let mut request = self.client.request(method, parsed_url);
+
+        if let Some(options) = options {
+            let headers: Option<HashMap<String, String>> = options.get("headers")?;
+            for (name, value) in headers.unwrap_or_default() {
+                request = request.header(name, value);
+            }
+        }
+
+        match body {
+            Some(mlua::Value::String(body)) => {
+                request = request.body(body.as_bytes().to_vec());
+            }
+            Some(mlua::Value::Table(body)) => {
+                let serde_value =
+                    lua_to_serde(mlua::Value::Table(body)).map_err(mlua::Error::runtime)?;
+                request = request.json(&serde_value);
+            }
+            Some(mlua::Value::Nil) | None => {}
+            Some(_) => {
+                return Err(mlua::Error::runtime(
+                    "The body has to be a string or a table.",
+                ))
+            }
+        }
+
+        let mut response = request
+            .send()
+            .await
+            .map_err(|err| self.request_error(err))?;
+
+        if response
+            .content_length()
+            .is_some_and(|length| length > self.settings.max_response_bytes as u64)
+        {
+            return Err(self.too_big_error());
+        }
+
+        let status = response.status();
+
+        let headers = lua.create_table()?;
+        for (name, value) in response.headers() {
+            if let Ok(value) = value.to_str() {
+                headers.set(name.as_str(), value)?;
+            }
+        }
+
+        let mut body = Vec::new();
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .map_err(|err| self.request_error(err))?
+        {
+            if body.len() + chunk.len() > self.settings.max_response_bytes {
+                return Err(self.too_big_error());
+            }
+            body.extend_from_slice(&chunk);
+        }
+
+        let response_table = lua.create_table()?;
+        response_table.set("status", status.as_u16())?;
+        response_table.set("ok", status.is_success())?;
+        response_table.set("headers", headers)?;
+        response_table.set("body", lua.create_string(&body)?)?;
+        Ok(response_table)
+    }
+}
+
+/// Limits how many http requests the lua code of a guild can make.
+///
+/// Like the `ActionLimiter`, this is stored outside of the lua instance so that restarting it doesn't reset the limit.
+pub struct HttpLimiter {
+    window_start: Instant,
+    used: u32,
+}
+
+impl Default for HttpLimiter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HttpLimiter {
+    pub fn new() -> Self {
+        Self {
+            window_start: Instant::now(),
+            used: 0,
+        }
+    }
+
+    /// Returns false if the limit has been reached.
+    fn try_use(&mut self, requests_per_window: u32) -> bool {
+        if self.window_start.elapsed() >= REQUEST_WINDOW {
+            self.window_start = Instant::now();
+            self.used = 0;
+        }
+
+        if self.used >= requests_per_window {
+            return false;
+        }
+
+        self.used += 1;
+        true
+    }
+}
+
+fn use_request(
+    client: &LuaHttpClient,
+    limiter: &std::sync::Mutex<HttpLimiter>,
+) -> mlua::Result<()> {
+    let mut limiter = limiter
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    if !limiter.try_use(client.settings.requests_per_minute) {
+        return Err(mlua::Error::runtime(format!(
+            "Rate limited. Lua code can only make {} http requests every {} seconds.",
+            client.settings.requests_per_minute,
+            REQUEST_WINDOW.as_secs()
+        )));
+    }
+
+    Ok(())
+}
+
+/// Makes the `http` global with `http.get(url, options)` and `http.post(url, body, options)`.
+///
+/// Both return a table with the `status`, `ok`, `headers` and `body` of the response.
+pub fn create_http_table(
+    lua: &Lua,
+    client: Arc<LuaHttpClient>,
+    limiter: Arc<std::sync::Mutex<HttpLimiter>>,
+) -> mlua::Result<Table> {
+    let http = lua.create_table()?;
+
+    let (get_client, get_limiter) = (client.clone(), limiter.clone());
+    http.set(
+        "get",
+        lua.create_async_function(move |lua, (url, options): (String, Option<Table>)| {
+            let (client, limiter) = (get_client.clone(), get_limiter.clone());
+            async move {
+                use_request(&client, &limiter)?;
+                client.request(&lua, Method::GET, &url, None, options).await
+            }
+        })?,
+    )?;
+
+    http.set(
+        "post",
+        lua.create_async_function(
+            move |lua, (url, body, options): (String, mlua::Value, Option<Table>)| {
+                let (client, limiter) = (client.clone(), limiter.clone());
+                async move {
+                    use_request(&client, &limiter)?;
+                    client
+                        .request(&lua, Method::POST, &url, Some(body), options)
+                        .await
+                }
+            },
+        )?,
+    )?;
+
+    Ok(http)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::start_server;
+
+    use super::*;
+
+    fn start_text_server(text: &'static str) -> String {
+        start_server(move |_| text.as_bytes().to_vec())
+    }
+
+    fn create_lua(settings: LuaHttpSettings) -> Lua {
+        let lua = Lua::new();
+        let http = create_http_table(
+            &lua,
+            Arc::new(LuaHttpClient::new(settings).unwrap()),
+            Arc::new(std::sync::Mutex::new(HttpLimiter::new())),
+        )
+        .unwrap();
+        lua.globals().set("http", http).unwrap();
+        lua
+    }
+
+    fn local_settings() -> LuaHttpSettings {
+        LuaHttpSettings {
+            allowed_domains: vec!["127.0.0.1".to_string()],
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn get_returns_the_response() {
+        let url = start_text_server("Server is online");
+        let lua = create_lua(local_settings());
+
+        let (status, ok, body): (u16, bool, String) = lua
+            .load(format!(
+                r#"
+                local response = http.get("{url}/status")
+                return response.status, response.ok, response.body
+                "#
+            ))
+            .eval_async()
+            .await
+            .unwrap();
+
+        assert_eq!(status, 200);
+        assert!(ok);
+        assert_eq!(body, "Server is online");
+    }
+
+    #[tokio::test]
+    async fn post_sends_tables_as_json() {
+        let url = start_server(|request| request.body);
+        let lua = create_lua(local_settings());
+
+        let body: String = lua
+            .load(format!(
+                r#"return http.post("{url}/echo", {{ players = 3 }}).body"#
+            ))
+            .eval_async()
+            .await
+            .unwrap();
+
+        assert_eq!(body, r#"{"players":3.0}"#);
+    }
+
+    #[tokio::test]
+    async fn domains_not_in_the_allowlist_are_rejected() {
+        let url = start_text_server("Secret");
+        let lua = create_lua(LuaHttpSettings {
+            allowed_domains: vec!["example.com".to_string()],
+            ..Default::default()
+        });
+
+        let result = lua
+            .load(format!(r#"return http.get("{url}")"#))
+            .eval_async::<Table>()
+            .await;
+
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn big_responses_are_rejected() {
+        let url = start_text_server("This response is too big");
+        let lua = create_lua(LuaHttpSettings {
+            max_response_bytes: 10,
+            ..local_settings()
+        });
+
+        let result = lua
+            .load(format!(r#"return http.get("{url}")"#))
+            .eval_async::<Table>()
+            .await;
+
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn requests_are_rate_limited() {
+        let url = start_text_server("Ok");
+        let lua = create_lua(LuaHttpSettings {
+            requests_per_minute: 1,
+            ..local_settings()
+        });
+
+        let code = format!(r#"return http.get("{url}")"#);
+        assert!(lua.load(&code).eval_async::<Table>().await.is_ok());
+        assert!(lua.load(&code).eval_async::<Table>().await.is_err());
+    }
+
+    #[test]
+    fn subdomains_of_allowed_domains_are_allowed() {
+        let allowed_domains = vec!["example.com".to_string()];
+        let is_allowed = |url: &str| is_allowed_url(&allowed_domains, &Url::parse(url).unwrap());
+
+        assert!(is_allowed("https://example.com/status"));
+        assert!(is_allowed("https://play.example.com/status"));
+        assert!(!is_allowed("https://notexample.com/status"));
+        assert!(!is_allowed("ftp://example.com/status"));
+    }
+}
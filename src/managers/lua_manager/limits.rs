@@ -0,0 +1,174 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use crate::{tokens::LuaLimits, Error};
+
+/// Stops the future of a lua call once it has used up its CPU time.
+///
+/// Lua only runs while the future is being polled, and the interrupt makes it yield often,
+/// so the time spent polling is roughly how much CPU time the call has used.
+struct CpuBudget<F> {
+    future: Pin<Box<F>>,
+    used: Duration,
+    limit: Duration,
+}
+
+impl<F: Future> Future for CpuBudget<F> {
+    /// None if the CPU time ran out.
+    type Output = Option<F::Output>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+
+        let start = Instant::now();
+        let poll = this.future.as_mut().poll(cx);
+        this.used += start.elapsed();
+
+        match poll {
+            Poll::Ready(output) => Poll::Ready(Some(output)),
+            Poll::Pending if this.used > this.limit => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// The lua code went over the memory limit of its lua instance.
+///
+/// Whatever filled up the memory is still there afterwards, so the lua instance should be restarted.
+#[derive(Debug)]
+pub struct MemoryLimitError(usize);
+
+impl std::fmt::Display for MemoryLimitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "The Lua code went over the memory limit of {} MB and was stopped.",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for MemoryLimitError {}
+
+pub fn is_memory_limit_error(err: &Error) -> bool {
+    err.downcast_ref::<MemoryLimitError>().is_some()
+        || err
+            .downcast_ref::<mlua::Error>()
+            .is_some_and(is_memory_error)
+}
+
+fn is_memory_error(err: &mlua::Error) -> bool {
+    match err {
+        mlua::Error::MemoryError(_) => true,
+        mlua::Error::CallbackError { cause, .. } | mlua::Error::WithContext { cause, .. } => {
+            is_memory_error(cause)
+        }
+        _ => false,
+    }
+}
+
+/// Runs lua code with the CPU time and run time limits.
+///
+/// Going over a limit, including the memory limit of the lua instance, results in an error that explains which limit it was.
+/// Going over the memory limit results in a `MemoryLimitError`.
+pub async fn run_with_limits<T>(
+    limits: &LuaLimits,
+    future: impl Future<Output = mlua::Result<T>>,
+) -> Result<T, Error> {
+    let cpu_budget = CpuBudget {
+        future: Box::pin(future),
+        used: Duration::ZERO,
+        limit: Duration::from_millis(limits.cpu_time_ms),
+    };
+
+    match tokio::time::timeout(Duration::from_secs(limits.run_time_secs), cpu_budget).await {
+        Ok(Some(Ok(value))) => Ok(value),
+        Ok(Some(Err(err))) if is_memory_error(&err) => {
+            Err(MemoryLimitError(limits.memory_limit_mb).into())
+        }
+        Ok(Some(Err(err))) => Err(err.into()),
+        Ok(None) => Err(format!(
+            "The Lua code used more than {} ms of CPU time and was stopped.",
+            limits.cpu_time_ms
+        )
+        .into()),
+        Err(_) => Err(format!(
+            "The Lua code ran for longer than {} seconds and was stopped.",
+            limits.run_time_secs
+        )
+        .into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{atomic::AtomicBool, Arc};
+
+    use crate::managers::lua_manager::create_limited_lua;
+
+    use super::*;
+
+    fn limits(cpu_time_ms: u64, run_time_secs: u64, memory_limit_mb: usize) -> LuaLimits {
+        LuaLimits {
+            cpu_time_ms,
+            run_time_secs,
+            memory_limit_mb,
+        }
+    }
+
+    #[tokio::test]
+    async fn infinite_loops_run_out_of_cpu_time() {
+        let limits = limits(100, 10, 32);
+        let lua = create_limited_lua(&limits, Arc::new(AtomicBool::new(false))).unwrap();
+
+        let result = run_with_limits(&limits, lua.load("while true do end").exec_async()).await;
+
+        assert!(result.is_err_and(|err| err.to_string().contains("ms of CPU time")));
+    }
+
+    #[tokio::test]
+    async fn growing_tables_go_over_the_memory_limit() {
+        let limits = limits(10_000, 10, 1);
+        let lua = create_limited_lua(&limits, Arc::new(AtomicBool::new(false))).unwrap();
+
+        let result = run_with_limits(
+            &limits,
+            lua.load(
+                r#"
+                local items = {}
+                for index = 1, 1e7 do
+                    items[index] = string.rep("x", 100) .. index
+                end
+                "#,
+            )
+            .exec_async(),
+        )
+        .await;
+
+        assert!(result.is_err_and(|err| is_memory_limit_error(&err)));
+    }
+
+    #[tokio::test]
+    async fn long_waits_go_over_the_run_time() {
+        let limits = limits(1000, 1, 32);
+        let lua = create_limited_lua(&limits, Arc::new(AtomicBool::new(false))).unwrap();
+        lua.globals()
+            .set(
+                "wait",
+                lua.create_async_function(|_lua, seconds: f64| async move {
+                    tokio::time::sleep(Duration::from_secs_f64(seconds)).await;
+                    Ok(())
+                })
+                .unwrap(),
+            )
+            .unwrap();
+
+        let result = run_with_limits(&limits, lua.load("wait(30)").exec_async()).await;
+
+        assert!(result.is_err_and(|err| err.to_string().contains("ran for longer than 1 seconds")));
+    }
+}
@@ -0,0 +1,972 @@
+//! Looks for mistakes in lua code that would otherwise only show up once the code runs.
+
+use std::collections::{HashMap, HashSet};
+
+use full_moon::{
+    ast::{
+        Block, Call, Expression, Field, FunctionArgs, FunctionBody, Index, LastStmt, Parameter,
+        Prefix, Stmt, Suffix, Var,
+    },
+    tokenizer::TokenReference,
+    LuaVersion,
+};
+
+/// Globals that the luau sandbox provides.
+const LUAU_GLOBALS: &[&str] = &[
+    "_G",
+    "_VERSION",
+    "assert",
+    "collectgarbage",
+    "error",
+    "gcinfo",
+    "getfenv",
+    "getmetatable",
+    "ipairs",
+    "loadstring",
+    "newproxy",
+    "next",
+    "pairs",
+    "pcall",
+    "rawequal",
+    "rawget",
+    "rawlen",
+    "rawset",
+    "select",
+    "setfenv",
+    "setmetatable",
+    "tonumber",
+    "tostring",
+    "type",
+    "typeof",
+    "unpack",
+    "xpcall",
+];
+
+/// Globals that `globals::register_globals` adds, other than the libraries.
+const BOT_GLOBALS: &[&str] = &[
+    "print",
+    "warn",
+    "wait",
+    "get_data_store",
+    "guild",
+    "timers",
+    "require",
+];
+
+/// Libraries and the functions in them, so functions that don't exist can be caught.
+const LIBRARIES: &[(&str, &[&str])] = &[
+    (
+        "bit32",
+        &[
+            "arshift", "band", "bnot", "bor", "btest", "bxor", "byteswap", "countlz", "countrz",
+            "extract", "lrotate", "lshift", "replace", "rrotate", "rshift",
+        ],
+    ),
+    (
+        "buffer",
+        &[
+            "copy",
+            "create",
+            "fill",
+            "fromstring",
+            "len",
+            "readbits",
+            "readf32",
+            "readf64",
+            "readi16",
+            "readi32",
+            "readi8",
+            "readstring",
+            "readu16",
+            "readu32",
+            "readu8",
+            "tostring",
+            "writebits",
+            "writef32",
+            "writef64",
+            "writei16",
+            "writei32",
+            "writei8",
+            "writestring",
+            "writeu16",
+            "writeu32",
+            "writeu8",
+        ],
+    ),
+    (
+        "coroutine",
+        &[
+            "close",
+            "create",
+            "isyieldable",
+            "resume",
+            "running",
+            "status",
+            "wrap",
+            "yield",
+        ],
+    ),
+    ("debug", &["info", "traceback"]),
+    (
+        "math",
+        &[
+            "abs",
+            "acos",
+            "asin",
+            "atan",
+            "atan2",
+            "ceil",
+            "clamp",
+            "cos",
+            "cosh",
+            "deg",
+            "exp",
+            "floor",
+            "fmod",
+            "frexp",
+            "huge",
+            "isfinite",
+            "isinf",
+            "isnan",
+            "ldexp",
+            "lerp",
+            "log",
+            "log10",
+            "map",
+            "max",
+            "min",
+            "modf",
+            "noise",
+            "pi",
+            "pow",
+            "rad",
+            "random",
+            "randomseed",
+            "round",
+            "sign",
+            "sin",
+            "sinh",
+            "sqrt",
+            "tan",
+            "tanh",
+        ],
+    ),
+    ("os", &["clock", "date", "difftime", "time"]),
+    (
+        "string",
+        &[
+            "byte",
+            "char",
+            "find",
+            "format",
+            "gmatch",
+            "gsub",
+            "len",
+            "lower",
+            "match",
+            "pack",
+            "packsize",
+            "rep",
+            "reverse",
+            "split",
+            "sub",
+            "unpack",
+            "upper",
+            "trim",
+            "starts_with",
+            "ends_with",
+            "truncate",
+        ],
+    ),
+    (
+        "table",
+        &[
+            "clear", "clone", "concat", "create", "find", "foreach", "foreachi", "freeze", "getn",
+            "insert", "isfrozen", "maxn", "move", "pack", "remove", "sort", "unpack",
+        ],
+    ),
+    (
+        "utf8",
+        &["char", "charpattern", "codepoint", "codes", "len", "offset"],
+    ),
+    (
+        "vector",
+        &[
+            "abs",
+            "angle",
+            "ceil",
+            "clamp",
+            "create",
+            "cross",
+            "dot",
+            "floor",
+            "lerp",
+            "magnitude",
+            "max",
+            "min",
+            "normalize",
+            "one",
+            "sign",
+            "zero",
+        ],
+    ),
+    ("json", &["encode", "decode"]),
+    ("time", &["now", "format"]),
+    ("random", &["int", "float", "new"]),
+    ("profile", &["get_timezone", "get_time_format"]),
+    ("http", &["get", "post"]),
+];
+
+/// Globals from regular lua that the sandbox doesn't have.
+const UNAVAILABLE_GLOBALS: &[&str] = &["dofile", "io", "load", "loadfile", "module", "package"];
+
+pub struct LintWarning {
+    pub line: usize,
+    pub message: String,
+}
+
+fn token_name(token: &TokenReference) -> String {
+    token.token().to_string()
+}
+
+fn token_line(token: &TokenReference) -> usize {
+    token.token().start_position().line()
+}
+
+struct Linter {
+    http_enabled: bool,
+    /// The names of the locals in every scope, innermost last.
+    scopes: Vec<HashSet<String>>,
+    /// Globals that are read but not provided, with the line they're first read on.
+    unknown_reads: HashMap<String, usize>,
+    /// Globals that the code sets itself.
+    assigned_globals: HashSet<String>,
+    warnings: Vec<LintWarning>,
+}
+
+impl Linter {
+    fn new(http_enabled: bool) -> Self {
+        Self {
+            http_enabled,
+            scopes: Vec::new(),
+            unknown_reads: HashMap::new(),
+            assigned_globals: HashSet::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    fn warn(&mut self, line: usize, message: String) {
+        self.warnings.push(LintWarning { line, message });
+    }
+
+    fn declare(&mut self, token: &TokenReference) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(token_name(token));
+        }
+    }
+
+    fn is_local(&self, name: &str) -> bool {
+        self.scopes.iter().any(|scope| scope.contains(name))
+    }
+
+    fn is_provided_global(&self, name: &str) -> bool {
+        (name != "http" || self.http_enabled)
+            && (LUAU_GLOBALS.contains(&name)
+                || BOT_GLOBALS.contains(&name)
+                || LIBRARIES.iter().any(|(library, _)| *library == name))
+    }
+
+    fn read_name(&mut self, token: &TokenReference) {
+        let name = token_name(token);
+        if self.is_local(&name) || self.is_provided_global(&name) {
+            return;
+        }
+
+        self.unknown_reads
+            .entry(name)
+            .or_insert_with(|| token_line(token));
+    }
+
+    fn assign_name(&mut self, token: &TokenReference) {
+        let name = token_name(token);
+        if !self.is_local(&name) {
+            self.assigned_globals.insert(name);
+        }
+    }
+
+    fn block(&mut self, block: &Block) {
+        self.scopes.push(HashSet::new());
+        self.block_in_scope(block);
+        self.scopes.pop();
+    }
+
+    /// Goes through a block without giving it its own scope, for when the scope has to include something else.
+    fn block_in_scope(&mut self, block: &Block) {
+        for stmt in block.stmts() {
+            self.stmt(stmt);
+        }
+
+        if let Some(LastStmt::Return(return_stmt)) = block.last_stmt() {
+            for expression in return_stmt.returns().iter() {
+                self.expression(expression);
+            }
+        }
+    }
+
+    fn function_body(&mut self, body: &FunctionBody, is_method: bool) {
+        let mut scope = HashSet::new();
+        if is_method {
+            scope.insert("self".to_string());
+        }
+        for parameter in body.parameters().iter() {
+            if let Parameter::Name(name) = parameter {
+                scope.insert(token_name(name));
+            }
+        }
+
+        self.scopes.push(scope);
+        self.block_in_scope(body.block());
+        self.scopes.pop();
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Assignment(assignment) => {
+                for expression in assignment.expressions().iter() {
+                    self.expression(expression);
+                }
+                for var in assignment.variables().iter() {
+                    match var {
+                        Var::Name(name) => self.assign_name(name),
+                        var => self.var(var),
+                    }
+                }
+            }
+            Stmt::Do(do_stmt) => self.block(do_stmt.block()),
+            Stmt::FunctionCall(function_call) => {
+                self.prefix_and_suffixes(function_call.prefix(), function_call.suffixes())
+            }
+            Stmt::FunctionDeclaration(declaration) => {
+                let name = declaration.name();
+                let mut names = name.names().iter();
+                if let Some(first_name) = names.next() {
+                    if names.next().is_none() && name.method_name().is_none() {
+                        self.assign_name(first_name);
+                    } else {
+                        self.read_name(first_name);
+                    }
+                }
+                self.function_body(declaration.body(), name.method_name().is_some());
+            }
+            Stmt::GenericFor(generic_for) => {
+                for expression in generic_for.expressions().iter() {
+                    self.expression(expression);
+                }
+                self.scopes
+                    .push(generic_for.names().iter().map(token_name).collect());
+                self.block_in_scope(generic_for.block());
+                self.scopes.pop();
+            }
+            Stmt::If(if_stmt) => {
+                self.expression(if_stmt.condition());
+                self.block(if_stmt.block());
+                for else_if in if_stmt.else_if().into_iter().flatten() {
+                    self.expression(else_if.condition());
+                    self.block(else_if.block());
+                }
+                if let Some(else_block) = if_stmt.else_block() {
+                    self.block(else_block);
+                }
+            }
+            Stmt::LocalAssignment(local_assignment) => {
+                for expression in local_assignment.expressions().iter() {
+                    self.expression(expression);
+                }
+                for name in local_assignment.names().iter() {
+                    self.declare(name);
+                }
+            }
+            Stmt::LocalFunction(local_function) => {
+                // Declared before the body, so the function can call itself.
+                self.declare(local_function.name());
+                self.function_body(local_function.body(), false);
+            }
+            Stmt::NumericFor(numeric_for) => {
+                self.expression(numeric_for.start());
+                self.expression(numeric_for.end());
+                if let Some(step) = numeric_for.step() {
+                    self.expression(step);
+                }
+                self.scopes
+                    .push(HashSet::from([token_name(numeric_for.index_variable())]));
+                self.block_in_scope(numeric_for.block());
+                self.scopes.pop();
+            }
+            Stmt::Repeat(repeat) => {
+                if is_symbol(repeat.until(), &["false", "nil"])
+                    && !can_leave_loop(repeat.block(), false)
+                {
+                    self.warn(
+                        token_line(repeat.repeat_token()),
+                        "This loop never ends, so it will always be stopped by the time limit."
+                            .to_string(),
+                    );
+                }
+
+                // The until condition can use the locals of the loop.
+                self.scopes.push(HashSet::new());
+                self.block_in_scope(repeat.block());
+                self.expression(repeat.until());
+                self.scopes.pop();
+            }
+            Stmt::While(while_stmt) => {
+                if is_symbol(while_stmt.condition(), &["true"])
+                    && !can_leave_loop(while_stmt.block(), false)
+                {
+                    self.warn(
+                        token_line(while_stmt.while_token()),
+                        "This loop never ends, so it will always be stopped by the time limit."
+                            .to_string(),
+                    );
+                }
+
+                self.expression(while_stmt.condition());
+                self.block(while_stmt.block());
+            }
+            Stmt::CompoundAssignment(compound_assignment) => {
+                self.expression(compound_assignment.rhs());
+                self.var(compound_assignment.lhs());
+            }
+            Stmt::ConstAssignment(const_assignment) => {
+                for expression in const_assignment.expressions().iter() {
+                    self.expression(expression);
+                }
+                for name in const_assignment.names().iter() {
+                    self.declare(name);
+                }
+            }
+            Stmt::ConstFunction(const_function) => {
+                self.declare(const_function.name());
+                self.function_body(const_function.body(), false);
+            }
+            // Types, gotos and labels can't use globals.
+            _ => {}
+        }
+    }
+
+    fn var(&mut self, var: &Var) {
+        match var {
+            Var::Name(name) => self.read_name(name),
+            Var::Expression(var_expression) => {
+                self.prefix_and_suffixes(var_expression.prefix(), var_expression.suffixes())
+            }
+            _ => {}
+        }
+    }
+
+    fn prefix_and_suffixes<'a>(
+        &mut self,
+        prefix: &Prefix,
+        mut suffixes: impl Iterator<Item = &'a Suffix>,
+    ) {
+        let first_suffix = suffixes.next();
+
+        match prefix {
+            Prefix::Name(name) => {
+                self.read_name(name);
+                self.check_library_function(name, first_suffix);
+            }
+            Prefix::Expression(expression) => self.expression(expression),
+            _ => {}
+        }
+
+        for suffix in first_suffix.into_iter().chain(suffixes) {
+            match suffix {
+                Suffix::Call(Call::AnonymousCall(args)) => self.function_args(args),
+                Suffix::Call(Call::MethodCall(method_call)) => {
+                    self.function_args(method_call.args())
+                }
+                Suffix::Index(Index::Brackets { expression, .. }) => self.expression(expression),
+                _ => {}
+            }
+        }
+    }
+
+    /// Warns about things like `os.execute`, which don't exist in the sandbox.
+    fn check_library_function(&mut self, library_token: &TokenReference, suffix: Option<&Suffix>) {
+        let library_name = token_name(library_token);
+        if self.is_local(&library_name) {
+            return;
+        }
+
+        let Some((_, functions)) = LIBRARIES
+            .iter()
+            .find(|(library, _)| *library == library_name)
+        else {
+            return;
+        };
+
+        if let Some(Suffix::Index(Index::Dot { name, .. })) = suffix {
+            let function_name = token_name(name);
+            if !functions.contains(&function_name.as_str()) {
+                self.warn(
+                    token_line(name),
+                    format!("`{library_name}.{function_name}` isn't available in the sandbox."),
+                );
+            }
+        }
+    }
+
+    fn function_args(&mut self, args: &FunctionArgs) {
+        match args {
+            FunctionArgs::Parentheses { arguments, .. } => {
+                for expression in arguments.iter() {
+                    self.expression(expression);
+                }
+            }
+            FunctionArgs::TableConstructor(table) => {
+                for field in table.fields().iter() {
+                    self.field(field);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn field(&mut self, field: &Field) {
+        match field {
+            Field::ExpressionKey { key, value, .. } => {
+                self.expression(key);
+                self.expression(value);
+            }
+            Field::NameKey { value, .. } => self.expression(value),
+            Field::NoKey(value) => self.expression(value),
+            _ => {}
+        }
+    }
+
+    fn expression(&mut self, expression: &Expression) {
+        match expression {
+            Expression::BinaryOperator { lhs, rhs, .. } => {
+                self.expression(lhs);
+                self.expression(rhs);
+            }
+            Expression::Parentheses { expression, .. }
+            | Expression::UnaryOperator { expression, .. }
+            | Expression::TypeAssertion { expression, .. } => self.expression(expression),
+            Expression::Function(function) => self.function_body(function.body(), false),
+            Expression::FunctionCall(function_call) => {
+                self.prefix_and_suffixes(function_call.prefix(), function_call.suffixes())
+            }
+            Expression::IfExpression(if_expression) => {
+                self.expression(if_expression.condition());
+                self.expression(if_expression.if_expression());
+                for else_if in if_expression.else_if_expressions().into_iter().flatten() {
+                    self.expression(else_if.condition());
+                    self.expression(else_if.expression());
+                }
+                self.expression(if_expression.else_expression());
+            }
+            Expression::InterpolatedString(interpolated_string) => {
+                for expression in interpolated_string.expressions() {
+                    self.expression(expression);
+                }
+            }
+            Expression::TableConstructor(table) => {
+                for field in table.fields().iter() {
+                    self.field(field);
+                }
+            }
+            Expression::Var(var) => self.var(var),
+            _ => {}
+        }
+    }
+
+    /// Turns the globals that were read but never provided or set into warnings.
+    fn finish(mut self) -> Vec<LintWarning> {
+        let mut unknown_reads = std::mem::take(&mut self.unknown_reads)
+            .into_iter()
+            .filter(|(name, _)| !self.assigned_globals.contains(name))
+            .collect::<Vec<_>>();
+        unknown_reads.sort_by_key(|(_, line)| *line);
+
+        for (name, line) in unknown_reads {
+            let message = if name == "http" {
+                "`http` isn't available because the bot owner hasn't allowed http requests."
+                    .to_string()
+            } else if UNAVAILABLE_GLOBALS.contains(&name.as_str()) {
+                format!("`{name}` isn't available in the sandbox.")
+            } else {
+                format!("`{name}` is never defined. Is it misspelled or missing a `local`?")
+            };
+            self.warn(line, message);
+        }
+
+        self.warnings.sort_by_key(|warning| warning.line);
+        self.warnings
+    }
+}
+
+fn is_symbol(expression: &Expression, symbols: &[&str]) -> bool {
+    match expression {
+        Expression::Symbol(token) => symbols.contains(&token_name(token).as_str()),
+        Expression::Parentheses { expression, .. } => is_symbol(expression, symbols),
+        _ => false,
+    }
+}
+
+/// Whether something in the block can stop the loop it's in, like a `break`, `return` or `error()`.
+///
+/// Breaks in nested loops only stop the nested loop, and nothing in a nested function can stop the loop.
+fn can_leave_loop(block: &Block, in_nested_loop: bool) -> bool {
+    let can_leave = block.stmts().any(|stmt| match stmt {
+        Stmt::Do(do_stmt) => can_leave_loop(do_stmt.block(), in_nested_loop),
+        Stmt::If(if_stmt) => {
+            can_leave_loop(if_stmt.block(), in_nested_loop)
+                || if_stmt
+                    .else_if()
+                    .into_iter()
+                    .flatten()
+                    .any(|else_if| can_leave_loop(else_if.block(), in_nested_loop))
+                || if_stmt
+                    .else_block()
+                    .is_some_and(|else_block| can_leave_loop(else_block, in_nested_loop))
+        }
+        Stmt::While(while_stmt) => can_leave_loop(while_stmt.block(), true),
+        Stmt::Repeat(repeat) => can_leave_loop(repeat.block(), true),
+        Stmt::NumericFor(numeric_for) => can_leave_loop(numeric_for.block(), true),
+        Stmt::GenericFor(generic_for) => can_leave_loop(generic_for.block(), true),
+        Stmt::FunctionCall(function_call) => matches!(
+            function_call.prefix(),
+            Prefix::Name(name) if token_name(name) == "error"
+        ),
+        _ => false,
+    });
+
+    can_leave
+        || match block.last_stmt() {
+            Some(LastStmt::Return(_)) => true,
+            Some(LastStmt::Break(_)) => !in_nested_loop,
+            _ => false,
+        }
+}
+
+/// Describes a value that can't be a function, or None if it could be one.
+fn describe_non_function(expression: &Expression) -> Option<&'static str> {
+    match expression {
+        Expression::Number(_) => Some("a number"),
+        Expression::String(_) | Expression::InterpolatedString(_) => Some("a string"),
+        Expression::TableConstructor(_) => Some("a table"),
+        Expression::Symbol(token) if token_name(token) == "nil" => Some("nil"),
+        Expression::Symbol(_) => Some("a boolean"),
+        Expression::Parentheses { expression, .. } => describe_non_function(expression),
+        _ => None,
+    }
+}
+
+/// Checks that the code returns what `LuaCommandFunctions` can be made from.
+fn check_command_return(block: &Block, end_line: usize) -> Option<LintWarning> {
+    const EXPECTED: &str =
+        "it has to return the function that runs the command, or a table with a `run` function.";
+
+    let Some(LastStmt::Return(return_stmt)) = block.last_stmt() else {
+        return Some(LintWarning {
+            line: end_line,
+            message: format!("The code doesn't return anything, but {EXPECTED}"),
+        });
+    };
+
+    let line = token_line(return_stmt.token());
+
+    let Some(returned) = return_stmt.returns().iter().next() else {
+        return Some(LintWarning {
+            line,
+            message: format!("The code returns nothing, but {EXPECTED}"),
+        });
+    };
+
+    if let Expression::TableConstructor(table) = returned {
+        let mut has_run = false;
+        for field in table.fields().iter() {
+            let Field::NameKey { key, value, .. } = field else {
+                continue;
+            };
+
+            let key_name = token_name(key);
+            if !matches!(key_name.as_str(), "run" | "autocomplete" | "component") {
+                continue;
+            }
+            has_run |= key_name == "run";
+
+            if let Some(description) = describe_non_function(value) {
+                return Some(LintWarning {
+                    line: token_line(key),
+                    message: format!("`{key_name}` is {description}, but it has to be a function."),
+                });
+            }
+        }
+
+        if !has_run {
+            return Some(LintWarning {
+                line,
+                message: "The returned table doesn't have a `run` function.".to_string(),
+            });
+        }
+
+        return None;
+    }
+
+    describe_non_function(returned).map(|description| LintWarning {
+        line,
+        message: format!("The code returns {description}, but {EXPECTED}"),
+    })
+}
+
+/// Finds unknown globals, functions the sandbox doesn't have, loops that never end,
+/// and return values that can't be used as a command.
+///
+/// Code that can't be parsed gets no warnings, since `LuaManager::try_parse_code` reports those errors better.
+pub fn lint_command_code(lua_code: &str, http_enabled: bool) -> Vec<LintWarning> {
+    let Ok(ast) = full_moon::parse_fallible(lua_code, LuaVersion::luau()).into_result() else {
+        return Vec::new();
+    };
+
+    let mut linter = Linter::new(http_enabled);
+    linter.block(ast.nodes());
+
+    if let Some(warning) = check_command_return(ast.nodes(), token_line(ast.eof())) {
+        linter.warnings.push(warning);
+    }
+
+    linter.finish()
+}
+
+/// Formats warnings like `command.luau:12: Message`, leaving some out if there are too many for a message.
+pub fn format_lint_report(filename: &str, warnings: &[LintWarning]) -> String {
+    const MAX_SHOWN_WARNINGS: usize = 10;
+
+    let mut lines = warnings
+        .iter()
+        .take(MAX_SHOWN_WARNINGS)
+        .map(|warning| format!("`{filename}:{}` {}", warning.line, warning.message))
+        .collect::<Vec<_>>();
+
+    if warnings.len() > MAX_SHOWN_WARNINGS {
+        lines.push(format!(
+            "...and {} more.",
+            warnings.len() - MAX_SHOWN_WARNINGS
+        ));
+    }
+
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use mlua::Table;
+
+    use crate::{managers::lua_manager::harness::LuaHarness, tokens::LuaHttpSettings};
+
+    use super::*;
+
+    /// A command that does nothing, so only the warnings about the code in front of it show up.
+    const RETURN_COMMAND: &str = "\nreturn function(ctx, args) end";
+
+    fn messages(lua_code: &str, http_enabled: bool) -> Vec<String> {
+        lint_command_code(lua_code, http_enabled)
+            .into_iter()
+            .map(|warning| warning.message)
+            .collect()
+    }
+
+    fn loop_warnings(lua_code: &str) -> usize {
+        messages(&format!("{lua_code}{RETURN_COMMAND}"), false)
+            .iter()
+            .filter(|message| message.contains("never ends"))
+            .count()
+    }
+
+    /// The keys of a table and of the tables it gets its values from, like the globals of a sandboxed instance.
+    fn keys(table: Table) -> HashSet<String> {
+        let mut keys = HashSet::new();
+        let mut table = Some(table);
+
+        while let Some(current) = table {
+            for pair in current.pairs::<String, mlua::Value>() {
+                keys.insert(pair.unwrap().0);
+            }
+            table = current
+                .metatable()
+                .and_then(|metatable| metatable.raw_get::<Table>("__index").ok());
+        }
+
+        keys
+    }
+
+    #[test]
+    fn undefined_globals_are_reported() {
+        let warnings =
+            lint_command_code(&format!("local count = cuont + 1{RETURN_COMMAND}"), false);
+
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].line, 1);
+        assert!(warnings[0].message.contains("`cuont` is never defined"));
+    }
+
+    #[test]
+    fn globals_set_by_the_code_and_locals_are_defined() {
+        let lua_code = format!(
+            "count = 1\nlocal function add(amount) count += amount end\nadd(2){RETURN_COMMAND}"
+        );
+
+        assert!(messages(&lua_code, false).is_empty());
+    }
+
+    #[test]
+    fn sandbox_missing_globals_and_functions_are_reported() {
+        let warnings = messages(
+            &format!("os.execute(\"ls\")\nio.write(\"hi\"){RETURN_COMMAND}"),
+            false,
+        );
+
+        assert_eq!(
+            warnings,
+            [
+                "`os.execute` isn't available in the sandbox.",
+                "`io` isn't available in the sandbox.",
+            ]
+        );
+    }
+
+    #[test]
+    fn http_is_only_defined_when_allowed() {
+        let lua_code = format!("http.get(\"https://example.com\"){RETURN_COMMAND}");
+
+        assert!(messages(&lua_code, true).is_empty());
+        assert!(messages(&lua_code, false)[0].contains("hasn't allowed http requests"));
+    }
+
+    #[test]
+    fn endless_loops_are_reported() {
+        assert_eq!(loop_warnings("while true do wait(1) end"), 1);
+        assert_eq!(loop_warnings("repeat wait(1) until false"), 1);
+        // A break in a nested loop only stops the nested loop.
+        assert_eq!(
+            loop_warnings("while true do for i = 1, 3 do break end end"),
+            1
+        );
+        // Returning from a nested function doesn't stop the loop.
+        assert_eq!(
+            loop_warnings("while true do local stop = function() return end end"),
+            1
+        );
+    }
+
+    #[test]
+    fn loops_that_can_stop_are_fine() {
+        assert_eq!(
+            loop_warnings("local done = false\nwhile true do if done then break end end"),
+            0
+        );
+        assert_eq!(
+            loop_warnings("local function f() while true do return 1 end end"),
+            0
+        );
+        assert_eq!(loop_warnings("while true do error(\"stop\") end"), 0);
+        assert_eq!(
+            loop_warnings("local done = false\nwhile not done do done = true end"),
+            0
+        );
+    }
+
+    #[test]
+    fn bad_returns_are_reported() {
+        let cases = [
+            ("local x = 1", "doesn't return anything"),
+            ("return", "returns nothing"),
+            ("return 5", "returns a number"),
+            ("return \"run\"", "returns a string"),
+            ("return nil", "returns nil"),
+            ("return true", "returns a boolean"),
+            (
+                "return { autocomplete = function() end }",
+                "doesn't have a `run` function",
+            ),
+            ("return { run = 5 }", "`run` is a number"),
+            (
+                "return { run = function() end, autocomplete = {} }",
+                "`autocomplete` is a table",
+            ),
+            (
+                "return { run = function() end, component = \"yes\" }",
+                "`component` is a string",
+            ),
+        ];
+
+        for (lua_code, expected) in cases {
+            let warnings = messages(lua_code, false);
+            assert_eq!(warnings.len(), 1, "{lua_code}");
+            assert!(
+                warnings[0].contains(expected),
+                "{lua_code}: {}",
+                warnings[0]
+            );
+        }
+    }
+
+    #[test]
+    fn good_returns_are_fine() {
+        assert!(messages("return function(ctx, args) end", false).is_empty());
+        assert!(messages(
+            "local function run(ctx, args) end\nreturn { run = run, autocomplete = function() end, component = function() end }",
+            false
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn every_global_can_be_used() {
+        let lua_code = r#"
+            local utils = require("utils")
+            local store = get_data_store("counts")
+
+            return function(ctx, args)
+                print(json.encode({ count = store:get("count") }))
+                warn(time.format(time.now(), "%Y"))
+                wait(random.int(1, 2))
+                timers:after(60, "reminder")
+                guild:send_message(args.channel, profile.get_timezone(args.user))
+                local response = http.get("https://example.com")
+                ctx:reply(tostring(math.floor(response.status)) .. string.upper(utils.name))
+            end
+        "#;
+
+        assert!(messages(lua_code, true).is_empty());
+    }
+
+    #[test]
+    fn lists_match_the_globals_of_lua_instances() {
+        let harness = LuaHarness::with_http(LuaHttpSettings::default()).unwrap();
+        let globals = harness.lua().globals();
+
+        let listed = LUAU_GLOBALS
+            .iter()
+            .chain(BOT_GLOBALS)
+            .chain(LIBRARIES.iter().map(|(library, _)| library))
+            .map(|name| name.to_string())
+            .collect::<HashSet<_>>();
+        assert_eq!(keys(globals.clone()), listed);
+
+        for (library, functions) in LIBRARIES {
+            let listed = functions
+                .iter()
+                .map(|name| name.to_string())
+                .collect::<HashSet<_>>();
+            assert_eq!(
+                keys(globals.get::<Table>(*library).unwrap()),
+                listed,
+                "{library}"
+            );
+        }
+    }
+}
@@ -0,0 +1,540 @@
+use std::collections::HashSet;
+
+use poise::serenity_prelude::{Permissions, RoleId};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    managers::lua_manager::{CommandOption, LuaCommandInfo, LuaSubcommand},
+    Error,
+};
+
+const MAX_NAME_LENGTH: usize = 32;
+const MAX_DESCRIPTION_LENGTH: usize = 100;
+const MAX_OPTIONS: usize = 25;
+const MAX_SUBCOMMANDS: usize = 25;
+
+#[derive(Clone, Copy, PartialEq, Eq, poise::ChoiceParameter)]
+pub enum ManifestFormat {
+    #[name = "TOML"]
+    Toml,
+    #[name = "JSON"]
+    Json,
+}
+
+impl ManifestFormat {
+    pub fn from_filename(filename: &str) -> Option<Self> {
+        if filename.ends_with(".toml") {
+            Some(ManifestFormat::Toml)
+        } else if filename.ends_with(".json") {
+            Some(ManifestFormat::Json)
+        } else {
+            None
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ManifestFormat::Toml => "toml",
+            ManifestFormat::Json => "json",
+        }
+    }
+}
+
+/// Describes a command and its options in a TOML or JSON file, instead of the params string.
+///
+/// ```toml
+/// name = "roll"
+/// description = "Rolls a die"
+/// permissions = ["MANAGE_MESSAGES"]
+///
+/// [[options]]
+/// name = "sides"
+/// type = "integer"
+/// min_value = 2
+/// required = false
+/// ```
+///
+/// Instead of options, a command can have up to 25 subcommands, used like `/c <command> <subcommand>`.
+///
+/// ```toml
+/// [[subcommands]]
+/// name = "add"
+/// description = "Adds a tag"
+///
+/// [[subcommands.options]]
+/// name = "tag"
+/// type = "string"
+/// ```
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CommandManifest {
+    pub name: String,
+    pub description: String,
+    /// Permission names like "MANAGE_MESSAGES", a member needs all of them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub permissions: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub required_roles: Vec<RoleId>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub options: Vec<CommandOption>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub subcommands: Vec<LuaSubcommand>,
+}
+
+impl CommandManifest {
+    pub fn parse(content: &str, format: ManifestFormat) -> Result<Self, Error> {
+        let manifest: CommandManifest = match format {
+            ManifestFormat::Toml => {
+                toml::from_str(content).map_err(|err| format!("The manifest isn't valid: {err}"))?
+            }
+            ManifestFormat::Json => serde_json::from_str(content)
+                .map_err(|err| format!("The manifest isn't valid: {err}"))?,
+        };
+
+        manifest.validate()?;
+
+        Ok(manifest)
+    }
+
+    /// Checks everything discord would reject when the guild commands get updated, so the error can say where the problem is.
+    fn validate(&self) -> Result<(), Error> {
+        validate_name(&self.name, "command")?;
+        validate_description(&self.description)?;
+
+        self.default_member_permissions()?;
+
+        validate_options(&self.options)?;
+
+        if self.subcommands.is_empty() {
+            return Ok(());
+        }
+
+        if !self.options.is_empty() {
+            return Err("Commands with subcommands can't have options of their own, give the options to the subcommands instead.".into());
+        }
+
+        if self.subcommands.len() > MAX_SUBCOMMANDS {
+            return Err(
+                format!("Commands can't have more than {MAX_SUBCOMMANDS} subcommands.").into(),
+            );
+        }
+
+        let mut subcommand_names = HashSet::new();
+
+        for subcommand in self.subcommands.iter() {
+            let subcommand_name = &subcommand.name;
+
+            validate_name(subcommand_name, "subcommand")?;
+
+            if !subcommand_names.insert(subcommand_name) {
+                return Err(
+                    format!("There's more than one subcommand named {subcommand_name}.").into(),
+                );
+            }
+
+            validate_description(&subcommand.description)
+                .and_then(|_| validate_options(&subcommand.options))
+                .map_err(|err| format!("Subcommand {subcommand_name}: {err}"))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn default_member_permissions(&self) -> Result<Option<Permissions>, Error> {
+        LuaCommandInfo::parse_permissions(&self.permissions.join(","))
+    }
+
+    pub fn from_command(name: &str, command_info: &LuaCommandInfo) -> Self {
+        CommandManifest {
+            name: name.to_string(),
+            description: command_info.description.clone(),
+            permissions: command_info
+                .default_member_permissions
+                .map(|permissions| {
+                    permissions
+                        .iter_names()
+                        .map(|(name, _)| name.to_string())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            required_roles: command_info.required_roles.clone(),
+            options: command_info.options.clone(),
+            subcommands: command_info.subcommands.clone(),
+        }
+    }
+
+    pub fn to_file_content(&self, format: ManifestFormat) -> Result<String, Error> {
+        Ok(match format {
+            ManifestFormat::Toml => toml::to_string_pretty(self)?,
+            ManifestFormat::Json => serde_json::to_string_pretty(self)?,
+        })
+    }
+}
+
+fn validate_name(name: &str, kind: &str) -> Result<(), Error> {
+    if name.is_empty()
+        || name.chars().count() > MAX_NAME_LENGTH
+        || !name.chars().all(|char| {
+            char.is_ascii_lowercase() || char.is_ascii_digit() || char == '_' || char == '-'
+        })
+    {
+        return Err(format!("\"{name}\" is not a valid {kind} name. Names must be 1 to {MAX_NAME_LENGTH} lowercase letters, numbers, dashes or underscores.").into());
+    }
+
+    Ok(())
+}
+
+fn validate_description(description: &str) -> Result<(), Error> {
+    let description_length = description.chars().count();
+    if description_length == 0 || description_length > MAX_DESCRIPTION_LENGTH {
+        return Err(format!(
+            "The description must be between 1 and {MAX_DESCRIPTION_LENGTH} characters long."
+        )
+        .into());
+    }
+
+    Ok(())
+}
+
+fn validate_options(options: &[CommandOption]) -> Result<(), Error> {
+    if options.len() > MAX_OPTIONS {
+        return Err(format!("Commands can't have more than {MAX_OPTIONS} options.").into());
+    }
+
+    let mut option_names = HashSet::new();
+    let mut found_optional = false;
+
+    for (index, option) in options.iter().enumerate() {
+        let option_name = &option.name;
+
+        option
+            .to_create_option()
+            .map_err(|err| format!("Option {} ({option_name}): {err}", index + 1))?;
+
+        if !option_names.insert(option_name) {
+            return Err(format!("There's more than one option named {option_name}.").into());
+        }
+
+        if option.required && found_optional {
+            return Err(format!("The option {option_name} is required, but comes after an optional option. Required options must come first.").into());
+        }
+        found_optional |= !option.required;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn option(kind: &str, name: &str) -> CommandOption {
+        CommandOption {
+            kind: kind.to_string(),
+            name: name.to_string(),
+            description: None,
+            required: true,
+            choices: Vec::new(),
+            min_value: None,
+            max_value: None,
+            min_length: None,
+            max_length: None,
+            autocomplete: false,
+        }
+    }
+
+    fn command_info(
+        options: Vec<CommandOption>,
+        subcommands: Vec<LuaSubcommand>,
+    ) -> LuaCommandInfo {
+        LuaCommandInfo {
+            lua_code: "return function() end".to_string(),
+            filename: "roll.luau".to_string(),
+            description: "Rolls a die".to_string(),
+            options,
+            default_member_permissions: Some(
+                Permissions::MANAGE_MESSAGES | Permissions::KICK_MEMBERS,
+            ),
+            required_roles: vec![RoleId::new(5)],
+            subcommands,
+        }
+    }
+
+    #[test]
+    fn toml_manifests_are_parsed() {
+        let manifest = CommandManifest::parse(
+            r#"
+            name = "roll"
+            description = "Rolls a die"
+            permissions = ["MANAGE_MESSAGES"]
+
+            [[options]]
+            name = "sides"
+            type = "integer"
+            min_value = 2
+            required = false
+            "#,
+            ManifestFormat::Toml,
+        )
+        .unwrap();
+
+        assert_eq!(manifest.name, "roll");
+        assert_eq!(manifest.description, "Rolls a die");
+        assert_eq!(
+            manifest.default_member_permissions().unwrap(),
+            Some(Permissions::MANAGE_MESSAGES)
+        );
+        assert_eq!(
+            manifest.options,
+            vec![CommandOption {
+                min_value: Some(2.0),
+                required: false,
+                ..option("integer", "sides")
+            }]
+        );
+    }
+
+    #[test]
+    fn json_manifests_are_parsed() {
+        let manifest = CommandManifest::parse(
+            r#"{
+                "name": "greet",
+                "description": "Greets someone",
+                "required_roles": ["5"],
+                "options": [
+                    { "name": "who", "type": "user" },
+                    { "name": "greeting", "type": "string", "choices": ["hi", "hello"], "required": false }
+                ]
+            }"#,
+            ManifestFormat::Json,
+        )
+        .unwrap();
+
+        assert_eq!(manifest.name, "greet");
+        assert_eq!(manifest.required_roles, vec![RoleId::new(5)]);
+        assert_eq!(manifest.default_member_permissions().unwrap(), None);
+        assert_eq!(
+            manifest.options,
+            vec![
+                option("user", "who"),
+                CommandOption {
+                    choices: vec!["hi".to_string(), "hello".to_string()],
+                    required: false,
+                    ..option("string", "greeting")
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        let Err(err) = CommandManifest::parse(
+            r#"
+            name = "roll"
+            description = "Rolls a die"
+            colour = "red"
+            "#,
+            ManifestFormat::Toml,
+        ) else {
+            panic!("The manifest shouldn't parse.");
+        };
+
+        assert!(err.to_string().contains("colour"), "{err}");
+    }
+
+    #[test]
+    fn bad_option_types_are_rejected() {
+        let Err(err) = CommandManifest::parse(
+            r#"{
+                "name": "roll",
+                "description": "Rolls a die",
+                "options": [{ "name": "sides", "type": "dice" }]
+            }"#,
+            ManifestFormat::Json,
+        ) else {
+            panic!("The manifest shouldn't parse.");
+        };
+
+        assert_eq!(
+            err.to_string(),
+            "Option 1 (sides): \"dice\" is not a valid option type."
+        );
+    }
+
+    #[test]
+    fn min_over_max_is_rejected() {
+        let Err(err) = CommandManifest::parse(
+            r#"
+            name = "roll"
+            description = "Rolls a die"
+
+            [[options]]
+            name = "sides"
+            type = "integer"
+            min_value = 10
+            max_value = 2
+            "#,
+            ManifestFormat::Toml,
+        ) else {
+            panic!("The manifest shouldn't parse.");
+        };
+
+        assert_eq!(
+            err.to_string(),
+            "Option 1 (sides): The min_value of sides can't be more than its max_value."
+        );
+
+        let Err(err) = CommandManifest::parse(
+            r#"
+            name = "say"
+            description = "Says something"
+
+            [[options]]
+            name = "text"
+            type = "string"
+            min_length = 10
+            max_length = 2
+            "#,
+            ManifestFormat::Toml,
+        ) else {
+            panic!("The manifest shouldn't parse.");
+        };
+
+        assert_eq!(
+            err.to_string(),
+            "Option 1 (text): The min_length of text can't be more than its max_length."
+        );
+    }
+
+    #[test]
+    fn subcommands_are_parsed() {
+        let manifest = CommandManifest::parse(
+            r#"
+            name = "tag"
+            description = "Manages tags"
+
+            [[subcommands]]
+            name = "add"
+            description = "Adds a tag"
+
+            [[subcommands.options]]
+            name = "tag"
+            type = "string"
+
+            [[subcommands]]
+            name = "list"
+            description = "Lists the tags"
+            "#,
+            ManifestFormat::Toml,
+        )
+        .unwrap();
+
+        assert_eq!(
+            manifest.subcommands,
+            vec![
+                LuaSubcommand {
+                    name: "add".to_string(),
+                    description: "Adds a tag".to_string(),
+                    options: vec![option("string", "tag")],
+                },
+                LuaSubcommand {
+                    name: "list".to_string(),
+                    description: "Lists the tags".to_string(),
+                    options: Vec::new(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn subcommands_cant_be_mixed_with_options() {
+        let Err(err) = CommandManifest::parse(
+            r#"{
+                "name": "tag",
+                "description": "Manages tags",
+                "options": [{ "name": "tag", "type": "string" }],
+                "subcommands": [{ "name": "add", "description": "Adds a tag" }]
+            }"#,
+            ManifestFormat::Json,
+        ) else {
+            panic!("The manifest shouldn't parse.");
+        };
+
+        assert!(err.to_string().contains("can't have options"), "{err}");
+
+        let Err(err) = CommandManifest::parse(
+            r#"{
+                "name": "tag",
+                "description": "Manages tags",
+                "subcommands": [{
+                    "name": "add",
+                    "description": "Adds a tag",
+                    "options": [{ "name": "nested", "type": "sub_command" }]
+                }]
+            }"#,
+            ManifestFormat::Json,
+        ) else {
+            panic!("The manifest shouldn't parse.");
+        };
+
+        assert!(
+            err.to_string()
+                .starts_with("Subcommand add: Option 1 (nested)"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn downloaded_manifests_create_the_same_command() {
+        let commands = [
+            command_info(
+                vec![
+                    CommandOption {
+                        min_value: Some(2.0),
+                        max_value: Some(100.0),
+                        ..option("integer", "sides")
+                    },
+                    CommandOption {
+                        description: Some("Why you're rolling".to_string()),
+                        required: false,
+                        autocomplete: true,
+                        max_length: Some(50),
+                        ..option("string", "reason")
+                    },
+                ],
+                Vec::new(),
+            ),
+            command_info(
+                Vec::new(),
+                vec![LuaSubcommand {
+                    name: "add".to_string(),
+                    description: "Adds a tag".to_string(),
+                    options: vec![CommandOption {
+                        choices: vec!["red".to_string(), "blue".to_string()],
+                        ..option("string", "colour")
+                    }],
+                }],
+            ),
+        ];
+
+        for command in commands {
+            for format in [ManifestFormat::Toml, ManifestFormat::Json] {
+                let content = CommandManifest::from_command("roll", &command)
+                    .to_file_content(format)
+                    .unwrap();
+
+                let manifest = CommandManifest::parse(&content, format).unwrap();
+
+                assert_eq!(manifest.name, "roll");
+                assert_eq!(manifest.description, command.description);
+                assert_eq!(manifest.options, command.options);
+                assert_eq!(manifest.subcommands, command.subcommands);
+                assert_eq!(
+                    manifest.default_member_permissions().unwrap(),
+                    command.default_member_permissions
+                );
+                assert_eq!(manifest.required_roles, command.required_roles);
+            }
+        }
+    }
+}
@@ -0,0 +1,286 @@
+use std::{collections::HashMap, sync::Arc};
+
+use mlua::{Function, Lua};
+use poise::serenity_prelude::GuildId;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex, OnceCell};
+
+use crate::{managers::db::Database, Error};
+
+use super::LuaManager;
+
+pub const MAX_MODULES_PER_GUILD: usize = 25;
+const MAX_MODULE_NAME_LENGTH: usize = 32;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LuaModuleInfo {
+    pub lua_code: String,
+    pub filename: String,
+}
+
+/// The modules of a guild. None until they're fetched from the database.
+///
+/// This is kept outside of the GuildLuaData lock because `require` is often called while the
+/// GuildLuaData is locked, like when a command gets loaded.
+pub type GuildLuaModules = Arc<Mutex<Option<HashMap<String, LuaModuleInfo>>>>;
+
+tokio::task_local! {
+    /// The modules that the current `require` call happens in, outermost first.
+    ///
+    /// This is per call chain, so that lua code requiring the same module at the same time isn't mistaken for a loop.
+    static REQUIRE_CHAIN: Vec<String>;
+}
+
+/// Modules that have been loaded by a lua instance.
+///
+/// The values belong to the lua instance that loaded them, so every new instance gets its own.
+#[derive(Default)]
+struct LoadedModules {
+    /// Empty while a module is being loaded, so that others requiring it wait for it instead of loading it again.
+    values: HashMap<String, Arc<OnceCell<mlua::Value>>>,
+    /// Which module each module that is being loaded is waiting on.
+    ///
+    /// Modules being loaded by different call chains can otherwise end up waiting on each other forever.
+    waiting_on: HashMap<String, String>,
+}
+
+fn module_loop_error(module_name: &str) -> mlua::Error {
+    mlua::Error::runtime(format!(
+        "The module {module_name} is already being loaded. Modules can't require each other in a loop."
+    ))
+}
+
+pub fn check_module_name(module_name: &str) -> Result<(), Error> {
+    if module_name.is_empty()
+        || module_name.chars().count() > MAX_MODULE_NAME_LENGTH
+        || !module_name
+            .chars()
+            .all(|char| char.is_ascii_alphanumeric() || char == '_' || char == '-')
+    {
+        return Err(format!("Module names must be between 1 and {MAX_MODULE_NAME_LENGTH} characters long and only contain letters, numbers, _ and -.").into());
+    }
+
+    Ok(())
+}
+
+pub async fn get_or_fetch_modules<'a>(
+    modules: &'a mut Option<HashMap<String, LuaModuleInfo>>,
+    guild_id: GuildId,
+    db: &dyn Database,
+) -> Result<&'a mut HashMap<String, LuaModuleInfo>, Error> {
+    match modules {
+        Some(modules) => Ok(modules),
+        None => {
+            let fetched_modules = db.get_all_guild_lua_modules(guild_id).await?;
+            Ok(modules.insert(fetched_modules))
+        }
+    }
+}
+
+/// Creates the `require` function of a lua instance.
+///
+/// Every module only runs once per lua instance, and after that `require` returns what it returned the first time.
+pub fn create_require_function(
+    lua: &Lua,
+    guild_id: GuildId,
+    modules: GuildLuaModules,
+    db: Arc<dyn Database>,
+) -> mlua::Result<Function> {
+    let loaded_modules = Arc::new(Mutex::new(LoadedModules::default()));
+
+    lua.create_async_function(move |lua, module_name: String| {
+        let loaded_modules = loaded_modules.clone();
+        let modules = modules.clone();
+        let db = db.clone();
+        async move {
+            let chain = REQUIRE_CHAIN
+                .try_with(|chain| chain.clone())
+                .unwrap_or_default();
+
+            if chain.contains(&module_name) {
+                return Err(module_loop_error(&module_name));
+            }
+
+            let value = {
+                let mut loaded_modules = loaded_modules.lock().await;
+
+                let value = loaded_modules
+                    .values
+                    .entry(module_name.clone())
+                    .or_default()
+                    .clone();
+
+                if let Some(value) = value.get() {
+                    return Ok(value.clone());
+                }
+
+                if let Some(requiring_module) = chain.last() {
+                    // Another call chain might be loading the module and be waiting on this one.
+                    let mut waited_on = loaded_modules.waiting_on.get(&module_name);
+                    while let Some(waited_module) = waited_on {
+                        if chain.contains(waited_module) {
+                            return Err(module_loop_error(&module_name));
+                        }
+                        waited_on = loaded_modules.waiting_on.get(waited_module);
+                    }
+
+                    loaded_modules
+                        .waiting_on
+                        .insert(requiring_module.clone(), module_name.clone());
+                }
+
+                value
+            };
+
+            let mut module_chain = chain.clone();
+            module_chain.push(module_name.clone());
+
+            let result = value
+                .get_or_try_init(|| {
+                    REQUIRE_CHAIN.scope(
+                        module_chain,
+                        load_module(&lua, &module_name, guild_id, &modules, db.as_ref()),
+                    )
+                })
+                .await
+                .cloned();
+
+            if let Some(requiring_module) = chain.last() {
+                loaded_modules
+                    .lock()
+                    .await
+                    .waiting_on
+                    .remove(requiring_module);
+            }
+
+            result
+        }
+    })
+}
+
+async fn load_module(
+    lua: &Lua,
+    module_name: &str,
+    guild_id: GuildId,
+    modules: &GuildLuaModules,
+    db: &dyn Database,
+) -> mlua::Result<mlua::Value> {
+    let module_info = {
+        let mut modules = modules.lock().await;
+        let modules = get_or_fetch_modules(&mut modules, guild_id, db)
+            .await
+            .map_err(mlua::Error::runtime)?;
+
+        let Some(module_info) = modules.get(module_name) else {
+            return Err(mlua::Error::runtime(format!(
+                "There is no module named {module_name}."
+            )));
+        };
+
+        module_info.clone()
+    };
+
+    let value: mlua::Value = lua
+        .load(&module_info.lua_code)
+        .set_name(format!("={} (Module: {module_name})", module_info.filename))
+        .eval_async()
+        .await?;
+
+    // Same as regular lua, modules that don't return anything make require return true.
+    if value.is_nil() {
+        return Ok(mlua::Value::Boolean(true));
+    }
+
+    Ok(value)
+}
+
+impl LuaManager {
+    /// Adds or replaces a module.
+    ///
+    /// Restarts the lua instance of the guild, so that commands that use the module get the new version.
+    pub async fn set_module(
+        self: &Arc<Self>,
+        guild_id: GuildId,
+        module_name: String,
+        lua_code: String,
+        filename: String,
+    ) -> Result<(), Error> {
+        check_module_name(&module_name)?;
+
+        // Make sure the provided code is valid lua code.
+        self.try_parse_code(&lua_code)?;
+
+        let guild_info = self.get_guild_lua_data(guild_id).await;
+        let mut locked_guild_info = guild_info.lock().await;
+
+        {
+            let modules = locked_guild_info.modules.clone();
+            let mut modules = modules.lock().await;
+            let modules = get_or_fetch_modules(&mut modules, guild_id, self.db.as_ref()).await?;
+
+            if !modules.contains_key(&module_name) && modules.len() >= MAX_MODULES_PER_GUILD {
+                return Err(
+                    format!("You may only have up to {MAX_MODULES_PER_GUILD} modules.").into(),
+                );
+            }
+
+            let lua_module_info = LuaModuleInfo { lua_code, filename };
+            self.db
+                .set_guild_lua_module(guild_id, &module_name, &lua_module_info)
+                .await?;
+            modules.insert(module_name, lua_module_info);
+        }
+
+        locked_guild_info.restart(false);
+
+        Ok(())
+    }
+
+    pub async fn delete_module(
+        self: &Arc<Self>,
+        guild_id: GuildId,
+        module_name: &str,
+    ) -> Result<(), Error> {
+        let guild_info = self.get_guild_lua_data(guild_id).await;
+        let mut locked_guild_info = guild_info.lock().await;
+
+        {
+            let modules = locked_guild_info.modules.clone();
+            let mut modules = modules.lock().await;
+            let modules = get_or_fetch_modules(&mut modules, guild_id, self.db.as_ref()).await?;
+
+            if !modules.contains_key(module_name) {
+                return Err(format!("There is no module named {module_name}.").into());
+            }
+
+            self.db
+                .remove_guild_lua_module(guild_id, module_name)
+                .await?;
+            modules.remove(module_name);
+        }
+
+        locked_guild_info.restart(false);
+
+        Ok(())
+    }
+
+    pub async fn get_modules(
+        self: &Arc<Self>,
+        guild_id: GuildId,
+    ) -> Result<HashMap<String, LuaModuleInfo>, Error> {
+        let modules = self
+            .get_guild_lua_data(guild_id)
+            .await
+            .lock()
+            .await
+            .modules
+            .clone();
+        let mut modules = modules.lock().await;
+
+        Ok(
+            get_or_fetch_modules(&mut modules, guild_id, self.db.as_ref())
+                .await?
+                .clone(),
+        )
+    }
+}
@@ -0,0 +1,273 @@
+use std::str::FromStr;
+
+use mlua::{FromLua, Lua, Table};
+use poise::serenity_prelude::{
+    CreateActionRow, CreateAllowedMentions, CreateEmbed, CreateEmbedAuthor, CreateEmbedFooter,
+    CreateInteractionResponseFollowup, CreateInteractionResponseMessage, EditInteractionResponse,
+    RoleId, UserId,
+};
+
+use super::components::parse_components;
+
+const MAX_CONTENT_LENGTH: usize = 2000;
+const MAX_EMBEDS: usize = 10;
+const MAX_EMBED_FIELDS: usize = 25;
+
+/// What lua code gives to `ctx:reply`, `ctx:followup` and `ctx:edit_reply`.
+///
+/// Either a string, or a table like `{ content = "Hi", embeds = { { title = "Title" } }, ephemeral = true }`.
+pub struct LuaReply {
+    pub(super) content: Option<String>,
+    pub(super) embeds: Vec<CreateEmbed>,
+    /// None leaves the components of an edited message as they are, an empty list removes them.
+    components: Option<Vec<CreateActionRow>>,
+    pub(super) ephemeral: bool,
+    allowed_mentions: CreateAllowedMentions,
+}
+
+/// By default only users can be mentioned, same as messages sent with the guild api.
+fn default_allowed_mentions() -> CreateAllowedMentions {
+    CreateAllowedMentions::new().all_users(true)
+}
+
+fn parse_ids<T: FromStr>(table: &Table, kind: &str) -> mlua::Result<Vec<T>> {
+    let mut ids = Vec::new();
+    for id in table.sequence_values::<String>() {
+        let id = id?;
+        let Ok(id) = id.parse::<T>() else {
+            return Err(mlua::Error::runtime(format!(
+                "\"{id}\" is not a valid {kind} id."
+            )));
+        };
+        ids.push(id);
+    }
+
+    Ok(ids)
+}
+
+/// Parses a table like `{ users = true, roles = { "123" } }`.
+///
+/// `users` can be a boolean or a list of user ids, `roles` can only be a list of role ids.
+/// @everyone and @here can't be mentioned.
+fn parse_allowed_mentions(table: Table) -> mlua::Result<CreateAllowedMentions> {
+    let mut allowed_mentions = CreateAllowedMentions::new();
+
+    match table.get::<mlua::Value>("users")? {
+        mlua::Value::Nil => {}
+        mlua::Value::Boolean(all_users) => {
+            allowed_mentions = allowed_mentions.all_users(all_users);
+        }
+        mlua::Value::Table(users) => {
+            allowed_mentions = allowed_mentions.users(parse_ids::<UserId>(&users, "user")?);
+        }
+        _ => {
+            return Err(mlua::Error::runtime(
+                "allowed_mentions.users must be a boolean or a list of user ids.",
+            ))
+        }
+    }
+
+    if let Some(roles) = table.get::<Option<Table>>("roles")? {
+        allowed_mentions = allowed_mentions.roles(parse_ids::<RoleId>(&roles, "role")?);
+    }
+
+    if let Some(replied_user) = table.get::<Option<bool>>("replied_user")? {
+        allowed_mentions = allowed_mentions.replied_user(replied_user);
+    }
+
+    Ok(allowed_mentions)
+}
+
+fn parse_embed(table: Table) -> mlua::Result<CreateEmbed> {
+    let mut embed = CreateEmbed::new();
+
+    if let Some(title) = table.get::<Option<String>>("title")? {
+        embed = embed.title(title);
+    }
+
+    if let Some(description) = table.get::<Option<String>>("description")? {
+        embed = embed.description(description);
+    }
+
+    if let Some(url) = table.get::<Option<String>>("url")? {
+        embed = embed.url(url);
+    }
+
+    if let Some(color) = table.get::<Option<u32>>("color")? {
+        embed = embed.color(color);
+    }
+
+    if let Some(image) = table.get::<Option<String>>("image")? {
+        embed = embed.image(image);
+    }
+
+    if let Some(thumbnail) = table.get::<Option<String>>("thumbnail")? {
+        embed = embed.thumbnail(thumbnail);
+    }
+
+    if let Some(footer) = table.get::<Option<String>>("footer")? {
+        embed = embed.footer(CreateEmbedFooter::new(footer));
+    }
+
+    if let Some(author) = table.get::<Option<String>>("author")? {
+        embed = embed.author(CreateEmbedAuthor::new(author));
+    }
+
+    if let Some(fields) = table.get::<Option<Table>>("fields")? {
+        if fields.raw_len() > MAX_EMBED_FIELDS {
+            return Err(mlua::Error::runtime(format!(
+                "Embeds can't have more than {MAX_EMBED_FIELDS} fields."
+            )));
+        }
+
+        for field in fields.sequence_values::<Table>() {
+            let field = field?;
+            embed = embed.field(
+                field.get::<String>("name")?,
+                field.get::<String>("value")?,
+                field.get::<Option<bool>>("inline")?.unwrap_or(false),
+            );
+        }
+    }
+
+    Ok(embed)
+}
+
+impl FromLua for LuaReply {
+    fn from_lua(value: mlua::Value, _lua: &Lua) -> mlua::Result<Self> {
+        let mut reply = LuaReply {
+            content: None,
+            embeds: Vec::new(),
+            components: None,
+            ephemeral: false,
+            allowed_mentions: default_allowed_mentions(),
+        };
+
+        match value {
+            mlua::Value::String(content) => reply.content = Some(content.to_str()?.to_string()),
+            mlua::Value::Table(table) => {
+                reply.content = table.get::<Option<String>>("content")?;
+                reply.ephemeral = table.get::<Option<bool>>("ephemeral")?.unwrap_or(false);
+
+                if let Some(embeds) = table.get::<Option<Table>>("embeds")? {
+                    if embeds.raw_len() > MAX_EMBEDS {
+                        return Err(mlua::Error::runtime(format!(
+                            "Replies can't have more than {MAX_EMBEDS} embeds."
+                        )));
+                    }
+
+                    for embed in embeds.sequence_values::<Table>() {
+                        reply.embeds.push(parse_embed(embed?)?);
+                    }
+                }
+
+                if let Some(components) = table.get::<Option<Table>>("components")? {
+                    reply.components = Some(parse_components(components)?);
+                }
+
+                if let Some(allowed_mentions) = table.get::<Option<Table>>("allowed_mentions")? {
+                    reply.allowed_mentions = parse_allowed_mentions(allowed_mentions)?;
+                }
+            }
+            _ => {
+                return Err(mlua::Error::runtime(
+                    "A reply must be a string or a table with content, embeds and/or components.",
+                ))
+            }
+        }
+
+        if let Some(content) = &reply.content {
+            if content.chars().count() > MAX_CONTENT_LENGTH {
+                return Err(mlua::Error::runtime(format!(
+                    "Replies can't be longer than {MAX_CONTENT_LENGTH} characters."
+                )));
+            }
+        }
+
+        if reply.content.as_deref().unwrap_or_default().is_empty()
+            && reply.embeds.is_empty()
+            && reply.components.as_ref().is_none_or(|rows| rows.is_empty())
+        {
+            return Err(mlua::Error::runtime(
+                "A reply must have content, an embed or a component.",
+            ));
+        }
+
+        Ok(reply)
+    }
+}
+
+impl LuaReply {
+    pub fn into_response_message(self) -> CreateInteractionResponseMessage {
+        let mut message = CreateInteractionResponseMessage::new()
+            .embeds(self.embeds)
+            .ephemeral(self.ephemeral)
+            .allowed_mentions(self.allowed_mentions);
+
+        if let Some(content) = self.content {
+            message = message.content(content);
+        }
+
+        if let Some(components) = self.components {
+            message = message.components(components);
+        }
+
+        message
+    }
+
+    pub fn into_followup(self) -> CreateInteractionResponseFollowup {
+        let mut followup = CreateInteractionResponseFollowup::new()
+            .embeds(self.embeds)
+            .ephemeral(self.ephemeral)
+            .allowed_mentions(self.allowed_mentions);
+
+        if let Some(content) = self.content {
+            followup = followup.content(content);
+        }
+
+        if let Some(components) = self.components {
+            followup = followup.components(components);
+        }
+
+        followup
+    }
+
+    /// Whether the message is ephemeral is decided by the original response, so it's ignored here.
+    pub fn into_edit(self) -> EditInteractionResponse {
+        let mut edit = EditInteractionResponse::new()
+            .content(self.content.unwrap_or_default())
+            .embeds(self.embeds)
+            .allowed_mentions(self.allowed_mentions);
+
+        if let Some(components) = self.components {
+            edit = edit.components(components);
+        }
+
+        edit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_mention_ids_are_errors() {
+        let lua = Lua::new();
+
+        let table: Table = lua
+            .load(r#"{ users = { "0" }, roles = { "5" } }"#)
+            .eval()
+            .unwrap();
+        let Err(err) = parse_allowed_mentions(table) else {
+            panic!("0 isn't a valid id.");
+        };
+        assert!(err.to_string().contains("\"0\" is not a valid user id."));
+
+        let table: Table = lua
+            .load(r#"{ users = { "123" }, roles = { "abc" } }"#)
+            .eval()
+            .unwrap();
+        assert!(parse_allowed_mentions(table).is_err());
+    }
+}
@@ -0,0 +1,366 @@
+use std::sync::Arc;
+
+use chrono::{
+    format::{Item, StrftimeItems},
+    TimeZone, Utc,
+};
+use chrono_tz::Tz;
+use mlua::{Lua, Table, UserData, UserDataMethods};
+use poise::serenity_prelude::UserId;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{
+    commands::utility_commands::profile::time_format::TimeFormat,
+    managers::{
+        db::Database,
+        lua_manager::serde_and_lua::{lua_to_serde, serde_to_lua},
+        profile_manager::ProfileManager,
+    },
+};
+
+const DEFAULT_TIME_FORMAT: &str = "%Y-%m-%d %H:%M";
+
+/// Adds the `json`, `time` and `random` globals, and more functions to the `string` library.
+///
+/// Must be called before the lua instance is sandboxed, since the `string` library is read only after that.
+pub fn register_stdlib(lua: &Lua) -> mlua::Result<()> {
+    lua.globals().set("json", create_json_table(lua)?)?;
+    lua.globals().set("time", create_time_table(lua)?)?;
+    lua.globals().set("random", create_random_table(lua)?)?;
+    add_string_functions(lua)?;
+    Ok(())
+}
+
+fn create_json_table(lua: &Lua) -> mlua::Result<Table> {
+    let json = lua.create_table()?;
+
+    json.set(
+        "encode",
+        lua.create_function(|_lua, value: mlua::Value| {
+            let serde_value = lua_to_serde(value).map_err(|err| {
+                mlua::Error::runtime(format!(
+                    "json.encode can only encode values that data stores can hold. {err}"
+                ))
+            })?;
+            serde_json::to_string(&serde_value).map_err(mlua::Error::external)
+        })?,
+    )?;
+
+    json.set(
+        "decode",
+        lua.create_function(|lua, json_string: String| {
+            let serde_value = serde_json::from_str(&json_string)
+                .map_err(|err| mlua::Error::runtime(format!("Invalid json: {err}")))?;
+            serde_to_lua(serde_value, lua)
+        })?,
+    )?;
+
+    Ok(json)
+}
+
+fn parse_timezone(timezone: Option<String>) -> mlua::Result<Tz> {
+    match timezone {
+        Some(timezone) => Tz::from_str_insensitive(&timezone)
+            .map_err(|_| mlua::Error::runtime(format!("\"{timezone}\" is not a valid timezone."))),
+        None => Ok(Tz::UTC),
+    }
+}
+
+fn create_time_table(lua: &Lua) -> mlua::Result<Table> {
+    let time = lua.create_table()?;
+
+    // Seconds since the unix epoch, with milliseconds as decimals.
+    time.set(
+        "now",
+        lua.create_function(|_lua, ()| Ok(Utc::now().timestamp_millis() as f64 / 1000.0))?,
+    )?;
+
+    // Formats a unix timestamp in a timezone, using strftime style formatting.
+    time.set(
+        "format",
+        lua.create_function(
+            |_lua, (timestamp, timezone, format): (f64, Option<String>, Option<String>)| {
+                let timezone = parse_timezone(timezone)?;
+                let format = format.unwrap_or_else(|| DEFAULT_TIME_FORMAT.to_string());
+
+                // Formatting with an invalid specifier would panic, so check for them first.
+                let items = StrftimeItems::new(&format).collect::<Vec<_>>();
+                if items.contains(&Item::Error) {
+                    return Err(mlua::Error::runtime(format!(
+                        "\"{format}\" is not a valid time format."
+                    )));
+                }
+
+                let Some(date_time) = timezone
+                    .timestamp_millis_opt((timestamp * 1000.0) as i64)
+                    .single()
+                else {
+                    return Err(mlua::Error::runtime(format!(
+                        "{timestamp} is not a valid timestamp."
+                    )));
+                };
+
+                Ok(date_time.format_with_items(items.into_iter()).to_string())
+            },
+        )?,
+    )?;
+
+    Ok(time)
+}
+
+/// Adds functions to the `string` library. Strings can call them as methods too, like `name:trim()`.
+fn add_string_functions(lua: &Lua) -> mlua::Result<()> {
+    let string: Table = lua.globals().get("string")?;
+
+    string.set(
+        "trim",
+        lua.create_function(|_lua, string: String| Ok(string.trim().to_string()))?,
+    )?;
+
+    string.set(
+        "starts_with",
+        lua.create_function(|_lua, (string, prefix): (String, String)| {
+            Ok(string.starts_with(&prefix))
+        })?,
+    )?;
+
+    string.set(
+        "ends_with",
+        lua.create_function(|_lua, (string, suffix): (String, String)| {
+            Ok(string.ends_with(&suffix))
+        })?,
+    )?;
+
+    // Cuts a string down to at most `max_length` characters, ending it with "..." if it was cut.
+    string.set(
+        "truncate",
+        lua.create_function(|_lua, (string, max_length): (String, usize)| {
+            if string.chars().count() <= max_length {
+                return Ok(string);
+            }
+
+            let mut truncated = string
+                .chars()
+                .take(max_length.saturating_sub(3))
+                .collect::<String>();
+            truncated.push_str(&"..."[..max_length.min(3)]);
+            Ok(truncated)
+        })?,
+    )?;
+
+    Ok(())
+}
+
+/// A random number generator made with `random.new(seed)`, which always gives the same numbers for the same seed.
+struct SeededRandom(StdRng);
+
+impl UserData for SeededRandom {
+    fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method_mut("int", |_lua, this, (min, max): (i64, i64)| {
+            random_int(&mut this.0, min, max)
+        });
+
+        methods.add_method_mut("float", |_lua, this, ()| Ok(this.0.gen::<f64>()));
+    }
+}
+
+fn random_int(rng: &mut impl Rng, min: i64, max: i64) -> mlua::Result<i64> {
+    if min > max {
+        return Err(mlua::Error::runtime(
+            "The min can't be bigger than the max.",
+        ));
+    }
+
+    Ok(rng.gen_range(min..=max))
+}
+
+fn create_random_table(lua: &Lua) -> mlua::Result<Table> {
+    let random = lua.create_table()?;
+
+    random.set(
+        "int",
+        lua.create_function(|_lua, (min, max): (i64, i64)| {
+            random_int(&mut rand::thread_rng(), min, max)
+        })?,
+    )?;
+
+    random.set(
+        "float",
+        lua.create_function(|_lua, ()| Ok(rand::thread_rng().gen::<f64>()))?,
+    )?;
+
+    random.set(
+        "new",
+        lua.create_function(|_lua, seed: Option<u64>| {
+            Ok(SeededRandom(match seed {
+                Some(seed) => StdRng::seed_from_u64(seed),
+                None => StdRng::from_entropy(),
+            }))
+        })?,
+    )?;
+
+    Ok(random)
+}
+
+/// Makes the `profile` global, so scripts can respect the timezone and time format users saved with `/profile`.
+pub fn create_profile_table(
+    lua: &Lua,
+    profile_manager: Arc<ProfileManager>,
+    db: Arc<dyn Database>,
+) -> mlua::Result<Table> {
+    let profile = lua.create_table()?;
+
+    let (timezone_profile_manager, timezone_db) = (profile_manager.clone(), db.clone());
+    profile.set(
+        "get_timezone",
+        lua.create_async_function(move |_lua, user_id: String| {
+            let (profile_manager, db) = (timezone_profile_manager.clone(), timezone_db.clone());
+            async move {
+                let user_id = parse_user_id(&user_id)?;
+                let profile_data = profile_manager.get_profile_data(user_id).await;
+                let mut profile_lock = profile_data.lock().await;
+                let profile = profile_lock
+                    .get_profile(db.as_ref())
+                    .await
+                    .map_err(mlua::Error::runtime)?;
+
+                // Only give timezones that are valid, so they can be passed to time.format.
+                Ok(profile
+                    .get_timezone()
+                    .and_then(|(_, timezone)| timezone)
+                    .map(|timezone| timezone.name().to_string()))
+            }
+        })?,
+    )?;
+
+    profile.set(
+        "get_time_format",
+        lua.create_async_function(move |_lua, user_id: String| {
+            let (profile_manager, db) = (profile_manager.clone(), db.clone());
+            async move {
+                let user_id = parse_user_id(&user_id)?;
+                let profile_data = profile_manager.get_profile_data(user_id).await;
+                let mut profile_lock = profile_data.lock().await;
+                let profile = profile_lock
+                    .get_profile(db.as_ref())
+                    .await
+                    .map_err(mlua::Error::runtime)?;
+
+                Ok(profile.time_format.map(|time_format| match time_format {
+                    TimeFormat::Twelve => 12,
+                    TimeFormat::TwentyFour => 24,
+                }))
+            }
+        })?,
+    )?;
+
+    Ok(profile)
+}
+
+fn parse_user_id(user_id: &str) -> mlua::Result<UserId> {
+    user_id
+        .parse::<UserId>()
+        .map_err(|_| mlua::Error::runtime(format!("\"{user_id}\" is not a valid user id.")))
+}
+
+#[cfg(test)]
+mod tests {
+    use mlua::FromLuaMulti;
+
+    use crate::managers::lua_manager::harness::LuaHarness;
+
+    async fn eval<T: FromLuaMulti>(lua_code: &str) -> mlua::Result<T> {
+        let harness = LuaHarness::new().unwrap();
+        harness.lua().load(lua_code).eval_async().await
+    }
+
+    #[tokio::test]
+    async fn json_round_trips() {
+        let (name, count, nested): (String, f64, bool) = eval(
+            r#"
+            local decoded = json.decode(json.encode({ name = "Bob", count = 3, nested = { ok = true } }))
+            return decoded.name, decoded.count, decoded.nested.ok
+            "#,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(name, "Bob");
+        assert_eq!(count, 3.0);
+        assert!(nested);
+    }
+
+    #[tokio::test]
+    async fn invalid_json_errors() {
+        let result = eval::<mlua::Value>(r#"return json.decode("{ not json")"#).await;
+        assert!(result.is_err_and(|err| err.to_string().contains("Invalid json")));
+
+        let result = eval::<String>(r#"return json.encode({ 1, nil, 3 })"#).await;
+        assert!(result.is_err_and(|err| err.to_string().contains("json.encode")));
+    }
+
+    #[tokio::test]
+    async fn time_is_formatted_in_the_timezone() {
+        // 2024-01-15 00:00 UTC, when Stockholm is an hour ahead.
+        let formatted: String =
+            eval(r#"return time.format(1705276800, "Europe/Stockholm", "%H:%M")"#)
+                .await
+                .unwrap();
+        assert_eq!(formatted, "01:00");
+
+        let formatted: String = eval(r#"return time.format(1705276800)"#).await.unwrap();
+        assert_eq!(formatted, "2024-01-15 00:00");
+    }
+
+    #[tokio::test]
+    async fn invalid_timezones_and_formats_error() {
+        let result = eval::<String>(r#"return time.format(0, "Europe/Nowhere")"#).await;
+        assert!(result.is_err_and(|err| err.to_string().contains("not a valid timezone")));
+
+        let result = eval::<String>(r#"return time.format(0, "UTC", "%Q")"#).await;
+        assert!(result.is_err_and(|err| err.to_string().contains("not a valid time format")));
+    }
+
+    #[tokio::test]
+    async fn seeded_random_gives_the_same_numbers() {
+        let code = r#"
+            local rng = random.new(42)
+            return rng:int(1, 1000000), rng:int(1, 1000000), rng:float()
+        "#;
+
+        let first: (i64, i64, f64) = eval(code).await.unwrap();
+        let second: (i64, i64, f64) = eval(code).await.unwrap();
+        assert_eq!(first, second);
+    }
+
+    #[tokio::test]
+    async fn random_int_needs_min_below_max() {
+        let result = eval::<i64>("return random.int(10, 1)").await;
+        assert!(result.is_err_and(|err| err.to_string().contains("min can't be bigger")));
+
+        let result = eval::<i64>("return random.new(1):int(10, 1)").await;
+        assert!(result.is_err());
+
+        let value: i64 = eval("return random.int(5, 5)").await.unwrap();
+        assert_eq!(value, 5);
+    }
+
+    #[tokio::test]
+    async fn string_functions_work_as_methods() {
+        let (trimmed, starts, ends, truncated, short): (String, bool, bool, String, String) = eval(
+            r#"
+                local name = "  Nizubot  "
+                return name:trim(), name:trim():starts_with("Nizu"), string.ends_with("bot", "Bot"),
+                    ("Hello world"):truncate(8), ("Hi"):truncate(8)
+                "#,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(trimmed, "Nizubot");
+        assert!(starts);
+        assert!(!ends);
+        assert_eq!(truncated, "Hello...");
+        assert_eq!(short, "Hi");
+    }
+}
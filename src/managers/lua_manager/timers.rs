@@ -0,0 +1,506 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use chrono::TimeZone;
+use chrono_tz::Tz;
+use mlua::{UserData, UserDataMethods};
+use poise::serenity_prelude::{Context, GuildId};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::Mutex;
+
+use crate::{
+    managers::{
+        db::{Database, DatabaseHealth, MAX_DATA_STORE_BYTES_PER_GUILD},
+        log_manager::{LogManager, LogSource, LogType},
+    },
+    utils::{get_seconds, IdType},
+    Error,
+};
+
+use super::{serde_and_lua::lua_to_serde, LuaEvent, LuaManager};
+
+pub const MAX_TIMERS_PER_GUILD: usize = 25;
+const MAX_TIMER_NAME_LENGTH: usize = 100;
+/// Repeating timers can't run more often than this.
+const MIN_INTERVAL_SECONDS: u64 = 60;
+/// Timers can't be set further ahead than this, which is a year.
+const MAX_TIMER_SECONDS: u64 = 365 * 24 * 60 * 60;
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TimerSchedule {
+    /// Runs once and then gets removed.
+    Once,
+    /// Runs every `seconds` seconds.
+    Interval { seconds: u64 },
+    /// Runs every day at the time in the timezone.
+    Daily {
+        hour: u32,
+        minute: u32,
+        timezone: String,
+    },
+}
+
+impl TimerSchedule {
+    /// Returns when the timer should run next after running at `previous_time`, or None if it shouldn't run again.
+    ///
+    /// Runs that were missed, like when the bot was offline, are skipped.
+    fn reschedule(&self, previous_time: u64, current_time: u64) -> Result<Option<u64>, Error> {
+        match self {
+            TimerSchedule::Once => Ok(None),
+            TimerSchedule::Interval { seconds } => {
+                let seconds = (*seconds).max(1);
+                let missed_runs = current_time.saturating_sub(previous_time) / seconds;
+                let next_time = (missed_runs + 1)
+                    .checked_mul(seconds)
+                    .and_then(|delay| previous_time.checked_add(delay))
+                    .ok_or("The next time of the timer is too far in the future.")?;
+                Ok(Some(next_time))
+            }
+            TimerSchedule::Daily {
+                hour,
+                minute,
+                timezone,
+            } => next_daily_time(*hour, *minute, timezone, current_time).map(Some),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LuaTimer {
+    pub schedule: TimerSchedule,
+    /// Unix time in seconds of when the timer runs next.
+    pub next_time: u64,
+    /// Given to the timer event handler.
+    #[serde(default)]
+    pub data: serde_json::Value,
+}
+
+/// Returns the first time after `after` where the clock in the timezone shows `hour`:`minute`.
+fn next_daily_time(hour: u32, minute: u32, timezone: &str, after: u64) -> Result<u64, Error> {
+    let Ok(tz) = Tz::from_str_insensitive(timezone) else {
+        return Err(format!("\"{timezone}\" is not a valid timezone.").into());
+    };
+
+    let Some(after_datetime) = tz.timestamp_opt(after as i64, 0).single() else {
+        return Err(format!("{after} is not a valid time.").into());
+    };
+
+    let mut date = after_datetime.date_naive();
+
+    // Checks a few days ahead because the time might not exist on some days due to daylight saving time.
+    for _ in 0..3 {
+        if let Some(datetime) = date
+            .and_hms_opt(hour, minute, 0)
+            .and_then(|naive_datetime| tz.from_local_datetime(&naive_datetime).earliest())
+        {
+            let timestamp = datetime.timestamp();
+            if timestamp > after as i64 {
+                return Ok(timestamp as u64);
+            }
+        }
+
+        let Some(next_date) = date.succ_opt() else {
+            break;
+        };
+        date = next_date;
+    }
+
+    Err(format!("Couldn't find when {hour:02}:{minute:02} happens next in {timezone}.").into())
+}
+
+/// Parses a time such as "09:00".
+fn parse_time_of_day(time: &str) -> Result<(u32, u32), Error> {
+    let invalid_time = || format!("\"{time}\" is not a valid time. Use the format HH:MM.");
+
+    let Some((hour, minute)) = time.trim().split_once(':') else {
+        return Err(invalid_time().into());
+    };
+
+    let (Ok(hour), Ok(minute)) = (hour.parse::<u32>(), minute.parse::<u32>()) else {
+        return Err(invalid_time().into());
+    };
+
+    if hour > 23 || minute > 59 {
+        return Err(invalid_time().into());
+    }
+
+    Ok((hour, minute))
+}
+
+/// Returns the time `seconds` from now, or an error if it's further ahead than timers can be set.
+fn seconds_from_now(seconds: u64) -> mlua::Result<u64> {
+    if seconds > MAX_TIMER_SECONDS {
+        return Err(mlua::Error::runtime(format!(
+            "Timers can't be set more than {MAX_TIMER_SECONDS} seconds ahead."
+        )));
+    }
+
+    get_seconds()
+        .checked_add(seconds)
+        .ok_or_else(|| mlua::Error::runtime("The time is too far in the future."))
+}
+
+/// The `timers` global that lets lua code schedule the timer event.
+///
+/// Timers are stored in the database, so they keep going after restarts.
+/// When a timer runs, the guild's timer event handler gets called with the name and data of the timer.
+pub struct TimersApi {
+    guild_id: GuildId,
+    db: Arc<dyn Database>,
+    /// The `timer_wait_until` of the LuaManager, which gets lowered so new timers aren't missed.
+    timer_wait_until: Arc<Mutex<u64>>,
+}
+
+impl TimersApi {
+    pub fn new(
+        guild_id: GuildId,
+        db: Arc<dyn Database>,
+        timer_wait_until: Arc<Mutex<u64>>,
+    ) -> Self {
+        Self {
+            guild_id,
+            db,
+            timer_wait_until,
+        }
+    }
+
+    async fn set_timer(
+        &self,
+        timer_name: String,
+        schedule: TimerSchedule,
+        next_time: u64,
+        data: mlua::Value,
+    ) -> mlua::Result<()> {
+        if timer_name.is_empty() || timer_name.chars().count() > MAX_TIMER_NAME_LENGTH {
+            return Err(mlua::Error::runtime(format!(
+                "Timer names must be between 1 and {MAX_TIMER_NAME_LENGTH} characters long."
+            )));
+        }
+
+        let lua_timer = LuaTimer {
+            schedule,
+            next_time,
+            data: lua_to_serde(data).map_err(mlua::Error::runtime)?,
+        };
+
+        let timers = self
+            .db
+            .get_guild_lua_timers(self.guild_id)
+            .await
+            .map_err(mlua::Error::runtime)?;
+        check_timer_data_size(&timer_name, &lua_timer.data, &timers)
+            .map_err(mlua::Error::runtime)?;
+
+        // Fails if the guild already has the most timers it can have.
+        self.db
+            .set_lua_timer(self.guild_id, &timer_name, &lua_timer)
+            .await
+            .map_err(mlua::Error::runtime)?;
+
+        let mut wait_until = self.timer_wait_until.lock().await;
+        *wait_until = (*wait_until).min(lua_timer.next_time);
+
+        Ok(())
+    }
+
+    /// Returns false if there was no timer with the name.
+    async fn cancel_timer(&self, timer_name: &str) -> Result<bool, Error> {
+        let timers = self.db.get_guild_lua_timers(self.guild_id).await?;
+        if !timers.contains_key(timer_name) {
+            return Ok(false);
+        }
+
+        self.db.remove_lua_timer(self.guild_id, timer_name).await?;
+
+        Ok(true)
+    }
+}
+
+/// The data of all timers in a guild can't be bigger than what its data stores can hold, so timers can't be used to store more.
+fn check_timer_data_size(
+    timer_name: &str,
+    data: &serde_json::Value,
+    timers: &HashMap<String, LuaTimer>,
+) -> Result<(), Error> {
+    let mut total_size = serde_json::to_string(data)?.len();
+    for (other_timer_name, timer) in timers {
+        // The timer gets replaced, so its old data doesn't count.
+        if other_timer_name != timer_name {
+            total_size += serde_json::to_string(&timer.data)?.len();
+        }
+    }
+
+    if total_size > MAX_DATA_STORE_BYTES_PER_GUILD {
+        return Err(format!(
+            "The data of all timers in this guild can't be bigger than {MAX_DATA_STORE_BYTES_PER_GUILD} bytes together."
+        )
+        .into());
+    }
+
+    Ok(())
+}
+
+impl UserData for TimersApi {
+    fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
+        methods.add_async_method(
+            "after",
+            async |_lua, this, (timer_name, seconds, data): (String, u64, mlua::Value)| {
+                let next_time = seconds_from_now(seconds.max(1))?;
+                this.set_timer(timer_name, TimerSchedule::Once, next_time, data)
+                    .await
+            },
+        );
+
+        methods.add_async_method(
+            "every",
+            async |_lua, this, (timer_name, seconds, data): (String, u64, mlua::Value)| {
+                if seconds < MIN_INTERVAL_SECONDS {
+                    return Err(mlua::Error::runtime(format!(
+                        "Repeating timers can't run more often than every {MIN_INTERVAL_SECONDS} seconds."
+                    )));
+                }
+
+                let next_time = seconds_from_now(seconds)?;
+                this.set_timer(
+                    timer_name,
+                    TimerSchedule::Interval { seconds },
+                    next_time,
+                    data,
+                )
+                .await
+            },
+        );
+
+        methods.add_async_method(
+            "daily",
+            async |_lua,
+                   this,
+                   (timer_name, time, timezone, data): (
+                String,
+                String,
+                String,
+                mlua::Value,
+            )| {
+                let (hour, minute) = parse_time_of_day(&time).map_err(mlua::Error::runtime)?;
+                let next_time = next_daily_time(hour, minute, &timezone, get_seconds())
+                    .map_err(mlua::Error::runtime)?;
+
+                this.set_timer(
+                    timer_name,
+                    TimerSchedule::Daily {
+                        hour,
+                        minute,
+                        timezone,
+                    },
+                    next_time,
+                    data,
+                )
+                .await
+            },
+        );
+
+        methods.add_async_method("cancel", async |_lua, this, timer_name: String| {
+            this.cancel_timer(&timer_name)
+                .await
+                .map_err(mlua::Error::runtime)
+        });
+
+        methods.add_async_method("list", async |lua, this, ()| {
+            let timers = this
+                .db
+                .get_guild_lua_timers(this.guild_id)
+                .await
+                .map_err(mlua::Error::runtime)?;
+
+            let table = lua.create_table()?;
+            for (timer_name, lua_timer) in timers {
+                table.set(timer_name, lua_timer.next_time)?;
+            }
+
+            Ok(table)
+        });
+    }
+}
+
+/// Loop that runs the lua timers of every guild when they're due.
+pub fn lua_timer_loop(
+    arc_ctx: Arc<Context>,
+    lua_manager: Arc<LuaManager>,
+    log_manager: Arc<LogManager>,
+) {
+    tokio::spawn(async move {
+        let db = &lua_manager.db;
+
+        loop {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+
+            let current_time = get_seconds();
+
+            {
+                let mut wait_until = lua_manager.timer_wait_until.lock().await;
+                if *wait_until > current_time || db.health() == DatabaseHealth::Down {
+                    continue;
+                }
+
+                // Timers added while this runs will lower it again.
+                *wait_until = u64::MAX;
+            }
+
+            let all_timers = match db.get_all_lua_timers().await {
+                Ok(all_timers) => all_timers,
+                Err(err) => {
+                    log_manager
+                        .add_owner_log(
+                            format!("Couldn't fetch the lua timers. {err}"),
+                            LogType::Warning,
+                            LogSource::Lua,
+                        )
+                        .await;
+                    *lua_manager.timer_wait_until.lock().await = current_time + 5;
+                    continue;
+                }
+            };
+
+            let mut next_wait_until = u64::MAX;
+
+            for (guild_id, timers) in all_timers {
+                for (timer_name, mut lua_timer) in timers {
+                    if lua_timer.next_time > current_time {
+                        next_wait_until = next_wait_until.min(lua_timer.next_time);
+                        continue;
+                    }
+
+                    let scheduled_time = lua_timer.next_time;
+
+                    // The bot might've been removed from the guild, in which case the reconciliation sweep removes the timers later.
+                    if arc_ctx.cache.guild(guild_id).is_some() {
+                        lua_manager.dispatch_event(
+                            guild_id,
+                            LuaEvent::Timer,
+                            json!({
+                                "name": timer_name,
+                                "data": lua_timer.data,
+                                "scheduled_time": scheduled_time,
+                            }),
+                        );
+                    }
+
+                    let result = match lua_timer.schedule.reschedule(scheduled_time, current_time) {
+                        Ok(Some(next_time)) => {
+                            lua_timer.next_time = next_time;
+                            next_wait_until = next_wait_until.min(next_time);
+                            db.set_lua_timer(guild_id, &timer_name, &lua_timer).await
+                        }
+                        Ok(None) => db.remove_lua_timer(guild_id, &timer_name).await,
+                        Err(err) => {
+                            log_manager
+                                .add_log(
+                                    IdType::GuildId(guild_id),
+                                    format!("Removing the timer {timer_name}. {err}"),
+                                    LogType::Error,
+                                    LogSource::Lua,
+                                )
+                                .await;
+                            db.remove_lua_timer(guild_id, &timer_name).await
+                        }
+                    };
+
+                    if let Err(err) = result {
+                        log_manager
+                            .add_log(
+                                IdType::GuildId(guild_id),
+                                format!("Couldn't reschedule the timer {timer_name}, so it might run again. {err}"),
+                                LogType::Error,
+                                LogSource::Lua,
+                            )
+                            .await;
+                    }
+                }
+            }
+
+            let mut wait_until = lua_manager.timer_wait_until.lock().await;
+            *wait_until = (*wait_until).min(next_wait_until);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn daily_timers_run_at_the_local_time() {
+        // 2024-01-15 00:00 UTC, when Stockholm is an hour ahead.
+        let next_time = next_daily_time(9, 0, "Europe/Stockholm", 1705276800).unwrap();
+        assert_eq!(next_time, 1705305600);
+    }
+
+    #[test]
+    fn daily_timers_skip_days_where_the_time_doesnt_exist() {
+        // Stockholm skips from 02:00 to 03:00 on 2024-03-31, so 02:30 only happens again on 2024-04-01.
+        let next_time = next_daily_time(2, 30, "Europe/Stockholm", 1711843200).unwrap();
+        assert_eq!(next_time, 1711931400);
+    }
+
+    #[test]
+    fn daily_timers_use_the_first_of_repeated_times() {
+        // Stockholm goes from 03:00 back to 02:00 on 2024-10-27, so 02:30 happens twice.
+        let next_time = next_daily_time(2, 30, "Europe/Stockholm", 1729987200).unwrap();
+        assert_eq!(next_time, 1729989000);
+    }
+
+    #[test]
+    fn daily_timers_need_a_valid_timezone() {
+        assert!(next_daily_time(9, 0, "Europe/Nowhere", 1705276800).is_err());
+    }
+
+    #[test]
+    fn intervals_skip_missed_runs() {
+        let schedule = TimerSchedule::Interval { seconds: 60 };
+
+        assert_eq!(schedule.reschedule(1000, 1000).unwrap(), Some(1060));
+        // Two runs were missed while the bot was offline.
+        assert_eq!(schedule.reschedule(1000, 1150).unwrap(), Some(1180));
+    }
+
+    #[test]
+    fn intervals_that_overflow_fail() {
+        let schedule = TimerSchedule::Interval { seconds: u64::MAX };
+
+        assert!(schedule.reschedule(10, 10).is_err());
+    }
+
+    #[test]
+    fn once_timers_dont_run_again() {
+        assert_eq!(TimerSchedule::Once.reschedule(1000, 1000).unwrap(), None);
+    }
+
+    #[test]
+    fn timer_data_cant_go_over_the_byte_limit() {
+        let half = json!("a".repeat(MAX_DATA_STORE_BYTES_PER_GUILD / 2));
+        let timers = HashMap::from([(
+            "first".to_string(),
+            LuaTimer {
+                schedule: TimerSchedule::Once,
+                next_time: 0,
+                data: half.clone(),
+            },
+        )]);
+
+        assert!(check_timer_data_size("second", &half, &timers).is_err());
+        // Replacing a timer doesn't count its old data.
+        assert!(check_timer_data_size("first", &half, &timers).is_ok());
+        assert!(check_timer_data_size("second", &json!({ "small": true }), &timers).is_ok());
+    }
+
+    #[test]
+    fn times_of_day_are_parsed() {
+        assert_eq!(parse_time_of_day("09:00").unwrap(), (9, 0));
+        assert_eq!(parse_time_of_day(" 23:59 ").unwrap(), (23, 59));
+
+        for time in ["24:00", "12:60", "9", "", "ab:cd", "-1:30"] {
+            assert!(parse_time_of_day(time).is_err(), "{time}");
+        }
+    }
+}
@@ -8,5 +8,6 @@ pub mod lua_manager;
 pub mod message_manager;
 pub mod profile_manager;
 pub mod reaction_manager;
+pub mod reconcile_manager;
 pub mod remind_manager;
 pub mod storage_manager;
@@ -6,7 +6,7 @@ use serde::{Deserialize, Serialize};
 use tokio::sync::{Mutex, RwLock};
 
 use crate::{
-    commands::utility_commands::profile::time_format::TimeFormat, managers::db::SurrealClient,
+    commands::utility_commands::profile::time_format::TimeFormat, managers::db::Database,
     utils::TtlMap, Error,
 };
 
@@ -50,7 +50,7 @@ impl ProfileDataHolder {
         }
     }
 
-    pub async fn get_profile(&mut self, db: &SurrealClient) -> Result<&mut ProfileData, Error> {
+    pub async fn get_profile(&mut self, db: &dyn Database) -> Result<&mut ProfileData, Error> {
         let profile_mut = &mut self.profile;
         match profile_mut {
             Some(profile) => return Ok(profile),
@@ -66,7 +66,7 @@ impl ProfileDataHolder {
     pub async fn update_profile(
         &mut self,
         profile: ProfileData,
-        db: &SurrealClient,
+        db: &dyn Database,
     ) -> Result<(), Error> {
         let user_id = self.user_id;
 
@@ -76,7 +76,7 @@ impl ProfileDataHolder {
         Ok(())
     }
 
-    pub async fn delete_profile(&mut self, db: &SurrealClient) -> Result<(), Error> {
+    pub async fn delete_profile(&mut self, db: &dyn Database) -> Result<(), Error> {
         db.delete_user_profile(self.user_id).await?;
         self.profile = Some(ProfileData::default());
         Ok(())
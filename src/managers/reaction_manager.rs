@@ -7,7 +7,7 @@ use tokio::sync::{Mutex, RwLock};
 
 use crate::{utils::TtlMap, Error};
 
-use super::{db::SurrealClient, message_manager::StoredMessageData};
+use super::{db::Database, message_manager::StoredMessageData};
 
 pub struct MessagesData {
     pub guild_id: GuildId,
@@ -24,7 +24,7 @@ impl MessagesData {
 
     pub async fn get_messages(
         &mut self,
-        db: &SurrealClient,
+        db: &dyn Database,
     ) -> Result<&mut HashMap<MessageId, StoredMessageData>, Error> {
         let messages_mut = &mut self.messages;
         match messages_mut {
@@ -42,7 +42,7 @@ impl MessagesData {
         &mut self,
         message_id: MessageId,
         message_data: StoredMessageData,
-        db: &SurrealClient,
+        db: &dyn Database,
     ) -> Result<(), Error> {
         let guild_id = self.guild_id;
         let messages = self.get_messages(db).await?;
@@ -55,7 +55,7 @@ impl MessagesData {
     pub async fn delete_message(
         &mut self,
         message_id: MessageId,
-        db: &SurrealClient,
+        db: &dyn Database,
     ) -> Result<(), Error> {
         let guild_id = self.guild_id;
         let messages = self.get_messages(db).await?;
@@ -66,7 +66,7 @@ impl MessagesData {
 }
 
 pub struct ReactionManager {
-    pub db: Arc<SurrealClient>,
+    pub db: Arc<dyn Database>,
     pub messages_data: RwLock<TtlMap<GuildId, Arc<Mutex<MessagesData>>>>,
 }
 
@@ -125,7 +125,7 @@ impl From<RoleId> for ReactionTypeOrRoleId {
 }
 
 impl ReactionManager {
-    pub fn new(db: Arc<SurrealClient>) -> Self {
+    pub fn new(db: Arc<dyn Database>) -> Self {
         Self {
             db,
             messages_data: RwLock::new(TtlMap::new(Duration::from_secs(60 * 60))),
@@ -184,15 +184,11 @@ impl ReactionManager {
             message_data = some_message_data;
         } else {
             message_data = StoredMessageData {
-                channel_id: Some(channel_id),
+                channel_id,
                 reaction_roles: HashMap::new(),
             }
         }
 
-        if message_data.needs_updating() {
-            message_data.channel_id = Some(channel_id);
-        }
-
         if let Some(role_id) = message_data.reaction_roles.get(&get_emoji_id(&emoji)) {
             return Err(ReactionError::EmojiTaken(*role_id));
         }
@@ -393,7 +389,7 @@ impl ReactionManager {
     pub async fn get_reaction_role_messages(
         &self,
         guild_id: GuildId,
-    ) -> Result<Vec<(MessageId, Option<ChannelId>, usize)>, ReactionError> {
+    ) -> Result<Vec<(MessageId, ChannelId, usize)>, ReactionError> {
         let db = &self.db;
 
         let messages_data = self.get_messages_data(guild_id).await;
@@ -464,14 +460,6 @@ impl ReactionManager {
             return Ok(());
         };
 
-        if guild_message.needs_updating() {
-            let mut guild_message = guild_message.clone();
-            guild_message.channel_id = Some(reaction.channel_id);
-            let _ = locked_messages_data
-                .add_or_replace_message(message_id, guild_message, db)
-                .await;
-        }
-
         let member = match guild_id.member(&ctx, user_id).await {
             Ok(ok) => ok,
             Err(err) => {
@@ -552,12 +540,6 @@ impl ReactionManager {
                 ));
             };
             return Err(ReactionError::BotReactionRemoved(removed_role_id, emoji_id));
-        } else if guild_message.needs_updating() {
-            let mut guild_message = guild_message.clone();
-            guild_message.channel_id = Some(reaction.channel_id);
-            let _ = locked_messages_data
-                .add_or_replace_message(message_id, guild_message, db)
-                .await;
         }
 
         let member = match guild_id.member(&ctx, user_id).await {
@@ -637,16 +619,10 @@ impl ReactionManager {
             return Ok(());
         };
 
-        if guild_message.reaction_roles.remove(&emoji_id).is_none()
-            && !guild_message.needs_updating()
-        {
+        if guild_message.reaction_roles.remove(&emoji_id).is_none() {
             return Ok(());
         }
 
-        if guild_message.needs_updating() {
-            guild_message.channel_id = Some(reaction.channel_id)
-        }
-
         if let Err(err) = locked_messages_data
             .add_or_replace_message(message_id, guild_message, db)
             .await
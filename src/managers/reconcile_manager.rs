@@ -0,0 +1,494 @@
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+
+use poise::serenity_prelude::{Context, EmojiId, GuildId, RoleId, UserId};
+
+use crate::{
+    managers::log_manager::{LogSource, LogType},
+    utils::{get_seconds, is_not_found, IdType},
+    Error,
+};
+
+use super::{
+    db::{Database, DatabaseHealth},
+    detector_manager::DetectorManager,
+    join_order_manager::JoinOrderManager,
+    log_manager::LogManager,
+    lua_manager::LuaManager,
+    reaction_manager::ReactionManager,
+    remind_manager::RemindManager,
+};
+
+/// How often stored data gets checked after the first check at startup.
+///
+/// This is also how often the data of guilds the bot has left gets checked for deletion,
+/// so the data may stay around for a bit longer than the grace period.
+const RECONCILE_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// Removes stored data that points at things that no longer exist on Discord.
+pub struct ReconcileManager {
+    db: Arc<dyn Database>,
+    reaction_manager: Arc<ReactionManager>,
+    remind_manager: Arc<RemindManager>,
+    lua_manager: Arc<LuaManager>,
+    detector_manager: Arc<DetectorManager>,
+    join_order_manager: Arc<JoinOrderManager>,
+    log_manager: Arc<LogManager>,
+    /// How long to keep the data of guilds the bot has left.
+    guild_data_grace_period: Duration,
+}
+
+impl ReconcileManager {
+    pub fn new(
+        db: Arc<dyn Database>,
+        reaction_manager: Arc<ReactionManager>,
+        remind_manager: Arc<RemindManager>,
+        lua_manager: Arc<LuaManager>,
+        detector_manager: Arc<DetectorManager>,
+        join_order_manager: Arc<JoinOrderManager>,
+        log_manager: Arc<LogManager>,
+        guild_data_grace_period: Duration,
+    ) -> Self {
+        Self {
+            db,
+            reaction_manager,
+            remind_manager,
+            lua_manager,
+            detector_manager,
+            join_order_manager,
+            log_manager,
+            guild_data_grace_period,
+        }
+    }
+
+    pub fn get_grace_period_hours(&self) -> u64 {
+        self.guild_data_grace_period.as_secs() / (60 * 60)
+    }
+
+    /// Drops everything cached about a guild. Used when the bot gets removed from a guild and when its data gets removed.
+    pub async fn drop_guild_caches(&self, guild_id: GuildId) {
+        let guild_lua_data = self.lua_manager.guild_data.write().await.remove(&guild_id);
+        if let Some(guild_lua_data) = guild_lua_data {
+            let mut lock = guild_lua_data.lock().await;
+            lock.restart(true);
+            lock.commands = None;
+        }
+
+        self.lua_manager.data_stores.write().await.remove(&guild_id);
+        self.lua_manager
+            .handled_events
+            .write()
+            .unwrap()
+            .remove(&guild_id);
+        self.reaction_manager
+            .messages_data
+            .write()
+            .await
+            .remove(&guild_id);
+        self.detector_manager
+            .detectors_data
+            .write()
+            .await
+            .remove(&IdType::GuildId(guild_id));
+        self.join_order_manager
+            .join_orders
+            .write()
+            .await
+            .remove(&guild_id);
+    }
+
+    /// Runs every check. Keeps going if one of them fails and returns the amount of things removed.
+    pub async fn reconcile(&self, ctx: &Context) -> Result<usize, Error> {
+        let mut removed = 0;
+        let mut errors = Vec::new();
+
+        match self.reconcile_left_guilds(ctx).await {
+            Ok(amount) => removed += amount,
+            Err(err) => errors.push(format!("Couldn't check for guilds I have left. {err}")),
+        }
+
+        match self.reconcile_reminders(ctx).await {
+            Ok(amount) => removed += amount,
+            Err(err) => errors.push(format!("Couldn't check reminders. {err}")),
+        }
+
+        match self.reconcile_reaction_roles(ctx).await {
+            Ok(amount) => removed += amount,
+            Err(err) => errors.push(format!("Couldn't check reaction roles. {err}")),
+        }
+
+        match self.reconcile_cotd_roles(ctx).await {
+            Ok(amount) => removed += amount,
+            Err(err) => errors.push(format!("Couldn't check cotd roles. {err}")),
+        }
+
+        if !errors.is_empty() {
+            return Err(errors.join("\n").into());
+        }
+
+        Ok(removed)
+    }
+
+    /// Removes everything stored about guilds the bot has been gone from for longer than the grace period.
+    ///
+    /// Guilds that the bot left while it was offline get marked as left, so they get removed later.
+    async fn reconcile_left_guilds(&self, ctx: &Context) -> Result<usize, Error> {
+        // Includes unavailable guilds, so an outage wont make it look like the bot left.
+        let current_guild_ids = ctx.cache.guilds().into_iter().collect::<HashSet<_>>();
+
+        if current_guild_ids.is_empty() {
+            // Most likely the cache isn't working, so it's not safe to remove anything.
+            return Ok(0);
+        }
+
+        let mut removed = 0;
+        let current_time = get_seconds();
+
+        for (guild_id, left_time) in self.db.get_stored_guilds().await? {
+            if current_guild_ids.contains(&guild_id) {
+                if left_time.is_some() {
+                    // The bot got added back.
+                    self.db.set_guild_left_time(guild_id, None).await?;
+                }
+                continue;
+            }
+
+            let Some(left_time) = left_time else {
+                self.db
+                    .set_guild_left_time(guild_id, Some(current_time))
+                    .await?;
+                self.log_manager
+                    .add_log(
+                        IdType::GuildId(guild_id),
+                        format!("I'm no longer in this guild. All the data about this guild will be removed in {} hours unless I'm added back.", self.get_grace_period_hours()),
+                        LogType::Info,
+                        LogSource::Guild,
+                    )
+                    .await;
+                continue;
+            };
+
+            if left_time + self.guild_data_grace_period.as_secs() > current_time {
+                continue;
+            }
+
+            let affected_user_ids = self.db.clear_bot_data(IdType::GuildId(guild_id)).await?;
+            self.clear_reminder_caches(&affected_user_ids).await;
+            self.drop_guild_caches(guild_id).await;
+
+            self.log_manager
+                .add_log(
+                    IdType::GuildId(guild_id),
+                    "Removed all the data about this guild because I'm no longer in it."
+                        .to_string(),
+                    LogType::Info,
+                    LogSource::Guild,
+                )
+                .await;
+            removed += 1;
+        }
+
+        Ok(removed)
+    }
+
+    /// Removes reminders in guilds where the user is no longer a member.
+    async fn reconcile_reminders(&self, ctx: &Context) -> Result<usize, Error> {
+        let mut is_member_cache: HashMap<(GuildId, UserId), bool> = HashMap::new();
+        let mut affected_user_ids = Vec::new();
+        let mut removed = 0;
+
+        for reminder in self.db.get_all_reminders().await? {
+            let Some(guild_id) = reminder.guild_id else {
+                continue;
+            };
+            let Some(reminder_id) = &reminder.id else {
+                continue;
+            };
+
+            if ctx.cache.guild(guild_id).is_none() {
+                continue;
+            }
+
+            let user_id = reminder.user_id;
+
+            let is_member = match is_member_cache.get(&(guild_id, user_id)) {
+                Some(is_member) => *is_member,
+                None => {
+                    let is_member = match guild_id.member(ctx, user_id).await {
+                        Ok(_) => true,
+                        // Only remove the reminder if discord says the member doesn't exist.
+                        Err(err) => !is_not_found(&err),
+                    };
+                    is_member_cache.insert((guild_id, user_id), is_member);
+                    is_member
+                }
+            };
+
+            if is_member {
+                continue;
+            }
+
+            self.db.delete_table_id(reminder_id).await?;
+            removed += 1;
+
+            if !affected_user_ids.contains(&user_id) {
+                affected_user_ids.push(user_id);
+            }
+
+            self.log_manager
+                .add_log(
+                    IdType::GuildId(guild_id),
+                    format!("Removed a reminder from <@{user_id}> because they're no longer in this guild."),
+                    LogType::Info,
+                    LogSource::Reminder,
+                )
+                .await;
+        }
+
+        self.clear_reminder_caches(&affected_user_ids).await;
+
+        Ok(removed)
+    }
+
+    /// Removes reaction roles whose message, role or emoji no longer exists.
+    async fn reconcile_reaction_roles(&self, ctx: &Context) -> Result<usize, Error> {
+        let db = &self.db;
+        let mut removed = 0;
+
+        for guild_id in ctx.cache.guilds() {
+            let Some((role_ids, emoji_ids)) = ctx.cache.guild(guild_id).map(|guild| {
+                (
+                    guild.roles.keys().copied().collect::<HashSet<RoleId>>(),
+                    guild.emojis.keys().copied().collect::<HashSet<EmojiId>>(),
+                )
+            }) else {
+                continue;
+            };
+
+            let messages_data = self.reaction_manager.get_messages_data(guild_id).await;
+
+            // Copy the messages so the lock isn't held while talking to discord.
+            let messages = messages_data.lock().await.get_messages(db).await?.clone();
+
+            for (message_id, message_data) in messages {
+                let mut reasons = Vec::new();
+                let mut message_deleted = false;
+
+                if let Err(err) = message_data.channel_id.message(ctx, message_id).await {
+                    message_deleted = is_not_found(&err);
+                }
+
+                // The emojis can be from any guild the bot is in, so they're looked for everywhere.
+                let mut deleted_emojis = HashSet::new();
+                if !message_deleted {
+                    for emoji in message_data.reaction_roles.keys() {
+                        if !emoji.chars().all(char::is_numeric) {
+                            continue;
+                        }
+
+                        let is_deleted = match EmojiId::from_str(emoji) {
+                            Ok(emoji_id) => {
+                                !emoji_ids.contains(&emoji_id)
+                                    && !custom_emoji_exists(ctx, emoji_id).await
+                            }
+                            Err(_) => true,
+                        };
+
+                        if is_deleted {
+                            deleted_emojis.insert(emoji.clone());
+                        }
+                    }
+                }
+
+                let removed_emojis = message_data
+                    .reaction_roles
+                    .iter()
+                    .filter(|(emoji, role_id)| {
+                        if message_deleted {
+                            return true;
+                        }
+
+                        if !role_ids.contains(role_id) {
+                            reasons.push(format!("the role {role_id} no longer exists"));
+                            return true;
+                        }
+
+                        if deleted_emojis.contains(*emoji) {
+                            reasons.push(format!("the emoji {emoji} no longer exists"));
+                            return true;
+                        }
+
+                        false
+                    })
+                    .map(|(emoji, _)| emoji.clone())
+                    .collect::<Vec<_>>();
+
+                if removed_emojis.is_empty() {
+                    continue;
+                }
+
+                let mut locked_messages_data = messages_data.lock().await;
+                let Some(mut current_message_data) = locked_messages_data
+                    .get_messages(db)
+                    .await?
+                    .get(&message_id)
+                    .cloned()
+                else {
+                    continue;
+                };
+
+                for emoji in removed_emojis.iter() {
+                    current_message_data.reaction_roles.remove(emoji);
+                }
+
+                if current_message_data.reaction_roles.is_empty() {
+                    locked_messages_data.delete_message(message_id, db).await?;
+                } else {
+                    locked_messages_data
+                        .add_or_replace_message(message_id, current_message_data, db)
+                        .await?;
+                }
+                drop(locked_messages_data);
+
+                let log_message = if message_deleted {
+                    format!("Removed {} reaction roles from message {message_id} because the message no longer exists.", removed_emojis.len())
+                } else {
+                    format!(
+                        "Removed {} reaction roles from message {message_id} because {}.",
+                        removed_emojis.len(),
+                        reasons.join(", ")
+                    )
+                };
+
+                self.log_manager
+                    .add_log(
+                        IdType::GuildId(guild_id),
+                        log_message,
+                        LogType::Info,
+                        LogSource::ReactionRole,
+                    )
+                    .await;
+                removed += removed_emojis.len();
+            }
+        }
+
+        Ok(removed)
+    }
+
+    /// Removes cotd roles whose role has been deleted.
+    async fn reconcile_cotd_roles(&self, ctx: &Context) -> Result<usize, Error> {
+        let mut removed = 0;
+
+        for cotd_role_data_query in self.db.get_all_guild_cotd_role().await? {
+            let Some(guild_id) = cotd_role_data_query
+                .id
+                .split(':')
+                .last()
+                .and_then(|id| GuildId::from_str(id).ok())
+            else {
+                continue;
+            };
+
+            let role_id = cotd_role_data_query.cotd_role.id;
+
+            let Some(role_exists) = ctx
+                .cache
+                .guild(guild_id)
+                .map(|guild| guild.roles.contains_key(&role_id))
+            else {
+                continue;
+            };
+
+            if role_exists {
+                continue;
+            }
+
+            self.db.update_guild_cotd_role(&None, guild_id).await?;
+
+            self.log_manager
+                .add_log(
+                    IdType::GuildId(guild_id),
+                    format!("Stopped updating the cotd role because the role {role_id} no longer exists."),
+                    LogType::Info,
+                    LogSource::CotdRole,
+                )
+                .await;
+            removed += 1;
+        }
+
+        Ok(removed)
+    }
+
+    async fn clear_reminder_caches(&self, user_ids: &[UserId]) {
+        let reminders_data_read = self.remind_manager.reminders_data.read().await;
+
+        for user_id in user_ids {
+            let Some(reminders_data) = reminders_data_read.get(user_id) else {
+                continue;
+            };
+            reminders_data.lock().await.reminders = None;
+        }
+    }
+}
+
+/// Bots can react with the emojis of every guild they're in, not just the guild of the message.
+///
+/// So an emoji only counts as deleted if no cached guild has it and every guild responds with 404.
+async fn custom_emoji_exists(ctx: &Context, emoji_id: EmojiId) -> bool {
+    let guild_ids = ctx.cache.guilds();
+
+    if guild_ids.iter().any(|guild_id| {
+        ctx.cache
+            .guild(*guild_id)
+            .is_some_and(|guild| guild.emojis.contains_key(&emoji_id))
+    }) {
+        return true;
+    }
+
+    for guild_id in guild_ids {
+        match ctx.http.get_emoji(guild_id, emoji_id).await {
+            Err(err) if is_not_found(&err) => {}
+            // Either it was found, or it's unknown if it exists.
+            _ => return true,
+        }
+    }
+
+    false
+}
+
+/// Loop that controls the reconcile manager.
+///
+/// Checks the stored data right away, and then every few hours.
+pub fn reconcile_manager_loop(
+    arc_ctx: Arc<Context>,
+    reconcile_manager: Arc<ReconcileManager>,
+    log_manager: Arc<LogManager>,
+) {
+    tokio::spawn(async move {
+        loop {
+            // There's no point in checking anything if the database can't be reached.
+            if reconcile_manager.db.health() != DatabaseHealth::Down {
+                match reconcile_manager.reconcile(&arc_ctx).await {
+                    Ok(removed) => {
+                        println!("Finished checking stored data. Removed {removed} things.")
+                    }
+                    Err(err) => {
+                        log_manager
+                            .add_owner_log(
+                                format!("Failed to check stored data. {err}"),
+                                LogType::Warning,
+                                LogSource::Database,
+                            )
+                            .await
+                    }
+                }
+            }
+
+            tokio::time::sleep(RECONCILE_INTERVAL).await;
+        }
+    });
+}
@@ -13,7 +13,10 @@ use crate::{
     Error,
 };
 
-use super::{db::SurrealClient, log_manager::LogManager};
+use super::{
+    db::{is_connection_error, Database},
+    log_manager::LogManager,
+};
 
 pub struct RemindersData {
     user_id: UserId,
@@ -30,7 +33,7 @@ impl RemindersData {
 
     pub async fn get_reminders(
         &mut self,
-        db: &SurrealClient,
+        db: &dyn Database,
     ) -> Result<&mut Vec<RemindInfo>, Error> {
         let reminders_mut = &mut self.reminders;
         match reminders_mut {
@@ -47,7 +50,7 @@ impl RemindersData {
     pub async fn add_reminder(
         &mut self,
         mut remind_info: RemindInfo,
-        db: &SurrealClient,
+        db: &dyn Database,
     ) -> Result<(), Error> {
         let user_id = self.user_id;
         assert_eq!(user_id, remind_info.user_id);
@@ -61,7 +64,7 @@ impl RemindersData {
         &mut self,
         removal_index: usize,
         guild_id: Option<GuildId>,
-        db: &SurrealClient,
+        db: &dyn Database,
     ) -> Result<RemindInfo, Error> {
         let reminders = self.get_reminders(db).await?;
 
@@ -97,7 +100,7 @@ impl RemindersData {
 }
 
 pub struct RemindManager {
-    db: Arc<SurrealClient>,
+    db: Arc<dyn Database>,
     pub reminders_data: RwLock<TtlMap<UserId, Arc<Mutex<RemindersData>>>>,
     pub wait_until: Mutex<u64>,
 }
@@ -119,7 +122,7 @@ pub struct RemindInfo {
 }
 
 impl RemindManager {
-    pub fn new(db: Arc<SurrealClient>) -> Self {
+    pub fn new(db: Arc<dyn Database>) -> Self {
         RemindManager {
             db,
             reminders_data: RwLock::new(TtlMap::new(Duration::from_secs(60 * 60))),
@@ -382,17 +385,12 @@ pub fn remind_manager_loop(
                         reminder_info.finish_time + wait_time * missed_reminders;
                     reminder_info.finish_time = reminder_info.request_time + wait_time;
 
-                    let json_string = serde_json::to_string(&reminder_info).unwrap();
-
                     loop {
-                        let res = db
-                            .query(format!("UPDATE {reminder_id} CONTENT {json_string}"))
-                            .await;
-                        if let Ok(ok) = res {
-                            let Some(err) = ok.take_err(0) else {
-                                break;
-                            };
-                            // this isn't a connection issue and shouldn't happen.
+                        let Err(err) = db.update_reminder(reminder_info).await else {
+                            break;
+                        };
+                        if !is_connection_error(&err) {
+                            // this isn't a connection issue and shouldn't happen
                             let _ = log_manager
                                 .add_owner_log(
                                     format!("Failed to update looped reminder. {err}"),
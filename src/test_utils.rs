@@ -0,0 +1,70 @@
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpListener,
+    thread,
+};
+
+/// A request that was sent to a server made with `start_server`.
+pub struct ServerRequest {
+    pub path: String,
+    pub body: Vec<u8>,
+}
+
+/// Starts an http server on a random local port and returns its url.
+///
+/// Every request gets a 200 response with the body that `respond` returns for it.
+pub fn start_server(respond: impl Fn(ServerRequest) -> Vec<u8> + Send + 'static) -> String {
+    start_server_with_status(move |request| (200, respond(request)))
+}
+
+/// Like `start_server`, but `respond` also picks the status code of each response.
+pub fn start_server_with_status(
+    respond: impl Fn(ServerRequest) -> (u16, Vec<u8>) + Send + 'static,
+) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else {
+                continue;
+            };
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            let path = request_line
+                .split(' ')
+                .nth(1)
+                .unwrap_or_default()
+                .to_string();
+
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap() == 0 || line == "\r\n" {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+
+            let (status, response_body) = respond(ServerRequest { path, body });
+
+            let _ = write!(
+                stream,
+                "HTTP/1.1 {status} Status\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                response_body.len()
+            );
+            let _ = stream.write_all(&response_body);
+        }
+    });
+
+    format!("http://{address}")
+}
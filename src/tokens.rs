@@ -11,7 +11,26 @@ pub struct BotSettings {
     pub open_exchange_rates_token: Option<String>,
     pub owner_user_ids: Vec<UserId>,
     pub temp_data_directory: PathBuf,
-    pub surrealdb: SurrealDbSignInInfo,
+    /// If not provided, the bot will use a local database instead.
+    pub surrealdb: Option<SurrealDbSignInInfo>,
+    /// Where the local database gets saved. If not provided, the local database only lives in memory.
+    #[serde(default)]
+    pub local_database_file: Option<PathBuf>,
+    /// How long to keep the data of a guild after the bot gets removed from it, in case it gets added back.
+    #[serde(default = "default_guild_data_grace_period_hours")]
+    pub guild_data_grace_period_hours: u64,
+    /// Sent to the system channel of guilds that add the bot.
+    #[serde(default)]
+    pub onboarding_message: Option<String>,
+    #[serde(default)]
+    pub lua_limits: LuaLimits,
+    /// Lets lua code make http requests to the allowed domains. If not provided, lua code can't make http requests.
+    #[serde(default)]
+    pub lua_http: Option<LuaHttpSettings>,
+}
+
+fn default_guild_data_grace_period_hours() -> u64 {
+    72
 }
 
 #[derive(Debug, Deserialize)]
@@ -21,6 +40,83 @@ pub struct SurrealDbSignInInfo {
     pub database: String,
     pub username: String,
     pub password: String,
+    #[serde(default)]
+    pub retry: SurrealRetrySettings,
+}
+
+/// Controls how the bot deals with the database being unreachable.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct SurrealRetrySettings {
+    /// How many times a read gets retried after failing to reach the database.
+    pub max_retries: u32,
+    /// How long to wait before the first retry. Doubles with every retry.
+    pub base_delay_ms: u64,
+    /// The longest the bot will wait between retries.
+    pub max_delay_ms: u64,
+    /// How many failed requests in a row it takes for the database to be considered down.
+    pub failure_threshold: u32,
+    /// While the database is down, queries fail immediately. After this many seconds a query is let through to check if it's back.
+    pub cooldown_secs: u64,
+}
+
+impl Default for SurrealRetrySettings {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            base_delay_ms: 200,
+            max_delay_ms: 2000,
+            failure_threshold: 5,
+            cooldown_secs: 30,
+        }
+    }
+}
+
+/// Limits for the lua code that guilds run.
+#[derive(Debug, Deserialize, Clone, Copy)]
+#[serde(default)]
+pub struct LuaLimits {
+    /// How much CPU time a single command or event handler can use.
+    pub cpu_time_ms: u64,
+    /// How long a single command or event handler can run, including time spent waiting.
+    pub run_time_secs: u64,
+    /// How much memory the lua instance of a guild can use.
+    pub memory_limit_mb: usize,
+}
+
+impl Default for LuaLimits {
+    fn default() -> Self {
+        Self {
+            cpu_time_ms: 1000,
+            run_time_secs: 60,
+            memory_limit_mb: 32,
+        }
+    }
+}
+
+/// Limits for the http requests that lua code makes.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct LuaHttpSettings {
+    /// The domains lua code can make requests to. Subdomains of them are allowed too.
+    pub allowed_domains: Vec<String>,
+    /// How many requests the lua code of a guild can make per minute.
+    pub requests_per_minute: u32,
+    /// How long a request can take, including reading the response.
+    pub timeout_secs: u64,
+    /// The biggest response body lua code can receive.
+    pub max_response_bytes: usize,
+}
+
+impl Default for LuaHttpSettings {
+    fn default() -> Self {
+        Self {
+            allowed_domains: Vec::new(),
+            requests_per_minute: 30,
+            timeout_secs: 10,
+            max_response_bytes: 1_000_000,
+        }
+    }
 }
 
 pub fn get_bot_settings() -> BotSettings {
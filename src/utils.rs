@@ -5,7 +5,7 @@ use std::{
     time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-use poise::serenity_prelude::{GuildId, UserId};
+use poise::serenity_prelude::{self, GuildId, HttpError, UserId};
 
 pub fn get_seconds() -> u64 {
     let start = SystemTime::now();
@@ -15,6 +15,20 @@ pub fn get_seconds() -> u64 {
 
     since_the_epoch.as_secs()
 }
+
+/// Checks if discord responded with 404, meaning the thing that was requested doesn't exist.
+///
+/// Other errors, such as missing permissions, don't mean that it's gone.
+pub fn is_not_found(error: &serenity_prelude::Error) -> bool {
+    match error {
+        serenity_prelude::Error::Http(HttpError::UnsuccessfulRequest(err)) => {
+            const NOT_FOUND: u16 = 404;
+            err.status_code == NOT_FOUND
+        }
+        _ => false,
+    }
+}
+
 #[derive(Eq, Hash, PartialEq, Clone, Copy)]
 pub enum IdType {
     UserId(UserId),
@@ -98,6 +112,10 @@ where
         self.map.contains_key(k)
     }
 
+    pub fn remove(&mut self, k: &K) -> Option<V> {
+        self.map.remove(k).map(|(value, _)| value)
+    }
+
     pub fn clear(&mut self) {
         self.map.clear();
     }